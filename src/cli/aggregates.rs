@@ -0,0 +1,95 @@
+use std::{error::Error, io::Write};
+
+use rust_decimal::Decimal;
+use toy_payments_engine::engine::{Engine, Totals};
+
+use crate::cli::{
+    metadata::{ClientMetadata, segment_label},
+    output::OutputScale,
+};
+
+#[derive(serde::Serialize)]
+struct AggregateRow {
+    segment: String,
+    clients: usize,
+    locked: usize,
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+}
+
+/// Writes the grand total (`total`) followed by a subtotal for every value of
+/// every metadata column (`region=eu`).
+pub fn write<W: Write>(
+    w: W,
+    engine: &Engine,
+    metadata: Option<&ClientMetadata>,
+    scale: OutputScale,
+) -> Result<(), Box<dyn Error>> {
+    let mut wtr = csv::Writer::from_writer(w);
+    let mut write_row = |segment: String, totals: Totals| {
+        wtr.serialize(AggregateRow {
+            segment,
+            clients: totals.clients,
+            locked: totals.locked,
+            available: scale.apply(totals.available),
+            held: scale.apply(totals.held),
+            total: scale.apply(totals.total),
+        })
+    };
+
+    write_row("total".to_string(), engine.totals())?;
+    if let Some(metadata) = metadata {
+        for column in metadata.columns() {
+            for (label, totals) in metadata.segments(engine, column)? {
+                write_row(format!("{column}={}", segment_label(label)), totals)?;
+            }
+        }
+    }
+    wtr.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+    use toy_payments_engine::types::transactions::{ChargebackTx, DepositTx, DisputeTx, Tx};
+
+    #[test]
+    fn test_grand_total_row() {
+        let mut engine = Engine::new();
+        let txs = [
+            Tx::Deposit(DepositTx::new(1, 1, dec!(10)).unwrap()),
+            Tx::Deposit(DepositTx::new(2, 2, dec!(4)).unwrap()),
+            Tx::Deposit(DepositTx::new(2, 3, dec!(1)).unwrap()),
+            Tx::Dispute(DisputeTx {
+                client_id: 2,
+                tx_id: 2,
+            }),
+            Tx::Dispute(DisputeTx {
+                client_id: 2,
+                tx_id: 3,
+            }),
+            Tx::Chargeback(ChargebackTx {
+                client_id: 2,
+                tx_id: 3,
+            }),
+        ];
+        for tx in txs {
+            engine.process_tx(tx).unwrap();
+        }
+
+        let mut buf = Vec::new();
+        write(&mut buf, &engine, None, OutputScale(Some(2))).unwrap();
+
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "\
+segment,clients,locked,available,held,total
+total,2,1,10.00,4.00,14.00
+"
+        );
+    }
+}
@@ -0,0 +1,181 @@
+use std::{
+    error::Error,
+    fs::{File, OpenOptions},
+    path::Path,
+};
+
+use rust_decimal::Decimal;
+use toy_payments_engine::{
+    engine::{
+        Engine,
+        alerts::{AlertMonitor, Threshold},
+    },
+    pipeline::results::{Outcome, RowResult},
+    types::common::TxId,
+};
+
+use crate::cli::pseudonym::{ClientIds, ClientLabel};
+
+#[derive(serde::Serialize)]
+struct AlertRow {
+    line: Option<u64>,
+    client: ClientLabel,
+    tx: TxId,
+    threshold: String,
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+}
+
+/// Checks every applied row against the `--alert` thresholds, reporting
+/// crossings to stderr right away and optionally to a CSV file.
+pub struct Alerts {
+    monitor: AlertMonitor,
+    /// Unbuffered, every alert reaches the file as soon as it is raised
+    wtr: Option<csv::Writer<File>>,
+    count: u64,
+    /// Print every alert to stderr as well
+    echo: bool,
+    ids: ClientIds,
+}
+
+impl Alerts {
+    /// `engine` is the state the run starts from, what it already crosses
+    /// isn't reported again.
+    pub fn new(
+        thresholds: Vec<Threshold>,
+        path: Option<&Path>,
+        append: bool,
+        engine: &Engine,
+    ) -> Result<Self, Box<dyn Error>> {
+        let wtr = match path {
+            Some(path) => {
+                let file = OpenOptions::new()
+                    .write(true)
+                    .create(true)
+                    .append(append)
+                    .truncate(!append)
+                    .open(path)?;
+                let has_content = file.metadata()?.len() > 0;
+                Some(
+                    csv::WriterBuilder::new()
+                        .has_headers(!has_content)
+                        .from_writer(file),
+                )
+            }
+            None => None,
+        };
+
+        let mut monitor = AlertMonitor::new(thresholds);
+        monitor.arm(engine.clients_iter());
+
+        Ok(Alerts {
+            monitor,
+            wtr,
+            count: 0,
+            echo: true,
+            ids: ClientIds::default(),
+        })
+    }
+
+    /// Only writes the alerts to the file, if there is one.
+    pub fn quiet(mut self, quiet: bool) -> Self {
+        self.echo = !quiet;
+        self
+    }
+
+    pub fn client_ids(mut self, ids: ClientIds) -> Self {
+        self.ids = ids;
+        self
+    }
+
+    pub fn record(&mut self, engine: &Engine, result: &RowResult) -> csv::Result<()> {
+        let (Outcome::Applied, Some(tx)) = (result.outcome, result.tx) else {
+            return Ok(());
+        };
+        let Some(client) = engine.client(tx.client_id()) else {
+            return Ok(());
+        };
+
+        let mut crossed = Vec::new();
+        self.monitor
+            .check(client, |threshold| crossed.push(*threshold));
+        let label = self.ids.label(client.id);
+        for threshold in crossed {
+            self.count += 1;
+            if self.echo {
+                eprintln!(
+                    "alert: client {} crossed {threshold} at tx {} (available {}, held {}, total {})",
+                    label,
+                    tx.tx_id(),
+                    client.available,
+                    client.held,
+                    client.total
+                );
+            }
+            if let Some(wtr) = self.wtr.as_mut() {
+                wtr.serialize(AlertRow {
+                    line: result.line,
+                    client: label,
+                    tx: tx.tx_id(),
+                    threshold: threshold.to_string(),
+                    available: client.available,
+                    held: client.held,
+                    total: client.total,
+                })?;
+                wtr.flush()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+    use toy_payments_engine::{
+        pipeline::InputOffset,
+        types::transactions::{DepositTx, Tx},
+    };
+
+    #[test]
+    fn test_alerts_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("alerts.csv");
+        let mut engine = Engine::new();
+        let mut alerts = Alerts::new(
+            vec!["total>10".parse().unwrap()],
+            Some(&path),
+            false,
+            &engine,
+        )
+        .unwrap();
+
+        for (tx_id, amount) in [(1, dec!(6)), (2, dec!(6)), (3, dec!(6))] {
+            let tx = Tx::Deposit(DepositTx::new(1, tx_id, amount).unwrap());
+            engine.process_tx(tx).unwrap();
+            let result = RowResult {
+                line: Some(u64::from(tx_id) + 1),
+                tx_id: Some(tx_id),
+                tx: Some(tx),
+                outcome: Outcome::Applied,
+                sequence: None,
+                position: InputOffset::default(),
+                dequeued: false,
+            };
+            alerts.record(&engine, &result).unwrap();
+        }
+
+        assert_eq!(alerts.count(), 1);
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "line,client,tx,threshold,available,held,total\n3,1,2,total>10,12,0,12\n"
+        );
+    }
+}
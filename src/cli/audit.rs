@@ -0,0 +1,295 @@
+//! Tamper-evident audit logs: CSV files whose every entry ends with the
+//! HMAC-SHA256 of the previous entry (`prev_hash`) and its own (`hash`), taken
+//! under a secret key over the entry's CSV encoding up to and including
+//! `prev_hash`. The first entry's `prev_hash` is the HMAC of the header, so
+//! the columns are covered too. Editing, inserting or removing an entry breaks
+//! the chain from there on, `tpe audit verify` finds where, and without the
+//! key the chain can't be recomputed to hide it. Only cutting entries off the
+//! end keeps a valid chain, which is caught by comparing the head hash with
+//! one noted earlier.
+
+use std::{
+    error::Error,
+    fs::{File, OpenOptions},
+    io::{self, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use clap::{Args, Subcommand};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+/// Shortest key accepted, in bytes.
+const MIN_KEY: usize = 16;
+
+#[derive(Debug, Args)]
+pub struct AuditArgs {
+    #[command(subcommand)]
+    pub command: AuditCommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum AuditCommand {
+    /// Check that no entry of an audit log was edited, inserted or removed
+    Verify {
+        /// Audit log, e.g. written by `tpe revert --audit-log`
+        log: PathBuf,
+
+        /// File whose bytes are the key the log was written with
+        #[arg(long, value_name = "PATH")]
+        key_file: PathBuf,
+    },
+}
+
+/// The secret key entries are hashed under.
+#[derive(Clone)]
+pub struct AuditKey(Hmac<Sha256>);
+
+impl AuditKey {
+    /// The key in `path`, its bytes as they are (at least 16).
+    pub fn read(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let key = std::fs::read(path).map_err(|err| format!("{}: {err}", path.display()))?;
+        if key.len() < MIN_KEY {
+            return Err(From::from(format!(
+                "{}: the audit key must be at least {MIN_KEY} bytes",
+                path.display()
+            )));
+        }
+        Ok(AuditKey::new(&key))
+    }
+
+    pub fn new(key: &[u8]) -> Self {
+        AuditKey(Hmac::new_from_slice(key).expect("HMAC takes keys of any length"))
+    }
+
+    fn hash(&self, fields: &csv::StringRecord) -> csv::Result<String> {
+        let mut wtr = csv::Writer::from_writer(Vec::new());
+        wtr.write_record(fields)?;
+        let line = wtr.into_inner().map_err(|err| err.into_error())?;
+        let mut mac = self.0.clone();
+        mac.update(&line);
+        Ok(mac
+            .finalize()
+            .into_bytes()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect())
+    }
+}
+
+/// Number of entries and the hash of the last one.
+#[derive(Debug, PartialEq)]
+pub struct Chain {
+    pub entries: usize,
+    pub head: String,
+}
+
+/// Walks the chain, failing at the first entry that doesn't follow from the one before.
+pub fn verify<R: Read>(mut rdr: csv::Reader<R>, key: &AuditKey) -> Result<Chain, Box<dyn Error>> {
+    let headers = rdr.headers()?.clone();
+    let n = headers.len();
+    if n < 2 || &headers[n - 2] != "prev_hash" || &headers[n - 1] != "hash" {
+        return Err(
+            "not a hash-chained audit log, the last columns must be prev_hash and hash".into(),
+        );
+    }
+
+    let mut chain = Chain {
+        entries: 0,
+        head: key.hash(&headers)?,
+    };
+    for record in rdr.records() {
+        let mut record = record?;
+        let line = record.position().map_or(0, |p| p.line());
+        if record[n - 2] != chain.head {
+            return Err(format!(
+                "line {line}: prev_hash doesn't match the entry before, an entry was removed or inserted, or the header was edited"
+            )
+            .into());
+        }
+        let stored = record[n - 1].to_string();
+        record.truncate(n - 1);
+        if key.hash(&record)? != stored {
+            return Err(format!(
+                "line {line}: hash doesn't match the entry, it was edited or the key is wrong"
+            )
+            .into());
+        }
+        chain.entries += 1;
+        chain.head = stored;
+    }
+    Ok(chain)
+}
+
+/// Appends entries to a chain.
+pub struct AuditLog<W: Write> {
+    wtr: csv::Writer<W>,
+    key: AuditKey,
+    head: String,
+}
+
+impl AuditLog<BufWriter<File>> {
+    /// Opens the log at `path` for appending, creating it with `columns` (plus
+    /// `prev_hash` and `hash`) as the header. An existing log is verified first,
+    /// a broken chain or one with other columns isn't extended.
+    pub fn open(path: &Path, columns: &[&str], key: AuditKey) -> Result<Self, Box<dyn Error>> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let head = if file.metadata()?.len() > 0 {
+            let mut rdr = csv::Reader::from_path(path)?;
+            let headers = rdr.headers()?;
+            if !headers
+                .iter()
+                .eq(columns.iter().chain(&["prev_hash", "hash"]).copied())
+            {
+                return Err(format!(
+                    "{}: the columns aren't {},prev_hash,hash, it's another log",
+                    path.display(),
+                    columns.join(",")
+                )
+                .into());
+            }
+            let chain = verify(rdr, &key).map_err(|err| format!("{}: {err}", path.display()))?;
+            Some(chain.head)
+        } else {
+            None
+        };
+        Ok(AuditLog::new(BufWriter::new(file), columns, key, head)?)
+    }
+}
+
+impl<W: Write> AuditLog<W> {
+    /// A log continuing after `head`, or a new one when there's no head yet.
+    pub fn new(w: W, columns: &[&str], key: AuditKey, head: Option<String>) -> csv::Result<Self> {
+        let mut wtr = csv::Writer::from_writer(w);
+        let head = match head {
+            Some(head) => head,
+            None => {
+                let headers: csv::StringRecord =
+                    columns.iter().chain(&["prev_hash", "hash"]).collect();
+                wtr.write_record(&headers)?;
+                key.hash(&headers)?
+            }
+        };
+        Ok(AuditLog { wtr, key, head })
+    }
+
+    pub fn append<I, T>(&mut self, fields: I) -> csv::Result<()>
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<str>,
+    {
+        let mut record = csv::StringRecord::new();
+        for field in fields {
+            record.push_field(field.as_ref());
+        }
+        record.push_field(&self.head);
+        let hash = self.key.hash(&record)?;
+        record.push_field(&hash);
+        self.wtr.write_record(&record)?;
+        self.head = hash;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.wtr.flush()
+    }
+
+    #[cfg(test)]
+    pub fn into_inner(self) -> io::Result<W> {
+        self.wtr.into_inner().map_err(|err| err.into_error())
+    }
+}
+
+pub fn run(args: AuditArgs) -> Result<(), Box<dyn Error>> {
+    let AuditCommand::Verify { log, key_file } = args.command;
+    let key = AuditKey::read(&key_file)?;
+    let chain = verify(csv::Reader::from_path(&log)?, &key)
+        .map_err(|err| format!("{}: {err}", log.display()))?;
+    println!("ok: {} entries, head {}", chain.entries, chain.head);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key() -> AuditKey {
+        AuditKey::new(b"0123456789abcdef")
+    }
+
+    fn log(entries: &[[&str; 2]]) -> String {
+        let mut log = AuditLog::new(Vec::new(), &["tx", "note"], key(), None).unwrap();
+        for entry in entries {
+            log.append(entry).unwrap();
+        }
+        String::from_utf8(log.into_inner().unwrap()).unwrap()
+    }
+
+    fn check(log: &str) -> Result<Chain, String> {
+        verify(csv::Reader::from_reader(log.as_bytes()), &key()).map_err(|err| err.to_string())
+    }
+
+    #[test]
+    fn test_chain_verifies_and_continues() {
+        let text = log(&[["1", "first"], ["2", "a, quoted \"note\""]]);
+        let chain = check(&text).unwrap();
+        assert_eq!(chain.entries, 2);
+
+        let mut appended = AuditLog::new(Vec::new(), &[], key(), Some(chain.head)).unwrap();
+        appended.append(["3", "third"]).unwrap();
+        let text = text + &String::from_utf8(appended.into_inner().unwrap()).unwrap();
+        assert_eq!(check(&text).unwrap().entries, 3);
+        assert_eq!(check(&log(&[])).unwrap().entries, 0);
+    }
+
+    #[test]
+    fn test_edits_and_removals_are_found() {
+        let text = log(&[["1", "first"], ["2", "second"], ["3", "third"]]);
+        let lines: Vec<&str> = text.lines().collect();
+
+        let edited = text.replacen("second", "sec0nd", 1);
+        assert_eq!(
+            check(&edited).unwrap_err(),
+            "line 3: hash doesn't match the entry, it was edited or the key is wrong"
+        );
+        let removed = [lines[0], lines[1], lines[3]].join("\n");
+        assert_eq!(
+            check(&removed).unwrap_err(),
+            "line 3: prev_hash doesn't match the entry before, an entry was removed or inserted, or the header was edited"
+        );
+        assert!(check("at,tx,note\n1,2,x\n").is_err());
+
+        // The header is chained into the first entry
+        let renamed = text.replacen("tx,note", "tx,memo", 1);
+        assert!(
+            check(&renamed)
+                .unwrap_err()
+                .starts_with("line 2: prev_hash")
+        );
+        // Without the key the chain can't be recomputed
+        let rdr = csv::Reader::from_reader(text.as_bytes());
+        assert!(verify(rdr, &AuditKey::new(b"fedcba9876543210")).is_err());
+    }
+
+    #[test]
+    fn test_open_checks_the_columns() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.csv");
+        let mut log = AuditLog::open(&path, &["tx", "note"], key()).unwrap();
+        log.append(["1", "first"]).unwrap();
+        log.flush().unwrap();
+        drop(log);
+
+        let mut log = AuditLog::open(&path, &["tx", "note"], key()).unwrap();
+        log.append(["2", "second"]).unwrap();
+        log.flush().unwrap();
+        drop(log);
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(check(&text).unwrap().entries, 2);
+
+        let err = AuditLog::open(&path, &["at", "tx", "note"], key())
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("it's another log"), "{err}");
+    }
+}
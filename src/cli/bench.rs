@@ -0,0 +1,352 @@
+use std::{
+    error::Error,
+    fmt::Write as _,
+    fs::File,
+    io::{self, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use clap::{Args, Subcommand};
+use toy_payments_engine::{engine::Engine, pipeline::source::CsvSource};
+
+use crate::cli::output::Balances;
+
+const STAGES: [&str; 5] = ["read", "parse", "convert", "apply", "write"];
+
+#[derive(Debug, Args)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+pub struct BenchArgs {
+    #[command(subcommand)]
+    pub command: Option<BenchCommand>,
+
+    /// Transactions CSV to process
+    #[arg(required = true)]
+    pub input: Option<PathBuf>,
+
+    /// Number of times to process the input, the breakdown is their mean
+    #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u32).range(1..))]
+    pub iterations: u32,
+
+    /// Also write the mean stage times and throughput as JSON, for `bench compare`
+    #[arg(long, value_name = "PATH")]
+    pub json: Option<PathBuf>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum BenchCommand {
+    /// Compare two `bench --json` results and fail if the throughput regressed
+    Compare(CompareArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct CompareArgs {
+    /// Result of the reference build
+    pub baseline: PathBuf,
+
+    /// Result of the build under test
+    pub current: PathBuf,
+
+    /// Largest drop in rows per second that still passes, e.g. `5%`
+    #[arg(long, value_name = "PERCENT", default_value = "5%", value_parser = parse_percent)]
+    pub max_regression: f64,
+}
+
+/// What `bench --json` writes: the mean of every stage over the iterations.
+#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct BenchResult {
+    pub input: PathBuf,
+    pub iterations: u32,
+    pub rows: u64,
+    pub stages: Vec<StageMean>,
+    /// Seconds, the sum of the stages
+    pub total: f64,
+    pub rows_per_sec: f64,
+}
+
+#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct StageMean {
+    pub stage: String,
+    /// Seconds
+    pub mean: f64,
+}
+
+impl BenchResult {
+    fn new(input: &Path, iterations: &[Iteration]) -> Self {
+        let n = iterations.len() as u32;
+        let mean = |stage: usize| iterations.iter().map(|i| i.stages[stage]).sum::<Duration>() / n;
+        let total: Duration = (0..STAGES.len()).map(mean).sum();
+        let rows = iterations.first().map_or(0, |i| i.rows);
+
+        BenchResult {
+            input: input.to_path_buf(),
+            iterations: n,
+            rows,
+            stages: STAGES
+                .iter()
+                .enumerate()
+                .map(|(stage, name)| StageMean {
+                    stage: name.to_string(),
+                    mean: mean(stage).as_secs_f64(),
+                })
+                .collect(),
+            total: total.as_secs_f64(),
+            rows_per_sec: rows as f64 / total.as_secs_f64().max(f64::EPSILON),
+        }
+    }
+
+    fn read(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let file = File::open(path).map_err(|err| format!("{}: {err}", path.display()))?;
+        serde_json::from_reader(BufReader::new(file))
+            .map_err(|err| From::from(format!("{}: {err}", path.display())))
+    }
+}
+
+/// Rows and time per stage of one pass over the input.
+#[derive(Debug, Default)]
+struct Iteration {
+    rows: u64,
+    stages: [Duration; STAGES.len()],
+}
+
+/// Processes the input `--iterations` times on a single thread, timing every
+/// stage separately, and prints the mean time of each stage.
+pub fn run(args: BenchArgs) -> Result<(), Box<dyn Error>> {
+    if let Some(BenchCommand::Compare(args)) = args.command {
+        return compare(args);
+    }
+    let input = args.input.expect("required without a subcommand");
+
+    let mut iterations = Vec::new();
+    for i in 1..=args.iterations {
+        let iteration = iteration(&input)?;
+        let total: Duration = iteration.stages.iter().sum();
+        eprintln!(
+            "bench: iteration {i}: {} rows in {:.3}s",
+            iteration.rows,
+            total.as_secs_f64()
+        );
+        iterations.push(iteration);
+    }
+    let result = BenchResult::new(&input, &iterations);
+    print!("{}", report(&result));
+    if let Some(path) = &args.json {
+        let mut w = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut w, &result)?;
+        writeln!(w)?;
+        w.flush()?;
+    }
+    Ok(())
+}
+
+fn iteration(input: &Path) -> Result<Iteration, Box<dyn Error>> {
+    // Rows are parsed on this thread instead of the pipeline's parser thread,
+    // so the stage times add up to the wall time
+    let mut source = CsvSource::open(input)?.timed(true);
+    let mut engine = Engine::new();
+    let mut apply = Duration::ZERO;
+    let mut rows = 0;
+    for row in &mut source {
+        rows += 1;
+        if let Some(tx) = row.tx {
+            let start = Instant::now();
+            let _ = engine.process_tx(tx);
+            apply += start.elapsed();
+        }
+    }
+
+    // Formatting only, so the terminal or disk doesn't skew the result
+    let start = Instant::now();
+    Balances::default().write(io::sink(), &engine)?;
+    let write = start.elapsed();
+
+    let read = source.timings().unwrap_or_default();
+    Ok(Iteration {
+        rows,
+        stages: [read.read, read.parse, read.convert, apply, write],
+    })
+}
+
+fn report(result: &BenchResult) -> String {
+    let mut report = format!("{:<8} {:>10} {:>6}\n", "stage", "mean", "share");
+    for StageMean { stage, mean } in &result.stages {
+        let share = match result.total {
+            0.0 => 0.0,
+            total => mean / total * 100.0,
+        };
+        let _ = writeln!(report, "{stage:<8} {mean:>9.3}s {share:>5.1}%");
+    }
+    let _ = writeln!(report, "{:<8} {:>9.3}s", "total", result.total);
+    let _ = writeln!(
+        report,
+        "{} rows, {:.0} rows/s",
+        result.rows, result.rows_per_sec
+    );
+    report
+}
+
+/// Prints both results side by side and fails if the current throughput is
+/// more than `--max-regression` below the baseline's.
+fn compare(args: CompareArgs) -> Result<(), Box<dyn Error>> {
+    let baseline = BenchResult::read(&args.baseline)?;
+    let current = BenchResult::read(&args.current)?;
+    let (report, regression) = comparison(&baseline, &current)?;
+    print!("{report}");
+
+    if regression > args.max_regression {
+        return Err(From::from(format!(
+            "throughput regressed by {regression:.1}%, more than the {}% allowed",
+            args.max_regression
+        )));
+    }
+    Ok(())
+}
+
+/// The side by side report and how many percent slower `current` is, negative
+/// when it is faster.
+fn comparison(baseline: &BenchResult, current: &BenchResult) -> Result<(String, f64), String> {
+    // Throughput over different inputs says nothing about the build
+    if baseline.rows != current.rows {
+        return Err(format!(
+            "the results are over different inputs ({} and {} rows)",
+            baseline.rows, current.rows
+        ));
+    }
+    let change = |before: f64, after: f64| match before {
+        0.0 => 0.0,
+        before => (after - before) / before * 100.0,
+    };
+
+    let mut report = format!(
+        "{:<8} {:>10} {:>10} {:>8}\n",
+        "stage", "baseline", "current", "change"
+    );
+    for stage in &baseline.stages {
+        let Some(after) = current.stages.iter().find(|s| s.stage == stage.stage) else {
+            continue;
+        };
+        let _ = writeln!(
+            report,
+            "{:<8} {:>9.3}s {:>9.3}s {:>+7.1}%",
+            stage.stage,
+            stage.mean,
+            after.mean,
+            change(stage.mean, after.mean)
+        );
+    }
+    let _ = writeln!(
+        report,
+        "{:<8} {:>9.3}s {:>9.3}s {:>+7.1}%",
+        "total",
+        baseline.total,
+        current.total,
+        change(baseline.total, current.total)
+    );
+    let throughput = change(baseline.rows_per_sec, current.rows_per_sec);
+    let _ = writeln!(
+        report,
+        "{:.0} -> {:.0} rows/s ({throughput:+.1}%)",
+        baseline.rows_per_sec, current.rows_per_sec
+    );
+    Ok((report, -throughput))
+}
+
+/// `5%` or `5`, as a number of percent.
+fn parse_percent(value: &str) -> Result<f64, String> {
+    let percent: f64 = value
+        .strip_suffix('%')
+        .unwrap_or(value)
+        .parse()
+        .map_err(|_| format!("`{value}` is not a percentage, expected e.g. `5%`"))?;
+    if percent < 0.0 {
+        return Err(format!("`{value}` is negative"));
+    }
+    Ok(percent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_means_over_iterations() {
+        let ms = Duration::from_millis;
+        let iterations = [
+            Iteration {
+                rows: 1_000,
+                stages: [ms(100), ms(300), ms(150), ms(150), ms(50)],
+            },
+            Iteration {
+                rows: 1_000,
+                stages: [ms(300), ms(500), ms(150), ms(250), ms(50)],
+            },
+        ];
+
+        assert_eq!(
+            report(&BenchResult::new(Path::new("input.csv"), &iterations)),
+            "\
+stage          mean  share
+read         0.200s  20.0%
+parse        0.400s  40.0%
+convert      0.150s  15.0%
+apply        0.200s  20.0%
+write        0.050s   5.0%
+total        1.000s
+1000 rows, 1000 rows/s
+"
+        );
+    }
+    fn result(rows: u64, stages: [f64; 5]) -> BenchResult {
+        let total = stages.iter().sum::<f64>();
+        BenchResult {
+            input: PathBuf::from("input.csv"),
+            iterations: 3,
+            rows,
+            stages: STAGES
+                .iter()
+                .zip(stages)
+                .map(|(stage, mean)| StageMean {
+                    stage: stage.to_string(),
+                    mean,
+                })
+                .collect(),
+            total,
+            rows_per_sec: rows as f64 / total,
+        }
+    }
+
+    #[test]
+    fn test_comparison() {
+        let baseline = result(1_000, [0.2, 0.4, 0.15, 0.2, 0.05]);
+        let current = result(1_000, [0.2, 0.4, 0.15, 0.3, 0.05]);
+
+        let (report, regression) = comparison(&baseline, &current).unwrap();
+        assert_eq!(
+            report,
+            "\
+stage      baseline    current   change
+read         0.200s     0.200s    +0.0%
+parse        0.400s     0.400s    +0.0%
+convert      0.150s     0.150s    +0.0%
+apply        0.200s     0.300s   +50.0%
+write        0.050s     0.050s    +0.0%
+total        1.000s     1.100s   +10.0%
+1000 -> 909 rows/s (-9.1%)
+"
+        );
+        assert!((regression - 9.0909).abs() < 0.001);
+
+        // Faster is a negative regression
+        let (_, regression) = comparison(&current, &baseline).unwrap();
+        assert!(regression < 0.0);
+        assert!(comparison(&baseline, &result(999, [0.2; 5])).is_err());
+    }
+
+    #[test]
+    fn test_parse_percent() {
+        assert_eq!(parse_percent("5%"), Ok(5.0));
+        assert_eq!(parse_percent("2.5"), Ok(2.5));
+        assert!(parse_percent("-1%").is_err());
+        assert!(parse_percent("five").is_err());
+    }
+}
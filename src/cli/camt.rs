@@ -0,0 +1,261 @@
+//! Balances as a simplified ISO 20022 bank-to-customer statement (camt.053),
+//! for imports that only take XML. Every client is a statement with three
+//! balances: available (`CLAV`), total (`CLBD`) and held, which has no ISO
+//! code and goes by the proprietary `HELD`.
+
+use std::{
+    error::Error,
+    io::{self, Write},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use quick_xml::{
+    Writer,
+    events::{BytesDecl, BytesText, Event},
+};
+use rust_decimal::Decimal;
+use toy_payments_engine::{
+    engine::{Engine, amount::CurrencyRules},
+    types::client::Client,
+};
+
+use crate::cli::{
+    output::{Balances, civil_date},
+    pseudonym::ClientLabel,
+};
+
+const NAMESPACE: &str = "urn:iso:std:iso:20022:tech:xsd:camt.053.001.08";
+
+/// What the statements say beyond the balances.
+pub struct Statement<'a> {
+    /// ISO 4217 code of every amount
+    pub currency: &'a str,
+    /// Amounts are rounded to the currency's minor units
+    pub rules: &'a CurrencyRules,
+    pub created: SystemTime,
+}
+
+pub fn write<W: Write>(
+    w: W,
+    balances: &Balances,
+    engine: &Engine,
+    statement: &Statement,
+) -> Result<(), Box<dyn Error>> {
+    let created = statement.created.duration_since(UNIX_EPOCH)?.as_secs();
+    let timestamp = format_timestamp(created);
+    let msg_id = format!("TPE-{created}");
+
+    let mut xml = Writer::new_with_indent(w, b' ', 2);
+    xml.write_event(Event::Decl(BytesDecl::new("1.0", Some("UTF-8"), None)))?;
+    xml.create_element("Document")
+        .with_attribute(("xmlns", NAMESPACE))
+        .write_inner_content(|xml| {
+            xml.create_element("BkToCstmrStmt")
+                .write_inner_content(|xml| {
+                    xml.create_element("GrpHdr").write_inner_content(|xml| {
+                        text(xml, "MsgId", &msg_id)?;
+                        text(xml, "CreDtTm", &timestamp)
+                    })?;
+                    let missing = balances.missing_clients(engine);
+                    let roster = missing.into_iter().map(Client::new);
+                    for client in engine.clients_iter().cloned().chain(roster) {
+                        let mut client = balances.scale.client(&client);
+                        let round = |amount| statement.rules.round(amount, statement.currency);
+                        // Derived, so CLAV and HELD add up to CLBD after rounding
+                        client.available = round(client.available);
+                        client.held = round(client.held);
+                        client.total = client.available + client.held;
+                        let label = balances.ids.label(client.id);
+                        write_statement(
+                            xml,
+                            &client,
+                            label,
+                            &msg_id,
+                            &timestamp,
+                            statement.currency,
+                        )?;
+                    }
+                    Ok(())
+                })?;
+            Ok(())
+        })?;
+    xml.into_inner().flush()?;
+    Ok(())
+}
+
+fn write_statement<W: Write>(
+    xml: &mut Writer<W>,
+    client: &Client,
+    label: ClientLabel,
+    msg_id: &str,
+    timestamp: &str,
+    currency: &str,
+) -> io::Result<()> {
+    xml.create_element("Stmt").write_inner_content(|xml| {
+        text(xml, "Id", &format!("{msg_id}-{label}"))?;
+        text(xml, "CreDtTm", timestamp)?;
+        xml.create_element("Acct").write_inner_content(|xml| {
+            xml.create_element("Id").write_inner_content(|xml| {
+                xml.create_element("Othr")
+                    .write_inner_content(|xml| text(xml, "Id", &label.to_string()))?;
+                Ok(())
+            })?;
+            text(xml, "Ccy", currency)
+        })?;
+        write_balance(xml, ("Cd", "CLAV"), client.available, timestamp, currency)?;
+        write_balance(xml, ("Prtry", "HELD"), client.held, timestamp, currency)?;
+        write_balance(xml, ("Cd", "CLBD"), client.total, timestamp, currency)?;
+        if client.locked {
+            text(xml, "AddtlStmtInf", "LOCKED")?;
+        }
+        Ok(())
+    })?;
+    Ok(())
+}
+
+/// `kind` is the balance type, an ISO code (`Cd`) or a proprietary one (`Prtry`).
+/// Amounts are unsigned, a negative balance is a debit.
+fn write_balance<W: Write>(
+    xml: &mut Writer<W>,
+    kind: (&str, &str),
+    amount: Decimal,
+    timestamp: &str,
+    currency: &str,
+) -> io::Result<()> {
+    xml.create_element("Bal").write_inner_content(|xml| {
+        xml.create_element("Tp").write_inner_content(|xml| {
+            xml.create_element("CdOrPrtry")
+                .write_inner_content(|xml| text(xml, kind.0, kind.1))?;
+            Ok(())
+        })?;
+        xml.create_element("Amt")
+            .with_attribute(("Ccy", currency))
+            .write_text_content(BytesText::new(&amount.abs().to_string()))?;
+        let indicator = if amount.is_sign_negative() && !amount.is_zero() {
+            "DBIT"
+        } else {
+            "CRDT"
+        };
+        text(xml, "CdtDbtInd", indicator)?;
+        xml.create_element("Dt")
+            .write_inner_content(|xml| text(xml, "DtTm", timestamp))?;
+        Ok(())
+    })?;
+    Ok(())
+}
+
+fn text<W: Write>(xml: &mut Writer<W>, name: &str, value: &str) -> io::Result<()> {
+    xml.create_element(name)
+        .write_text_content(BytesText::new(value))?;
+    Ok(())
+}
+
+/// UTC `YYYY-MM-DDThh:mm:ssZ` for Unix seconds.
+fn format_timestamp(secs: u64) -> String {
+    let (year, month, day) = civil_date(secs / 86_400);
+    let secs = secs % 86_400;
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        secs / 3_600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use toy_payments_engine::types::transactions::{DepositTx, DisputeTx, Tx, WithdrawalTx};
+
+    #[test]
+    fn test_timestamps_are_utc_dates() {
+        assert_eq!(format_timestamp(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_timestamp(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(format_timestamp(1_792_234_861), "2026-10-17T11:01:01Z");
+    }
+
+    #[test]
+    fn test_statement_per_client() {
+        let mut engine = Engine::new();
+        engine
+            .process_tx(Tx::Deposit(
+                DepositTx::new(1, 1, "1.5".parse().unwrap()).unwrap(),
+            ))
+            .unwrap();
+        engine
+            .process_tx(Tx::Withdrawal(
+                WithdrawalTx::new(1, 2, "0.5".parse().unwrap()).unwrap(),
+            ))
+            .unwrap();
+        engine
+            .process_tx(Tx::Dispute(DisputeTx {
+                client_id: 1,
+                tx_id: 1,
+            }))
+            .unwrap();
+        let balances = Balances {
+            roster: &[2],
+            ..Balances::default()
+        };
+        let statement = Statement {
+            currency: "EUR",
+            rules: &CurrencyRules::default(),
+            created: UNIX_EPOCH + Duration::from_secs(60),
+        };
+
+        let mut out = Vec::new();
+        write(&mut out, &balances, &engine, &statement).unwrap();
+        let xml = String::from_utf8(out).unwrap();
+
+        assert!(xml.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<Document"));
+        assert_eq!(xml.matches("<Stmt>").count(), 2);
+        assert!(xml.contains("<Id>TPE-60-1</Id>"));
+        assert!(xml.contains("<CreDtTm>1970-01-01T00:01:00Z</CreDtTm>"));
+        let compact: String = xml.lines().map(str::trim).collect();
+        // Disputing the deposit after the withdrawal leaves available negative,
+        // amounts get the two decimals of EUR
+        assert!(compact.contains(
+            "<Cd>CLAV</Cd></CdOrPrtry></Tp><Amt Ccy=\"EUR\">0.50</Amt><CdtDbtInd>DBIT</CdtDbtInd>"
+        ));
+        assert!(compact.contains(
+            "<Prtry>HELD</Prtry></CdOrPrtry></Tp><Amt Ccy=\"EUR\">1.50</Amt><CdtDbtInd>CRDT"
+        ));
+        assert!(!xml.contains("LOCKED"));
+    }
+
+    #[test]
+    fn test_rounded_balances_add_up() {
+        let mut engine = Engine::new();
+        for tx_id in 1..=2 {
+            let deposit = DepositTx::new(1, tx_id, "0.5".parse().unwrap()).unwrap();
+            engine.process_tx(Tx::Deposit(deposit)).unwrap();
+        }
+        engine
+            .process_tx(Tx::Dispute(DisputeTx {
+                client_id: 1,
+                tx_id: 2,
+            }))
+            .unwrap();
+        let statement = Statement {
+            currency: "JPY",
+            rules: &CurrencyRules::default(),
+            created: UNIX_EPOCH,
+        };
+
+        let mut out = Vec::new();
+        write(&mut out, &Balances::default(), &engine, &statement).unwrap();
+        let compact: String = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(str::trim)
+            .collect();
+        // Half a yen available and half held round to nothing each
+        for code in ["<Cd>CLAV</Cd>", "<Prtry>HELD</Prtry>", "<Cd>CLBD</Cd>"] {
+            assert!(
+                compact.contains(&format!("{code}</CdOrPrtry></Tp><Amt Ccy=\"JPY\">0</Amt>")),
+                "{code} in {compact}"
+            );
+        }
+    }
+}
@@ -0,0 +1,150 @@
+//! `--chaos <SPEC>`, only in builds with the `chaos` feature: injects the
+//! faults a run has to recover from, for the tests in `tests/chaos.rs`. The
+//! spec is a comma-separated list of
+//!
+//! - `interrupt=N` - stop like on SIGINT once N rows of this run are applied
+//! - `abort=N` - kill the process at the same point, nothing is saved
+//! - `abort=state|manifest|output` - kill it right before writing that
+//! - `read-errors=P` - fail every read of the input with probability P
+//! - `slow-storage=DURATION` - stall before writing the state and the
+//!   manifest, announced on stderr
+//! - `seed=N` - seeds `read-errors`, random otherwise and printed to stderr
+
+use std::{io, str::FromStr, thread, time::Duration};
+
+use rand::{RngExt, SeedableRng, rngs::StdRng};
+use toy_payments_engine::pipeline::source::Fault;
+
+/// Where `abort=` kills the run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AbortAt {
+    /// Before the row after the first N of this run
+    Row(u64),
+    State,
+    Manifest,
+    Output,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Chaos {
+    interrupt: Option<u64>,
+    abort: Option<AbortAt>,
+    read_errors: f64,
+    slow_storage: Duration,
+    seed: Option<u64>,
+}
+
+impl Chaos {
+    /// Whether the run should stop before its next row, `rows` being the
+    /// rows this run has applied so far.
+    pub fn before_row(&self, rows: u64) -> bool {
+        if self.abort == Some(AbortAt::Row(rows)) {
+            std::process::abort();
+        }
+        self.interrupt == Some(rows)
+    }
+
+    /// Called right before the run writes `point`.
+    pub fn before_write(&self, point: AbortAt) {
+        if matches!(point, AbortAt::State | AbortAt::Manifest) && !self.slow_storage.is_zero() {
+            // For tests waiting for the run to get this far
+            eprintln!("chaos: stalling before writing the {point:?}");
+            thread::sleep(self.slow_storage);
+        }
+        if self.abort == Some(point) {
+            std::process::abort();
+        }
+    }
+
+    /// The fault failing input reads, `None` without `read-errors`.
+    pub fn fault(&self) -> Option<Fault> {
+        if self.read_errors <= 0.0 {
+            return None;
+        }
+        let seed = self.seed.unwrap_or_else(|| {
+            let seed = rand::random();
+            eprintln!("chaos: seed {seed}");
+            seed
+        });
+        let mut rng = StdRng::seed_from_u64(seed);
+        let probability = self.read_errors;
+        Some(Box::new(move || {
+            if rng.random_bool(probability) {
+                return Err(io::Error::other("injected read error"));
+            }
+            Ok(())
+        }))
+    }
+}
+
+impl FromStr for Chaos {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let mut chaos = Chaos::default();
+        for fault in spec.split(',') {
+            let Some((key, value)) = fault.split_once('=') else {
+                return Err(format!("`{fault}` is not a `key=value` fault"));
+            };
+            let number = || format!("`{fault}`: `{value}` is not a number");
+            match key {
+                "interrupt" => chaos.interrupt = Some(value.parse().map_err(|_| number())?),
+                "abort" => {
+                    chaos.abort = Some(match value {
+                        "state" => AbortAt::State,
+                        "manifest" => AbortAt::Manifest,
+                        "output" => AbortAt::Output,
+                        _ => AbortAt::Row(value.parse().map_err(|_| {
+                            format!(
+                                "`{fault}`: expected a row count, `state`, `manifest` or `output`"
+                            )
+                        })?),
+                    })
+                }
+                "read-errors" => {
+                    chaos.read_errors = value.parse().map_err(|_| number())?;
+                    if !(0.0..=1.0).contains(&chaos.read_errors) {
+                        return Err(format!("`{fault}`: not a probability between 0 and 1"));
+                    }
+                }
+                "slow-storage" => chaos.slow_storage = super::parse_duration(value)?,
+                "seed" => chaos.seed = Some(value.parse().map_err(|_| number())?),
+                _ => {
+                    return Err(format!(
+                        "unknown fault `{key}`, expected interrupt, abort, read-errors, \
+                         slow-storage or seed"
+                    ));
+                }
+            }
+        }
+        Ok(chaos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spec() {
+        let chaos: Chaos = "interrupt=5,abort=manifest,read-errors=0.5,slow-storage=10ms,seed=3"
+            .parse()
+            .unwrap();
+        assert!(!chaos.before_row(4));
+        assert!(chaos.before_row(5));
+        assert_eq!(chaos.abort, Some(AbortAt::Manifest));
+        assert_eq!(chaos.slow_storage, Duration::from_millis(10));
+
+        // The same seed fails the same reads
+        let reads = |mut fault: Fault| (0..64).map(|_| fault().is_err()).collect::<Vec<_>>();
+        assert_eq!(reads(chaos.fault().unwrap()), reads(chaos.fault().unwrap()));
+        assert!(reads(chaos.fault().unwrap()).contains(&true));
+
+        assert_eq!(
+            "abort=12".parse::<Chaos>().unwrap().abort,
+            Some(AbortAt::Row(12))
+        );
+        assert!("read-errors=2".parse::<Chaos>().is_err());
+        assert!("flood=1".parse::<Chaos>().is_err());
+    }
+}
@@ -0,0 +1,73 @@
+//! `tpe completions <shell>` and `tpe man`: the CLI described for shells and
+//! `man`, generated from the same definitions clap parses with so they can't
+//! drift from the flags that actually exist.
+
+use std::{
+    error::Error,
+    io::{self, Write},
+    path::PathBuf,
+};
+
+use clap::{Args, CommandFactory};
+use clap_complete::Shell;
+
+use crate::cli::Cli;
+
+#[derive(Debug, Args)]
+pub struct CompletionsArgs {
+    /// Shell to complete for
+    pub shell: Shell,
+}
+
+#[derive(Debug, Args)]
+pub struct ManArgs {
+    /// Directory to write the pages to, `tpe.1` and one `tpe-<command>.1` per subcommand
+    #[arg(long, default_value = ".")]
+    pub out_dir: PathBuf,
+}
+
+/// Prints the completion script to stdout, e.g. for
+/// `tpe completions bash > /etc/bash_completion.d/tpe`.
+pub fn run(args: CompletionsArgs) -> Result<(), Box<dyn Error>> {
+    // clap_complete panics on write errors, e.g. a closed pipe, so it writes to memory
+    let mut script = Vec::new();
+    clap_complete::generate(args.shell, &mut Cli::command(), "tpe", &mut script);
+    io::stdout().write_all(&script)?;
+    Ok(())
+}
+
+pub fn run_man(args: ManArgs) -> Result<(), Box<dyn Error>> {
+    clap_mangen::generate_to(Cli::command(), &args.out_dir)
+        .map_err(|err| format!("{}: {err}", args.out_dir.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_completions_cover_flags_and_subcommands() {
+        for shell in [Shell::Bash, Shell::Zsh, Shell::Fish] {
+            let mut script = Vec::new();
+            clap_complete::generate(shell, &mut Cli::command(), "tpe", &mut script);
+            let script = String::from_utf8(script).unwrap();
+            for word in ["progress", "statement", "audit-log"] {
+                assert!(script.contains(word), "{shell}: no `{word}`");
+            }
+        }
+    }
+
+    #[test]
+    fn test_man_pages_per_subcommand() {
+        let dir = tempfile::tempdir().unwrap();
+        run_man(ManArgs {
+            out_dir: dir.path().to_path_buf(),
+        })
+        .unwrap();
+        let page = std::fs::read_to_string(dir.path().join("tpe.1")).unwrap();
+        assert!(page.contains("progress"));
+        assert!(dir.path().join("tpe-audit-verify.1").exists());
+        assert!(dir.path().join("tpe-statement.1").exists());
+    }
+}
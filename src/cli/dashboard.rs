@@ -0,0 +1,268 @@
+//! `--tui`: a live dashboard of the run on stderr (stdout keeps the
+//! balances), redrawn a few times a second: progress, throughput, rejects by
+//! reason, the clients holding the most disputed funds and memory usage.
+
+use std::{
+    collections::BTreeMap,
+    io::{self, IsTerminal, Stderr},
+    time::{Duration, Instant},
+};
+
+use ratatui::{
+    Frame, Terminal,
+    backend::CrosstermBackend,
+    crossterm::{
+        cursor::{Hide, Show},
+        execute,
+        terminal::{EnterAlternateScreen, LeaveAlternateScreen},
+    },
+    layout::{Constraint, Layout},
+    widgets::{Block, Gauge, Paragraph, Row, Table},
+};
+use toy_payments_engine::{
+    engine::Engine,
+    pipeline::results::{Outcome, RowResult},
+    types::{client::Balance, reject::RejectReason},
+};
+
+use crate::cli::{resources::Resources, top};
+
+/// How often the dashboard is redrawn
+const REDRAW_INTERVAL: Duration = Duration::from_millis(250);
+/// Checking the clock on every row is wasteful, so only every N rows
+const CHECK_EVERY_ROWS: u64 = 1024;
+/// Clients listed by held funds
+const TOP_CLIENTS: usize = 10;
+
+/// Everything one frame shows.
+#[derive(Debug, Default)]
+struct Snapshot {
+    rows: u64,
+    applied: u64,
+    elapsed: Duration,
+    /// Share of the input read, 0 to 1
+    done: Option<f64>,
+    rejects: BTreeMap<&'static str, u64>,
+    /// `(client, held, total)` by held funds, largest first
+    top_held: Vec<(String, String, String)>,
+    resources: Option<Resources>,
+}
+
+/// The dashboard, drawn on the alternate screen of the terminal stderr is
+/// attached to and taken down again when dropped.
+pub struct Dashboard {
+    terminal: Terminal<CrosstermBackend<Stderr>>,
+    started: Instant,
+    last_draw: Instant,
+    total_bytes: u64,
+    snapshot: Snapshot,
+}
+
+impl Dashboard {
+    pub fn start(total_bytes: u64) -> io::Result<Self> {
+        if !io::stderr().is_terminal() {
+            return Err(io::Error::other("--tui needs stderr to be a terminal"));
+        }
+        execute!(io::stderr(), EnterAlternateScreen, Hide)?;
+        let terminal = Terminal::new(CrosstermBackend::new(io::stderr()))?;
+        let now = Instant::now();
+        Ok(Dashboard {
+            terminal,
+            started: now,
+            last_draw: now,
+            total_bytes,
+            snapshot: Snapshot::default(),
+        })
+    }
+
+    pub fn record(&mut self, engine: &Engine, result: &RowResult) -> io::Result<()> {
+        let snapshot = &mut self.snapshot;
+        if result.dequeued {
+            if let Some(queued) = snapshot.rejects.get_mut(RejectReason::Queued.code()) {
+                *queued -= 1;
+            }
+        } else {
+            snapshot.rows += 1;
+        }
+        match result.outcome {
+            Outcome::Applied => snapshot.applied += 1,
+            Outcome::Rejected(reason) => *snapshot.rejects.entry(reason.code()).or_default() += 1,
+            Outcome::Skipped => {}
+        }
+        if !snapshot.rows.is_multiple_of(CHECK_EVERY_ROWS) {
+            return Ok(());
+        }
+        let now = Instant::now();
+        if now.duration_since(self.last_draw) < REDRAW_INTERVAL {
+            return Ok(());
+        }
+        self.last_draw = now;
+        self.draw(engine, result.position.byte)
+    }
+
+    fn draw(&mut self, engine: &Engine, byte_offset: u64) -> io::Result<()> {
+        let snapshot = &mut self.snapshot;
+        snapshot.elapsed = self.started.elapsed();
+        snapshot.done = (self.total_bytes > 0)
+            .then(|| byte_offset.min(self.total_bytes) as f64 / self.total_bytes as f64);
+        snapshot.top_held = top::top_clients(engine.clients_iter(), TOP_CLIENTS, Balance::Held)
+            .into_iter()
+            .filter(|client| !client.held.is_zero())
+            .map(|client| {
+                (
+                    client.id.to_string(),
+                    client.held.to_string(),
+                    client.total.to_string(),
+                )
+            })
+            .collect();
+        snapshot.resources = Some(Resources::sample(engine));
+        self.terminal.draw(|frame| view(frame, snapshot))?;
+        Ok(())
+    }
+}
+
+impl Drop for Dashboard {
+    fn drop(&mut self) {
+        let _ = execute!(io::stderr(), LeaveAlternateScreen, Show);
+    }
+}
+
+fn view(frame: &mut Frame, snapshot: &Snapshot) {
+    let [gauge, middle, bottom] = Layout::vertical([
+        Constraint::Length(3),
+        Constraint::Length(7),
+        Constraint::Min(5),
+    ])
+    .areas(frame.area());
+    let [throughput, memory] =
+        Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)]).areas(middle);
+    let [rejects, held] =
+        Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)]).areas(bottom);
+
+    let done = snapshot.done.unwrap_or_default();
+    let secs = snapshot.elapsed.as_secs_f64();
+    let eta = if done > 0.0 {
+        format!(", ETA {:.0}s", secs * (1.0 - done) / done)
+    } else {
+        String::new()
+    };
+    frame.render_widget(
+        Gauge::default()
+            .block(Block::bordered().title(" tpe "))
+            .ratio(done.clamp(0.0, 1.0))
+            .label(format!("{:.1}% of input{eta}", done * 100.0)),
+        gauge,
+    );
+
+    let rate = if secs > 0.0 {
+        snapshot.rows as f64 / secs
+    } else {
+        0.0
+    };
+    let rejected: u64 = snapshot.rejects.values().sum();
+    frame.render_widget(
+        Paragraph::new(vec![
+            format!("rows      {}", snapshot.rows).into(),
+            format!("rows/s    {rate:.0}").into(),
+            format!("applied   {}", snapshot.applied).into(),
+            format!("rejected  {rejected}").into(),
+            format!("elapsed   {:.0}s", secs).into(),
+        ])
+        .block(Block::bordered().title(" Throughput ")),
+        throughput,
+    );
+
+    let memory_lines = match &snapshot.resources {
+        Some(resources) => {
+            let mb = |bytes: u64| format!("{:.1} MB", bytes as f64 / 1_000_000.0);
+            let heap = |bytes: Option<usize>| bytes.map_or("n/a".to_string(), |b| mb(b as u64));
+            vec![
+                format!(
+                    "peak RSS  {}",
+                    resources.peak_rss.map_or("n/a".to_string(), mb)
+                )
+                .into(),
+                format!("heap      {}", heap(resources.heap.map(|h| h.live))).into(),
+                format!("peak heap {}", heap(resources.heap.map(|h| h.peak))).into(),
+                format!("clients   {}", resources.clients).into(),
+                format!("tracked   {} txs", resources.tracked_txs).into(),
+            ]
+        }
+        None => Vec::new(),
+    };
+    frame.render_widget(
+        Paragraph::new(memory_lines).block(Block::bordered().title(" Memory ")),
+        memory,
+    );
+
+    let mut by_count: Vec<_> = snapshot.rejects.iter().collect();
+    by_count.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+    frame.render_widget(
+        Table::new(
+            by_count
+                .into_iter()
+                .map(|(reason, count)| Row::new([reason.to_string(), count.to_string()])),
+            [Constraint::Fill(1), Constraint::Length(12)],
+        )
+        .header(Row::new(["reason", "rows"]))
+        .block(Block::bordered().title(" Rejects ")),
+        rejects,
+    );
+
+    frame.render_widget(
+        Table::new(
+            snapshot
+                .top_held
+                .iter()
+                .map(|(client, held, total)| Row::new([client.as_str(), held, total])),
+            [
+                Constraint::Length(8),
+                Constraint::Fill(1),
+                Constraint::Fill(1),
+            ],
+        )
+        .header(Row::new(["client", "held", "total"]))
+        .block(Block::bordered().title(" Most held in disputes ")),
+        held,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::backend::TestBackend;
+
+    #[test]
+    fn test_view_shows_every_panel() {
+        let snapshot = Snapshot {
+            rows: 2048,
+            applied: 2000,
+            elapsed: Duration::from_secs(2),
+            done: Some(0.5),
+            rejects: BTreeMap::from([("insufficient_funds", 40), ("unknown_tx", 8)]),
+            top_held: vec![("7".to_string(), "12.5".to_string(), "20".to_string())],
+            resources: None,
+        };
+        let mut terminal = Terminal::new(TestBackend::new(80, 20)).unwrap();
+        terminal.draw(|frame| view(frame, &snapshot)).unwrap();
+
+        let screen: String = terminal
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(|cell| cell.symbol())
+            .collect();
+        for text in [
+            "50.0% of input, ETA 2s",
+            "rows/s    1024",
+            "rejected  48",
+            "insufficient_funds",
+            "Most held in disputes",
+            "12.5",
+        ] {
+            assert!(screen.contains(text), "no `{text}` on screen");
+        }
+    }
+}
@@ -0,0 +1,281 @@
+//! Detailed reports for problems with the input file, rendered by `main`
+//! with the offending line and a hint instead of a one-line error.
+
+use std::{
+    error::Error,
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use miette::{Diagnostic, MietteError, MietteSpanContents, SourceCode, SourceSpan, SpanContents};
+
+/// Columns every input must have.
+const REQUIRED_COLUMNS: [&str; 4] = ["type", "client", "tx", "amount"];
+
+/// How much of the end of the file is read to look at the last record.
+const TAIL_BYTES: u64 = 4096;
+
+#[derive(Debug, thiserror::Error, Diagnostic)]
+pub enum InputError {
+    #[error("can't open input `{}`", path.display())]
+    #[diagnostic(code(tpe::input::open), help("{hint}"))]
+    Open {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+        hint: &'static str,
+    },
+
+    #[error("input `{}` is missing the {missing} column", path.display())]
+    #[diagnostic(
+        code(tpe::input::headers),
+        help("the first row must name the columns `type,client,tx,amount`, in any order")
+    )]
+    Headers {
+        path: PathBuf,
+        missing: String,
+        #[source_code]
+        src: Snippet,
+        #[label("{label}")]
+        span: SourceSpan,
+        label: &'static str,
+    },
+
+    #[error("can't read input `{}`: {message}", path.display())]
+    #[diagnostic(code(tpe::input::malformed), help("{hint}"))]
+    Malformed {
+        path: PathBuf,
+        message: String,
+        #[source_code]
+        src: Snippet,
+        #[label("here")]
+        span: SourceSpan,
+        hint: &'static str,
+    },
+}
+
+/// The last record of the input is cut short, reported as a warning.
+#[derive(Debug, thiserror::Error, Diagnostic)]
+#[error("input `{}` looks truncated, its last record was rejected", path.display())]
+#[diagnostic(
+    code(tpe::input::truncated),
+    severity(Warning),
+    help(
+        "the file doesn't end with a newline and the last record has {fields} of {expected} fields, check the transfer was complete"
+    )
+)]
+pub struct TruncatedInput {
+    path: PathBuf,
+    fields: usize,
+    expected: usize,
+    #[source_code]
+    src: Snippet,
+    #[label("last field")]
+    span: SourceSpan,
+}
+
+/// A few lines cut out of the input, reported with their line numbers in the file.
+#[derive(Debug)]
+pub struct Snippet {
+    name: String,
+    text: String,
+    /// Zero based line of the file `text` starts at
+    first_line: usize,
+}
+
+impl SourceCode for Snippet {
+    fn read_span<'a>(
+        &'a self,
+        span: &SourceSpan,
+        context_lines_before: usize,
+        context_lines_after: usize,
+    ) -> Result<Box<dyn SpanContents<'a> + 'a>, MietteError> {
+        let contents = self
+            .text
+            .read_span(span, context_lines_before, context_lines_after)?;
+        Ok(Box::new(MietteSpanContents::new_named(
+            self.name.clone(),
+            contents.data(),
+            *contents.span(),
+            contents.line() + self.first_line,
+            contents.column(),
+            contents.line_count(),
+        )))
+    }
+}
+
+/// Turns an error from opening the input into an `InputError` where there
+/// is more to say than the error itself.
+pub fn open_error(path: &Path, err: Box<dyn Error>) -> Box<dyn Error> {
+    let err = match err.downcast::<io::Error>() {
+        Ok(source) => {
+            let hint = match source.kind() {
+                io::ErrorKind::NotFound => {
+                    "check the path, a relative one is resolved against the current directory"
+                }
+                io::ErrorKind::PermissionDenied => "check the file permissions",
+                io::ErrorKind::IsADirectory => "pass the transactions CSV, not its directory",
+                _ => "check the input is a readable file",
+            };
+            return Box::new(InputError::Open {
+                path: path.to_path_buf(),
+                source: *source,
+                hint,
+            });
+        }
+        Err(err) => err,
+    };
+
+    match err.downcast::<csv::Error>() {
+        Ok(err) => match malformed(path, &err) {
+            Some(diagnostic) => Box::new(diagnostic),
+            None => err,
+        },
+        Err(err) => err,
+    }
+}
+
+fn malformed(path: &Path, err: &csv::Error) -> Option<InputError> {
+    let position = err.position()?;
+    let hint = match err.kind() {
+        csv::ErrorKind::Utf8 { .. } => {
+            "the input isn't UTF-8, pass its encoding with `--encoding`, e.g. `--encoding latin1`"
+        }
+        _ => "check the file is a comma separated CSV",
+    };
+
+    // Only the offending line is read, the input may be large
+    let mut file = File::open(path).ok()?;
+    file.seek(SeekFrom::Start(position.byte())).ok()?;
+    let mut line = Vec::new();
+    file.take(TAIL_BYTES).read_to_end(&mut line).ok()?;
+    let end = line.iter().position(|b| *b == b'\n').unwrap_or(line.len());
+    let text = String::from_utf8_lossy(&line[..end]).into_owned();
+
+    let message = match err.kind() {
+        csv::ErrorKind::Utf8 { err, .. } => format!("invalid UTF-8 in field {}", err.field() + 1),
+        _ => err.to_string(),
+    };
+    Some(InputError::Malformed {
+        path: path.to_path_buf(),
+        message,
+        span: (0, text.len()).into(),
+        src: Snippet {
+            name: path.display().to_string(),
+            text,
+            first_line: position.line().saturating_sub(1) as usize,
+        },
+        hint,
+    })
+}
+
+/// Checks the header row names every column the engine reads.
+pub fn check_headers(path: &Path, headers: &csv::StringRecord) -> Result<(), Box<dyn Error>> {
+    let Some(missing) = REQUIRED_COLUMNS
+        .into_iter()
+        .find(|column| !headers.iter().any(|header| header == *column))
+    else {
+        return Ok(());
+    };
+
+    // Points at the first column that isn't one of ours, likely a misspelling
+    let text = headers.iter().collect::<Vec<_>>().join(",");
+    let mut start = 0;
+    let mut span = (0, text.len()).into();
+    let mut label = "header row";
+    for header in headers.iter() {
+        if !REQUIRED_COLUMNS.contains(&header) {
+            span = (start, header.len()).into();
+            label = "unknown column";
+            break;
+        }
+        start += header.len() + 1;
+    }
+
+    Err(Box::new(InputError::Headers {
+        path: path.to_path_buf(),
+        missing: format!("`{missing}`"),
+        src: Snippet {
+            name: path.display().to_string(),
+            text,
+            first_line: 0,
+        },
+        span,
+        label,
+    }))
+}
+
+/// Looks at the end of the input after its last record (on `line`) was
+/// rejected. Returns a warning if the file doesn't end with a newline and
+/// the record has fewer fields than the header.
+pub fn check_truncated(path: &Path, line: u64, expected: usize) -> Option<TruncatedInput> {
+    let mut file = File::open(path).ok()?;
+    let len = file.metadata().ok()?.len();
+    file.seek(SeekFrom::Start(len.saturating_sub(TAIL_BYTES)))
+        .ok()?;
+    let mut tail = Vec::new();
+    file.read_to_end(&mut tail).ok()?;
+    if tail.is_empty() || tail.ends_with(b"\n") {
+        return None;
+    }
+
+    let start = tail.iter().rposition(|b| *b == b'\n').map_or(0, |i| i + 1);
+    let text = String::from_utf8_lossy(&tail[start..]).into_owned();
+    let fields = text.split(',').count();
+    let last_field = text.rfind(',').map_or(0, |i| i + 1);
+    if fields >= expected {
+        return None;
+    }
+
+    Some(TruncatedInput {
+        path: path.to_path_buf(),
+        fields,
+        expected,
+        span: (last_field, text.len() - last_field).into(),
+        src: Snippet {
+            name: path.display().to_string(),
+            text,
+            first_line: line.saturating_sub(1) as usize,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_missing_column_points_at_unknown_header() {
+        let headers = csv::StringRecord::from(vec!["kind", "client", "tx", "amount"]);
+        let err = check_headers(Path::new("in.csv"), &headers).unwrap_err();
+        let Ok(InputError::Headers {
+            missing,
+            span,
+            label,
+            ..
+        }) = err.downcast::<InputError>().map(|err| *err)
+        else {
+            panic!("expected a headers error");
+        };
+        assert_eq!(missing, "`type`");
+        assert_eq!(span, SourceSpan::from((0, 4)));
+        assert_eq!(label, "unknown column");
+
+        let headers = csv::StringRecord::from(vec!["amount", "tx", "client", "type"]);
+        assert!(check_headers(Path::new("in.csv"), &headers).is_ok());
+    }
+
+    #[test]
+    fn test_truncated_last_record() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        write!(file, "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1").unwrap();
+        let warning = check_truncated(file.path(), 3, 4).unwrap();
+        assert_eq!(warning.fields, 2);
+        assert_eq!(warning.src.text, "deposit,1");
+
+        writeln!(file, ",2,1.0").unwrap();
+        assert!(check_truncated(file.path(), 3, 4).is_none());
+    }
+}
@@ -0,0 +1,186 @@
+//! `tpe difftest`: randomized differential testing of two builds. Both run
+//! over a corpus and over inputs from the generator, and any difference in
+//! their balances or exit status is reported. The inputs are random rather
+//! than coverage-guided, nothing steers them towards unexplored code.
+
+use std::{
+    error::Error,
+    fs::{self, File},
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use clap::Args;
+use rand::{SeedableRng, rngs::StdRng};
+
+use crate::cli::generate::{self, GenArgs};
+
+/// Lines of a differing output shown per input
+const SHOWN_LINES: usize = 5;
+
+#[derive(Debug, Args)]
+pub struct DifftestArgs {
+    /// Binary of the previous release
+    pub old: PathBuf,
+
+    /// Binary under test
+    pub new: PathBuf,
+
+    /// Directory of input CSVs, random inputs that differ are added to it
+    pub corpus: PathBuf,
+
+    /// Number of random inputs to try after the corpus
+    #[arg(long, default_value_t = 20)]
+    pub random: u64,
+
+    /// Rows per random input
+    #[arg(long, default_value_t = 2_000)]
+    pub rows: u64,
+
+    /// Seed of the random inputs, the run prints the one it used
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// Arguments passed to both binaries after the input path
+    #[arg(last = true, value_name = "ARGS")]
+    pub args: Vec<String>,
+}
+
+/// What a binary printed for an input, stdout lines sorted since the order
+/// of the balances is unspecified.
+#[derive(Debug, PartialEq)]
+struct Run {
+    success: bool,
+    lines: Vec<String>,
+}
+
+/// Runs both binaries over every CSV in the corpus and then over random
+/// inputs, reporting every input their output differs on.
+pub fn run(args: DifftestArgs) -> Result<(), Box<dyn Error>> {
+    let mut inputs: Vec<PathBuf> = fs::read_dir(&args.corpus)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Result<_, _>>()?;
+    inputs.retain(|path| path.extension().is_some_and(|ext| ext == "csv"));
+    inputs.sort();
+
+    let mut tried = 0;
+    let mut differing = 0;
+    for input in &inputs {
+        tried += 1;
+        if !same(&args, input, input)? {
+            differing += 1;
+        }
+    }
+
+    let seed = args.seed.unwrap_or_else(rand::random);
+    eprintln!("difftest: random inputs from seed {seed}");
+    let gen_args = GenArgs {
+        rows: args.rows,
+        clients: (args.rows / 20).clamp(1, 1_000),
+        dispute_rate: 0.05,
+        fraud_scenarios: true,
+        output: None,
+        seed: None,
+    };
+    let mut rng = StdRng::seed_from_u64(seed);
+    let scratch = std::env::temp_dir().join(format!("tpe-difftest-{}.csv", std::process::id()));
+    for i in 0..args.random {
+        tried += 1;
+        generate::generate(&gen_args, &mut rng, File::create(&scratch)?)?;
+        // Kept so the difference can be reproduced and stays covered
+        let kept = args.corpus.join(format!("random-{seed}-{i}.csv"));
+        if !same(&args, &scratch, &kept)? {
+            differing += 1;
+            fs::copy(&scratch, &kept)?;
+        }
+    }
+    let _ = fs::remove_file(&scratch);
+
+    eprintln!("difftest: {tried} inputs, {differing} differ");
+    if differing > 0 {
+        return Err(From::from(format!("Outputs differ on {differing} inputs")));
+    }
+    Ok(())
+}
+
+/// Runs both binaries on `input`, printing the difference under `name` if
+/// there is one.
+fn same(args: &DifftestArgs, input: &Path, name: &Path) -> Result<bool, Box<dyn Error>> {
+    let old = run_binary(&args.old, input, &args.args)?;
+    let new = run_binary(&args.new, input, &args.args)?;
+    if old == new {
+        return Ok(true);
+    }
+
+    println!("DIFF {}", name.display());
+    if old.success != new.success {
+        println!(
+            "  exit: old {}, new {}",
+            status(old.success),
+            status(new.success)
+        );
+    }
+    let (removed, added) = sorted_diff(&old.lines, &new.lines);
+    for line in removed.iter().take(SHOWN_LINES) {
+        println!("  - {line}");
+    }
+    for line in added.iter().take(SHOWN_LINES) {
+        println!("  + {line}");
+    }
+    Ok(false)
+}
+
+/// Lines only in `old` and lines only in `new`, both sorted.
+fn sorted_diff<'a>(old: &'a [String], new: &'a [String]) -> (Vec<&'a str>, Vec<&'a str>) {
+    let (mut removed, mut added) = (Vec::new(), Vec::new());
+    let (mut old, mut new) = (old.iter().peekable(), new.iter().peekable());
+    loop {
+        match (old.peek(), new.peek()) {
+            (Some(a), Some(b)) if a == b => {
+                old.next();
+                new.next();
+            }
+            (Some(a), Some(b)) if a < b => removed.extend(old.next().map(String::as_str)),
+            (Some(_), Some(_)) | (None, Some(_)) => added.extend(new.next().map(String::as_str)),
+            (Some(_), None) => removed.extend(old.next().map(String::as_str)),
+            (None, None) => return (removed, added),
+        }
+    }
+}
+
+fn run_binary(binary: &Path, input: &Path, args: &[String]) -> Result<Run, Box<dyn Error>> {
+    let output = Command::new(binary)
+        .arg(input)
+        .args(args)
+        .output()
+        .map_err(|err| format!("Cannot run {}: {err}", binary.display()))?;
+    let mut lines: Vec<String> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::to_string)
+        .collect();
+    lines.sort();
+    Ok(Run {
+        success: output.status.success(),
+        lines,
+    })
+}
+
+fn status(success: bool) -> &'static str {
+    if success { "success" } else { "failure" }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sorted_diff() {
+        let lines = |s: &str| s.split(' ').map(str::to_string).collect::<Vec<_>>();
+        let old = lines("1,10 2,5 3,0 5,1");
+        let new = lines("1,10 2,6 3,0 4,2");
+        assert_eq!(
+            sorted_diff(&old, &new),
+            (vec!["2,5", "5,1"], vec!["2,6", "4,2"])
+        );
+    }
+}
@@ -0,0 +1,42 @@
+use std::{error::Error, fs::File, io::BufWriter, path::Path};
+
+use rust_decimal::Decimal;
+use toy_payments_engine::{engine::Engine, types::common::TxId};
+
+use crate::cli::{
+    output::OutputScale,
+    pseudonym::{ClientIds, ClientLabel},
+};
+
+#[derive(serde::Serialize)]
+struct DisputeRow {
+    client: ClientLabel,
+    tx: TxId,
+    amount: Decimal,
+}
+
+/// Writes every transaction still under dispute, grouped by client.
+pub fn write_report(
+    engine: &Engine,
+    path: &Path,
+    scale: OutputScale,
+    ids: &ClientIds,
+) -> Result<(), Box<dyn Error>> {
+    let mut disputes: Vec<DisputeRow> = engine
+        .open_disputes()
+        .map(|dispute| DisputeRow {
+            client: ids.label(dispute.client_id),
+            tx: dispute.tx_id,
+            amount: scale.apply(dispute.amount),
+        })
+        .collect();
+    disputes.sort_by_key(|row| (row.client, row.tx));
+
+    let mut wtr = csv::Writer::from_writer(BufWriter::new(File::create(path)?));
+    for row in disputes {
+        wtr.serialize(row)?;
+    }
+    wtr.flush()?;
+
+    Ok(())
+}
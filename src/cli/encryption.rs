@@ -0,0 +1,269 @@
+//! Encryption of saved state at rest, AES-256-GCM in the chunked STREAM
+//! construction age uses: the snapshot is sealed in 64 KiB chunks, each with
+//! its own nonce (a random prefix, the chunk number and a last-chunk flag),
+//! so snapshots of any size stream through a fixed buffer and a truncated or
+//! reordered file fails to decrypt instead of loading partial state.
+
+use std::{
+    env,
+    error::Error,
+    io::{self, Read, Write},
+    path::Path,
+};
+
+use aes_gcm::{
+    Aes256Gcm, Key, Nonce,
+    aead::{AeadInPlace, KeyInit},
+};
+
+/// Starts every encrypted file, snapshots start with `TPES` or a client count.
+pub const MAGIC: &[u8; 8] = b"TPEENC1\0";
+/// Environment variable holding the key when no key file is given.
+pub const KEY_ENV: &str = "TPE_STATE_KEY";
+
+const CHUNK: usize = 64 * 1024;
+const TAG: usize = 16;
+const PREFIX: usize = 7;
+
+/// A 256-bit key, written as 64 hex digits.
+#[derive(Clone)]
+pub struct StateKey(Key<Aes256Gcm>);
+
+impl StateKey {
+    /// The key in `path`, or else in `TPE_STATE_KEY`, or none.
+    pub fn resolve(path: Option<&Path>) -> Result<Option<StateKey>, Box<dyn Error>> {
+        let (text, source) = match path {
+            Some(path) => (std::fs::read_to_string(path)?, path.display().to_string()),
+            None => match env::var(KEY_ENV) {
+                Ok(text) => (text, KEY_ENV.to_string()),
+                Err(env::VarError::NotPresent) => return Ok(None),
+                Err(err) => return Err(format!("{KEY_ENV}: {err}").into()),
+            },
+        };
+        StateKey::parse(text.trim())
+            .map(Some)
+            .map_err(|err| format!("{source}: {err}").into())
+    }
+
+    pub fn parse(hex: &str) -> Result<StateKey, String> {
+        let digits = hex.as_bytes();
+        if digits.len() != 64 {
+            return Err("the key must be 64 hex digits (32 bytes)".to_string());
+        }
+        let mut key = [0; 32];
+        for (byte, pair) in key.iter_mut().zip(digits.chunks(2)) {
+            let pair = std::str::from_utf8(pair).map_err(|err| err.to_string())?;
+            *byte = u8::from_str_radix(pair, 16)
+                .map_err(|_| format!("`{pair}` in the key isn't hex"))?;
+        }
+        Ok(StateKey(key.into()))
+    }
+}
+
+/// Whether `header`, the start of a file, is an encrypted file's.
+pub fn is_encrypted(header: &[u8]) -> bool {
+    header.starts_with(MAGIC)
+}
+
+fn nonce(
+    prefix: &[u8; PREFIX],
+    counter: u32,
+    last: bool,
+) -> Nonce<aes_gcm::aes::cipher::consts::U12> {
+    let mut nonce = [0; 12];
+    nonce[..PREFIX].copy_from_slice(prefix);
+    nonce[PREFIX..11].copy_from_slice(&counter.to_be_bytes());
+    nonce[11] = last as u8;
+    nonce.into()
+}
+
+fn next_counter(counter: u32) -> io::Result<u32> {
+    counter
+        .checked_add(1)
+        .ok_or_else(|| io::Error::other("encrypted file has too many chunks"))
+}
+
+/// Encrypts everything written through it. `finish` seals the last chunk,
+/// a writer dropped without it leaves a file that won't decrypt.
+pub struct EncryptWriter<W: Write> {
+    w: W,
+    cipher: Aes256Gcm,
+    prefix: [u8; PREFIX],
+    counter: u32,
+    buf: Vec<u8>,
+}
+
+impl<W: Write> EncryptWriter<W> {
+    pub fn new(mut w: W, key: &StateKey) -> io::Result<Self> {
+        let prefix: [u8; PREFIX] = rand::random();
+        w.write_all(MAGIC)?;
+        w.write_all(&prefix)?;
+        Ok(EncryptWriter {
+            w,
+            cipher: Aes256Gcm::new(&key.0),
+            prefix,
+            counter: 0,
+            buf: Vec::with_capacity(CHUNK + TAG),
+        })
+    }
+
+    fn seal(&mut self, last: bool) -> io::Result<()> {
+        let nonce = nonce(&self.prefix, self.counter, last);
+        self.cipher
+            .encrypt_in_place(&nonce, b"", &mut self.buf)
+            .map_err(|_| io::Error::other("encryption failed"))?;
+        self.w.write_all(&self.buf)?;
+        self.buf.clear();
+        self.counter = next_counter(self.counter)?;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        // Only the last chunk is ever shorter than a full one, even if empty
+        if self.buf.len() == CHUNK {
+            self.seal(false)?;
+        }
+        self.seal(true)?;
+        self.w.flush()?;
+        Ok(self.w)
+    }
+}
+
+impl<W: Write> Write for EncryptWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.buf.len() == CHUNK {
+            self.seal(false)?;
+        }
+        let n = buf.len().min(CHUNK - self.buf.len());
+        self.buf.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+
+    /// Flushes what was sealed so far, a partial chunk waits for more data or `finish`.
+    fn flush(&mut self) -> io::Result<()> {
+        self.w.flush()
+    }
+}
+
+/// Decrypts a file written by `EncryptWriter`.
+pub struct DecryptReader<R: Read> {
+    r: R,
+    cipher: Aes256Gcm,
+    prefix: [u8; PREFIX],
+    counter: u32,
+    buf: Vec<u8>,
+    pos: usize,
+    done: bool,
+}
+
+impl<R: Read> DecryptReader<R> {
+    pub fn new(mut r: R, key: &StateKey) -> io::Result<Self> {
+        let mut header = [0; MAGIC.len() + PREFIX];
+        r.read_exact(&mut header)?;
+        if !is_encrypted(&header) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not an encrypted snapshot",
+            ));
+        }
+        Ok(DecryptReader {
+            r,
+            cipher: Aes256Gcm::new(&key.0),
+            prefix: header[MAGIC.len()..].try_into().expect("prefix length"),
+            counter: 0,
+            buf: Vec::with_capacity(CHUNK + TAG),
+            pos: 0,
+            done: false,
+        })
+    }
+
+    fn open_next(&mut self) -> io::Result<()> {
+        self.buf.resize(CHUNK + TAG, 0);
+        let mut len = 0;
+        while len < self.buf.len() {
+            match self.r.read(&mut self.buf[len..])? {
+                0 => break,
+                n => len += n,
+            }
+        }
+        self.buf.truncate(len);
+        let last = len < CHUNK + TAG;
+        let nonce = nonce(&self.prefix, self.counter, last);
+        self.cipher
+            .decrypt_in_place(&nonce, b"", &mut self.buf)
+            .map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "snapshot doesn't decrypt: wrong key, or the file is damaged or truncated",
+                )
+            })?;
+        self.pos = 0;
+        self.done = last;
+        self.counter = next_counter(self.counter)?;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for DecryptReader<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.buf.len() {
+            if self.done {
+                return Ok(0);
+            }
+            self.open_next()?;
+        }
+        let n = out.len().min(self.buf.len() - self.pos);
+        out[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(digit: char) -> StateKey {
+        StateKey::parse(&digit.to_string().repeat(64)).unwrap()
+    }
+
+    fn encrypt(data: &[u8], key: &StateKey) -> Vec<u8> {
+        let mut w = EncryptWriter::new(Vec::new(), key).unwrap();
+        w.write_all(data).unwrap();
+        w.finish().unwrap()
+    }
+
+    fn decrypt(data: &[u8], key: &StateKey) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        DecryptReader::new(data, key)?.read_to_end(&mut out)?;
+        Ok(out)
+    }
+
+    #[test]
+    fn test_round_trip_across_chunk_boundaries() {
+        let key = key('a');
+        for len in [0, 1, CHUNK - 1, CHUNK, CHUNK + 1, 3 * CHUNK] {
+            let data: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            let encrypted = encrypt(&data, &key);
+            assert!(is_encrypted(&encrypted));
+            assert_eq!(decrypt(&encrypted, &key).unwrap(), data, "length {len}");
+        }
+    }
+
+    #[test]
+    fn test_wrong_key_and_tampering_are_detected() {
+        let data = vec![7; 2 * CHUNK + 10];
+        let encrypted = encrypt(&data, &key('a'));
+
+        assert!(decrypt(&encrypted, &key('b')).is_err());
+        // Dropping the last chunk leaves a full chunk at the end, not marked last
+        let truncated = &encrypted[..encrypted.len() - (10 + TAG)];
+        assert!(decrypt(truncated, &key('a')).is_err());
+        let mut flipped = encrypted.clone();
+        flipped[MAGIC.len() + PREFIX + 5] ^= 1;
+        assert!(decrypt(&flipped, &key('a')).is_err());
+
+        assert!(StateKey::parse("abc").is_err());
+        assert!(StateKey::parse(&"g".repeat(64)).is_err());
+    }
+}
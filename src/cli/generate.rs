@@ -0,0 +1,340 @@
+use std::{
+    error::Error,
+    fs::File,
+    io::{self, Write},
+    path::PathBuf,
+};
+
+use clap::Args;
+use rand::{Rng, RngExt, SeedableRng, rngs::StdRng};
+use rust_decimal::Decimal;
+
+use toy_payments_engine::types::common::{ClientId, TxId};
+
+/// Chance that a row starts a fraud scenario instead of a regular transaction
+const FRAUD_SCENARIO_RATE: f64 = 0.001;
+/// How many recent deposits are remembered as dispute candidates
+const RECENT_DEPOSITS: usize = 10_000;
+
+#[derive(Debug, Args)]
+pub struct GenArgs {
+    /// Number of rows to emit (accepts k/M/G suffixes, e.g. 10M)
+    #[arg(long, value_parser = parse_count, default_value = "1000")]
+    pub rows: u64,
+
+    /// Number of distinct clients (accepts k/M/G suffixes, at most 65535)
+    #[arg(long, value_parser = parse_count, default_value = "100")]
+    pub clients: u64,
+
+    /// Fraction of rows that dispute an earlier deposit
+    #[arg(long, value_parser = parse_rate, default_value_t = 0.01)]
+    pub dispute_rate: f64,
+
+    /// Mix in tricky sequences: dispute after withdrawal, duplicate ids, locked-account activity
+    #[arg(long)]
+    pub fraud_scenarios: bool,
+
+    /// Write to a file instead of stdout
+    #[arg(long, short)]
+    pub output: Option<PathBuf>,
+
+    /// Seed for the random rows, a run without one prints the seed it used
+    #[arg(long)]
+    pub seed: Option<u64>,
+}
+
+pub fn run(args: GenArgs) -> Result<(), Box<dyn Error>> {
+    // Before the output file is created
+    check(&args)?;
+    let output: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout().lock()),
+    };
+    let seed = args.seed.unwrap_or_else(rand::random);
+    if args.seed.is_none() {
+        eprintln!("gen: seed {seed}");
+    }
+    generate(&args, StdRng::seed_from_u64(seed), output)
+}
+
+/// Writes the rows `args` asks for to `output` (`args.output` is ignored).
+pub fn generate<R: Rng, W: Write>(args: &GenArgs, rng: R, output: W) -> Result<(), Box<dyn Error>> {
+    let clients = check(args)?;
+    let mut generator = Generator {
+        rng,
+        wtr: csv::Writer::from_writer(io::BufWriter::new(output)),
+        clients,
+        dispute_rate: args.dispute_rate,
+        fraud_scenarios: args.fraud_scenarios,
+        rows_left: args.rows,
+        last_tx: 0,
+        deposits: Vec::new(),
+        disputes: Vec::new(),
+    };
+    generator.generate()?;
+    generator.wtr.flush()?;
+
+    Ok(())
+}
+
+/// Validates the counts, returning the number of clients.
+fn check(args: &GenArgs) -> Result<ClientId, Box<dyn Error>> {
+    if args.rows > TxId::MAX as u64 {
+        return Err(From::from(format!("--rows can be at most {}", TxId::MAX)));
+    }
+    match ClientId::try_from(args.clients) {
+        Ok(c) if c > 0 => Ok(c),
+        _ => Err(From::from(format!(
+            "--clients must be between 1 and {}",
+            ClientId::MAX
+        ))),
+    }
+}
+
+#[derive(serde::Serialize)]
+struct GenRow {
+    r#type: &'static str,
+    client: ClientId,
+    tx: TxId,
+    amount: Option<Decimal>,
+}
+
+struct Generator<R, W: Write> {
+    rng: R,
+    wtr: csv::Writer<W>,
+    clients: ClientId,
+    dispute_rate: f64,
+    fraud_scenarios: bool,
+    rows_left: u64,
+    last_tx: TxId,
+    // Deposits that can still be disputed
+    deposits: Vec<(ClientId, TxId)>,
+    // Disputes waiting for a resolve or chargeback
+    disputes: Vec<(ClientId, TxId)>,
+}
+
+impl<R: Rng, W: Write> Generator<R, W> {
+    fn generate(&mut self) -> csv::Result<()> {
+        while self.rows_left > 0 {
+            if self.fraud_scenarios && self.rng.random_bool(FRAUD_SCENARIO_RATE) {
+                self.fraud_scenario()?;
+            } else if !self.disputes.is_empty() && self.rng.random_bool(self.dispute_rate) {
+                self.settle_dispute()?;
+            } else if !self.deposits.is_empty() && self.rng.random_bool(self.dispute_rate) {
+                self.open_dispute()?;
+            } else if self.rng.random_bool(0.6) {
+                let client = self.random_client();
+                self.deposit(client)?;
+            } else {
+                let client = self.random_client();
+                self.withdrawal(client)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn fraud_scenario(&mut self) -> csv::Result<()> {
+        let client = self.random_client();
+
+        match self.rng.random_range(0..3) {
+            0 => {
+                // Deposit, withdraw it all, then dispute and charge back the deposit
+                let amount = self.random_amount();
+                let tx = self.next_tx();
+                self.emit("deposit", client, tx, Some(amount))?;
+                let withdrawal_tx = self.next_tx();
+                self.emit("withdrawal", client, withdrawal_tx, Some(amount))?;
+                self.emit("dispute", client, tx, None)?;
+                self.emit("chargeback", client, tx, None)?;
+            }
+            1 => {
+                // Replay an already used tx id with a different amount
+                if self.deposits.is_empty() {
+                    return Ok(());
+                }
+                let (client, tx) = self.deposits[self.rng.random_range(0..self.deposits.len())];
+                let amount = self.random_amount();
+                self.emit("deposit", client, tx, Some(amount))?;
+            }
+            _ => {
+                // Lock the account and keep transacting on it
+                let tx = self.deposit(client)?;
+                self.emit("dispute", client, tx, None)?;
+                self.emit("chargeback", client, tx, None)?;
+                self.deposit(client)?;
+                self.withdrawal(client)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn open_dispute(&mut self) -> csv::Result<()> {
+        let index = self.rng.random_range(0..self.deposits.len());
+        let (client, tx) = self.deposits.swap_remove(index);
+        self.emit("dispute", client, tx, None)?;
+        self.disputes.push((client, tx));
+
+        Ok(())
+    }
+
+    fn settle_dispute(&mut self) -> csv::Result<()> {
+        let index = self.rng.random_range(0..self.disputes.len());
+        let (client, tx) = self.disputes.swap_remove(index);
+        let r#type = if self.rng.random_bool(0.9) {
+            "resolve"
+        } else {
+            "chargeback"
+        };
+
+        self.emit(r#type, client, tx, None)
+    }
+
+    fn deposit(&mut self, client: ClientId) -> csv::Result<TxId> {
+        let tx = self.next_tx();
+        let amount = self.random_amount();
+        self.emit("deposit", client, tx, Some(amount))?;
+
+        if self.deposits.len() < RECENT_DEPOSITS {
+            self.deposits.push((client, tx));
+        } else {
+            let index = self.rng.random_range(0..RECENT_DEPOSITS);
+            self.deposits[index] = (client, tx);
+        }
+
+        Ok(tx)
+    }
+
+    fn withdrawal(&mut self, client: ClientId) -> csv::Result<()> {
+        let tx = self.next_tx();
+        // Smaller than deposits on average so most withdrawals succeed
+        let amount = (self.random_amount() / Decimal::TWO).round_dp(4);
+        self.emit("withdrawal", client, tx, Some(amount))
+    }
+
+    fn emit(
+        &mut self,
+        r#type: &'static str,
+        client: ClientId,
+        tx: TxId,
+        amount: Option<Decimal>,
+    ) -> csv::Result<()> {
+        // Scenarios spanning several rows get truncated once the budget runs out
+        if self.rows_left == 0 {
+            return Ok(());
+        }
+        self.rows_left -= 1;
+
+        self.wtr.serialize(GenRow {
+            r#type,
+            client,
+            tx,
+            amount,
+        })
+    }
+
+    fn next_tx(&mut self) -> TxId {
+        self.last_tx += 1;
+        self.last_tx
+    }
+
+    fn random_client(&mut self) -> ClientId {
+        self.rng.random_range(1..=self.clients)
+    }
+
+    fn random_amount(&mut self) -> Decimal {
+        // Up to 10k with 4 decimal places
+        Decimal::new(self.rng.random_range(1..100_000_000), 4)
+    }
+}
+
+/// Parses counts like `500`, `50k` or `10M`.
+pub(crate) fn parse_count(value: &str) -> Result<u64, String> {
+    let (digits, multiplier) = match value.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&value[..i], 1_000),
+        Some((i, 'm' | 'M')) => (&value[..i], 1_000_000),
+        Some((i, 'g' | 'G')) => (&value[..i], 1_000_000_000),
+        _ => (value, 1),
+    };
+
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(|| format!("invalid count `{value}`"))
+}
+
+fn parse_rate(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(rate) if (0.0..=1.0).contains(&rate) => Ok(rate),
+        _ => Err(format!("`{value}` is not a rate between 0 and 1")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use toy_payments_engine::{engine::Engine, io::csv::CsvRow, types::transactions::Tx};
+
+    fn generate(rows: u64, fraud_scenarios: bool, seed: u64) -> Vec<u8> {
+        let mut generator = Generator {
+            rng: StdRng::seed_from_u64(seed),
+            wtr: csv::Writer::from_writer(Vec::new()),
+            clients: 10,
+            dispute_rate: 0.1,
+            fraud_scenarios,
+            rows_left: rows,
+            last_tx: 0,
+            deposits: Vec::new(),
+            disputes: Vec::new(),
+        };
+        generator.generate().unwrap();
+        generator.wtr.into_inner().unwrap()
+    }
+
+    #[test]
+    fn test_parse_count() {
+        assert_eq!(parse_count("500"), Ok(500));
+        assert_eq!(parse_count("50k"), Ok(50_000));
+        assert_eq!(parse_count("10M"), Ok(10_000_000));
+        assert_eq!(parse_count("2G"), Ok(2_000_000_000));
+        assert!(parse_count("").is_err());
+        assert!(parse_count("M").is_err());
+        assert!(parse_count("1.5M").is_err());
+    }
+
+    #[test]
+    fn test_parse_rate() {
+        assert_eq!(parse_rate("0.01"), Ok(0.01));
+        assert!(parse_rate("1.5").is_err());
+        assert!(parse_rate("-0.1").is_err());
+    }
+
+    #[test]
+    fn test_same_seed_same_rows() {
+        assert_eq!(generate(1_000, true, 42), generate(1_000, true, 42));
+        assert_ne!(generate(1_000, true, 42), generate(1_000, true, 43));
+    }
+
+    #[test]
+    fn test_generated_csv_is_processable() {
+        let csv = generate(5_000, true, 7);
+
+        let mut rdr = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_slice());
+        let mut engine = Engine::new();
+        let mut rows = 0;
+
+        for result in rdr.deserialize() {
+            let record: CsvRow = result.unwrap();
+            let _ = engine.process_tx(Tx::try_from(record).unwrap());
+            rows += 1;
+        }
+
+        assert_eq!(rows, 5_000);
+        assert!(engine.clients_iter().next().is_some());
+    }
+}
@@ -0,0 +1,115 @@
+//! `--snapshot-on-signal`: SIGUSR1 saves the state as of the current row
+//! while the run goes on. The engine is forked between two rows, which only
+//! copies the clients, and the fork is written on a thread of its own.
+
+use std::{
+    io,
+    path::PathBuf,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::{self, JoinHandle},
+};
+
+use signal_hook::consts::SIGUSR1;
+use toy_payments_engine::engine::Engine;
+
+use crate::cli::{encryption::StateKey, state::replace_state};
+
+pub struct HotSnapshots {
+    path: PathBuf,
+    key: Option<StateKey>,
+    requested: Arc<AtomicBool>,
+    writer: Option<JoinHandle<()>>,
+    quiet: bool,
+}
+
+impl HotSnapshots {
+    pub fn install(path: PathBuf, key: Option<StateKey>, quiet: bool) -> io::Result<Self> {
+        let requested = Arc::new(AtomicBool::new(false));
+        signal_hook::flag::register(SIGUSR1, requested.clone())?;
+        Ok(HotSnapshots {
+            path,
+            key,
+            requested,
+            writer: None,
+            quiet,
+        })
+    }
+
+    /// Starts writing a snapshot of `engine` if one was asked for since the
+    /// last call. `rows` is how many rows it has seen, for the messages.
+    pub fn poll(&mut self, engine: &Engine, rows: u64) {
+        // Checked on every row, the swap only once there was a signal
+        if !self.requested.load(Ordering::Relaxed) || !self.requested.swap(false, Ordering::Relaxed)
+        {
+            return;
+        }
+        if self
+            .writer
+            .as_ref()
+            .is_some_and(|writer| !writer.is_finished())
+        {
+            if !self.quiet {
+                eprintln!("snapshot: still writing the previous one, skipped");
+            }
+            return;
+        }
+        self.wait();
+
+        let engine = engine.fork();
+        let (path, key, quiet) = (self.path.clone(), self.key.clone(), self.quiet);
+        self.writer = Some(thread::spawn(move || {
+            // A failed backup is reported, the run itself goes on
+            match replace_state(&engine, &path, key.as_ref()) {
+                Ok(()) if !quiet => {
+                    eprintln!(
+                        "snapshot: state after {rows} rows written to {}",
+                        path.display()
+                    )
+                }
+                Ok(()) => {}
+                Err(err) => eprintln!("warning: snapshot to {} failed: {err}", path.display()),
+            }
+        }));
+    }
+
+    /// Waits for a snapshot still being written.
+    pub fn wait(&mut self) {
+        if let Some(writer) = self.writer.take() {
+            writer.join().expect("snapshot writer panicked");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::state::load_state;
+    use rust_decimal_macros::dec;
+    use toy_payments_engine::types::transactions::{DepositTx, Tx};
+
+    #[test]
+    fn test_signal_snapshots_the_current_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("hot.state");
+        let mut snapshots = HotSnapshots::install(path.clone(), None, true).unwrap();
+        let mut engine = Engine::new();
+        let deposit = |tx_id| Tx::Deposit(DepositTx::new(1, tx_id, dec!(1)).unwrap());
+
+        engine.process_tx(deposit(1)).unwrap();
+        snapshots.poll(&engine, 1);
+        assert!(!path.exists());
+
+        signal_hook::low_level::raise(SIGUSR1).unwrap();
+        snapshots.poll(&engine, 1);
+        // Applied after the fork, so not in the snapshot
+        engine.process_tx(deposit(2)).unwrap();
+        snapshots.wait();
+
+        let saved = load_state(&path, None).unwrap();
+        assert_eq!(saved.client(1).unwrap().total, dec!(1));
+        assert_eq!(engine.client(1).unwrap().total, dec!(2));
+    }
+}
@@ -0,0 +1,398 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    error::Error,
+    path::PathBuf,
+};
+
+use clap::Args;
+use rust_decimal::Decimal;
+use toy_payments_engine::types::{
+    common::{ClientId, TxId},
+    transactions::TxType,
+};
+
+use crate::cli::mapping::{Mapping, Role};
+
+/// Header spellings that hint at a role, compared lowercased without `_`, `-` or spaces.
+const HINTS: [(Role, &[&str]); 6] = [
+    (
+        Role::Type,
+        &[
+            "type",
+            "txtype",
+            "transactiontype",
+            "kind",
+            "action",
+            "operation",
+        ],
+    ),
+    (
+        Role::Client,
+        &[
+            "client",
+            "clientid",
+            "customer",
+            "customerid",
+            "account",
+            "accountid",
+            "user",
+        ],
+    ),
+    (
+        Role::Tx,
+        &[
+            "tx",
+            "txid",
+            "transaction",
+            "transactionid",
+            "id",
+            "reference",
+            "ref",
+        ],
+    ),
+    (Role::Amount, &["amount", "amt", "value", "sum", "quantity"]),
+    (
+        Role::Timestamp,
+        &[
+            "timestamp",
+            "time",
+            "ts",
+            "date",
+            "datetime",
+            "createdat",
+            "created",
+        ],
+    ),
+    (Role::Currency, &["currency", "ccy", "cur", "currencycode"]),
+];
+
+/// Scores below this don't get a role, a matching header alone is worth 0.5.
+const MIN_SCORE: f64 = 0.5;
+/// Values quoted in an anomaly.
+const EXAMPLES: usize = 3;
+
+#[derive(Debug, Args)]
+pub struct InspectArgs {
+    /// Transactions CSV to look at
+    pub input: PathBuf,
+
+    /// Rows read from the start of the input
+    #[arg(long, value_name = "ROWS", default_value_t = 10_000)]
+    pub sample: usize,
+}
+
+/// What the sampled values of one column look like.
+#[derive(Debug, Default)]
+struct ColumnStats {
+    non_empty: usize,
+    client_ids: usize,
+    tx_ids: usize,
+    decimals: usize,
+    types: usize,
+    currencies: usize,
+    unix_times: usize,
+    dates: usize,
+    distinct: HashSet<String>,
+}
+
+impl ColumnStats {
+    fn add(&mut self, value: &str) {
+        if value.is_empty() {
+            return;
+        }
+        self.non_empty += 1;
+        self.client_ids += value.parse::<ClientId>().is_ok() as usize;
+        self.tx_ids += value.parse::<TxId>().is_ok() as usize;
+        let decimal = value.parse::<Decimal>().ok();
+        self.decimals += decimal.is_some() as usize;
+        self.unix_times += decimal.is_some_and(|d| {
+            (Decimal::from(1_000_000_000)..Decimal::from(10_000_000_000_u64)).contains(&d)
+        }) as usize;
+        self.dates += (value.len() >= 10
+            && value.as_bytes()[4] == b'-'
+            && value.as_bytes()[7] == b'-') as usize;
+        self.types += TxType::parse_lenient(value).is_some() as usize;
+        self.currencies +=
+            (value.len() == 3 && value.bytes().all(|b| b.is_ascii_uppercase())) as usize;
+        self.distinct.insert(value.to_string());
+    }
+
+    fn share(&self, count: usize) -> f64 {
+        count as f64 / self.non_empty.max(1) as f64
+    }
+
+    /// How much the values look like they play `role`, 0 to 1.
+    fn score(&self, role: Role) -> f64 {
+        let distinct = self.distinct.len() as f64 / self.non_empty.max(1) as f64;
+        match role {
+            Role::Type => self.share(self.types),
+            // Clients repeat, transaction ids only for disputes and their outcome
+            Role::Client => self.share(self.client_ids) * (1.0 - distinct / 2.0),
+            Role::Tx => self.share(self.tx_ids) * distinct,
+            Role::Amount => self.share(self.decimals) * (1.0 - self.share(self.unix_times)),
+            Role::Timestamp => self.share(self.unix_times + self.dates),
+            Role::Currency => self.share(self.currencies),
+        }
+    }
+}
+
+fn hint(role: Role, header: &str) -> f64 {
+    let header: String = header
+        .chars()
+        .filter(|c| !matches!(c, '_' | '-' | ' '))
+        .flat_map(char::to_lowercase)
+        .collect();
+    match HINTS.iter().find(|(r, _)| *r == role) {
+        Some((_, names)) if names.contains(&header.as_str()) => 0.5,
+        _ => 0.0,
+    }
+}
+
+/// The mapping inferred from a sample and what looked wrong in it.
+#[derive(Debug)]
+struct Inspection {
+    rows: usize,
+    mapping: Mapping,
+    anomalies: Vec<String>,
+}
+
+fn inspect<R: std::io::Read>(mut rdr: csv::Reader<R>, sample: usize) -> csv::Result<Inspection> {
+    let headers = rdr.headers()?.clone();
+    let mut stats: Vec<ColumnStats> = headers.iter().map(|_| ColumnStats::default()).collect();
+    let mut records = Vec::new();
+    let mut ragged = Vec::new();
+    for record in rdr.records().take(sample) {
+        let record = record?;
+        if record.len() != headers.len() {
+            ragged.push(record.position().map_or(0, |p| p.line()));
+        }
+        for (column, value) in stats.iter_mut().zip(record.iter()) {
+            column.add(value);
+        }
+        records.push(record);
+    }
+
+    // Best scoring column first, every column and role used once
+    let mut candidates = Vec::new();
+    for role in Role::ALL {
+        for (i, header) in headers.iter().enumerate() {
+            let score = stats[i].score(role) + hint(role, header);
+            if score >= MIN_SCORE {
+                candidates.push((score, role, i));
+            }
+        }
+    }
+    candidates.sort_by(|a, b| b.0.total_cmp(&a.0));
+    let mut columns: BTreeMap<Role, usize> = BTreeMap::new();
+    for (_, role, i) in candidates {
+        if !columns.contains_key(&role) && !columns.values().any(|&c| c == i) {
+            columns.insert(role, i);
+        }
+    }
+
+    let mut anomalies = Vec::new();
+    if let Some(&line) = ragged.first() {
+        anomalies.push(format!(
+            "{} rows have a different number of fields than the header, the first on line {line}",
+            ragged.len()
+        ));
+    }
+    for role in Role::ALL {
+        if role.is_required() && !columns.contains_key(&role) {
+            anomalies.push(format!(
+                "no column looks like `{role}`, add it to the mapping"
+            ));
+        }
+    }
+    let values = |role| {
+        let column = columns.get(&role).copied();
+        records
+            .iter()
+            .filter_map(move |record| record.get(column?))
+            .filter(|value| !value.is_empty())
+    };
+    let mut report = |what: &str, bad: Vec<&str>| {
+        if !bad.is_empty() {
+            let examples: Vec<_> = bad
+                .iter()
+                .take(EXAMPLES)
+                .map(|v| format!("`{v}`"))
+                .collect();
+            anomalies.push(format!(
+                "{} {what}, e.g. {}",
+                bad.len(),
+                examples.join(", ")
+            ));
+        }
+    };
+    report(
+        "types only read with --lenient-types",
+        values(Role::Type)
+            .filter(|v| v.parse::<TxType>().is_err() && TxType::parse_lenient(v).is_some())
+            .collect(),
+    );
+    report(
+        "unknown types",
+        values(Role::Type)
+            .filter(|v| TxType::parse_lenient(v).is_none())
+            .collect(),
+    );
+    report(
+        "client ids outside 0-65535",
+        values(Role::Client)
+            .filter(|v| v.parse::<ClientId>().is_err())
+            .collect(),
+    );
+    report(
+        "transaction ids outside 0-4294967295",
+        values(Role::Tx)
+            .filter(|v| v.parse::<TxId>().is_err())
+            .collect(),
+    );
+    report(
+        "amounts that aren't plain numbers (see --number-format)",
+        values(Role::Amount)
+            .filter(|v| v.parse::<Decimal>().is_err())
+            .collect(),
+    );
+    report(
+        "negative amounts",
+        values(Role::Amount)
+            .filter(|v| v.parse::<Decimal>().is_ok_and(|d| d.is_sign_negative()))
+            .collect(),
+    );
+    report(
+        "timestamps that aren't Unix seconds, which --reorder-window reads",
+        values(Role::Timestamp)
+            .filter(|v| v.parse::<Decimal>().is_err())
+            .collect(),
+    );
+    if let (Some(&type_column), Some(&tx_column)) =
+        (columns.get(&Role::Type), columns.get(&Role::Tx))
+    {
+        let mut seen = HashSet::new();
+        let duplicates = records
+            .iter()
+            .filter(|record| {
+                let tx_type = record.get(type_column).and_then(TxType::parse_lenient);
+                matches!(tx_type, Some(TxType::Deposit | TxType::Withdrawal))
+            })
+            .filter_map(|record| record.get(tx_column))
+            .filter(|tx| !seen.insert(*tx))
+            .collect();
+        report("repeated deposit or withdrawal ids", duplicates);
+    }
+    let currencies: HashSet<_> = values(Role::Currency).collect();
+    if currencies.len() > 1 {
+        let mut currencies: Vec<_> = currencies.into_iter().collect();
+        currencies.sort_unstable();
+        anomalies.push(format!(
+            "{} currencies ({}), the engine adds amounts up without converting them",
+            currencies.len(),
+            currencies.join(", ")
+        ));
+    }
+
+    let mapping = Mapping {
+        columns: columns
+            .into_iter()
+            .map(|(role, i)| (role, headers[i].to_string()))
+            .collect(),
+    };
+    Ok(Inspection {
+        rows: records.len(),
+        mapping,
+        anomalies,
+    })
+}
+
+/// Prints the inferred column roles and anomalies to stderr and the mapping,
+/// ready for `--mapping`, to stdout.
+pub fn run(args: InspectArgs) -> Result<(), Box<dyn Error>> {
+    let rdr = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_path(&args.input)?;
+    let inspection = inspect(rdr, args.sample)?;
+
+    eprintln!("inspect: sampled {} rows", inspection.rows);
+    for role in Role::ALL {
+        match inspection.mapping.columns.get(&role) {
+            Some(column) => eprintln!("  {:<10} <- {column}", role.name()),
+            None => eprintln!("  {:<10} (none)", role.name()),
+        }
+    }
+    for anomaly in &inspection.anomalies {
+        eprintln!("warning: {anomaly}");
+    }
+    print!("{}", inspection.mapping.to_toml());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inspect_str(input: &str) -> Inspection {
+        inspect(
+            csv::ReaderBuilder::new()
+                .flexible(true)
+                .from_reader(input.as_bytes()),
+            100,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_roles_from_values_and_headers() {
+        let inspection = inspect_str(
+            "\
+created_at,customer,ref,kind,value,ccy
+1717171717,3,100,Deposit,10.5,EUR
+1717171718,3,101,withdrawal,2.25,EUR
+1717171719,4,102,deposit,7,USD
+1717171720,3,100,dispute,,EUR
+1717171721,4,101,deposit,1.0,EUR
+",
+        );
+        let columns: Vec<_> = inspection
+            .mapping
+            .columns
+            .iter()
+            .map(|(role, column)| (role.name(), column.as_str()))
+            .collect();
+        assert_eq!(
+            columns,
+            vec![
+                ("type", "kind"),
+                ("client", "customer"),
+                ("tx", "ref"),
+                ("amount", "value"),
+                ("timestamp", "created_at"),
+                ("currency", "ccy"),
+            ]
+        );
+        assert_eq!(
+            inspection.anomalies,
+            vec![
+                "1 types only read with --lenient-types, e.g. `Deposit`",
+                "1 repeated deposit or withdrawal ids, e.g. `101`",
+                "2 currencies (EUR, USD), the engine adds amounts up without converting them",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unrecognizable_columns_are_reported() {
+        let inspection = inspect_str("a,b\nfoo,bar\nbaz\n");
+        assert!(inspection.mapping.columns.is_empty());
+        assert_eq!(
+            inspection.anomalies[0],
+            "1 rows have a different number of fields than the header, the first on line 3"
+        );
+        assert_eq!(
+            inspection.anomalies[1],
+            "no column looks like `type`, add it to the mapping"
+        );
+    }
+}
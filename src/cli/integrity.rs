@@ -0,0 +1,194 @@
+//! Integrity manifests (`--input-manifest`, `--output-manifest`): the row
+//! count, size and SHA-256 of a CSV file, so a file cut short or altered on
+//! its way between systems is caught instead of processed.
+
+use std::{
+    error::Error,
+    fs::File,
+    io::{self, BufReader, BufWriter, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use sha2::{Digest, Sha256};
+use toy_payments_engine::pipeline::source::Tap;
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct IntegrityManifest {
+    /// Records after the header
+    pub rows: u64,
+    /// Checked before the file is read when present
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bytes: Option<u64>,
+    /// Lowercase hex
+    pub sha256: String,
+}
+
+impl IntegrityManifest {
+    pub fn read(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let file = File::open(path)?;
+        let mut manifest: IntegrityManifest = serde_json::from_reader(BufReader::new(file))?;
+        manifest.sha256.make_ascii_lowercase();
+        Ok(manifest)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let mut w = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut w, self)?;
+        writeln!(w)?;
+        w.flush()?;
+        Ok(())
+    }
+
+    /// Fails on the first of rows, size or hash that doesn't match `actual`.
+    pub fn verify(&self, actual: &IntegrityManifest) -> Result<(), String> {
+        if actual.rows != self.rows {
+            return Err(format!(
+                "{} rows instead of the {} in its manifest",
+                actual.rows, self.rows
+            ));
+        }
+        if let (Some(expected), Some(bytes)) = (self.bytes, actual.bytes)
+            && expected != bytes
+        {
+            return Err(format!(
+                "{bytes} bytes instead of the {expected} in its manifest"
+            ));
+        }
+        if actual.sha256 != self.sha256 {
+            return Err(format!(
+                "SHA-256 {} instead of the {} in its manifest",
+                actual.sha256, self.sha256
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn hex(hasher: Sha256) -> String {
+    hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// Size and SHA-256 of the input, fed by the thread reading it.
+#[derive(Clone, Default)]
+pub struct InputDigest(Arc<Mutex<(u64, Sha256)>>);
+
+impl InputDigest {
+    pub fn tap(&self) -> Tap {
+        let digest = self.0.clone();
+        Box::new(move |chunk| {
+            let mut digest = digest.lock().unwrap_or_else(|err| err.into_inner());
+            digest.0 += chunk.len() as u64;
+            digest.1.update(chunk);
+        })
+    }
+
+    pub fn manifest(&self, rows: u64) -> IntegrityManifest {
+        let digest = self.0.lock().unwrap_or_else(|err| err.into_inner());
+        IntegrityManifest {
+            rows,
+            bytes: Some(digest.0),
+            sha256: hex(digest.1.clone()),
+        }
+    }
+}
+
+/// Passes writes through, hashing them and counting bytes and lines.
+pub struct DigestWriter<W> {
+    inner: W,
+    bytes: u64,
+    lines: u64,
+    hasher: Sha256,
+}
+
+impl<W: Write> DigestWriter<W> {
+    pub fn new(inner: W) -> Self {
+        DigestWriter {
+            inner,
+            bytes: 0,
+            lines: 0,
+            hasher: Sha256::new(),
+        }
+    }
+
+    /// The manifest of what was written, a CSV with a header line.
+    pub fn finish(self) -> (W, IntegrityManifest) {
+        let manifest = IntegrityManifest {
+            rows: self.lines.saturating_sub(1),
+            bytes: Some(self.bytes),
+            sha256: hex(self.hasher),
+        };
+        (self.inner, manifest)
+    }
+}
+
+impl<W: Write> Write for DigestWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.bytes += n as u64;
+        self.lines += buf[..n].iter().filter(|&&b| b == b'\n').count() as u64;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_output_manifest_verifies_against_the_input_digest() {
+        let csv = b"type,client,tx,amount\ndeposit,1,1,2.5\ndeposit,1,2,1.0\n";
+        let mut w = DigestWriter::new(Vec::new());
+        w.write_all(csv).unwrap();
+        let (written, manifest) = w.finish();
+        assert_eq!(written, csv);
+        assert_eq!(manifest.rows, 2);
+        assert_eq!(manifest.bytes, Some(csv.len() as u64));
+
+        let file = NamedTempFile::new().unwrap();
+        manifest.write(file.path()).unwrap();
+        let expected = IntegrityManifest::read(file.path()).unwrap();
+
+        let digest = InputDigest::default();
+        let mut tap = digest.tap();
+        tap(&csv[..10]);
+        tap(&csv[10..]);
+        assert_eq!(expected.verify(&digest.manifest(2)), Ok(()));
+    }
+
+    #[test]
+    fn test_verify_reports_the_first_mismatch() {
+        let expected = IntegrityManifest {
+            rows: 2,
+            bytes: None,
+            sha256: "ab".repeat(32),
+        };
+        let actual = |rows, sha256: &str| IntegrityManifest {
+            rows,
+            bytes: Some(50),
+            sha256: sha256.to_string(),
+        };
+
+        assert_eq!(
+            expected.verify(&actual(1, "00")),
+            Err("1 rows instead of the 2 in its manifest".to_string())
+        );
+        assert!(
+            expected
+                .verify(&actual(2, &"cd".repeat(32)))
+                .unwrap_err()
+                .starts_with("SHA-256 cdcd")
+        );
+        assert_eq!(expected.verify(&actual(2, &"ab".repeat(32))), Ok(()));
+    }
+}
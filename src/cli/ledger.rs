@@ -0,0 +1,152 @@
+use std::{
+    error::Error,
+    fs::{File, OpenOptions},
+    io::BufWriter,
+    path::Path,
+};
+
+use rust_decimal::Decimal;
+use toy_payments_engine::{
+    engine::Engine,
+    pipeline::results::RowResult,
+    types::{common::TxId, transactions::Tx},
+};
+
+use crate::cli::{
+    output::OutputScale,
+    pseudonym::{ClientIds, ClientLabel},
+};
+
+#[derive(serde::Serialize)]
+struct LedgerRow {
+    tx: TxId,
+    client: ClientLabel,
+    r#type: &'static str,
+    amount: Option<Decimal>,
+    status: &'static str,
+    available: Option<Decimal>,
+    held: Option<Decimal>,
+    total: Option<Decimal>,
+    locked: Option<bool>,
+}
+
+/// Append-only CSV with the outcome of every transaction and the client
+/// balances right after it.
+pub struct LedgerWriter {
+    wtr: csv::Writer<BufWriter<File>>,
+    scale: OutputScale,
+    ids: ClientIds,
+}
+
+impl LedgerWriter {
+    /// When `append` is set the ledger continues an existing file (resumed runs).
+    pub fn create(path: &Path, append: bool, scale: OutputScale) -> Result<Self, Box<dyn Error>> {
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .append(append)
+            .truncate(!append)
+            .open(path)?;
+        let has_content = file.metadata()?.len() > 0;
+
+        let wtr = csv::WriterBuilder::new()
+            .has_headers(!has_content)
+            .from_writer(BufWriter::new(file));
+
+        Ok(LedgerWriter {
+            wtr,
+            scale,
+            ids: ClientIds::default(),
+        })
+    }
+
+    pub fn client_ids(mut self, ids: ClientIds) -> Self {
+        self.ids = ids;
+        self
+    }
+
+    /// Records the row's transaction after the engine has processed it,
+    /// unparsed and skipped rows never reached the engine and are left out.
+    pub fn record(&mut self, engine: &Engine, row_result: &RowResult) -> csv::Result<()> {
+        let Some((tx, result)) = row_result.engine_result() else {
+            return Ok(());
+        };
+        let amount = match tx {
+            Tx::Deposit(deposit_tx) => Some(deposit_tx.amount()),
+            Tx::Withdrawal(withdrawal_tx) => Some(withdrawal_tx.amount()),
+            Tx::OpeningBalance(opening_tx) => Some(opening_tx.amount()),
+            _ => None,
+        };
+        let client = engine
+            .client(tx.client_id())
+            .map(|client| self.scale.client(client));
+
+        self.wtr.serialize(LedgerRow {
+            tx: tx.tx_id(),
+            client: self.ids.label(tx.client_id()),
+            r#type: tx.type_name(),
+            amount: amount.map(|amount| self.scale.apply(amount)),
+            status: match result {
+                Ok(()) => "applied",
+                Err(reason) => reason.code(),
+            },
+            available: client.as_ref().map(|c| c.available),
+            held: client.as_ref().map(|c| c.held),
+            total: client.as_ref().map(|c| c.total),
+            locked: client.as_ref().map(|c| c.locked),
+        })
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.wtr.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+    use tempfile::NamedTempFile;
+    use toy_payments_engine::{
+        pipeline::{InputOffset, results::Results, source::Row},
+        types::transactions::{DepositTx, DisputeTx, WithdrawalTx},
+    };
+
+    #[test]
+    fn test_ledger_rows() {
+        let file = NamedTempFile::new().unwrap();
+        let mut ledger = LedgerWriter::create(file.path(), false, OutputScale::default()).unwrap();
+        let mut engine = Engine::new();
+
+        let txs = [
+            Tx::Withdrawal(WithdrawalTx::new(1, 1, dec!(5)).unwrap()),
+            Tx::Deposit(DepositTx::new(1, 2, dec!(10)).unwrap()),
+            Tx::Dispute(DisputeTx {
+                client_id: 1,
+                tx_id: 2,
+            }),
+        ];
+        let rows = txs.into_iter().map(|tx| Row {
+            line: None,
+            tx: Some(tx),
+            timestamp: None,
+            seq: None,
+            position: InputOffset::default(),
+        });
+        let mut results = Results::new(rows, &mut engine);
+        while let Some(result) = results.next() {
+            ledger.record(results.engine(), &result).unwrap();
+        }
+        ledger.flush().unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(file.path()).unwrap(),
+            "\
+tx,client,type,amount,status,available,held,total,locked
+1,1,withdrawal,5,unknown_client,,,,
+2,1,deposit,10,applied,10,0,10,false
+2,1,dispute,,applied,0,10,10,false
+"
+        );
+    }
+}
@@ -0,0 +1,85 @@
+//! `--lock`: of the runs sharing storage (state, manifest, ledger, ...) only
+//! the one holding the lock file applies transactions. The lock is an OS
+//! file lock, dropped when its process ends however it ends, so there is no
+//! lease to renew or expire. File locks aren't reliable on network file
+//! systems, the lock file should be on a local one.
+
+use std::{
+    error::Error,
+    fs::{File, OpenOptions, TryLockError},
+    io::{Read, Seek, Write},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Held for as long as it is alive.
+pub struct RunLock {
+    _file: File,
+}
+
+impl RunLock {
+    /// Takes the lock on `path`, failing when another run holds it, or with
+    /// `wait` blocking until that run ends (a passive standby).
+    pub fn acquire(path: &Path, wait: bool, quiet: bool) -> Result<Self, Box<dyn Error>> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let holder = holder(&mut file);
+                if !wait {
+                    return Err(From::from(format!(
+                        "{} is locked by another run ({holder})",
+                        path.display()
+                    )));
+                }
+                if !quiet {
+                    eprintln!("lock: waiting for {} ({holder})", path.display());
+                }
+                file.lock()?;
+            }
+            Err(TryLockError::Error(err)) => return Err(err.into()),
+        }
+
+        let since = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        file.set_len(0)?;
+        file.rewind()?;
+        writeln!(file, "pid {} since {since}", std::process::id())?;
+        file.flush()?;
+        Ok(RunLock { _file: file })
+    }
+}
+
+/// What the holder wrote into the lock file, for the error message.
+fn holder(file: &mut File) -> String {
+    let mut holder = String::new();
+    match file.read_to_string(&mut holder) {
+        Ok(_) if !holder.trim().is_empty() => holder.trim().to_string(),
+        _ => "unknown holder".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_second_run_is_refused_until_the_first_ends() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tpe.lock");
+
+        let first = RunLock::acquire(&path, false, true).unwrap();
+        let err = RunLock::acquire(&path, false, true).err().unwrap();
+        let expected = format!("locked by another run (pid {} since", std::process::id());
+        assert!(err.to_string().contains(&expected), "{err}");
+
+        drop(first);
+        assert!(RunLock::acquire(&path, false, true).is_ok());
+    }
+}
@@ -0,0 +1,118 @@
+use std::{
+    error::Error,
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use toy_payments_engine::{engine::rules::Rules, pipeline::InputOffset};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunStatus {
+    Completed,
+    Interrupted,
+    /// Stopped before the first row that would exceed a `--max-*` limit
+    CapacityExceeded,
+    /// Stopped after the last row read before the input failed
+    ReadFailed,
+}
+
+/// Position right after the last row that was fully applied.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Offset {
+    pub byte: u64,
+    pub line: u64,
+    pub record: u64,
+}
+
+impl From<InputOffset> for Offset {
+    fn from(offset: InputOffset) -> Self {
+        Offset {
+            byte: offset.byte,
+            line: offset.line,
+            record: offset.record,
+        }
+    }
+}
+
+impl From<&Offset> for InputOffset {
+    fn from(offset: &Offset) -> Self {
+        InputOffset {
+            byte: offset.byte,
+            line: offset.line,
+            record: offset.record,
+        }
+    }
+}
+
+/// Summary of a run, written last so that the files it references are complete.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct RunManifest {
+    pub input: PathBuf,
+    pub status: RunStatus,
+    pub rows: u64,
+    pub rejected: u64,
+    pub offset: Offset,
+    pub snapshot: Option<PathBuf>,
+    pub rejects: Option<PathBuf>,
+    pub ledger: Option<PathBuf>,
+    #[serde(default)]
+    pub quarantine: Option<PathBuf>,
+    /// Manifests written before rule sets existed ran under v1
+    #[serde(default)]
+    pub rules: Rules,
+}
+
+impl RunManifest {
+    pub fn read(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let file = File::open(path)?;
+        Ok(serde_json::from_reader(BufReader::new(file))?)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let mut w = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut w, self)?;
+        writeln!(w)?;
+        w.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_manifest_round_trip() {
+        let manifest = RunManifest {
+            input: PathBuf::from("transactions.csv"),
+            status: RunStatus::Interrupted,
+            rows: 10,
+            rejected: 2,
+            offset: Offset {
+                byte: 200,
+                line: 12,
+                record: 11,
+            },
+            snapshot: Some(PathBuf::from("engine.state")),
+            rejects: None,
+            ledger: None,
+            quarantine: None,
+            rules: Rules::V2,
+        };
+
+        let file = NamedTempFile::new().unwrap();
+        manifest.write(file.path()).unwrap();
+        let read = RunManifest::read(file.path()).unwrap();
+
+        assert_eq!(read.status, RunStatus::Interrupted);
+        assert_eq!(read.rows, 10);
+        assert_eq!(read.rejected, 2);
+        assert_eq!(read.offset, manifest.offset);
+        assert_eq!(read.snapshot, manifest.snapshot);
+        assert_eq!(read.rejects, None);
+        assert_eq!(read.rules, Rules::V2);
+    }
+}
@@ -0,0 +1,121 @@
+//! Column mapping for inputs that don't use our column names, written by
+//! `tpe inspect` and read by `--mapping`:
+//!
+//! ```toml
+//! [columns]
+//! type = "TransactionType"
+//! client = "customer_id"
+//! tx = "id"
+//! amount = "value"
+//! ```
+
+use std::{collections::BTreeMap, error::Error, fmt, path::Path, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+/// What a column holds, named after the column the engine reads it from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Type,
+    Client,
+    Tx,
+    Amount,
+    Timestamp,
+    /// Not read by the engine, which doesn't convert currencies
+    Currency,
+}
+
+impl Role {
+    pub const ALL: [Role; 6] = [
+        Role::Type,
+        Role::Client,
+        Role::Tx,
+        Role::Amount,
+        Role::Timestamp,
+        Role::Currency,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Role::Type => "type",
+            Role::Client => "client",
+            Role::Tx => "tx",
+            Role::Amount => "amount",
+            Role::Timestamp => "timestamp",
+            Role::Currency => "currency",
+        }
+    }
+
+    /// Without it no row can be processed.
+    pub fn is_required(&self) -> bool {
+        matches!(self, Role::Type | Role::Client | Role::Tx | Role::Amount)
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Role::ALL
+            .into_iter()
+            .find(|role| role.name() == s)
+            .ok_or_else(|| format!("unknown column role `{s}`"))
+    }
+}
+
+/// The input column of every role, roles left out are read from the column
+/// with their own name.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Mapping {
+    pub columns: BTreeMap<Role, String>,
+}
+
+impl Mapping {
+    pub fn read(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let text = std::fs::read_to_string(path)?;
+        toml::from_str(&text).map_err(|err| From::from(format!("{}: {err}", path.display())))
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).expect("a mapping is always valid TOML")
+    }
+
+    /// `(input column, role column)` pairs for `CsvSource::rename_columns`.
+    pub fn renames(&self) -> Vec<(&str, &str)> {
+        self.columns
+            .iter()
+            .map(|(role, column)| (column.as_str(), role.name()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mapping_round_trip() {
+        let mapping = Mapping {
+            columns: BTreeMap::from([
+                (Role::Type, "kind".to_string()),
+                (Role::Amount, "value".to_string()),
+            ]),
+        };
+        let text = mapping.to_toml();
+        assert_eq!(text, "[columns]\ntype = \"kind\"\namount = \"value\"\n");
+        assert_eq!(toml::from_str::<Mapping>(&text).unwrap(), mapping);
+        assert_eq!(
+            mapping.renames(),
+            vec![("kind", "type"), ("value", "amount")]
+        );
+        assert!(toml::from_str::<Mapping>("[columns]\nfee = \"x\"\n").is_err());
+    }
+}
@@ -0,0 +1,149 @@
+use std::{collections::HashMap, error::Error, path::Path};
+
+use toy_payments_engine::{
+    engine::{Engine, Totals},
+    types::common::ClientId,
+};
+
+/// Labels from a sidecar CSV, a `client` column plus any number of others
+/// (region, tier, ...) that are carried through to the output.
+#[derive(Debug, Default)]
+pub struct ClientMetadata {
+    columns: Vec<String>,
+    labels: HashMap<ClientId, Vec<String>>,
+}
+
+impl ClientMetadata {
+    pub fn read(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut rdr = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_path(path)?;
+
+        let headers = rdr.headers()?.clone();
+        let Some(client_column) = headers.iter().position(|h| h == "client") else {
+            return Err(From::from(format!(
+                "{}: expected a `client` column",
+                path.display()
+            )));
+        };
+        let columns = headers
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i != client_column)
+            .map(|(_, h)| h.to_string())
+            .collect();
+
+        let mut labels = HashMap::new();
+        for record in rdr.records() {
+            let record = record?;
+            let id: ClientId = record[client_column].parse().map_err(|err| {
+                format!(
+                    "{}: invalid client `{}`: {err}",
+                    path.display(),
+                    &record[client_column]
+                )
+            })?;
+            let values = record
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| *i != client_column)
+                .map(|(_, v)| v.to_string())
+                .collect();
+            labels.insert(id, values);
+        }
+
+        Ok(ClientMetadata { columns, labels })
+    }
+
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    /// The client's labels in `columns()` order, empty strings when it has none.
+    pub fn labels(&self, id: ClientId) -> Vec<&str> {
+        match self.labels.get(&id) {
+            Some(values) => values.iter().map(String::as_str).collect(),
+            None => vec![""; self.columns.len()],
+        }
+    }
+
+    /// Totals per label of `column` over the engine's clients, sorted by label.
+    pub fn segments(&self, engine: &Engine, column: &str) -> Result<Vec<(&str, Totals)>, String> {
+        let Some(index) = self.columns.iter().position(|c| c == column) else {
+            return Err(format!("no `{column}` column in the client metadata"));
+        };
+
+        let mut segments: HashMap<&str, Totals> = HashMap::new();
+        for client in engine.clients_iter() {
+            let label = self
+                .labels
+                .get(&client.id)
+                .map_or("", |values| values[index].as_str());
+            segments.entry(label).or_default().add_client(client);
+        }
+
+        let mut segments: Vec<_> = segments.into_iter().collect();
+        segments.sort_by_key(|(label, _)| *label);
+        Ok(segments)
+    }
+
+    pub fn segment_lines(&self, engine: &Engine, column: &str) -> Result<Vec<String>, String> {
+        Ok(self
+            .segments(engine, column)?
+            .into_iter()
+            .map(|(label, totals)| {
+                format!(
+                    "{column} {}: clients {}, available {}, held {}, total {}",
+                    segment_label(label),
+                    totals.clients,
+                    totals.available,
+                    totals.held,
+                    totals.total
+                )
+            })
+            .collect())
+    }
+}
+
+/// Label shown for clients without one.
+pub fn segment_label(label: &str) -> &str {
+    if label.is_empty() { "(none)" } else { label }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+    use toy_payments_engine::types::transactions::{DepositTx, Tx};
+
+    #[test]
+    fn test_segment_totals() {
+        let mut file = NamedTempFile::new().unwrap();
+        write!(file, "region,client,tier\neu,1,gold\nus,2,\neu,3,silver\n").unwrap();
+        let metadata = ClientMetadata::read(file.path()).unwrap();
+        assert_eq!(metadata.columns(), ["region", "tier"]);
+        assert_eq!(metadata.labels(3), ["eu", "silver"]);
+        assert_eq!(metadata.labels(9), ["", ""]);
+
+        let mut engine = Engine::new();
+        for (client_id, amount) in [(1, dec!(10)), (2, dec!(5)), (3, dec!(2.5)), (4, dec!(1))] {
+            engine
+                .process_tx(Tx::Deposit(
+                    DepositTx::new(client_id, u32::from(client_id), amount).unwrap(),
+                ))
+                .unwrap();
+        }
+
+        assert_eq!(
+            metadata.segment_lines(&engine, "region").unwrap(),
+            [
+                "region (none): clients 1, available 1, held 0, total 1",
+                "region eu: clients 2, available 12.5, held 0, total 12.5",
+                "region us: clients 1, available 5, held 0, total 5",
+            ]
+        );
+        assert!(metadata.segment_lines(&engine, "country").is_err());
+    }
+}
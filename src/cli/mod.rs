@@ -0,0 +1,571 @@
+pub mod aggregates;
+pub mod alerts;
+pub mod audit;
+pub mod bench;
+#[cfg(feature = "xml")]
+pub mod camt;
+#[cfg(feature = "chaos")]
+pub mod chaos;
+pub mod completions;
+#[cfg(feature = "tui")]
+pub mod dashboard;
+pub mod diagnostic;
+pub mod difftest;
+pub mod disputes;
+pub mod encryption;
+pub mod generate;
+#[cfg(unix)]
+pub mod hot_snapshot;
+pub mod inspect;
+pub mod integrity;
+pub mod ledger;
+pub mod lock;
+pub mod manifest;
+pub mod mapping;
+pub mod metadata;
+pub mod opening;
+#[cfg(feature = "otel")]
+pub mod otel;
+pub mod output;
+pub mod process;
+pub mod progress;
+pub mod pseudonym;
+pub mod quarantine;
+pub mod query;
+pub mod rejects;
+pub mod resources;
+pub mod revert;
+pub mod roster;
+pub mod scenario;
+pub mod security;
+pub mod state;
+pub mod statement;
+pub mod summary;
+pub mod top;
+pub mod trace;
+pub mod what_if;
+
+use std::{path::PathBuf, time::Duration};
+
+use clap::{Args, Parser, Subcommand};
+use encoding_rs::Encoding;
+use rust_decimal::Decimal;
+use toy_payments_engine::{
+    engine::{
+        alerts::Threshold,
+        amount::AmountContext,
+        config::{EngineConfig, MissingClient, MissingDeposit, TxKeys, UnlockPolicy},
+        rules::Rules,
+        settled::SettledPolicy,
+    },
+    pipeline::{number_format::NumberFormat, sequence::SequencePolicy},
+    types::{client::Balance, transactions::TxType},
+};
+
+use crate::cli::output::OutputFormat;
+
+#[derive(Debug, Parser)]
+#[command(name = "tpe", version, about = "Toy payments engine")]
+#[command(args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[command(flatten)]
+    pub process: ProcessArgs,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Generate a synthetic transactions CSV for load testing and demos
+    Gen(generate::GenArgs),
+    /// Look up client balances in a saved state snapshot
+    Query(query::QueryArgs),
+    /// Turn a previous run's balances into opening_balance rows for the next feed
+    Opening(opening::OpeningArgs),
+    /// Preview the impact of proposed disputes and chargebacks on a saved state
+    WhatIf(what_if::WhatIfArgs),
+    /// Replay an input and print the decision trail of one client or transaction
+    Trace(trace::TraceArgs),
+    /// Undo a transaction accepted by mistake in a saved state, with an audit trail
+    Revert(revert::RevertArgs),
+    /// Verify the hash chain of an audit log
+    Audit(audit::AuditArgs),
+    /// Run declarative test scenarios against a fresh engine
+    Scenario(scenario::ScenarioArgs),
+    /// Randomized differential testing: compare two builds over a corpus and random inputs
+    Difftest(difftest::DifftestArgs),
+    /// Time each stage of processing an input (read, parse, convert, apply, write)
+    Bench(bench::BenchArgs),
+    /// Export one client's activity from a ledger as OFX or QIF
+    Statement(statement::StatementArgs),
+    /// Infer the column roles of an unfamiliar input and print a mapping for `--mapping`
+    Inspect(inspect::InspectArgs),
+    /// Print a shell completion script (bash, zsh, fish, ...) to stdout
+    Completions(completions::CompletionsArgs),
+    /// Write man pages for `tpe` and each of its subcommands
+    Man(completions::ManArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct ProcessArgs {
+    /// Transactions CSV to process
+    pub input: Option<PathBuf>,
+
+    /// Print periodic progress lines (rows, rows/s, ETA) to stderr
+    #[arg(long)]
+    pub progress: bool,
+
+    /// Show a live dashboard (throughput, rejects by reason, top disputed clients,
+    /// memory) on stderr's terminal while the input is processed
+    #[cfg(feature = "tui")]
+    #[arg(long, conflicts_with_all = ["progress", "quiet"])]
+    pub tui: bool,
+
+    /// Export metrics and a trace of the run over OTLP/HTTP, to the collector set by the
+    /// standard OTEL_EXPORTER_OTLP_* variables (http://localhost:4318 by default)
+    #[cfg(feature = "otel")]
+    #[arg(long)]
+    pub otel: bool,
+
+    /// Transcode the input from this encoding (`latin1`, `utf-16le`, `windows-1250`, ...);
+    /// UTF-16 input with a byte order mark is detected without it
+    #[arg(long, value_name = "LABEL", value_parser = parse_encoding)]
+    pub encoding: Option<&'static Encoding>,
+
+    /// How amounts are written: plain (`1234.56`), comma-thousands (`1,234.56`) or
+    /// dot-thousands (`1.234,56`)
+    #[arg(long, value_name = "FORMAT", default_value_t = NumberFormat::Plain)]
+    pub number_format: NumberFormat,
+
+    /// TOML file naming the input column of each role (`type`, `client`, `tx`, `amount`,
+    /// `timestamp`), as printed by `tpe inspect`
+    #[arg(long, value_name = "PATH")]
+    pub mapping: Option<PathBuf>,
+
+    /// Accept transaction types in any case and common aliases (`withdraw`, `charge_back`)
+    #[arg(long)]
+    pub lenient_types: bool,
+
+    /// Skip these transaction types, e.g. `chargeback,resolve` for a pre-settlement preview
+    #[arg(long, value_name = "TYPES", value_delimiter = ',')]
+    pub disable: Vec<TxType>,
+
+    /// Rule set to apply: v1 (default) or v2, which allows disputing withdrawals and
+    /// freezes resolves on locked accounts. A resumed run keeps the rules it started with
+    #[arg(long, value_name = "VERSION")]
+    pub rules: Option<Rules>,
+
+    /// Abort the run at the first deposit or withdrawal reusing the id of another
+    /// transaction with a different type, client or amount, instead of rejecting it
+    /// as `conflicting_tx`
+    #[arg(long)]
+    pub strict: bool,
+
+    #[command(flatten)]
+    pub engine: EngineArgs,
+
+    /// Every million rows, move resolved and charged back transactions out of the tables
+    /// kept for disputes: compress them (rows naming them stay `not_disputable`) or drop
+    /// them (rows naming them become `unknown_tx`)
+    #[arg(long, value_name = "POLICY")]
+    pub compact_settled: Option<SettledPolicy>,
+
+    /// Start from a previously saved state snapshot
+    #[arg(long, value_name = "PATH", conflicts_with = "resume")]
+    pub load_state: Option<PathBuf>,
+
+    /// Start from the balances of a previous run's CSV output instead of a snapshot;
+    /// nothing before them can be disputed
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["resume", "load_state"])]
+    pub opening_balances: Option<PathBuf>,
+
+    /// CSV of deposits from earlier periods (`client`, `tx`, `amount`, optional `state`)
+    /// that disputes in this run may name, typically with `--opening-balances`
+    #[arg(long, value_name = "PATH", conflicts_with = "resume")]
+    pub prior_deposits: Option<PathBuf>,
+
+    /// Deposit index saved by `--save-deposit-index` in an earlier run, searched on disk
+    /// for deposits that disputes name but this run doesn't hold
+    #[arg(long, value_name = "PATH")]
+    pub deposit_index: Option<PathBuf>,
+
+    /// Save an index of every deposit seen so far, the `--deposit-index` ones included,
+    /// at the end of the run
+    #[arg(long, value_name = "PATH")]
+    pub save_deposit_index: Option<PathBuf>,
+
+    /// Save a state snapshot at the end of the run (or when interrupted)
+    #[arg(long, value_name = "PATH")]
+    pub save_state: Option<PathBuf>,
+
+    /// On SIGUSR1, save the state as of the current row to this file without stopping
+    /// the run (unsharded, replacing the previous one)
+    #[cfg(unix)]
+    #[arg(long, value_name = "PATH")]
+    pub snapshot_on_signal: Option<PathBuf>,
+
+    /// File with the 64 hex digit key that `--save-state` encrypts the state with and
+    /// loading decrypts it with (default: the `TPE_STATE_KEY` environment variable)
+    #[arg(long, value_name = "PATH")]
+    pub state_key_file: Option<PathBuf>,
+
+    /// Split the saved state into this many shard files by client id range,
+    /// loaded in parallel by `--load-state`/`--resume`
+    #[arg(long, value_name = "N", default_value_t = 1, requires = "save_state")]
+    pub state_shards: u16,
+
+    /// Reject deposits and withdrawals whose id was already applied in an earlier
+    /// run, keeping the seen ids in this filter file (created on first use)
+    #[arg(long, value_name = "PATH")]
+    pub dedupe: Option<PathBuf>,
+
+    /// How many ids a new `--dedupe` filter is sized for (k/M/G suffixes)
+    #[arg(long, value_name = "N", default_value = "10M", value_parser = parse_limit, requires = "dedupe")]
+    pub dedupe_capacity: usize,
+
+    /// Accepted chance of a new id being rejected as a duplicate, sizes a new `--dedupe` filter
+    #[arg(long, value_name = "RATE", default_value_t = 1e-6, value_parser = parse_fp_rate, requires = "dedupe")]
+    pub dedupe_fp_rate: f64,
+
+    /// Write every rejected row with the reason to a CSV file
+    #[arg(long, value_name = "PATH")]
+    pub rejects: Option<PathBuf>,
+
+    /// Add the client's balances, lock and the named transaction's dispute state
+    /// at the time of the decision to every row of `--rejects`
+    #[arg(long, requires = "rejects")]
+    pub explain: bool,
+
+    /// Write a CSV ledger with the outcome and resulting balances of every transaction
+    #[arg(long, value_name = "PATH")]
+    pub ledger: Option<PathBuf>,
+
+    /// Append every applied opening balance to a hash-chained CSV, see `tpe audit verify`
+    #[arg(long, value_name = "PATH", requires = "audit_key_file")]
+    pub audit_log: Option<PathBuf>,
+
+    /// File whose bytes (at least 16) are the secret key the audit log is chained under
+    #[arg(long, value_name = "PATH", requires = "audit_log")]
+    pub audit_key_file: Option<PathBuf>,
+
+    /// Write deposits and withdrawals rejected because the account was locked to a CSV
+    /// in the input format, to replay once the account is unlocked
+    #[arg(long, value_name = "PATH")]
+    pub quarantine: Option<PathBuf>,
+
+    /// Write the deposits still under dispute at the end of the run, grouped by client
+    #[arg(long, value_name = "PATH")]
+    pub disputes_report: Option<PathBuf>,
+
+    /// Write rows referencing another client's transaction to a CSV security report,
+    /// noting the actual owner
+    #[arg(long, value_name = "PATH")]
+    pub security_report: Option<PathBuf>,
+
+    /// Flag clients crossing a balance threshold as soon as it happens, e.g.
+    /// `held>1000000` or `available<-10000` (repeatable)
+    #[arg(long, value_name = "THRESHOLD")]
+    pub alert: Vec<Threshold>,
+
+    /// Also write the alerts to a CSV file
+    #[arg(long, value_name = "PATH", requires = "alert")]
+    pub alerts: Option<PathBuf>,
+
+    /// Write a JSON run manifest noting the last processed input offset
+    #[arg(long, value_name = "PATH")]
+    pub manifest: Option<PathBuf>,
+
+    /// Hold an exclusive lock on this file for the run, failing if another run holds it,
+    /// so two instances sharing the same state and reports never both apply transactions
+    #[arg(long, value_name = "PATH")]
+    pub lock: Option<PathBuf>,
+
+    /// Inject faults (interrupts, crashes, read errors, slow storage) to test
+    /// recovery with, see src/cli/chaos.rs
+    #[cfg(feature = "chaos")]
+    #[arg(long, value_name = "SPEC", hide = true)]
+    pub chaos: Option<chaos::Chaos>,
+
+    /// Wait until the run holding `--lock` ends instead of failing, for a passive standby
+    #[arg(long, requires = "lock")]
+    pub wait_for_lock: bool,
+
+    /// JSON manifest of the input (`rows`, `sha256` and optionally `bytes`) to check
+    /// it against while reading, the run fails instead of writing balances on a mismatch
+    #[arg(long, value_name = "PATH", conflicts_with = "resume")]
+    pub input_manifest: Option<PathBuf>,
+
+    /// Write a JSON manifest (`rows`, `bytes`, `sha256`) of the balances output
+    #[arg(long, value_name = "PATH")]
+    pub output_manifest: Option<PathBuf>,
+
+    /// CSV of all known client ids (`client` column), clients without activity
+    /// get a zero balance row in the output
+    #[arg(long, value_name = "PATH")]
+    pub clients: Option<PathBuf>,
+
+    /// Sidecar CSV mapping a `client` column to labels (region, tier, ...), added as
+    /// extra output columns
+    #[arg(long, value_name = "PATH")]
+    pub client_metadata: Option<PathBuf>,
+
+    /// Add balance totals per value of this metadata column to the summary
+    #[arg(
+        long,
+        value_name = "COLUMN",
+        requires_all = ["client_metadata", "summary"]
+    )]
+    pub summary_by: Option<String>,
+
+    /// Write totals over all clients and per metadata segment to a CSV file
+    #[arg(long, value_name = "PATH")]
+    pub aggregates: Option<PathBuf>,
+
+    /// Write the balances to this file instead of stdout. The file is written under a
+    /// temporary name and renamed once complete, so it never holds partial output
+    #[arg(long, value_name = "PATH")]
+    pub output: Option<PathBuf>,
+
+    /// Balances format: csv, or with the `xml` feature an ISO 20022 camt.053-style
+    /// statement per client
+    #[arg(long, value_name = "FORMAT", default_value_t = OutputFormat::Csv)]
+    pub output_format: OutputFormat,
+
+    /// Currency code of the amounts in `--output-format xml`
+    #[cfg(feature = "xml")]
+    #[arg(long, value_name = "CODE", default_value = "XXX", value_parser = output::parse_currency)]
+    pub currency: String,
+
+    /// TOML file with the `rounding` (half-even, half-up or down) and `[minor_units]`
+    /// per currency code that `--output-format xml` amounts are rounded with,
+    /// on top of the ISO 4217 defaults
+    #[cfg(feature = "xml")]
+    #[arg(long, value_name = "PATH")]
+    pub currency_rules: Option<PathBuf>,
+
+    /// Add per-client counters to the output: deposits, withdrawals,
+    /// rejected_withdrawals and open_disputes, and what locked the account: lock_reason,
+    /// lock_tx, lock_line and lock_timestamp
+    #[arg(long)]
+    pub extended_output: bool,
+
+    /// Write these balance columns, in this order: client, available, held, total, locked,
+    /// deposits, withdrawals, rejected_withdrawals, open_disputes, lock_reason, lock_tx,
+    /// lock_line, lock_timestamp, net_change or a
+    /// `--client-metadata` column
+    #[arg(
+        long,
+        value_name = "COLUMNS",
+        value_delimiter = ',',
+        conflicts_with = "extended_output"
+    )]
+    pub columns: Option<Vec<output::Column>>,
+
+    /// State snapshot the `net_change` column (change in total) is taken against,
+    /// e.g. the previous run's `--save-state`
+    #[arg(long, value_name = "PATH")]
+    pub previous_state: Option<PathBuf>,
+
+    /// Replace client ids in every output (balances, reports, alerts) with an HMAC of the
+    /// id under `--salt-file`, the same id always getting the same pseudonym
+    #[arg(long, requires = "salt_file")]
+    pub pseudonymize: bool,
+
+    /// File whose bytes (at least 16) are the secret `--pseudonymize` key
+    #[arg(long, value_name = "PATH", requires = "pseudonymize")]
+    pub salt_file: Option<PathBuf>,
+
+    /// Emit every amount with exactly this many decimal places (banker's rounding)
+    #[arg(long, value_name = "DIGITS", value_parser = output::parse_scale)]
+    pub output_scale: Option<u32>,
+
+    /// Print nothing to stderr but errors: no warnings, alerts or end-of-run counts.
+    /// Stdout only ever carries the balances, with or without it
+    #[arg(long, conflicts_with_all = ["progress", "summary", "top_n"])]
+    pub quiet: bool,
+
+    /// Print a run summary (row counts, clients, house accounts) to stderr
+    #[arg(long)]
+    pub summary: bool,
+
+    /// Print the N clients with the largest balances to stderr as CSV
+    #[arg(long, value_name = "N")]
+    pub top_n: Option<usize>,
+
+    /// Balance `--top-n` ranks by: available, held or total
+    #[arg(long, value_name = "BALANCE", default_value_t = Balance::Total, requires = "top_n")]
+    pub by: Balance,
+
+    /// Parse rows on a separate thread, handing them to the engine through a bounded channel
+    #[arg(long)]
+    pub pipeline: bool,
+
+    /// How many parsed rows the pipeline may buffer ahead of the engine
+    #[arg(
+        long,
+        value_name = "ROWS",
+        default_value_t = 1024,
+        requires = "pipeline"
+    )]
+    pub channel_capacity: usize,
+
+    /// Hold rows back this long (`500ms`, `5s`, `2m`) by their `timestamp` column
+    /// (Unix seconds) and apply them in timestamp order
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = parse_duration,
+        conflicts_with_all = ["resume", "manifest"]
+    )]
+    pub reorder_window: Option<Duration>,
+
+    /// Check the per-client `seq` column and handle rows that don't follow the
+    /// client's previous one: warn, reject or buffer them until the gap is filled
+    #[arg(long, value_name = "POLICY")]
+    pub sequence_policy: Option<SequencePolicy>,
+
+    /// Continue an interrupted run from its manifest
+    #[arg(long, value_name = "MANIFEST")]
+    pub resume: Option<PathBuf>,
+}
+
+/// The engine's config, shared by the commands that apply transactions.
+#[derive(Debug, Args)]
+pub struct EngineArgs {
+    /// What to do with disputes, resolves and chargebacks naming a deposit that hasn't
+    /// arrived yet: reject (default) or queue them until it does
+    #[arg(long, value_name = "POLICY", default_value_t = MissingDeposit::Reject)]
+    pub missing_deposit: MissingDeposit,
+
+    /// What to do with withdrawals from a client that hasn't been seen: reject them as
+    /// unknown_client (default) or create the client with a zero balance, so the
+    /// withdrawal fails as insufficient_funds and the client is listed in the output
+    #[arg(long, value_name = "POLICY", default_value_t = MissingClient::Reject)]
+    pub missing_client: MissingClient,
+
+    /// How disputes, resolves and chargebacks find their deposit: by tx id alone
+    /// (global, default) or by client and tx id (per-client), for feeds whose tx ids
+    /// are only unique per client
+    #[arg(long, value_name = "KEYS", default_value_t = TxKeys::Global)]
+    pub tx_keys: TxKeys,
+
+    /// When locked accounts are reinstated: never (default), disputes-settled once
+    /// none of the client's transactions is under dispute, or <N>d at the client's
+    /// first row N days after the row that locked it, by the timestamp column
+    #[arg(long, value_name = "POLICY", default_value_t = UnlockPolicy::Never)]
+    pub auto_unlock: UnlockPolicy,
+
+    /// Reject deposits that would take a client's total above this amount
+    #[arg(long, value_name = "AMOUNT")]
+    pub max_balance: Option<Decimal>,
+
+    /// Reject deposits and withdrawals with more decimal places than this as `invalid_amount`
+    #[arg(long, value_name = "DIGITS")]
+    pub max_scale: Option<u32>,
+
+    /// Reject deposits and withdrawals above this as `invalid_amount`, and transactions
+    /// that would take a balance further from zero as `overflow`
+    #[arg(long, value_name = "AMOUNT")]
+    pub max_amount: Option<Decimal>,
+
+    /// Store amounts and balances without trailing zeros (`1.50` becomes `1.5`)
+    #[arg(long)]
+    pub normalize_amounts: bool,
+
+    /// Stop the run (saving partial results) before the number of clients exceeds this
+    #[arg(long, value_name = "N", value_parser = parse_limit)]
+    pub max_clients: Option<usize>,
+
+    /// Stop the run before the deposits kept for disputes exceed this (k/M/G suffixes)
+    #[arg(long, value_name = "N", value_parser = parse_limit)]
+    pub max_deposits: Option<usize>,
+
+    /// Stop the run before the estimated state size exceeds this many bytes (k/M/G suffixes)
+    #[arg(long, value_name = "BYTES", value_parser = parse_limit)]
+    pub max_memory: Option<usize>,
+}
+
+impl EngineArgs {
+    pub fn config(&self, rules: Rules) -> EngineConfig {
+        EngineConfig {
+            max_balance: self.max_balance,
+            rules,
+            max_clients: self.max_clients,
+            max_deposits: self.max_deposits,
+            max_memory: self.max_memory,
+            missing_deposit: self.missing_deposit,
+            missing_client: self.missing_client,
+            tx_keys: self.tx_keys,
+            unlock: self.auto_unlock,
+            amounts: AmountContext {
+                max_scale: self.max_scale,
+                max_magnitude: self.max_amount,
+                normalize: self.normalize_amounts,
+            },
+            ..EngineConfig::default()
+        }
+    }
+}
+
+fn parse_limit(value: &str) -> Result<usize, String> {
+    let count = generate::parse_count(value)?;
+    usize::try_from(count).map_err(|_| format!("`{value}` is too large"))
+}
+
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (digits, unit) = value.split_at(split);
+    let count: u64 = digits
+        .parse()
+        .map_err(|_| format!("`{value}` is not a duration, expected e.g. `5s`"))?;
+
+    let seconds = |per_unit: u64| {
+        count
+            .checked_mul(per_unit)
+            .map(Duration::from_secs)
+            .ok_or_else(|| format!("`{value}`: duration too large"))
+    };
+    match unit {
+        "ms" => Ok(Duration::from_millis(count)),
+        "s" => Ok(Duration::from_secs(count)),
+        "m" => seconds(60),
+        "h" => seconds(3600),
+        _ => Err(format!("unknown unit in `{value}`, expected ms, s, m or h")),
+    }
+}
+
+fn parse_fp_rate(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(rate) if rate > 0.0 && rate < 1.0 => Ok(rate),
+        _ => Err(format!(
+            "`{value}` is not a rate between 0 and 1 (exclusive)"
+        )),
+    }
+}
+
+fn parse_encoding(value: &str) -> Result<&'static Encoding, String> {
+    Encoding::for_label(value.as_bytes()).ok_or_else(|| format!("unknown encoding `{value}`"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("250ms"), Ok(Duration::from_millis(250)));
+        assert_eq!(parse_duration("5s"), Ok(Duration::from_secs(5)));
+        assert_eq!(parse_duration("2m"), Ok(Duration::from_secs(120)));
+        assert_eq!(parse_duration("1h"), Ok(Duration::from_secs(3600)));
+        assert!(parse_duration("5").is_err());
+        assert!(parse_duration("s").is_err());
+        assert_eq!(
+            parse_duration("999999999999999999m"),
+            Err("`999999999999999999m`: duration too large".to_string())
+        );
+        assert!(parse_duration("999999999999999999h").is_err());
+    }
+}
@@ -0,0 +1,184 @@
+use std::{
+    error::Error,
+    io::{self, Write},
+    path::PathBuf,
+};
+
+use clap::Args;
+use rust_decimal::Decimal;
+use toy_payments_engine::{
+    engine::Engine,
+    types::{
+        common::{ClientId, TxId},
+        transactions::{OpeningBalanceTx, Tx},
+    },
+};
+
+use crate::cli::{audit::AuditLog, pseudonym::ClientIds, state::load_opening_balances};
+
+#[derive(Debug, Args)]
+pub struct OpeningArgs {
+    /// Id of the first opening transaction, the rest count up from it
+    #[arg(long, value_name = "ID", default_value_t = 1)]
+    pub first_tx: TxId,
+
+    /// Balances CSV printed by the previous run
+    pub input: PathBuf,
+}
+
+#[derive(serde::Serialize)]
+struct OpeningRow {
+    r#type: &'static str,
+    client: ClientId,
+    tx: TxId,
+    amount: Decimal,
+}
+
+/// Prints the previous run's balances as `opening_balance` rows, by client id,
+/// to put in front of the next period's feed.
+pub fn run(args: OpeningArgs) -> Result<(), Box<dyn Error>> {
+    // Refuses what `--opening-balances` would: held funds, repeated clients
+    let engine = load_opening_balances(&args.input)?;
+    let count = write(io::stdout().lock(), &engine, args.first_tx)?;
+    eprintln!("opening: {count} clients");
+    Ok(())
+}
+
+fn write<W: Write>(w: W, engine: &Engine, first_tx: TxId) -> Result<usize, Box<dyn Error>> {
+    let mut clients: Vec<_> = engine.clients_iter().collect();
+    clients.sort_by_key(|client| client.id);
+
+    if clients.len() as u64 > u64::from(TxId::MAX - first_tx) + 1 {
+        return Err(From::from("--first-tx: not enough transaction ids left"));
+    }
+
+    let mut wtr = csv::Writer::from_writer(w);
+    for (tx_id, client) in (first_tx..).zip(&clients) {
+        let opening_tx = OpeningBalanceTx::new(client.id, tx_id, client.total, client.locked)
+            .map_err(|reason| format!("client {}: {reason}", client.id))?;
+        let tx = Tx::OpeningBalance(opening_tx);
+        wtr.serialize(OpeningRow {
+            r#type: tx.type_name(),
+            client: client.id,
+            tx: tx_id,
+            amount: client.total,
+        })?;
+    }
+    wtr.flush()?;
+    Ok(clients.len())
+}
+
+/// `--audit-log` columns, `at` in Unix seconds and `line` empty when unknown.
+pub const AUDIT_COLUMNS: [&str; 6] = ["at", "tx", "client", "amount", "locked", "line"];
+
+/// Appends the opening balances a run applied to its audit log.
+pub fn write_audit<W: Write>(
+    audit: &mut AuditLog<W>,
+    openings: &[(Option<u64>, OpeningBalanceTx)],
+    at: u64,
+    ids: &ClientIds,
+) -> csv::Result<()> {
+    for (line, opening_tx) in openings {
+        audit.append([
+            at.to_string(),
+            opening_tx.tx_id().to_string(),
+            ids.label(opening_tx.client_id()).to_string(),
+            opening_tx.amount().to_string(),
+            opening_tx.locked().to_string(),
+            line.map(|line| line.to_string()).unwrap_or_default(),
+        ])?;
+    }
+    audit.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use toy_payments_engine::pipeline::{results::Results, source::CsvSource};
+
+    use crate::cli::{
+        audit::{AuditKey, verify},
+        output::Balances,
+    };
+
+    #[test]
+    fn test_replayed_openings_match_the_balances() {
+        let dir = tempfile::tempdir().unwrap();
+        let previous = dir.path().join("day1.csv");
+        fs::write(
+            &previous,
+            "\
+type,client,tx,amount
+deposit,2,1,10.50
+withdrawal,2,2,0.25
+deposit,1,3,3
+dispute,1,3,
+chargeback,1,3,
+deposit,3,4,1
+",
+        )
+        .unwrap();
+        let mut engine = Engine::new();
+        Results::new(CsvSource::open(&previous).unwrap(), &mut engine).for_each(drop);
+        let balances = dir.path().join("balances.csv");
+        Balances::default()
+            .write(fs::File::create(&balances).unwrap(), &engine)
+            .unwrap();
+
+        let mut rows = Vec::new();
+        write(&mut rows, &load_opening_balances(&balances).unwrap(), 100).unwrap();
+        let rows = String::from_utf8(rows).unwrap();
+        assert_eq!(
+            rows,
+            "\
+type,client,tx,amount
+opening_balance_locked,1,100,0
+opening_balance,2,101,10.25
+opening_balance,3,102,1
+"
+        );
+
+        let openings = dir.path().join("openings.csv");
+        fs::write(&openings, rows).unwrap();
+        let mut replayed = Engine::new();
+        let results: Vec<_> =
+            Results::new(CsvSource::open(&openings).unwrap(), &mut replayed).collect();
+        assert!(
+            results
+                .iter()
+                .all(|result| result.engine_result().unwrap().1.is_ok())
+        );
+        assert_eq!(replayed.totals(), engine.totals());
+        for client in engine.clients_iter() {
+            let opened = &replayed.client(client.id).unwrap();
+            assert_eq!(
+                (opened.available, opened.total, opened.locked),
+                (client.available, client.total, client.locked)
+            );
+        }
+    }
+
+    #[test]
+    fn test_write_audit() {
+        let key = AuditKey::new(b"0123456789abcdef");
+        let mut audit = AuditLog::new(Vec::new(), &AUDIT_COLUMNS, key.clone(), None).unwrap();
+        let opening = |client_id, tx_id, amount, locked| {
+            OpeningBalanceTx::new(client_id, tx_id, amount, locked).unwrap()
+        };
+        let openings = [
+            (Some(2), opening(1, 100, Decimal::new(-25, 1), true)),
+            (None, opening(2, 101, Decimal::new(10, 0), false)),
+        ];
+        write_audit(&mut audit, &openings, 1_700_000_000, &ClientIds::default()).unwrap();
+
+        let out = String::from_utf8(audit.into_inner().unwrap()).unwrap();
+        let lines: Vec<_> = out.lines().collect();
+        assert_eq!(lines[0], "at,tx,client,amount,locked,line,prev_hash,hash");
+        assert!(lines[1].starts_with("1700000000,100,1,-2.5,true,2,"));
+        assert!(lines[2].starts_with("1700000000,101,2,10,false,,"));
+        let chain = verify(csv::Reader::from_reader(out.as_bytes()), &key).unwrap();
+        assert_eq!(chain.entries, 2);
+    }
+}
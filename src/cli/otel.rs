@@ -0,0 +1,156 @@
+//! `--otel`: exports the run's metrics and a trace of it over OTLP/HTTP to
+//! the collector the standard `OTEL_EXPORTER_OTLP_*` variables point to
+//! (`http://localhost:4318` by default). The service name is `tpe` unless
+//! `OTEL_SERVICE_NAME` says otherwise.
+
+use std::error::Error;
+
+use opentelemetry::{
+    Context, KeyValue,
+    metrics::{Counter, Histogram, Meter, MeterProvider},
+    trace::{Span, Status, TraceContextExt, Tracer, TracerProvider},
+};
+use opentelemetry_sdk::{
+    Resource,
+    metrics::SdkMeterProvider,
+    trace::{SdkTracer, SdkTracerProvider},
+};
+use rust_decimal::{Decimal, prelude::ToPrimitive};
+use toy_payments_engine::{
+    engine::metrics::{EngineMetrics, outcome},
+    types::{reject::RejectReason, transactions::TxType},
+};
+
+use crate::cli::summary::RunSummary;
+
+/// `tpe.transactions` counted by `type` and `outcome`, and the
+/// `tpe.transaction.amount` histogram by `type`.
+pub struct OtelMetrics {
+    transactions: Counter<u64>,
+    amounts: Histogram<f64>,
+}
+
+impl OtelMetrics {
+    pub fn new(meter: &Meter) -> Self {
+        OtelMetrics {
+            transactions: meter
+                .u64_counter("tpe.transactions")
+                .with_description("Transactions by type and outcome")
+                .build(),
+            amounts: meter
+                .f64_histogram("tpe.transaction.amount")
+                .with_description("Amounts of applied deposits and withdrawals")
+                .build(),
+        }
+    }
+}
+
+impl EngineMetrics for OtelMetrics {
+    fn increment(&self, tx_type: TxType, result: Result<(), RejectReason>) {
+        self.transactions.add(
+            1,
+            &[
+                KeyValue::new("type", tx_type.name()),
+                KeyValue::new("outcome", outcome(result)),
+            ],
+        );
+    }
+
+    fn observe(&self, tx_type: TxType, amount: Decimal) {
+        self.amounts.record(
+            amount.to_f64().unwrap_or_default(),
+            &[KeyValue::new("type", tx_type.name())],
+        );
+    }
+}
+
+/// The exporters and the `process` span covering the run. Dropping it ends
+/// the span (as failed unless `finish` was called) and flushes everything.
+pub struct Telemetry {
+    meters: SdkMeterProvider,
+    tracers: SdkTracerProvider,
+    tracer: SdkTracer,
+    run: Context,
+    finished: bool,
+    quiet: bool,
+}
+
+impl Telemetry {
+    pub fn start(input: &str, quiet: bool) -> Result<Self, Box<dyn Error>> {
+        let mut resource = Resource::builder();
+        if std::env::var_os("OTEL_SERVICE_NAME").is_none() {
+            resource = resource.with_service_name("tpe");
+        }
+        let resource = resource.build();
+
+        let meters = SdkMeterProvider::builder()
+            .with_periodic_exporter(
+                opentelemetry_otlp::MetricExporter::builder()
+                    .with_http()
+                    .build()?,
+            )
+            .with_resource(resource.clone())
+            .build();
+        let tracers = SdkTracerProvider::builder()
+            .with_batch_exporter(
+                opentelemetry_otlp::SpanExporter::builder()
+                    .with_http()
+                    .build()?,
+            )
+            .with_resource(resource)
+            .build();
+
+        let tracer = tracers.tracer("tpe");
+        let mut span = tracer.start("process");
+        span.set_attribute(KeyValue::new("tpe.input", input.to_string()));
+        Ok(Telemetry {
+            meters,
+            tracers,
+            tracer,
+            run: Context::new().with_span(span),
+            finished: false,
+            quiet,
+        })
+    }
+
+    pub fn metrics(&self) -> OtelMetrics {
+        OtelMetrics::new(&self.meters.meter("tpe"))
+    }
+
+    /// A step of the run, ended when the returned span is dropped.
+    pub fn phase(&self, name: &'static str) -> impl Span + use<> {
+        self.tracer.start_with_context(name, &self.run)
+    }
+
+    /// Ends the run's span with the row counts.
+    pub fn finish(mut self, summary: &RunSummary) {
+        let span = self.run.span();
+        span.set_attribute(KeyValue::new("tpe.rows", summary.rows as i64));
+        span.set_attribute(KeyValue::new("tpe.applied", summary.applied as i64));
+        span.set_attribute(KeyValue::new("tpe.rejected", summary.rejected as i64));
+        span.set_status(Status::Ok);
+        self.finished = true;
+    }
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        let span = self.run.span();
+        if !self.finished {
+            span.set_status(Status::error("the run failed"));
+        }
+        span.end();
+        // Nothing to do about a collector that can't be reached, the run itself is done
+        let tracers = self.tracers.shutdown();
+        let meters = self.meters.shutdown();
+        if self.quiet {
+            return;
+        }
+        if let Err(err) = tracers {
+            eprintln!("warning: exporting the trace failed: {err}");
+        }
+        if let Err(err) = meters {
+            eprintln!("warning: exporting metrics failed: {err}");
+        }
+    }
+}
@@ -0,0 +1,721 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    fmt,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use rust_decimal::{Decimal, RoundingStrategy};
+use toy_payments_engine::{
+    engine::{Engine, amount::CurrencyRules},
+    types::{client::Client, common::ClientId},
+};
+
+use crate::cli::{metadata::ClientMetadata, pseudonym::ClientIds};
+
+/// Number of decimal places every emitted amount is normalized to, `None`
+/// leaves amounts at whatever scale they ended up with.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OutputScale(pub Option<u32>);
+
+impl OutputScale {
+    /// Rounds half to even and pads with zeros, so `100` becomes `100.0000` at scale 4.
+    pub fn apply(&self, value: Decimal) -> Decimal {
+        match self.0 {
+            Some(scale) => {
+                let mut value =
+                    value.round_dp_with_strategy(scale, RoundingStrategy::MidpointNearestEven);
+                value.rescale(scale);
+                value
+            }
+            None => value,
+        }
+    }
+
+    pub fn client(&self, client: &Client) -> Client {
+        Client {
+            available: self.apply(client.available),
+            held: self.apply(client.held),
+            total: self.apply(client.total),
+            ..client.clone()
+        }
+    }
+}
+
+/// Format of the final balances.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Csv,
+    /// ISO 20022 camt.053-style statements, see `camt`
+    #[cfg(feature = "xml")]
+    Xml,
+}
+
+impl OutputFormat {
+    pub const ALL: &[OutputFormat] = &[
+        OutputFormat::Csv,
+        #[cfg(feature = "xml")]
+        OutputFormat::Xml,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            OutputFormat::Csv => "csv",
+            #[cfg(feature = "xml")]
+            OutputFormat::Xml => "xml",
+        }
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        OutputFormat::ALL
+            .iter()
+            .copied()
+            .find(|format| format.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<_> = OutputFormat::ALL.iter().map(|f| f.name()).collect();
+                format!(
+                    "unknown output format `{s}`, expected {}",
+                    names.join(" or ")
+                )
+            })
+    }
+}
+
+/// A column of the balances CSV.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Column {
+    Client,
+    Available,
+    Held,
+    Total,
+    Locked,
+    Deposits,
+    Withdrawals,
+    RejectedWithdrawals,
+    OpenDisputes,
+    /// What locked the account, see `LockReason`
+    LockReason,
+    /// The chargeback or opening transaction that locked the account
+    LockTx,
+    /// Input line of the row that locked the account
+    LockLine,
+    /// That row's `timestamp`, in milliseconds since the Unix epoch
+    LockTimestamp,
+    /// `total` minus the client's total in a previous snapshot
+    NetChange,
+    /// A `--client-metadata` column
+    Metadata(String),
+}
+
+impl Column {
+    pub const ALL: [Column; 14] = [
+        Column::Client,
+        Column::Available,
+        Column::Held,
+        Column::Total,
+        Column::Locked,
+        Column::Deposits,
+        Column::Withdrawals,
+        Column::RejectedWithdrawals,
+        Column::OpenDisputes,
+        Column::LockReason,
+        Column::LockTx,
+        Column::LockLine,
+        Column::LockTimestamp,
+        Column::NetChange,
+    ];
+
+    pub fn name(&self) -> &str {
+        match self {
+            Column::Client => "client",
+            Column::Available => "available",
+            Column::Held => "held",
+            Column::Total => "total",
+            Column::Locked => "locked",
+            Column::Deposits => "deposits",
+            Column::Withdrawals => "withdrawals",
+            Column::RejectedWithdrawals => "rejected_withdrawals",
+            Column::OpenDisputes => "open_disputes",
+            Column::LockReason => "lock_reason",
+            Column::LockTx => "lock_tx",
+            Column::LockLine => "lock_line",
+            Column::LockTimestamp => "lock_timestamp",
+            Column::NetChange => "net_change",
+            Column::Metadata(name) => name,
+        }
+    }
+}
+
+impl fmt::Display for Column {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Column {
+    type Err = String;
+
+    /// Any name that isn't a built-in column is taken for a metadata column,
+    /// `Balances::check_columns` tells whether it exists.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err("empty column name".to_string());
+        }
+        Ok(Column::ALL
+            .into_iter()
+            .find(|column| column.name() == s)
+            .unwrap_or_else(|| Column::Metadata(s.to_string())))
+    }
+}
+
+/// How the final balances are written.
+#[derive(Default)]
+pub struct Balances<'a> {
+    pub scale: OutputScale,
+    /// Clients that get a zero balance row if the engine has never seen them
+    pub roster: &'a [ClientId],
+    /// Labels appended as extra columns
+    pub metadata: Option<&'a ClientMetadata>,
+    /// Adds the per-client activity counters, open dispute counts and lock details
+    pub extended: bool,
+    /// How the `client` column is written
+    pub ids: ClientIds,
+    /// The columns and their order, instead of the ones `extended` and
+    /// `metadata` lead to
+    pub columns: Option<&'a [Column]>,
+    /// The snapshot `net_change` is taken against
+    pub previous: Option<&'a Engine>,
+}
+
+impl Balances<'_> {
+    /// The columns written: the balances, the counters and lock details when `extended`, then
+    /// the metadata columns, unless chosen with `columns`.
+    pub fn columns(&self) -> Vec<Column> {
+        if let Some(columns) = self.columns {
+            return columns.to_vec();
+        }
+        let mut columns = Column::ALL[..5].to_vec();
+        if self.extended {
+            columns.extend_from_slice(&Column::ALL[5..13]);
+        }
+        if let Some(metadata) = self.metadata {
+            columns.extend(metadata.columns().iter().cloned().map(Column::Metadata));
+        }
+        columns
+    }
+
+    /// Fails on a metadata column that doesn't exist or `net_change` without
+    /// a previous snapshot.
+    pub fn check_columns(&self) -> Result<(), String> {
+        let metadata = self.metadata.map_or(&[][..], |m| m.columns());
+        for column in self.columns() {
+            match column {
+                Column::Metadata(name) if !metadata.contains(&name) => {
+                    let names: Vec<_> = Column::ALL.iter().map(Column::name).collect();
+                    return Err(format!(
+                        "unknown column `{name}`, expected one of {} or a --client-metadata column",
+                        names.join(", ")
+                    ));
+                }
+                Column::NetChange if self.previous.is_none() => {
+                    return Err("the net_change column needs --previous-state".to_string());
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    pub fn write<W: Write>(&self, w: W, engine: &Engine) -> Result<(), Box<dyn Error>> {
+        self.check_columns()?;
+        let mut wtr = csv::Writer::from_writer(w);
+        let columns = self.columns();
+        wtr.write_record(columns.iter().map(Column::name))?;
+
+        let mut open_disputes: HashMap<ClientId, u64> = HashMap::new();
+        if columns.contains(&Column::OpenDisputes) {
+            for dispute in engine.open_disputes() {
+                *open_disputes.entry(dispute.client_id).or_default() += 1;
+            }
+        }
+
+        for client in engine.clients_iter() {
+            let open = open_disputes.get(&client.id).copied().unwrap_or_default();
+            self.write_client(&mut wtr, &columns, client, open)?;
+        }
+
+        for id in self.missing_clients(engine) {
+            self.write_client(&mut wtr, &columns, &Client::new(id), 0)?;
+        }
+        wtr.flush()?;
+
+        Ok(())
+    }
+
+    /// Roster clients the engine has never seen, in id order.
+    pub fn missing_clients(&self, engine: &Engine) -> Vec<ClientId> {
+        let mut missing: Vec<ClientId> = self
+            .roster
+            .iter()
+            .copied()
+            .filter(|id| engine.client(*id).is_none())
+            .collect();
+        missing.sort_unstable();
+        missing.dedup();
+        missing
+    }
+
+    fn write_client<W: Write>(
+        &self,
+        wtr: &mut csv::Writer<W>,
+        columns: &[Column],
+        client: &Client,
+        open_disputes: u64,
+    ) -> csv::Result<()> {
+        let scaled = self.scale.client(client);
+        let labels = self.metadata.map(|m| m.labels(client.id));
+        let lock = client.lock.as_ref();
+        let record = columns.iter().map(|column| match column {
+            Column::Client => self.ids.label(client.id).to_string(),
+            Column::Available => scaled.available.to_string(),
+            Column::Held => scaled.held.to_string(),
+            Column::Total => scaled.total.to_string(),
+            Column::Locked => scaled.locked.to_string(),
+            Column::Deposits => client.stats.deposits.to_string(),
+            Column::Withdrawals => client.stats.withdrawals.to_string(),
+            Column::RejectedWithdrawals => client.stats.rejected_withdrawals.to_string(),
+            Column::OpenDisputes => open_disputes.to_string(),
+            Column::LockReason => lock.map_or(String::new(), |lock| lock.reason.to_string()),
+            Column::LockTx => optional(lock.and_then(|lock| lock.tx_id)),
+            Column::LockLine => optional(lock.and_then(|lock| lock.line)),
+            Column::LockTimestamp => optional(lock.and_then(|lock| lock.timestamp)),
+            Column::NetChange => {
+                let previous = self
+                    .previous
+                    .and_then(|previous| previous.client(client.id))
+                    .map_or(Decimal::ZERO, |previous| previous.total);
+                self.scale
+                    .apply(client.total.saturating_sub(previous))
+                    .to_string()
+            }
+            Column::Metadata(name) => {
+                let metadata = self.metadata.map_or(&[][..], |m| m.columns());
+                let index = metadata.iter().position(|column| column == name);
+                match (&labels, index) {
+                    (Some(labels), Some(index)) => labels[index].to_string(),
+                    _ => String::new(),
+                }
+            }
+        });
+        wtr.write_record(record)
+    }
+}
+
+/// `value`, or an empty field for `None`.
+fn optional<T: ToString>(value: Option<T>) -> String {
+    value.map_or(String::new(), |value| value.to_string())
+}
+
+/// Buffer size for the balances, large enough that a write is rarely a syscall.
+const OUTPUT_BUFFER: usize = 1 << 20;
+
+/// Where the balances go: stdout, or a file that only appears under its name
+/// once it is complete.
+pub enum Output {
+    Stdout(BufWriter<io::Stdout>),
+    File(AtomicFile),
+}
+
+impl Output {
+    pub fn open(path: Option<&Path>) -> io::Result<Self> {
+        Ok(match path {
+            Some(path) => Output::File(AtomicFile::create(path)?),
+            None => Output::Stdout(BufWriter::with_capacity(OUTPUT_BUFFER, io::stdout())),
+        })
+    }
+
+    /// Flushes the output, moving a file into place.
+    pub fn finish(self) -> io::Result<()> {
+        match self {
+            Output::Stdout(mut w) => w.flush(),
+            Output::File(file) => file.commit(),
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Stdout(w) => w.write(buf),
+            Output::File(file) => file.w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Stdout(w) => w.flush(),
+            Output::File(file) => file.w.flush(),
+        }
+    }
+}
+
+/// A file written under a temporary name next to `path` and renamed to it by
+/// `commit`. Dropped without a commit, the temporary file is removed, so a
+/// failed run never leaves a partial file where downstream jobs look for one.
+pub struct AtomicFile {
+    w: BufWriter<File>,
+    tmp: PathBuf,
+    path: PathBuf,
+    committed: bool,
+}
+
+impl AtomicFile {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let name = path.file_name().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "output path has no file name")
+        })?;
+        let mut tmp_name = std::ffi::OsString::from(".");
+        tmp_name.push(name);
+        tmp_name.push(format!(".{}.tmp", std::process::id()));
+        let tmp = path.with_file_name(tmp_name);
+        Ok(AtomicFile {
+            w: BufWriter::with_capacity(OUTPUT_BUFFER, File::create(&tmp)?),
+            tmp,
+            path: path.to_path_buf(),
+            committed: false,
+        })
+    }
+
+    pub fn commit(mut self) -> io::Result<()> {
+        self.w.flush()?;
+        self.w.get_ref().sync_all()?;
+        fs::rename(&self.tmp, &self.path)?;
+        self.committed = true;
+        Ok(())
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = fs::remove_file(&self.tmp);
+        }
+    }
+}
+
+pub fn parse_scale(value: &str) -> Result<u32, String> {
+    match value.parse::<u32>() {
+        Ok(scale) if scale <= 28 => Ok(scale),
+        _ => Err(format!("`{value}` is not a scale between 0 and 28")),
+    }
+}
+
+/// Year, month and day of the UTC date `days` days after 1970-01-01.
+pub fn civil_date(days: u64) -> (i64, u32, u32) {
+    // Counting from 0000-03-01 so leap days end a year
+    let days = days as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    (year, month as u32, day as u32)
+}
+
+/// Overrides of the ISO 4217 `CurrencyRules`, as read by `--currency-rules`:
+///
+/// ```toml
+/// rounding = "half-up"
+///
+/// [minor_units]
+/// XAU = 3
+/// ```
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CurrencyRulesFile {
+    rounding: Option<String>,
+    #[serde(default)]
+    minor_units: BTreeMap<String, u32>,
+}
+
+/// The default `CurrencyRules` with the overrides in `path`, if any.
+pub fn read_currency_rules(path: Option<&Path>) -> Result<CurrencyRules, Box<dyn Error>> {
+    let mut rules = CurrencyRules::default();
+    let Some(path) = path else {
+        return Ok(rules);
+    };
+    let error = |err: String| format!("{}: {err}", path.display());
+    let file: CurrencyRulesFile =
+        toml::from_str(&fs::read_to_string(path)?).map_err(|err| error(err.to_string()))?;
+    if let Some(rounding) = file.rounding {
+        rules.rounding = rounding.parse().map_err(error)?;
+    }
+    for (code, units) in file.minor_units {
+        parse_currency(&code).map_err(error)?;
+        rules.set_minor_units(&code, units);
+    }
+    Ok(rules)
+}
+
+/// An ISO 4217 currency code for the XML and OFX outputs.
+pub fn parse_currency(value: &str) -> Result<String, String> {
+    if value.len() == 3 && value.bytes().all(|b| b.is_ascii_uppercase()) {
+        Ok(value.to_string())
+    } else {
+        Err(format!("`{value}` is not an ISO 4217 code like EUR"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+    use toy_payments_engine::{
+        pipeline::{results::Results, source::CsvSource},
+        types::{
+            client::LockReason,
+            transactions::{DepositTx, DisputeTx, Tx, WithdrawalTx},
+        },
+    };
+
+    #[test]
+    fn test_roster_clients_get_zero_rows() {
+        let mut engine = Engine::new();
+        engine
+            .process_tx(Tx::Deposit(DepositTx::new(2, 1, dec!(1.5)).unwrap()))
+            .unwrap();
+
+        let mut buf = Vec::new();
+        Balances {
+            scale: OutputScale(Some(2)),
+            roster: &[3, 2, 1, 3],
+            metadata: None,
+            extended: false,
+            ids: ClientIds::default(),
+            columns: None,
+            previous: None,
+        }
+        .write(&mut buf, &engine)
+        .unwrap();
+
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "\
+client,available,held,total,locked
+2,1.50,0.00,1.50,false
+1,0.00,0.00,0.00,false
+3,0.00,0.00,0.00,false
+"
+        );
+    }
+
+    #[test]
+    fn test_extended_output_adds_counters() {
+        let mut engine = Engine::new();
+        let txs = [
+            Tx::Deposit(DepositTx::new(1, 1, dec!(5)).unwrap()),
+            Tx::Deposit(DepositTx::new(1, 2, dec!(5)).unwrap()),
+            Tx::Withdrawal(WithdrawalTx::new(1, 3, dec!(2)).unwrap()),
+            Tx::Withdrawal(WithdrawalTx::new(1, 4, dec!(20)).unwrap()),
+            Tx::Dispute(DisputeTx {
+                client_id: 1,
+                tx_id: 2,
+            }),
+        ];
+        for tx in txs {
+            let _ = engine.process_tx(tx);
+        }
+
+        let mut buf = Vec::new();
+        Balances {
+            roster: &[2],
+            extended: true,
+            ..Balances::default()
+        }
+        .write(&mut buf, &engine)
+        .unwrap();
+
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "\
+client,available,held,total,locked,deposits,withdrawals,rejected_withdrawals,open_disputes,lock_reason,lock_tx,lock_line,lock_timestamp
+1,3,5,8,false,2,1,1,1,,,,
+2,0,0,0,false,0,0,0,0,,,,
+"
+        );
+    }
+
+    #[test]
+    fn test_extended_output_tells_what_locked_an_account() {
+        let mut input = tempfile::NamedTempFile::new().unwrap();
+        write!(
+            input,
+            "type,client,tx,amount,timestamp\n\
+             deposit,1,1,5,1000\n\
+             dispute,1,1,,2000\n\
+             chargeback,1,1,,3000\n\
+             opening_balance_locked,2,2,4,4000\n"
+        )
+        .unwrap();
+        let mut engine = Engine::new();
+        Results::new(CsvSource::open(input.path()).unwrap(), &mut engine).for_each(drop);
+
+        let lock = engine.lock_info(1).unwrap();
+        assert_eq!(lock.reason, LockReason::Chargeback);
+        let columns: Vec<Column> = [
+            "client",
+            "lock_reason",
+            "lock_tx",
+            "lock_line",
+            "lock_timestamp",
+        ]
+        .iter()
+        .map(|name| name.parse().unwrap())
+        .collect();
+        let mut buf = Vec::new();
+        Balances {
+            roster: &[3],
+            columns: Some(&columns),
+            ..Balances::default()
+        }
+        .write(&mut buf, &engine)
+        .unwrap();
+
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "\
+client,lock_reason,lock_tx,lock_line,lock_timestamp
+1,chargeback,1,4,3000000
+2,opening_balance,2,5,4000000
+3,,,,
+"
+        );
+    }
+
+    #[test]
+    fn test_chosen_columns_and_net_change() {
+        let deposit = |client_id, tx_id, amount| {
+            Tx::Deposit(DepositTx::new(client_id, tx_id, amount).unwrap())
+        };
+        let mut previous = Engine::new();
+        previous.process_tx(deposit(1, 1, dec!(10))).unwrap();
+        previous.process_tx(deposit(2, 2, dec!(4))).unwrap();
+        let mut engine = previous.fork();
+        engine.process_tx(deposit(1, 3, dec!(2.5))).unwrap();
+        engine.process_tx(deposit(3, 4, dec!(1))).unwrap();
+
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        write!(file, "client,region\n1,eu\n3,us\n").unwrap();
+        let metadata = ClientMetadata::read(file.path()).unwrap();
+        let columns: Vec<Column> = ["total", "client", "net_change", "region", "locked"]
+            .iter()
+            .map(|name| name.parse().unwrap())
+            .collect();
+        let mut balances = Balances {
+            scale: OutputScale(Some(1)),
+            metadata: Some(&metadata),
+            columns: Some(&columns),
+            previous: Some(&previous),
+            ..Balances::default()
+        };
+
+        let mut buf = Vec::new();
+        balances.write(&mut buf, &engine).unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "\
+total,client,net_change,region,locked
+12.5,1,2.5,eu,false
+4.0,2,0.0,,false
+1.0,3,1.0,us,false
+"
+        );
+
+        balances.previous = None;
+        assert_eq!(
+            balances.check_columns().unwrap_err(),
+            "the net_change column needs --previous-state"
+        );
+        let columns = [Column::Client, "tier".parse().unwrap()];
+        balances.columns = Some(&columns);
+        assert!(
+            balances
+                .check_columns()
+                .unwrap_err()
+                .starts_with("unknown column `tier`")
+        );
+    }
+
+    #[test]
+    fn test_scale_rounds_half_to_even_and_pads() {
+        let scale = OutputScale(Some(4));
+
+        assert_eq!(scale.apply(dec!(100.0)).to_string(), "100.0000");
+        assert_eq!(scale.apply(dec!(1.00005)).to_string(), "1.0000");
+        assert_eq!(scale.apply(dec!(1.00015)).to_string(), "1.0002");
+        assert_eq!(scale.apply(dec!(-2.5)).to_string(), "-2.5000");
+        assert_eq!(OutputScale(Some(0)).apply(dec!(2.5)).to_string(), "2");
+        assert_eq!(OutputScale(None).apply(dec!(100.0)).to_string(), "100.0");
+    }
+
+    #[test]
+    fn test_currency_rules_file_overrides_defaults() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("currencies.toml");
+        fs::write(
+            &path,
+            "rounding = \"down\"\n[minor_units]\nXAU = 3\nEUR = 0\n",
+        )
+        .unwrap();
+
+        let rules = read_currency_rules(Some(&path)).unwrap();
+        assert_eq!(rules.round(dec!(1.23456), "XAU").to_string(), "1.234");
+        assert_eq!(rules.round(dec!(9.9), "EUR").to_string(), "9");
+        assert_eq!(rules.minor_units("JPY"), Some(0));
+
+        fs::write(&path, "[minor_units]\neur = 2\n").unwrap();
+        assert!(read_currency_rules(Some(&path)).is_err());
+        assert_eq!(read_currency_rules(None).unwrap(), CurrencyRules::default());
+    }
+
+    #[test]
+    fn test_output_file_appears_only_when_committed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("balances.csv");
+        let entries = || fs::read_dir(dir.path()).unwrap().count();
+
+        let mut file = AtomicFile::create(&path).unwrap();
+        file.w.write_all(b"client\n").unwrap();
+        assert!(!path.exists());
+        drop(file);
+        assert_eq!(entries(), 0);
+
+        let mut output = Output::open(Some(&path)).unwrap();
+        output.write_all(b"client\n1\n").unwrap();
+        assert!(!path.exists());
+        output.finish().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "client\n1\n");
+        assert_eq!(entries(), 1);
+    }
+}
@@ -0,0 +1,754 @@
+use std::{
+    error::Error,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    process,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use toy_payments_engine::{
+    engine::{
+        Engine,
+        config::{MissingDeposit, TxKeys},
+    },
+    pipeline::{
+        Pipeline,
+        reorder::Reorder,
+        results::{Outcome, Results},
+        sequence::SequencePolicy,
+        source::{CsvSource, Row},
+    },
+    types::{reject::RejectReason, transactions::Tx},
+};
+
+#[cfg(feature = "xml")]
+use crate::cli::camt;
+#[cfg(feature = "chaos")]
+use crate::cli::chaos::{AbortAt, Chaos};
+#[cfg(feature = "tui")]
+use crate::cli::dashboard::Dashboard;
+#[cfg(unix)]
+use crate::cli::hot_snapshot::HotSnapshots;
+#[cfg(feature = "otel")]
+use crate::cli::otel::Telemetry;
+use crate::cli::{
+    ProcessArgs, aggregates,
+    alerts::Alerts,
+    audit::{AuditKey, AuditLog},
+    diagnostic, disputes,
+    encryption::StateKey,
+    integrity::{DigestWriter, InputDigest, IntegrityManifest},
+    ledger::LedgerWriter,
+    lock::RunLock,
+    manifest::{RunManifest, RunStatus},
+    mapping::Mapping,
+    metadata::ClientMetadata,
+    opening,
+    output::{Balances, Output, OutputFormat, OutputScale},
+    progress::Progress,
+    pseudonym::ClientIds,
+    quarantine::QuarantineWriter,
+    rejects::RejectsWriter,
+    resources::Resources,
+    roster,
+    security::SecurityReport,
+    state::{
+        load_deposit_index, load_filter, load_opening_balances, load_prior_deposits, load_state,
+        save_deposit_index, save_filter, save_state,
+    },
+    summary::RunSummary,
+    top,
+};
+
+/// Rows between two `--compact-settled` passes.
+const COMPACT_EVERY: u64 = 1_000_000;
+
+pub fn run(args: ProcessArgs) -> Result<(), Box<dyn Error>> {
+    let Some(file_path) = args.input.clone() else {
+        return Err(From::from("Expected 1 argument, but got none"));
+    };
+    // Taken before anything is read from the storage it guards
+    let _lock = args
+        .lock
+        .as_deref()
+        .map(|path| RunLock::acquire(path, args.wait_for_lock, args.quiet))
+        .transpose()?;
+
+    let resume = args.resume.as_deref().map(RunManifest::read).transpose()?;
+    let state_path = match &resume {
+        Some(manifest) => match &manifest.snapshot {
+            Some(path) => Some(path.as_path()),
+            None => {
+                return Err(From::from(
+                    "Cannot resume a run that saved no state snapshot",
+                ));
+            }
+        },
+        None => args.load_state.as_deref(),
+    };
+    let rules = match (&resume, args.rules) {
+        (Some(manifest), Some(rules)) if manifest.rules != rules => {
+            return Err(From::from(format!(
+                "Cannot resume a run started with rules {} under rules {rules}",
+                manifest.rules
+            )));
+        }
+        (Some(manifest), _) => manifest.rules,
+        (None, rules) => rules.unwrap_or_default(),
+    };
+
+    // These still key transactions by id alone, see `TxKeys::PerClient`
+    if args.engine.tx_keys == TxKeys::PerClient {
+        let conflict = if rules.policy().withdrawals_disputable() {
+            Some(format!(
+                "rules {rules}, which keep withdrawals for disputes"
+            ))
+        } else if args.engine.missing_deposit == MissingDeposit::Queue {
+            Some("--missing-deposit queue".to_string())
+        } else if args.compact_settled.is_some() {
+            Some("--compact-settled".to_string())
+        } else {
+            None
+        };
+        if let Some(conflict) = conflict {
+            return Err(From::from(format!(
+                "--tx-keys per-client can't be combined with {conflict}"
+            )));
+        }
+    }
+
+    for (flag, set) in [
+        ("--extended-output", args.extended_output),
+        ("--columns", args.columns.is_some()),
+        ("--output-manifest", args.output_manifest.is_some()),
+    ] {
+        if set && args.output_format != OutputFormat::Csv {
+            return Err(From::from(format!(
+                "{flag} only applies to csv output, not {}",
+                args.output_format
+            )));
+        }
+    }
+
+    let scale = OutputScale(args.output_scale);
+    // Read up front so a bad roster fails the run before any work is done
+    let roster = match &args.clients {
+        Some(path) => roster::read(path)?,
+        None => Vec::new(),
+    };
+    let mapping = args.mapping.as_deref().map(Mapping::read).transpose()?;
+    #[cfg(feature = "xml")]
+    let currency_rules = crate::cli::output::read_currency_rules(args.currency_rules.as_deref())?;
+    let state_key = StateKey::resolve(args.state_key_file.as_deref())?;
+    // `--salt-file` and `--pseudonymize` require each other
+    let ids = args
+        .salt_file
+        .as_deref()
+        .map(ClientIds::read)
+        .transpose()?
+        .unwrap_or_default();
+    let metadata = args
+        .client_metadata
+        .as_deref()
+        .map(ClientMetadata::read)
+        .transpose()?;
+    if let (Some(metadata), Some(column)) = (&metadata, &args.summary_by)
+        && !metadata.columns().contains(column)
+    {
+        return Err(From::from(format!(
+            "--summary-by: no `{column}` column in the client metadata"
+        )));
+    }
+
+    let previous = args
+        .previous_state
+        .as_deref()
+        .map(|path| load_state(path, state_key.as_ref()))
+        .transpose()?;
+    Balances {
+        metadata: metadata.as_ref(),
+        columns: args.columns.as_deref(),
+        previous: previous.as_ref(),
+        ..Balances::default()
+    }
+    .check_columns()
+    .map_err(|err| format!("--columns: {err}"))?;
+
+    let input_manifest = args
+        .input_manifest
+        .as_deref()
+        .map(IntegrityManifest::read)
+        .transpose()?;
+    // A size mismatch fails before any work is done, the hash only at the end
+    if let Some(expected) = input_manifest.as_ref().and_then(|m| m.bytes) {
+        let bytes = std::fs::metadata(&file_path)?.len();
+        if bytes != expected {
+            return Err(From::from(format!(
+                "{}: {bytes} bytes instead of the {expected} in its manifest, \
+                 the input is truncated or was altered",
+                file_path.display()
+            )));
+        }
+    }
+    let input_digest = input_manifest.as_ref().map(|_| InputDigest::default());
+
+    #[cfg(feature = "otel")]
+    let telemetry = args
+        .otel
+        .then(|| Telemetry::start(&file_path.display().to_string(), args.quiet))
+        .transpose()?;
+
+    let interrupted = install_signal_handler()?;
+
+    let mut progress = if args.progress {
+        Some(Progress::new(std::fs::metadata(&file_path)?.len()))
+    } else {
+        None
+    };
+
+    #[cfg(feature = "tui")]
+    let mut dashboard = if args.tui {
+        Some(Dashboard::start(std::fs::metadata(&file_path)?.len())?)
+    } else {
+        None
+    };
+    #[cfg(feature = "tui")]
+    let quiet_echo = args.quiet || args.tui;
+    #[cfg(not(feature = "tui"))]
+    let quiet_echo = args.quiet;
+
+    let mut rejects = args
+        .rejects
+        .as_deref()
+        .map(|path| RejectsWriter::create(path, resume.is_some()))
+        .transpose()?
+        .map(|rejects| rejects.client_ids(ids.clone()))
+        .map(|rejects| {
+            if args.explain {
+                rejects.explain(scale)
+            } else {
+                rejects
+            }
+        });
+    let mut security = args
+        .security_report
+        .as_deref()
+        .map(|path| SecurityReport::create(path, resume.is_some()))
+        .transpose()?
+        .map(|security| security.client_ids(ids.clone()));
+    let mut ledger = args
+        .ledger
+        .as_deref()
+        .map(|path| LedgerWriter::create(path, resume.is_some(), scale))
+        .transpose()?
+        .map(|ledger| ledger.client_ids(ids.clone()));
+    let mut quarantine = args
+        .quarantine
+        .as_deref()
+        .map(|path| QuarantineWriter::create(path, resume.is_some()))
+        .transpose()?;
+    // Verified up front, entries are only appended once the run is through
+    let mut audit = match (&args.audit_log, &args.audit_key_file) {
+        (Some(path), Some(key_file)) => Some(AuditLog::open(
+            path,
+            &opening::AUDIT_COLUMNS,
+            AuditKey::read(key_file)?,
+        )?),
+        _ => None,
+    };
+    let mut openings = Vec::new();
+
+    let mut engine = match (state_path, &args.opening_balances) {
+        (Some(path), _) => load_state(path, state_key.as_ref())?,
+        (None, Some(path)) => load_opening_balances(path)?,
+        (None, None) => Engine::new(),
+    };
+    // Prior deposits are keyed and checked like this run's deposits
+    engine.set_config(args.engine.config(rules));
+    if let Some(path) = &args.prior_deposits {
+        let count = load_prior_deposits(&mut engine, path)?;
+        if !args.quiet {
+            eprintln!("Registered {count} prior deposits");
+        }
+    }
+    if let Some(path) = &args.deposit_index {
+        engine.set_prior_index(load_deposit_index(path, state_key.as_ref())?);
+        if !args.quiet {
+            eprintln!("Deposit index: {} deposits", engine.prior_deposits());
+        }
+    }
+    #[cfg(feature = "otel")]
+    if let Some(telemetry) = &telemetry {
+        engine.set_metrics(Arc::new(telemetry.metrics()));
+    }
+    let mut dedupe = args
+        .dedupe
+        .as_deref()
+        .map(|path| load_filter(path, args.dedupe_capacity, args.dedupe_fp_rate))
+        .transpose()?;
+    let mut alerts = (!args.alert.is_empty())
+        .then(|| {
+            Alerts::new(
+                args.alert.clone(),
+                args.alerts.as_deref(),
+                resume.is_some(),
+                &engine,
+            )
+            .map(|alerts| alerts.quiet(quiet_echo).client_ids(ids.clone()))
+        })
+        .transpose()?;
+
+    let mut source = CsvSource::open_tapped(
+        &file_path,
+        args.encoding,
+        input_digest.as_ref().map(InputDigest::tap),
+    )
+    .map_err(|err| diagnostic::open_error(&file_path, err))?
+    .lenient_types(args.lenient_types)
+    .number_format(args.number_format);
+    if let Some(mapping) = &mapping {
+        source = source.rename_columns(&mapping.renames());
+    }
+    diagnostic::check_headers(&file_path, source.headers())?;
+    let columns = source.headers().len();
+    let read_error = source.read_error();
+    #[cfg(feature = "chaos")]
+    if let Some(fault) = args.chaos.as_ref().and_then(Chaos::fault) {
+        source = source.inject_faults(fault);
+    }
+    let mut summary = RunSummary::default();
+
+    if let Some(manifest) = &resume {
+        source.seek((&manifest.offset).into())?;
+        summary.rows = manifest.rows;
+    }
+    let mut last_position = source.position();
+
+    if args.reorder_window.is_some() && !source.headers().iter().any(|h| h == "timestamp") {
+        return Err(From::from(
+            "--reorder-window: the input has no `timestamp` column",
+        ));
+    }
+    if args.sequence_policy.is_some() && !source.headers().iter().any(|h| h == "seq") {
+        return Err(From::from(
+            "--sequence-policy: the input has no `seq` column",
+        ));
+    }
+    // Held rows have no input offset to resume from
+    if args.sequence_policy == Some(SequencePolicy::Buffer)
+        && (args.manifest.is_some() || resume.is_some())
+    {
+        return Err(From::from(
+            "--sequence-policy buffer can't be combined with --manifest or --resume",
+        ));
+    }
+
+    let mut queue_metrics = None;
+    let mut source: Box<dyn Iterator<Item = Row>> = if args.pipeline {
+        let pipeline = Pipeline::spawn(source, args.channel_capacity);
+        queue_metrics = Some(pipeline.metrics().clone());
+        if let Some(progress) = progress.as_mut() {
+            progress.watch_queue(pipeline.metrics().clone());
+        }
+        Box::new(pipeline)
+    } else {
+        Box::new(source)
+    };
+    let mut reorder_metrics = None;
+    if let Some(window) = args.reorder_window {
+        let reorder = Reorder::new(source, window);
+        reorder_metrics = Some(reorder.metrics().clone());
+        source = Box::new(reorder);
+    }
+
+    let mut results = Results::new(source, &mut engine).skip_types(args.disable.clone());
+    if let Some(filter) = dedupe.as_mut() {
+        results = results.dedupe(filter);
+    }
+    if let Some(policy) = args.sequence_policy {
+        results = results.sequence(policy);
+    }
+    #[cfg(unix)]
+    let mut hot_snapshots = args
+        .snapshot_on_signal
+        .clone()
+        .map(|path| HotSnapshots::install(path, state_key.clone(), quiet_echo))
+        .transpose()?;
+    let mut sequence_issues = 0;
+    let mut status = RunStatus::Completed;
+    #[cfg(feature = "otel")]
+    let phase = telemetry.as_ref().map(|telemetry| telemetry.phase("apply"));
+    let mut last_parse_error = None;
+    loop {
+        #[cfg(feature = "chaos")]
+        if let Some(chaos) = &args.chaos
+            && chaos.before_row(summary.rows - resume.as_ref().map_or(0, |m| m.rows))
+        {
+            interrupted.store(true, Ordering::Relaxed);
+        }
+        // Checked before the next row is applied, so the engine stops right after `last_position`
+        if interrupted.load(Ordering::Relaxed) && !results.has_dequeued() {
+            status = RunStatus::Interrupted;
+            break;
+        }
+        #[cfg(unix)]
+        if let Some(snapshots) = hot_snapshots.as_mut() {
+            snapshots.poll(results.engine(), summary.rows);
+        }
+        let Some(result) = results.next() else {
+            break;
+        };
+        // Nothing was applied, the row is processed again when the run is resumed
+        if result.outcome == Outcome::Rejected(RejectReason::CapacityExceeded) {
+            status = RunStatus::CapacityExceeded;
+            break;
+        }
+
+        summary.record(&result);
+        if let Some(expected) = &input_manifest
+            && summary.rows > expected.rows
+        {
+            drop((rejects, ledger, quarantine, security, alerts));
+            discard_reports(&args);
+            return Err(From::from(format!(
+                "{}: more than the {} rows in its manifest, the input was altered",
+                file_path.display(),
+                expected.rows
+            )));
+        }
+        if let Some(progress) = progress.as_mut() {
+            progress.tick(summary.rows, result.position.byte, results.engine());
+        }
+        #[cfg(feature = "tui")]
+        if let Some(dashboard) = dashboard.as_mut() {
+            dashboard.record(results.engine(), &result)?;
+        }
+        if let Some(rejects) = rejects.as_mut() {
+            rejects.record(results.engine(), &result)?;
+        }
+        if let Some(ledger) = ledger.as_mut() {
+            ledger.record(results.engine(), &result)?;
+        }
+        if let Some(quarantine) = quarantine.as_mut() {
+            quarantine.record(&result)?;
+        }
+        if let Some(security) = security.as_mut() {
+            security.record(results.engine(), &result)?;
+        }
+        if let (Some(_), Outcome::Applied, Some(Tx::OpeningBalance(opening_tx))) =
+            (&audit, result.outcome, result.tx)
+        {
+            openings.push((result.line, opening_tx));
+        }
+        if let Some(alerts) = alerts.as_mut() {
+            alerts.record(results.engine(), &result)?;
+        }
+        if let (Outcome::Rejected(RejectReason::ConflictingTx), Some(tx)) =
+            (result.outcome, result.tx)
+        {
+            let incident = format!(
+                "line {}: tx {} was seen before with another type, client or amount, \
+                 or its deposit index record is broken, the input looks corrupt",
+                result.line.unwrap_or_default(),
+                tx.tx_id()
+            );
+            if args.strict {
+                return Err(From::from(incident));
+            }
+            if !quiet_echo {
+                eprintln!("incident: {incident}");
+            }
+        }
+        if let (Some(issue), Some(tx)) = (result.sequence, result.tx) {
+            sequence_issues += 1;
+            if args.sequence_policy == Some(SequencePolicy::Warn) && !quiet_echo {
+                eprintln!(
+                    "warning: line {}: client {} {issue}",
+                    result.line.unwrap_or_default(),
+                    ids.label(tx.client_id())
+                );
+            }
+        }
+        last_parse_error = match result.outcome {
+            Outcome::Rejected(RejectReason::ParseError) => result.line,
+            _ => None,
+        };
+        last_position = result.position;
+        if let Some(policy) = args.compact_settled
+            && summary.rows % COMPACT_EVERY == 0
+        {
+            results.engine_mut().compact(policy);
+        }
+    }
+    // Also stops the parser thread when the loop was interrupted
+    drop(results);
+    // The rows before the failed read were applied, so it is resumable like an interrupt
+    if status == RunStatus::Completed && read_error.get().is_some() {
+        status = RunStatus::ReadFailed;
+    }
+    #[cfg(feature = "otel")]
+    drop(phase);
+    #[cfg(unix)]
+    if let Some(snapshots) = hot_snapshots.as_mut() {
+        snapshots.wait();
+    }
+
+    // Checked before anything is written from the run's results
+    if let (Some(expected), Some(digest), RunStatus::Completed) =
+        (&input_manifest, &input_digest, status)
+        && let Err(err) = expected.verify(&digest.manifest(summary.rows))
+    {
+        drop((rejects, ledger, quarantine, security, alerts));
+        discard_reports(&args);
+        return Err(From::from(format!(
+            "{}: {err}, the input is truncated or was altered",
+            file_path.display()
+        )));
+    }
+
+    if let Some(progress) = progress {
+        progress.finish(summary.rows);
+    }
+    // Back to the normal screen for the end-of-run messages
+    #[cfg(feature = "tui")]
+    drop(dashboard);
+    // Transcoded input has no byte-for-byte tail to look at
+    if let (Some(line), None, false) = (last_parse_error, args.encoding, args.quiet)
+        && let Some(warning) = diagnostic::check_truncated(&file_path, line, columns)
+    {
+        eprintln!("{:?}", miette::Report::new(warning));
+    }
+    if let (Some(metrics), false) = (queue_metrics, args.quiet) {
+        eprintln!("pipeline: {metrics}");
+    }
+    if let (Some(metrics), false) = (reorder_metrics, args.quiet) {
+        eprintln!("reorder: {metrics}");
+    }
+    if !args.quiet {
+        eprintln!("resources: {}", Resources::sample(&engine));
+    }
+    if sequence_issues > 0 && !args.quiet {
+        eprintln!("sequence: {sequence_issues} rows didn't follow their client's previous one");
+    }
+    if args.summary {
+        summary.print(&engine);
+        if let (Some(metadata), Some(column)) = (&metadata, &args.summary_by) {
+            for line in metadata.segment_lines(&engine, column)? {
+                eprintln!("{line}");
+            }
+        }
+    }
+
+    if let Some(n) = args.top_n {
+        let top = top::top_clients(engine.clients_iter(), n, args.by);
+        eprintln!("top {n} by {}:", args.by);
+        top::write(std::io::stderr(), &top, scale, &ids)?;
+    }
+
+    // Everything the manifest points to must be complete before it is written
+    if let Some(rejects) = rejects.as_mut() {
+        rejects.flush()?;
+    }
+    if let Some(ledger) = ledger.as_mut() {
+        ledger.flush()?;
+    }
+    if let Some(audit) = audit.as_mut() {
+        let at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        opening::write_audit(audit, &openings, at, &ids)?;
+    }
+    if let Some(quarantine) = quarantine.as_mut() {
+        quarantine.flush()?;
+        if quarantine.count() > 0 && !args.quiet {
+            eprintln!(
+                "quarantine: {} deposits and withdrawals to locked accounts set aside",
+                quarantine.count()
+            );
+        }
+    }
+    if let Some(security) = security.as_mut() {
+        security.flush()?;
+        if security.count() > 0 && !args.quiet {
+            eprintln!(
+                "security: {} rows referenced another client's transaction",
+                security.count()
+            );
+        }
+    }
+    if let (Some(alerts), false) = (&alerts, args.quiet) {
+        eprintln!("alerts: {} thresholds crossed", alerts.count());
+    }
+    if let Some(path) = &args.save_state {
+        #[cfg(feature = "chaos")]
+        if let Some(chaos) = &args.chaos {
+            chaos.before_write(AbortAt::State);
+        }
+        save_state(&engine, path, args.state_shards, state_key.as_ref())?;
+    }
+    if let Some(path) = &args.save_deposit_index {
+        let count = save_deposit_index(&engine, path, state_key.as_ref())?;
+        if !args.quiet {
+            eprintln!("Saved a deposit index of {count} deposits");
+        }
+    }
+    if let (Some(filter), Some(path)) = (&dedupe, &args.dedupe) {
+        save_filter(filter, path)?;
+        if filter.is_over_capacity() && !args.quiet {
+            eprintln!(
+                "warning: the dedupe filter holds {} ids but was sized for {}, \
+                 new transactions are increasingly rejected as duplicates",
+                filter.len(),
+                filter.capacity()
+            );
+        }
+    }
+    if let Some(path) = &args.manifest {
+        let manifest = RunManifest {
+            input: file_path.clone(),
+            status,
+            rows: summary.rows,
+            rejected: rejects.as_ref().map_or(0, |r| r.count())
+                + resume.as_ref().map_or(0, |m| m.rejected),
+            offset: last_position.into(),
+            snapshot: args.save_state.clone(),
+            rejects: args.rejects.clone(),
+            ledger: args.ledger.clone(),
+            quarantine: args.quarantine.clone(),
+            rules,
+        };
+        #[cfg(feature = "chaos")]
+        if let Some(chaos) = &args.chaos {
+            chaos.before_write(AbortAt::Manifest);
+        }
+        manifest.write(path)?;
+    }
+
+    match status {
+        RunStatus::Completed => {}
+        RunStatus::Interrupted => {
+            return Err(From::from(format!(
+                "Interrupted after {} rows, partial results were saved",
+                summary.rows
+            )));
+        }
+        RunStatus::CapacityExceeded => {
+            return Err(From::from(format!(
+                "Capacity limit reached after {} rows ({} clients, {} tracked transactions, ~{} MB), \
+                 partial results were saved",
+                summary.rows,
+                engine.clients_iter().len(),
+                engine.tracked_txs(),
+                engine.memory_estimate() / 1_000_000
+            )));
+        }
+        RunStatus::ReadFailed => {
+            return Err(From::from(format!(
+                "Reading {} failed after {} rows: {}, partial results were saved",
+                file_path.display(),
+                summary.rows,
+                read_error.get().expect("set when the read failed")
+            )));
+        }
+    }
+
+    if let Some(path) = &args.disputes_report {
+        disputes::write_report(&engine, path, scale, &ids)?;
+    }
+    if let Some(path) = &args.aggregates {
+        let w = BufWriter::new(File::create(path)?);
+        aggregates::write(w, &engine, metadata.as_ref(), scale)?;
+    }
+
+    #[cfg(feature = "otel")]
+    let phase = telemetry
+        .as_ref()
+        .map(|telemetry| telemetry.phase("write_output"));
+    let balances = Balances {
+        scale,
+        roster: &roster,
+        metadata: metadata.as_ref(),
+        extended: args.extended_output,
+        ids,
+        columns: args.columns.as_deref(),
+        previous: previous.as_ref(),
+    };
+    #[cfg(feature = "chaos")]
+    if let Some(chaos) = &args.chaos {
+        chaos.before_write(AbortAt::Output);
+    }
+    let write_balances = |w: &mut dyn Write| -> Result<(), Box<dyn Error>> {
+        match args.output_format {
+            OutputFormat::Csv => balances.write(w, &engine),
+            #[cfg(feature = "xml")]
+            OutputFormat::Xml => {
+                let statement = camt::Statement {
+                    currency: &args.currency,
+                    rules: &currency_rules,
+                    created: SystemTime::now(),
+                };
+                camt::write(w, &balances, &engine, &statement)
+            }
+        }
+    };
+    let mut output = Output::open(args.output.as_deref())?;
+    // Only hashed when the manifest is asked for
+    match &args.output_manifest {
+        Some(path) => {
+            let mut digest = DigestWriter::new(&mut output);
+            write_balances(&mut digest)?;
+            let (_, manifest) = digest.finish();
+            output.finish()?;
+            manifest.write(path)?;
+        }
+        None => {
+            write_balances(&mut output)?;
+            output.finish()?;
+        }
+    }
+    #[cfg(feature = "otel")]
+    {
+        drop(phase);
+        if let Some(telemetry) = telemetry {
+            telemetry.finish(&summary);
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes the reports written row by row when the input turns out not to
+/// match its manifest, they describe input that shouldn't have been processed.
+/// The run can't be resumed, so they were all created by it.
+fn discard_reports(args: &ProcessArgs) {
+    let reports = [
+        &args.rejects,
+        &args.ledger,
+        &args.quarantine,
+        &args.security_report,
+        &args.alerts,
+    ];
+    for path in reports.into_iter().flatten() {
+        if let Err(err) = fs::remove_file(path)
+            && err.kind() != io::ErrorKind::NotFound
+        {
+            eprintln!("warning: {}: {err}", path.display());
+        }
+    }
+}
+
+/// SIGINT/SIGTERM stop the run after the current row, a second signal exits right away.
+fn install_signal_handler() -> Result<Arc<AtomicBool>, ctrlc::Error> {
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let flag = interrupted.clone();
+
+    ctrlc::set_handler(move || {
+        if flag.swap(true, Ordering::Relaxed) {
+            process::exit(130);
+        }
+    })?;
+
+    Ok(interrupted)
+}
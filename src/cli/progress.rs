@@ -0,0 +1,135 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use toy_payments_engine::{engine::Engine, pipeline::QueueMetrics};
+
+use crate::cli::resources::Resources;
+
+/// How often a progress line is printed
+const REPORT_INTERVAL: Duration = Duration::from_secs(2);
+/// Checking the clock on every row is wasteful, so only every N rows
+const CHECK_EVERY_ROWS: u64 = 1024;
+
+/// Periodic progress log lines on stderr, with the ETA estimated from the
+/// byte offset within the input file and the resources used so far.
+pub struct Progress {
+    started: Instant,
+    last_report: Instant,
+    total_bytes: u64,
+    queue: Option<Arc<QueueMetrics>>,
+}
+
+impl Progress {
+    pub fn new(total_bytes: u64) -> Self {
+        let now = Instant::now();
+        Progress {
+            started: now,
+            last_report: now,
+            total_bytes,
+            queue: None,
+        }
+    }
+
+    /// Includes the pipeline queue depth in every progress line.
+    pub fn watch_queue(&mut self, queue: Arc<QueueMetrics>) {
+        self.queue = Some(queue);
+    }
+
+    pub fn tick(&mut self, rows: u64, byte_offset: u64, engine: &Engine) {
+        if !rows.is_multiple_of(CHECK_EVERY_ROWS) {
+            return;
+        }
+
+        let now = Instant::now();
+        if now.duration_since(self.last_report) < REPORT_INTERVAL {
+            return;
+        }
+        self.last_report = now;
+
+        eprintln!(
+            "{}, {}",
+            self.line(rows, byte_offset, now),
+            Resources::sample(engine)
+        );
+    }
+
+    pub fn finish(&self, rows: u64) {
+        let elapsed = self.started.elapsed();
+        eprintln!(
+            "processed {} rows in {} ({:.0} rows/s)",
+            rows,
+            format_duration(elapsed),
+            rate(rows, elapsed)
+        );
+    }
+
+    fn line(&self, rows: u64, byte_offset: u64, now: Instant) -> String {
+        let elapsed = now.duration_since(self.started);
+        let mut line = format!(
+            "processed {} rows ({:.0} rows/s)",
+            rows,
+            rate(rows, elapsed)
+        );
+
+        if self.total_bytes > 0 && byte_offset > 0 {
+            let done = byte_offset.min(self.total_bytes) as f64 / self.total_bytes as f64;
+            let eta = elapsed.as_secs_f64() * (1.0 - done) / done;
+            line.push_str(&format!(
+                ", {:.1}% of input, ETA {}",
+                done * 100.0,
+                format_duration(Duration::from_secs_f64(eta))
+            ));
+        }
+
+        if let Some(queue) = &self.queue {
+            line.push_str(&format!(", queue depth {}", queue.depth()));
+        }
+
+        line
+    }
+}
+
+fn rate(rows: u64, elapsed: Duration) -> f64 {
+    let secs = elapsed.as_secs_f64();
+    if secs > 0.0 { rows as f64 / secs } else { 0.0 }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::from_secs(0)), "00:00:00");
+        assert_eq!(format_duration(Duration::from_secs(3723)), "01:02:03");
+    }
+
+    #[test]
+    fn test_line_estimates_eta_from_byte_offset() {
+        let progress = Progress::new(1000);
+        let now = progress.started + Duration::from_secs(10);
+
+        assert_eq!(
+            progress.line(500, 250, now),
+            "processed 500 rows (50 rows/s), 25.0% of input, ETA 00:00:30"
+        );
+    }
+
+    #[test]
+    fn test_line_without_file_size() {
+        let progress = Progress::new(0);
+        let now = progress.started + Duration::from_secs(10);
+
+        assert_eq!(
+            progress.line(500, 250, now),
+            "processed 500 rows (50 rows/s)"
+        );
+    }
+}
@@ -0,0 +1,95 @@
+//! `--pseudonymize`: client ids in the outputs replaced by an HMAC-SHA256 of
+//! the id under a secret salt, cut to 64 bits. The same salt gives the same
+//! pseudonym in every output and every run, so datasets can still be joined,
+//! but without the salt the ids can't be recovered by trying all 65536 of them.
+
+use std::{error::Error, fmt, path::Path};
+
+use hmac::{Hmac, Mac};
+use serde::{Serialize, Serializer};
+use sha2::Sha256;
+use toy_payments_engine::types::common::ClientId;
+
+/// Shortest salt accepted, in bytes.
+const MIN_SALT: usize = 16;
+
+/// How client ids are written, as they are or pseudonymized.
+#[derive(Clone, Default)]
+pub struct ClientIds(Option<Hmac<Sha256>>);
+
+impl ClientIds {
+    /// Pseudonymizes under the salt in `path`, its bytes as they are.
+    pub fn read(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let salt = std::fs::read(path)?;
+        if salt.len() < MIN_SALT {
+            return Err(From::from(format!(
+                "{}: the salt must be at least {MIN_SALT} bytes",
+                path.display()
+            )));
+        }
+        Ok(ClientIds::salted(&salt))
+    }
+
+    fn salted(salt: &[u8]) -> Self {
+        ClientIds(Some(
+            Hmac::new_from_slice(salt).expect("HMAC takes keys of any length"),
+        ))
+    }
+
+    pub fn label(&self, id: ClientId) -> ClientLabel {
+        match &self.0 {
+            Some(mac) => {
+                let mut mac = mac.clone();
+                mac.update(&id.to_be_bytes());
+                let tag = mac.finalize().into_bytes();
+                ClientLabel::Pseudonym(u64::from_be_bytes(tag[..8].try_into().expect("8 bytes")))
+            }
+            None => ClientLabel::Id(id),
+        }
+    }
+}
+
+/// A client id as written to an output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ClientLabel {
+    Id(ClientId),
+    /// Written as 16 hex digits
+    Pseudonym(u64),
+}
+
+impl fmt::Display for ClientLabel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientLabel::Id(id) => write!(f, "{id}"),
+            ClientLabel::Pseudonym(pseudonym) => write!(f, "{pseudonym:016x}"),
+        }
+    }
+}
+
+impl Serialize for ClientLabel {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            ClientLabel::Id(id) => serializer.serialize_u16(*id),
+            ClientLabel::Pseudonym(_) => serializer.collect_str(self),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pseudonyms_depend_on_the_salt_only() {
+        let ids = ClientIds::salted(b"0123456789abcdef");
+        assert_eq!(ids.label(7), ids.label(7));
+        assert_ne!(ids.label(7), ids.label(8));
+        assert_ne!(
+            ClientIds::salted(b"fedcba9876543210").label(7),
+            ids.label(7)
+        );
+        assert_eq!(ids.label(7).to_string().len(), 16);
+        assert_eq!(ClientIds::default().label(7), ClientLabel::Id(7));
+        assert_eq!(ClientIds::default().label(7).to_string(), "7");
+    }
+}
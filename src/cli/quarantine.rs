@@ -0,0 +1,132 @@
+use std::{
+    error::Error,
+    fs::{File, OpenOptions},
+    io::BufWriter,
+    path::Path,
+};
+
+use rust_decimal::Decimal;
+use toy_payments_engine::{
+    pipeline::results::{Outcome, RowResult},
+    types::{
+        common::{ClientId, TxId},
+        reject::RejectReason,
+        transactions::Tx,
+    },
+};
+
+#[derive(serde::Serialize)]
+struct QuarantineRow {
+    r#type: &'static str,
+    client: ClientId,
+    tx: TxId,
+    amount: Decimal,
+}
+
+/// Deposits and withdrawals rejected because the account was locked, written
+/// in the input format so they can be replayed once the account is unlocked.
+/// Client ids stay real, like in saved state.
+pub struct QuarantineWriter {
+    wtr: csv::Writer<BufWriter<File>>,
+    count: u64,
+}
+
+impl QuarantineWriter {
+    /// When `append` is set the file continues an existing one (resumed runs).
+    pub fn create(path: &Path, append: bool) -> Result<Self, Box<dyn Error>> {
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .append(append)
+            .truncate(!append)
+            .open(path)?;
+        let has_content = file.metadata()?.len() > 0;
+
+        let wtr = csv::WriterBuilder::new()
+            .has_headers(!has_content)
+            .from_writer(BufWriter::new(file));
+
+        Ok(QuarantineWriter { wtr, count: 0 })
+    }
+
+    pub fn record(&mut self, result: &RowResult) -> csv::Result<()> {
+        if result.outcome != Outcome::Rejected(RejectReason::AccountLocked) {
+            return Ok(());
+        }
+        let (client, tx, amount) = match result.tx {
+            Some(Tx::Deposit(deposit_tx)) => (
+                deposit_tx.client_id(),
+                deposit_tx.tx_id(),
+                deposit_tx.amount(),
+            ),
+            Some(Tx::Withdrawal(withdrawal_tx)) => (
+                withdrawal_tx.client_id(),
+                withdrawal_tx.tx_id(),
+                withdrawal_tx.amount(),
+            ),
+            _ => return Ok(()),
+        };
+        self.count += 1;
+        self.wtr.serialize(QuarantineRow {
+            r#type: result.tx.map_or("", |tx| tx.type_name()),
+            client,
+            tx,
+            amount,
+        })
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.wtr.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use toy_payments_engine::{
+        engine::Engine,
+        pipeline::{results::Results, source::CsvSource},
+    };
+
+    #[test]
+    fn test_quarantined_rows_replay_as_input() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input.csv");
+        std::fs::write(
+            &input,
+            "type,client,tx,amount\n\
+             deposit,1,1,10.0\n\
+             dispute,1,1,\n\
+             chargeback,1,1,\n\
+             deposit,1,2,2.5\n\
+             withdrawal,1,3,1.0\n\
+             dispute,1,2,\n\
+             deposit,2,4,1.0\n",
+        )
+        .unwrap();
+        let path = dir.path().join("quarantine.csv");
+        let mut quarantine = QuarantineWriter::create(&path, false).unwrap();
+        let mut engine = Engine::new();
+        for result in Results::new(CsvSource::open(&input).unwrap(), &mut engine) {
+            quarantine.record(&result).unwrap();
+        }
+        quarantine.flush().unwrap();
+
+        assert_eq!(quarantine.count(), 2);
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "type,client,tx,amount\n\
+             deposit,1,2,2.5\n\
+             withdrawal,1,3,1.0\n"
+        );
+        let replayed: Vec<_> = CsvSource::open(&path)
+            .unwrap()
+            .map(|row| row.tx.map(|tx| tx.tx_id()))
+            .collect();
+        assert_eq!(replayed, vec![Some(2), Some(3)]);
+    }
+}
@@ -0,0 +1,133 @@
+use std::{error::Error, path::PathBuf};
+
+use clap::Args;
+use rust_decimal::Decimal;
+use toy_payments_engine::types::{client::Client, common::ClientId};
+
+use crate::cli::{encryption::StateKey, state::load_state};
+
+#[derive(Debug, Args)]
+pub struct QueryArgs {
+    /// State snapshot saved with `--save-state`
+    #[arg(long, value_name = "PATH")]
+    pub state: PathBuf,
+
+    /// Key file of an encrypted snapshot (default: the `TPE_STATE_KEY` environment variable)
+    #[arg(long, value_name = "PATH")]
+    pub state_key_file: Option<PathBuf>,
+
+    /// Only this client
+    #[arg(long)]
+    pub client: Option<ClientId>,
+
+    /// Only locked accounts
+    #[arg(long)]
+    pub locked: bool,
+
+    /// Only accounts holding at least this amount
+    #[arg(long, value_name = "AMOUNT")]
+    pub min_held: Option<Decimal>,
+}
+
+impl QueryArgs {
+    fn matches(&self, client: &Client) -> bool {
+        self.client.is_none_or(|id| client.id == id)
+            && (!self.locked || client.locked)
+            && self.min_held.is_none_or(|min| client.held >= min)
+    }
+}
+
+/// Prints the clients matching all given filters in the balances format.
+pub fn run(args: QueryArgs) -> Result<(), Box<dyn Error>> {
+    let key = StateKey::resolve(args.state_key_file.as_deref())?;
+    let engine = load_state(&args.state, key.as_ref())?;
+
+    let mut matching: Vec<&Client> = match args.client {
+        // Direct lookup rather than a scan for the common support case
+        Some(id) => engine.client(id).into_iter().collect(),
+        None => engine.clients_iter().collect(),
+    };
+    matching.retain(|client| args.matches(client));
+    matching.sort_by_key(|client| client.id);
+
+    let mut wtr = csv::Writer::from_writer(std::io::stdout());
+    for client in matching {
+        wtr.serialize(client)?;
+    }
+    wtr.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn args() -> QueryArgs {
+        QueryArgs {
+            state: PathBuf::new(),
+            state_key_file: None,
+            client: None,
+            locked: false,
+            min_held: None,
+        }
+    }
+
+    fn client(id: ClientId, held: Decimal, locked: bool) -> Client {
+        Client {
+            held,
+            total: held,
+            locked,
+            ..Client::new(id)
+        }
+    }
+
+    #[test]
+    fn test_filters_are_combined() {
+        let clients = [
+            client(1, dec!(0), false),
+            client(2, dec!(150), false),
+            client(3, dec!(150), true),
+            client(4, dec!(50), true),
+        ];
+        let matching = |args: &QueryArgs| -> Vec<ClientId> {
+            clients
+                .iter()
+                .filter(|c| args.matches(c))
+                .map(|c| c.id)
+                .collect()
+        };
+
+        assert_eq!(matching(&args()), vec![1, 2, 3, 4]);
+        assert_eq!(
+            matching(&QueryArgs {
+                client: Some(2),
+                ..args()
+            }),
+            vec![2]
+        );
+        assert_eq!(
+            matching(&QueryArgs {
+                locked: true,
+                ..args()
+            }),
+            vec![3, 4]
+        );
+        assert_eq!(
+            matching(&QueryArgs {
+                min_held: Some(dec!(100)),
+                ..args()
+            }),
+            vec![2, 3]
+        );
+        assert_eq!(
+            matching(&QueryArgs {
+                locked: true,
+                min_held: Some(dec!(100)),
+                ..args()
+            }),
+            vec![3]
+        );
+    }
+}
@@ -0,0 +1,190 @@
+use std::{
+    error::Error,
+    fs::{File, OpenOptions},
+    io::BufWriter,
+    path::Path,
+};
+
+use rust_decimal::Decimal;
+use toy_payments_engine::{
+    engine::Engine,
+    pipeline::results::{Outcome, RowResult},
+    types::{common::TxId, reject::RejectReason},
+};
+
+use crate::cli::{
+    output::OutputScale,
+    pseudonym::{ClientIds, ClientLabel},
+};
+
+#[derive(serde::Serialize)]
+struct RejectRow {
+    line: Option<u64>,
+    r#type: Option<&'static str>,
+    client: Option<ClientLabel>,
+    tx: Option<TxId>,
+    reason: RejectReason,
+}
+
+/// With `--explain`, the state the row was rejected against, read right after
+/// the engine decided. That includes what the rejection itself did, such as a
+/// client created by a deposit that was then refused.
+#[derive(serde::Serialize)]
+struct Explanation {
+    available: Option<Decimal>,
+    held: Option<Decimal>,
+    total: Option<Decimal>,
+    locked: Option<bool>,
+    /// Dispute state of the client's transaction with the row's id
+    tx_state: Option<&'static str>,
+}
+
+/// CSV report of every row that was not applied, with the reason.
+pub struct RejectsWriter {
+    wtr: csv::Writer<BufWriter<File>>,
+    count: u64,
+    ids: ClientIds,
+    explain: Option<OutputScale>,
+}
+
+impl RejectsWriter {
+    /// When `append` is set the report continues an existing file (resumed runs).
+    pub fn create(path: &Path, append: bool) -> Result<Self, Box<dyn Error>> {
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .append(append)
+            .truncate(!append)
+            .open(path)?;
+        let has_content = file.metadata()?.len() > 0;
+
+        let wtr = csv::WriterBuilder::new()
+            .has_headers(!has_content)
+            .from_writer(BufWriter::new(file));
+
+        Ok(RejectsWriter {
+            wtr,
+            count: 0,
+            ids: ClientIds::default(),
+            explain: None,
+        })
+    }
+
+    pub fn client_ids(mut self, ids: ClientIds) -> Self {
+        self.ids = ids;
+        self
+    }
+
+    /// Adds the client's balances and the named transaction's dispute state
+    /// to every row, amounts written at `scale`.
+    pub fn explain(mut self, scale: OutputScale) -> Self {
+        self.explain = Some(scale);
+        self
+    }
+
+    /// Writes the row if it was rejected, whether by the parser or the engine.
+    pub fn record(&mut self, engine: &Engine, result: &RowResult) -> csv::Result<()> {
+        let Outcome::Rejected(reason) = result.outcome else {
+            return Ok(());
+        };
+        let row = RejectRow {
+            line: result.line,
+            r#type: result.tx.map(|tx| tx.type_name()),
+            client: result.tx.map(|tx| self.ids.label(tx.client_id())),
+            tx: result.tx_id,
+            reason,
+        };
+        self.count += 1;
+        let Some(scale) = self.explain else {
+            return self.wtr.serialize(row);
+        };
+
+        let client = result
+            .tx
+            .and_then(|tx| engine.client(tx.client_id()))
+            .map(|client| scale.client(client));
+        let explanation = Explanation {
+            available: client.as_ref().map(|c| c.available),
+            held: client.as_ref().map(|c| c.held),
+            total: client.as_ref().map(|c| c.total),
+            locked: client.as_ref().map(|c| c.locked),
+            tx_state: result
+                .tx
+                .and_then(|tx| engine.tx_state(tx.client_id(), tx.tx_id()))
+                .map(|state| state.name()),
+        };
+        self.wtr.serialize((row, explanation))
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.wtr.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+    use tempfile::NamedTempFile;
+    use toy_payments_engine::{
+        pipeline::{InputOffset, results::Results, source::Row},
+        types::transactions::{DepositTx, DisputeTx, ResolveTx, Tx, WithdrawalTx},
+    };
+
+    #[test]
+    fn test_explained_rejects() {
+        let file = NamedTempFile::new().unwrap();
+        let mut rejects = RejectsWriter::create(file.path(), false)
+            .unwrap()
+            .explain(OutputScale(Some(2)));
+        let mut engine = Engine::new();
+
+        let txs = [
+            Tx::Deposit(DepositTx::new(1, 1, dec!(10)).unwrap()),
+            Tx::Withdrawal(WithdrawalTx::new(1, 2, dec!(20)).unwrap()),
+            Tx::Dispute(DisputeTx {
+                client_id: 1,
+                tx_id: 1,
+            }),
+            Tx::Resolve(ResolveTx {
+                client_id: 1,
+                tx_id: 1,
+            }),
+            Tx::Dispute(DisputeTx {
+                client_id: 1,
+                tx_id: 1,
+            }),
+            Tx::Dispute(DisputeTx {
+                client_id: 2,
+                tx_id: 1,
+            }),
+        ];
+        let rows = txs.into_iter().enumerate().map(|(i, tx)| Row {
+            line: Some(i as u64 + 2),
+            tx: Some(tx),
+            timestamp: None,
+            seq: None,
+            position: InputOffset::default(),
+        });
+        let mut results = Results::new(rows, &mut engine);
+        while let Some(result) = results.next() {
+            rejects.record(results.engine(), &result).unwrap();
+        }
+        rejects.flush().unwrap();
+
+        assert_eq!(rejects.count(), 3);
+        assert_eq!(
+            std::fs::read_to_string(file.path()).unwrap(),
+            "\
+line,type,client,tx,reason,available,held,total,locked,tx_state
+3,withdrawal,1,2,insufficient_funds,10.00,0.00,10.00,false,
+6,dispute,1,1,not_disputable,10.00,0.00,10.00,false,resolved
+7,dispute,2,1,unknown_client,,,,,
+"
+        );
+    }
+}
@@ -0,0 +1,200 @@
+//! What a run costs: peak RSS, the engine's table sizes and, with the
+//! `heap-stats` feature, heap usage counted by the `tpe` binary's global
+//! allocator, for sizing machines without an external profiler.
+
+use std::fmt;
+#[cfg(feature = "heap-stats")]
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+};
+
+use toy_payments_engine::engine::Engine;
+
+#[cfg(feature = "heap-stats")]
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+#[cfg(feature = "heap-stats")]
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+#[cfg(feature = "heap-stats")]
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+/// The system allocator, counting live bytes, their peak and allocations.
+#[cfg(feature = "heap-stats")]
+pub struct CountingAlloc;
+
+#[cfg(feature = "heap-stats")]
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        // SAFETY: forwarded as is, the caller upholds `alloc`'s contract
+        let ptr = unsafe { System.alloc(layout) };
+        if !ptr.is_null() {
+            grow(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        // SAFETY: as for `alloc`
+        let ptr = unsafe { System.alloc_zeroed(layout) };
+        if !ptr.is_null() {
+            grow(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // SAFETY: `ptr` was allocated by `System` with `layout`
+        unsafe { System.dealloc(ptr, layout) };
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        // SAFETY: `ptr` was allocated by `System` with `layout`
+        let new = unsafe { System.realloc(ptr, layout, new_size) };
+        if !new.is_null() {
+            ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+            grow(new_size);
+        }
+        new
+    }
+}
+
+#[cfg(feature = "heap-stats")]
+fn grow(size: usize) {
+    let now = ALLOCATED.fetch_add(size, Ordering::Relaxed) + size;
+    // A plain load first, most allocations don't set a new peak
+    if now > PEAK.load(Ordering::Relaxed) {
+        PEAK.fetch_max(now, Ordering::Relaxed);
+    }
+    ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Peak resident set size in bytes, from `/proc` (Linux only).
+fn peak_rss() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kb: u64 = line["VmHWM:".len()..]
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()?;
+    Some(kb * 1024)
+}
+
+/// Heap usage counted by `CountingAlloc`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Heap {
+    pub live: usize,
+    pub peak: usize,
+    pub allocations: u64,
+}
+
+impl Heap {
+    /// `None` unless the binary was built with the `heap-stats` feature.
+    fn sample() -> Option<Self> {
+        #[cfg(feature = "heap-stats")]
+        return Some(Heap {
+            live: ALLOCATED.load(Ordering::Relaxed),
+            peak: PEAK.load(Ordering::Relaxed),
+            allocations: ALLOCATIONS.load(Ordering::Relaxed),
+        });
+        #[cfg(not(feature = "heap-stats"))]
+        None
+    }
+}
+
+/// Resource usage at one point of a run.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Resources {
+    pub peak_rss: Option<u64>,
+    pub clients: usize,
+    /// Deposits and withdrawals kept for disputes
+    pub tracked_txs: usize,
+    pub heap: Option<Heap>,
+}
+
+impl Resources {
+    pub fn sample(engine: &Engine) -> Self {
+        Resources {
+            peak_rss: peak_rss(),
+            clients: engine.clients_iter().len(),
+            tracked_txs: engine.tracked_txs(),
+            heap: Heap::sample(),
+        }
+    }
+}
+
+fn megabytes(bytes: u64) -> String {
+    format!("{:.1} MB", bytes as f64 / 1_000_000.0)
+}
+
+impl fmt::Display for Resources {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(rss) = self.peak_rss {
+            write!(f, "peak RSS {}, ", megabytes(rss))?;
+        }
+        write!(
+            f,
+            "{} clients, {} tracked transactions",
+            self.clients, self.tracked_txs
+        )?;
+        if let Some(heap) = self.heap {
+            write!(
+                f,
+                ", heap {} (peak {}, {} allocations)",
+                megabytes(heap.live as u64),
+                megabytes(heap.peak as u64),
+                heap.allocations
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resources_line() {
+        let resources = Resources {
+            peak_rss: Some(52_400_000),
+            clients: 3,
+            tracked_txs: 1200,
+            heap: Some(Heap {
+                live: 1_240_000,
+                peak: 2_000_000,
+                allocations: 42,
+            }),
+        };
+        assert_eq!(
+            resources.to_string(),
+            "peak RSS 52.4 MB, 3 clients, 1200 tracked transactions, heap 1.2 MB (peak 2.0 MB, 42 allocations)"
+        );
+        let resources = Resources {
+            peak_rss: None,
+            heap: None,
+            ..resources
+        };
+        assert_eq!(
+            resources.to_string(),
+            "3 clients, 1200 tracked transactions"
+        );
+    }
+
+    #[test]
+    fn test_allocations_are_counted() {
+        let before = Resources::sample(&Engine::new());
+        let buf = vec![0u8; 1 << 20];
+        let after = Resources::sample(&Engine::new());
+        assert_eq!(after.heap.is_some(), cfg!(feature = "heap-stats"));
+        if let (Some(before), Some(after)) = (before.heap, after.heap) {
+            assert!(after.allocations > before.allocations);
+            assert!(after.peak >= buf.len());
+        }
+        if cfg!(target_os = "linux") {
+            assert!(after.peak_rss.is_some());
+        }
+    }
+}
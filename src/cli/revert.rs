@@ -0,0 +1,151 @@
+use std::{
+    error::Error,
+    io::Write,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use clap::Args;
+use toy_payments_engine::{engine::revert::Reversal, types::common::TxId};
+
+use crate::cli::{
+    audit::{AuditKey, AuditLog},
+    encryption::StateKey,
+    pseudonym::ClientIds,
+    state::{load_state, save_state},
+};
+
+#[derive(Debug, Args)]
+pub struct RevertArgs {
+    /// State snapshot saved with `--save-state`, rewritten with the reverts applied
+    #[arg(long, value_name = "PATH")]
+    pub state: PathBuf,
+
+    /// Key file of an encrypted snapshot (default: the `TPE_STATE_KEY` environment variable)
+    #[arg(long, value_name = "PATH")]
+    pub state_key_file: Option<PathBuf>,
+
+    /// Hash-chained CSV every revert is appended to, see `tpe audit verify`
+    #[arg(long, value_name = "PATH")]
+    pub audit_log: PathBuf,
+
+    /// File whose bytes (at least 16) are the secret key the audit log is chained under
+    #[arg(long, value_name = "PATH")]
+    pub audit_key_file: PathBuf,
+
+    /// Write client ids to the audit log and stderr as HMACs under `--salt-file`
+    #[arg(long, requires = "salt_file")]
+    pub pseudonymize: bool,
+
+    /// File whose bytes (at least 16) are the secret `--pseudonymize` key
+    #[arg(long, value_name = "PATH", requires = "pseudonymize")]
+    pub salt_file: Option<PathBuf>,
+
+    /// Why the transactions are reverted, kept in the audit log
+    #[arg(long, value_name = "TEXT", default_value = "")]
+    pub note: String,
+
+    /// Transactions to revert, each one step back
+    #[arg(required = true, value_name = "TX")]
+    pub txs: Vec<TxId>,
+}
+
+/// Audit log columns, `at` in Unix seconds.
+const AUDIT_COLUMNS: [&str; 6] = ["at", "tx", "client", "reverted", "amount", "note"];
+
+/// Reverts the transactions in a saved state, all of them or none: the
+/// state is only rewritten, and the audit log only appended to, when every
+/// revert went through.
+pub fn run(args: RevertArgs) -> Result<(), Box<dyn Error>> {
+    let key = StateKey::resolve(args.state_key_file.as_deref())?;
+    let ids = args
+        .salt_file
+        .as_deref()
+        .map(ClientIds::read)
+        .transpose()?
+        .unwrap_or_default();
+    let mut engine = load_state(&args.state, key.as_ref())?;
+    // Opened first so a bad path fails before the state is touched
+    let audit_key = AuditKey::read(&args.audit_key_file)?;
+    let mut audit = AuditLog::open(&args.audit_log, &AUDIT_COLUMNS, audit_key)?;
+
+    let mut reversals = Vec::with_capacity(args.txs.len());
+    for &tx_id in &args.txs {
+        let reversal = engine
+            .revert(tx_id)
+            .map_err(|reason| format!("Cannot revert tx {tx_id}: {reason}, nothing was changed"))?;
+        reversals.push(reversal);
+    }
+
+    save_state(&engine, &args.state, 1, key.as_ref())?;
+    let at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    write_audit(&mut audit, &reversals, at, &args.note, &ids)?;
+
+    for reversal in &reversals {
+        eprintln!(
+            "reverted {} of tx {} for client {} ({})",
+            reversal.reverted.name(),
+            reversal.tx_id,
+            ids.label(reversal.client_id),
+            reversal.amount
+        );
+    }
+    Ok(())
+}
+
+fn write_audit<W: Write>(
+    audit: &mut AuditLog<W>,
+    reversals: &[Reversal],
+    at: u64,
+    note: &str,
+    ids: &ClientIds,
+) -> csv::Result<()> {
+    for reversal in reversals {
+        audit.append([
+            at.to_string(),
+            reversal.tx_id.to_string(),
+            ids.label(reversal.client_id).to_string(),
+            reversal.reverted.name().to_string(),
+            reversal.amount.to_string(),
+            note.to_string(),
+        ])?;
+    }
+    audit.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+    use toy_payments_engine::engine::revert::Reverted;
+
+    #[test]
+    fn test_write_audit() {
+        let key = AuditKey::new(b"0123456789abcdef");
+        let mut audit = AuditLog::new(vec![], &AUDIT_COLUMNS, key.clone(), None).unwrap();
+        let reversals = [Reversal {
+            reverted: Reverted::Deposit,
+            client_id: 3,
+            tx_id: 12,
+            amount: dec!(1.5),
+        }];
+        write_audit(
+            &mut audit,
+            &reversals,
+            1_700_000_000,
+            "typo, see ticket",
+            &ClientIds::default(),
+        )
+        .unwrap();
+
+        let out = String::from_utf8(audit.into_inner().unwrap()).unwrap();
+        assert!(out.starts_with(
+            "at,tx,client,reverted,amount,note,prev_hash,hash
+1700000000,12,3,deposit,1.5,\"typo, see ticket\","
+        ));
+        let rdr = csv::Reader::from_reader(out.as_bytes());
+        let chain = crate::cli::audit::verify(rdr, &key).unwrap();
+        assert_eq!(chain.entries, 1);
+    }
+}
@@ -0,0 +1,24 @@
+use std::{error::Error, path::Path};
+
+use toy_payments_engine::types::common::ClientId;
+
+#[derive(serde::Deserialize)]
+struct RosterRow {
+    client: ClientId,
+}
+
+/// Reads the known client ids from a CSV with a `client` column, other columns are ignored.
+pub fn read(path: &Path) -> Result<Vec<ClientId>, Box<dyn Error>> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_path(path)?;
+
+    let mut clients = Vec::new();
+    for row in rdr.deserialize::<RosterRow>() {
+        let row = row.map_err(|err| format!("{}: {err}", path.display()))?;
+        clients.push(row.client);
+    }
+
+    Ok(clients)
+}
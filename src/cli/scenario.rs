@@ -0,0 +1,245 @@
+use std::{collections::BTreeMap, error::Error, fmt::Display, path::PathBuf, str::FromStr};
+
+use clap::{Args, Subcommand};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Deserializer, de};
+use toy_payments_engine::{
+    engine::{
+        Engine,
+        config::{EngineConfig, MissingClient, MissingDeposit},
+        rules::Rules,
+    },
+    io::csv::CsvRow,
+    types::{
+        common::{ClientId, TxId},
+        reject::RejectReason,
+        transactions::Tx,
+    },
+};
+
+#[derive(Debug, Args)]
+pub struct ScenarioArgs {
+    #[command(subcommand)]
+    pub command: ScenarioCommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ScenarioCommand {
+    /// Run scenario files and check their expectations
+    Run {
+        /// YAML scenario files
+        #[arg(required = true, value_name = "FILE")]
+        files: Vec<PathBuf>,
+    },
+}
+
+/// A named sequence of transactions and what it must lead to.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Scenario {
+    name: Option<String>,
+    #[serde(default)]
+    rules: Rules,
+    #[serde(default, deserialize_with = "from_str")]
+    missing_deposit: MissingDeposit,
+    #[serde(default, deserialize_with = "from_str")]
+    missing_client: MissingClient,
+    /// Account names used by the steps, and their client ids
+    accounts: BTreeMap<String, ClientId>,
+    steps: Vec<Step>,
+    /// Balances by account name after the last step
+    #[serde(default)]
+    expect: BTreeMap<String, Expected>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Step {
+    r#type: String,
+    account: String,
+    tx: TxId,
+    amount: Option<Decimal>,
+    /// `ok` (the default) or the reject reason the step must get
+    #[serde(default = "ok")]
+    expect: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Expected {
+    available: Option<Decimal>,
+    held: Option<Decimal>,
+    total: Option<Decimal>,
+    locked: Option<bool>,
+}
+
+fn ok() -> String {
+    "ok".to_string()
+}
+
+fn from_str<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr<Err: Display>,
+{
+    String::deserialize(deserializer)?
+        .parse()
+        .map_err(de::Error::custom)
+}
+
+pub fn run(args: ScenarioArgs) -> Result<(), Box<dyn Error>> {
+    let ScenarioCommand::Run { files } = args.command;
+
+    let mut failed = 0;
+    for path in &files {
+        let scenario: Scenario = serde_yaml::from_reader(std::fs::File::open(path)?)
+            .map_err(|err| format!("{}: {err}", path.display()))?;
+        let name = scenario
+            .name
+            .clone()
+            .unwrap_or_else(|| path.display().to_string());
+        let failures = scenario
+            .check()
+            .map_err(|err| format!("{}: {err}", path.display()))?;
+
+        if failures.is_empty() {
+            println!("PASS {name}");
+        } else {
+            failed += 1;
+            println!("FAIL {name}");
+            for failure in failures {
+                println!("  {failure}");
+            }
+        }
+    }
+
+    if failed > 0 {
+        return Err(From::from(format!(
+            "{failed} of {} scenarios failed",
+            files.len()
+        )));
+    }
+    Ok(())
+}
+
+impl Scenario {
+    /// Runs the steps on a fresh engine, returning every unmet expectation.
+    /// Errors when the scenario itself is invalid.
+    fn check(&self) -> Result<Vec<String>, String> {
+        let mut engine = Engine::with_config(EngineConfig {
+            rules: self.rules,
+            missing_deposit: self.missing_deposit,
+            missing_client: self.missing_client,
+            ..EngineConfig::default()
+        });
+        let client_id = |account: &str| {
+            self.accounts
+                .get(account)
+                .copied()
+                .ok_or_else(|| format!("unknown account `{account}`"))
+        };
+
+        let mut failures = Vec::new();
+        for (i, step) in self.steps.iter().enumerate() {
+            let row = CsvRow {
+                r#type: step.r#type.clone(),
+                client: client_id(&step.account)?,
+                tx: step.tx,
+                amount: step.amount,
+            };
+            let tx = Tx::try_from(row).map_err(|()| {
+                format!(
+                    "step {}: `{}` isn't a transaction type, or lacks a valid amount",
+                    i + 1,
+                    step.r#type
+                )
+            })?;
+            let expected = match step.expect.as_str() {
+                "ok" => None,
+                code => Some(
+                    code.parse::<RejectReason>()
+                        .map_err(|err| format!("step {}: {err}", i + 1))?,
+                ),
+            };
+            let result = engine.process_tx(tx);
+            if result.err() != expected {
+                let outcome =
+                    result.map_or_else(|reason| reason.to_string(), |()| "ok".to_string());
+                failures.push(format!(
+                    "step {} ({} {} tx {}): expected {}, got {outcome}",
+                    i + 1,
+                    step.r#type,
+                    step.account,
+                    step.tx,
+                    step.expect
+                ));
+            }
+        }
+
+        for (account, expected) in &self.expect {
+            let client = engine.client(client_id(account)?);
+            let actual = client.map(|c| (c.available, c.held, c.total, c.locked));
+            let (available, held, total, locked) =
+                actual.unwrap_or((Decimal::ZERO, Decimal::ZERO, Decimal::ZERO, false));
+            let mut check = |field: &str, expected: Option<String>, actual: String| {
+                if let Some(expected) = expected
+                    && expected != actual
+                {
+                    failures.push(format!(
+                        "{account}: expected {field} {expected}, got {actual}"
+                    ));
+                }
+            };
+            // Compared normalized, so `10` matches `10.0000`
+            let amount = |d: Decimal| d.normalize().to_string();
+            check(
+                "available",
+                expected.available.map(amount),
+                amount(available),
+            );
+            check("held", expected.held.map(amount), amount(held));
+            check("total", expected.total.map(amount), amount(total));
+            check(
+                "locked",
+                expected.locked.map(|l| l.to_string()),
+                locked.to_string(),
+            );
+        }
+
+        Ok(failures)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scenario_expectations() {
+        let scenario: Scenario = serde_yaml::from_str(
+            "
+name: chargeback locks the account
+accounts: { alice: 1, bob: 2 }
+steps:
+  - { type: deposit, account: alice, tx: 1, amount: 10.5 }
+  - { type: deposit, account: bob, tx: 2, amount: '3' }
+  - { type: dispute, account: bob, tx: 1, expect: client_mismatch }
+  - { type: dispute, account: alice, tx: 1 }
+  - { type: chargeback, account: alice, tx: 1 }
+  - { type: withdrawal, account: alice, tx: 3, amount: 1 }
+expect:
+  alice: { available: 0, held: 0, total: 0, locked: true }
+  bob: { total: 4 }
+",
+        )
+        .unwrap();
+
+        assert_eq!(
+            scenario.check().unwrap(),
+            vec![
+                "step 6 (withdrawal alice tx 3): expected ok, got account_locked",
+                "bob: expected total 4, got 3",
+            ]
+        );
+    }
+}
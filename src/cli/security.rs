@@ -0,0 +1,185 @@
+use std::{
+    error::Error,
+    fs::{File, OpenOptions},
+    io::BufWriter,
+    path::Path,
+};
+
+use toy_payments_engine::{
+    engine::Engine,
+    pipeline::results::{Outcome, RowResult},
+    types::{common::TxId, reject::RejectReason, transactions::Tx},
+};
+
+use crate::cli::pseudonym::{ClientIds, ClientLabel};
+
+#[derive(serde::Serialize)]
+struct AnomalyRow {
+    line: Option<u64>,
+    r#type: &'static str,
+    client: ClientLabel,
+    tx: TxId,
+    /// Client the referenced transaction actually belongs to
+    owner: Option<ClientLabel>,
+    anomaly: RejectReason,
+    severity: Severity,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Severity {
+    Medium,
+    /// The input itself is corrupt
+    High,
+}
+
+/// CSV of rows that reference another client's transaction, either data
+/// corruption or someone probing for ids, and of deposits and withdrawals
+/// reusing the id of another transaction, which is corruption. All worth an
+/// alert.
+pub struct SecurityReport {
+    wtr: csv::Writer<BufWriter<File>>,
+    count: u64,
+    ids: ClientIds,
+}
+
+impl SecurityReport {
+    /// With `append` (a resumed run) the anomalies found before the
+    /// interruption are kept.
+    pub fn create(path: &Path, append: bool) -> Result<Self, Box<dyn Error>> {
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .append(append)
+            .truncate(!append)
+            .open(path)?;
+        let has_content = file.metadata()?.len() > 0;
+
+        let wtr = csv::WriterBuilder::new()
+            .has_headers(!has_content)
+            .from_writer(BufWriter::new(file));
+        Ok(SecurityReport {
+            wtr,
+            count: 0,
+            ids: ClientIds::default(),
+        })
+    }
+
+    pub fn client_ids(mut self, ids: ClientIds) -> Self {
+        self.ids = ids;
+        self
+    }
+
+    pub fn record(&mut self, engine: &Engine, result: &RowResult) -> csv::Result<()> {
+        let (Outcome::Rejected(reason), Some(tx)) = (result.outcome, result.tx) else {
+            return Ok(());
+        };
+        let owner = engine.tx_owner(tx.tx_id());
+        let (anomaly, severity) = match reason {
+            RejectReason::ClientMismatch => (RejectReason::ClientMismatch, Severity::Medium),
+            // The engine checks the client first, a client that doesn't exist
+            // referencing someone else's transaction is the same probe
+            RejectReason::UnknownClient
+                if matches!(tx, Tx::Dispute(_) | Tx::Resolve(_) | Tx::Chargeback(_))
+                    && owner.is_some_and(|owner| owner != tx.client_id()) =>
+            {
+                (RejectReason::ClientMismatch, Severity::Medium)
+            }
+            RejectReason::ConflictingTx => (RejectReason::ConflictingTx, Severity::High),
+            _ => return Ok(()),
+        };
+
+        self.count += 1;
+        self.wtr.serialize(AnomalyRow {
+            line: result.line,
+            r#type: tx.type_name(),
+            client: self.ids.label(tx.client_id()),
+            tx: tx.tx_id(),
+            owner: owner.map(|owner| self.ids.label(owner)),
+            anomaly,
+            severity,
+        })
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.wtr.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+    use tempfile::NamedTempFile;
+    use toy_payments_engine::{
+        pipeline::{InputOffset, results::Results, source::Row},
+        types::transactions::{DepositTx, DisputeTx},
+    };
+
+    #[test]
+    fn test_only_mismatches_and_conflicts_are_reported() {
+        let file = NamedTempFile::new().unwrap();
+        let mut report = SecurityReport::create(file.path(), false).unwrap();
+        let mut engine = Engine::new();
+
+        let txs = [
+            Tx::Deposit(DepositTx::new(1, 7, dec!(10)).unwrap()),
+            Tx::Deposit(DepositTx::new(2, 8, dec!(10)).unwrap()),
+            Tx::Dispute(DisputeTx {
+                client_id: 2,
+                tx_id: 7,
+            }),
+            Tx::Dispute(DisputeTx {
+                client_id: 3,
+                tx_id: 8,
+            }),
+            Tx::Dispute(DisputeTx {
+                client_id: 2,
+                tx_id: 9,
+            }),
+            Tx::Deposit(DepositTx::new(2, 7, dec!(5)).unwrap()),
+        ];
+        let rows = |txs: &[Tx]| {
+            let rows = txs.iter().enumerate().map(|(i, tx)| Row {
+                line: Some(i as u64 + 2),
+                tx: Some(*tx),
+                timestamp: None,
+                seq: None,
+                position: InputOffset::default(),
+            });
+            rows.collect::<Vec<_>>().into_iter()
+        };
+        let mut results = Results::new(rows(&txs), &mut engine);
+        while let Some(result) = results.next() {
+            report.record(results.engine(), &result).unwrap();
+        }
+        report.flush().unwrap();
+
+        assert_eq!(report.count(), 3);
+        assert_eq!(
+            std::fs::read_to_string(file.path()).unwrap(),
+            "\
+line,type,client,tx,owner,anomaly,severity
+4,dispute,2,7,1,client_mismatch,medium
+5,dispute,3,8,2,client_mismatch,medium
+7,deposit,2,7,1,conflicting_tx,high
+"
+        );
+
+        // A resumed run adds to the report
+        let mut report = SecurityReport::create(file.path(), true).unwrap();
+        let mut engine = Engine::new();
+        let mut results = Results::new(rows(&txs[..3]), &mut engine);
+        while let Some(result) = results.next() {
+            report.record(results.engine(), &result).unwrap();
+        }
+        report.flush().unwrap();
+        let report = std::fs::read_to_string(file.path()).unwrap();
+        assert_eq!(report.matches("line,").count(), 1);
+        assert!(report.ends_with("high\n4,dispute,2,7,1,client_mismatch,medium\n"));
+    }
+}
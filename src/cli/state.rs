@@ -0,0 +1,617 @@
+use std::{
+    error::Error,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    thread,
+};
+
+use rust_decimal::Decimal;
+use toy_payments_engine::{
+    dedupe::TxFilter,
+    engine::{Engine, dispute_state::DisputeState, prior::PriorIndex, snapshot::shard_ranges},
+    types::{
+        client::Client,
+        common::{ClientId, TxId},
+        transactions::DepositTx,
+    },
+};
+
+use crate::cli::encryption::{self, DecryptReader, EncryptWriter, StateKey};
+
+/// First line of the index written in place of a sharded snapshot.
+const SHARD_INDEX_HEADER: &str = "tpe-shards";
+
+/// Saves the engine state to `path`. With more than one shard, `path` becomes
+/// an index listing the shard files `<path>.0`, `<path>.1`, ... which are
+/// written in parallel. Saved over a sharded state, the shards are
+/// `<path>.0.alt`, ... instead, or back, so the files the previous index names
+/// stay intact until the new index replaces it. With a `key` the snapshot
+/// files are encrypted, the index only names them and isn't.
+pub fn save_state(
+    engine: &Engine,
+    path: &Path,
+    shards: u16,
+    key: Option<&StateKey>,
+) -> Result<(), Box<dyn Error>> {
+    if shards <= 1 {
+        replace_state(engine, path, key)?;
+        return Ok(());
+    }
+
+    let previous = shard_index(path).unwrap_or_default();
+    let alt = previous.contains(&shard_path(path, 0, false));
+    let ranges = shard_ranges(shards);
+    let shard_paths: Vec<PathBuf> = (0..ranges.len())
+        .map(|i| shard_path(path, i, alt))
+        .collect();
+
+    thread::scope(|scope| {
+        let handles: Vec<_> = ranges
+            .into_iter()
+            .zip(&shard_paths)
+            .enumerate()
+            .map(|(i, (range, shard_path))| {
+                scope.spawn(move || {
+                    replace_file(shard_path, |tmp| {
+                        create_snapshot(tmp, key, |w| engine.write_snapshot_shard(w, range, i == 0))
+                    })
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .try_for_each(|handle| handle.join().expect("snapshot writer panicked"))
+    })?;
+
+    // Written last, so an index only ever points at complete shards
+    let mut index = format!("{SHARD_INDEX_HEADER} {}\n", shard_paths.len());
+    for shard_path in &shard_paths {
+        let name = shard_path.file_name().unwrap_or_default();
+        index.push_str(&name.to_string_lossy());
+        index.push('\n');
+    }
+    replace_file(path, |tmp| {
+        let mut file = File::create(tmp)?;
+        file.write_all(index.as_bytes())?;
+        file.sync_all()
+    })?;
+
+    // Nothing names the previous shards any more
+    for stale in previous.iter().filter(|p| !shard_paths.contains(p)) {
+        if let Err(err) = fs::remove_file(stale)
+            && err.kind() != io::ErrorKind::NotFound
+        {
+            eprintln!("warning: {}: {err}", stale.display());
+        }
+    }
+    Ok(())
+}
+
+/// The shard files the index at `path` lists, `None` if there is no index.
+fn shard_index(path: &Path) -> Option<Vec<PathBuf>> {
+    let index = fs::read_to_string(path).ok()?;
+    let mut lines = index.lines();
+    if !lines.next()?.starts_with(SHARD_INDEX_HEADER) {
+        return None;
+    }
+    let dir = path.parent().unwrap_or(Path::new(""));
+    Some(lines.map(|name| dir.join(name)).collect())
+}
+
+/// Loads a snapshot saved by `save_state`, reading the shards of a sharded
+/// one in parallel. Encrypted snapshots need the `key` they were saved with.
+pub fn load_state(path: &Path, key: Option<&StateKey>) -> Result<Engine, Box<dyn Error>> {
+    let mut file = BufReader::new(File::open(path)?);
+    if !file.fill_buf()?.starts_with(SHARD_INDEX_HEADER.as_bytes()) {
+        return Ok(Engine::read_snapshot(open_snapshot(file, path, key)?)?);
+    }
+
+    let mut index = String::new();
+    file.read_to_string(&mut index)?;
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let shard_paths: Vec<PathBuf> = index.lines().skip(1).map(|name| dir.join(name)).collect();
+
+    let shards = thread::scope(|scope| {
+        let handles: Vec<_> = shard_paths
+            .iter()
+            .map(|shard_path| {
+                scope.spawn(move || {
+                    let file = File::open(shard_path).map_err(|err| {
+                        io::Error::new(err.kind(), format!("{}: {err}", shard_path.display()))
+                    })?;
+                    Engine::read_snapshot(open_snapshot(BufReader::new(file), shard_path, key)?)
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("snapshot reader panicked"))
+            .collect::<io::Result<Vec<_>>>()
+    })?;
+
+    let mut engine = Engine::new();
+    for shard in shards {
+        engine.merge_shard(shard);
+    }
+    Ok(engine)
+}
+
+/// Opens an engine with the balances of a previous run's CSV output (plain
+/// number format, real client ids), for a run that carries on from them.
+pub fn load_opening_balances(path: &Path) -> Result<Engine, Box<dyn Error>> {
+    let mut rdr = csv::Reader::from_path(path)?;
+    let mut engine = Engine::new();
+    for client in rdr.deserialize::<Client>() {
+        let client = client.map_err(|err| format!("{}: {err}", path.display()))?;
+        engine
+            .open_balance(&client)
+            .map_err(|err| format!("{}: {err}", path.display()))?;
+    }
+    Ok(engine)
+}
+
+/// A row of `--prior-deposits`.
+#[derive(serde::Deserialize)]
+struct PriorDeposit {
+    client: ClientId,
+    tx: TxId,
+    // Parsed by hand, serde would read `1.5` through an `f64`
+    amount: String,
+    #[serde(default)]
+    state: Option<String>,
+}
+
+/// Registers the deposits of earlier periods listed in `path` (`client`, `tx`,
+/// `amount` and an optional `state`, `normal` when empty) so that this run's
+/// disputes can name them. Returns how many there were.
+pub fn load_prior_deposits(engine: &mut Engine, path: &Path) -> Result<u64, Box<dyn Error>> {
+    let mut rdr = csv::Reader::from_path(path)?;
+    let mut count = 0;
+    for row in rdr.deserialize::<PriorDeposit>() {
+        let at = |err: &dyn std::fmt::Display| format!("{}: {err}", path.display());
+        let row = row.map_err(|err| at(&err))?;
+        let amount: Decimal = row.amount.parse().map_err(|err| at(&err))?;
+        let state = match row.state.as_deref() {
+            None | Some("") => DisputeState::Normal,
+            Some(state) => state.parse().map_err(|err| at(&err))?,
+        };
+        let deposit_tx = DepositTx::new(row.client, row.tx, amount).map_err(|_| {
+            at(&format_args!(
+                "tx {}: amount {amount} is not positive with at most 4 decimal places",
+                row.tx
+            ))
+        })?;
+        engine
+            .add_prior_deposit(deposit_tx, state)
+            .map_err(|err| at(&err))?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Maps the deposit index at `path` for `Engine::set_prior_index`. An
+/// encrypted index is decrypted into memory instead, which needs `key`.
+pub fn load_deposit_index(
+    path: &Path,
+    key: Option<&StateKey>,
+) -> Result<PriorIndex, Box<dyn Error>> {
+    let mut file = BufReader::new(File::open(path)?);
+    let index = if encryption::is_encrypted(file.fill_buf()?) {
+        let mut bytes = Vec::new();
+        open_snapshot(file, path, key)?.read_to_end(&mut bytes)?;
+        PriorIndex::new(bytes)
+    } else {
+        // SAFETY: indexes are only ever replaced by a rename, never written in place
+        let map = unsafe { memmap2::Mmap::map(file.get_ref()) }?;
+        PriorIndex::new(map)
+    };
+    index.map_err(|err| format!("{}: {err}", path.display()).into())
+}
+
+/// Saves the engine's deposit index under a temporary name and renames it to
+/// `path`, so a run mapping the previous index at `path` keeps reading it.
+/// Encrypted like the state when there is a `key`. Returns the number of
+/// deposits in it.
+pub fn save_deposit_index(
+    engine: &Engine,
+    path: &Path,
+    key: Option<&StateKey>,
+) -> Result<usize, Box<dyn Error>> {
+    let mut count = 0;
+    replace_file(path, |tmp| {
+        create_snapshot(tmp, key, |w| {
+            count = engine.write_prior_index(w)?;
+            Ok(())
+        })
+    })?;
+    Ok(count)
+}
+
+fn create_snapshot(
+    path: &Path,
+    key: Option<&StateKey>,
+    write: impl FnOnce(&mut dyn Write) -> io::Result<()>,
+) -> io::Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    let file = match key {
+        Some(key) => {
+            let mut w = EncryptWriter::new(file, key)?;
+            write(&mut w)?;
+            w.finish()?
+        }
+        None => {
+            let mut w = file;
+            write(&mut w)?;
+            w
+        }
+    };
+    file.into_inner()
+        .map_err(|err| err.into_error())?
+        .sync_all()
+}
+
+/// Writes `path` under a temporary name, synced to disk, and renames it into
+/// place, so `path` holds either the complete old file or the complete new one.
+fn replace_file<T>(path: &Path, write: impl FnOnce(&Path) -> io::Result<T>) -> io::Result<T> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let value = write(&tmp)?;
+    fs::rename(&tmp, path)?;
+    Ok(value)
+}
+
+/// Saves an unsharded snapshot under a temporary name and renames it to
+/// `path`, so `path` always holds a complete snapshot.
+pub fn replace_state(
+    engine: &Engine,
+    path: &Path,
+    key: Option<&StateKey>,
+) -> Result<(), Box<dyn Error>> {
+    replace_file(path, |tmp| {
+        create_snapshot(tmp, key, |w| engine.write_snapshot(w))
+    })?;
+    Ok(())
+}
+
+/// The snapshot in `file`, decrypted if it was saved encrypted.
+fn open_snapshot(
+    mut file: BufReader<File>,
+    path: &Path,
+    key: Option<&StateKey>,
+) -> io::Result<Box<dyn Read>> {
+    if !encryption::is_encrypted(file.fill_buf()?) {
+        return Ok(Box::new(file));
+    }
+    let with_path =
+        |err: io::Error| io::Error::new(err.kind(), format!("{}: {err}", path.display()));
+    match key {
+        Some(key) => Ok(Box::new(BufReader::new(
+            DecryptReader::new(file, key).map_err(with_path)?,
+        ))),
+        None => Err(with_path(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "the snapshot is encrypted, pass --state-key-file or set {}",
+                encryption::KEY_ENV
+            ),
+        ))),
+    }
+}
+
+/// Loads the `--dedupe` filter, or creates an empty one sized by
+/// `capacity` and `fp_rate` on the first run.
+pub fn load_filter(path: &Path, capacity: usize, fp_rate: f64) -> Result<TxFilter, Box<dyn Error>> {
+    match File::open(path) {
+        Ok(file) => TxFilter::read(BufReader::new(file))
+            .map_err(|err| From::from(format!("{}: {err}", path.display()))),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            Ok(TxFilter::new(capacity as u64, fp_rate))
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Saves the `--dedupe` filter under a temporary name and renames it to
+/// `path`, so a failed save leaves the previous filter intact.
+pub fn save_filter(filter: &TxFilter, path: &Path) -> Result<(), Box<dyn Error>> {
+    replace_file(path, |tmp| {
+        let mut w = BufWriter::new(File::create(tmp)?);
+        filter.write(&mut w)?;
+        w.into_inner().map_err(|err| err.into_error())?.sync_all()
+    })?;
+    Ok(())
+}
+
+fn shard_path(path: &Path, shard: usize, alt: bool) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{shard}"));
+    if alt {
+        name.push(".alt");
+    }
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+    use toy_payments_engine::{
+        engine::{
+            amount::AmountContext,
+            config::{EngineConfig, TxKeys},
+        },
+        types::transactions::{ChargebackTx, DepositTx, DisputeTx, Tx, WithdrawalTx},
+    };
+
+    use crate::cli::output::Balances;
+
+    #[test]
+    fn test_sharded_state_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("engine.state");
+
+        let mut engine = Engine::new();
+        for client_id in [1, 20_000, 40_000, 60_000] {
+            engine
+                .process_tx(Tx::Deposit(
+                    DepositTx::new(client_id, u32::from(client_id), dec!(1.5)).unwrap(),
+                ))
+                .unwrap();
+        }
+
+        save_state(&engine, &path, 4, None).unwrap();
+        assert!(dir.path().join("engine.state.3").exists());
+
+        let restored = load_state(&path, None).unwrap();
+        assert_eq!(restored.clients_iter().len(), 4);
+        assert_eq!(restored.house(), engine.house());
+        assert_eq!(restored.client(60_000).unwrap().available, dec!(1.5));
+    }
+
+    #[test]
+    fn test_failed_save_keeps_the_previous_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("engine.state");
+        let mut engine = Engine::new();
+        engine
+            .process_tx(Tx::Deposit(DepositTx::new(1, 1, dec!(1)).unwrap()))
+            .unwrap();
+        save_state(&engine, &path, 1, None).unwrap();
+        assert!(!dir.path().join("engine.state.tmp").exists());
+
+        engine
+            .process_tx(Tx::Deposit(DepositTx::new(2, 2, dec!(1)).unwrap()))
+            .unwrap();
+        fs::create_dir(dir.path().join("engine.state.tmp")).unwrap();
+        assert!(save_state(&engine, &path, 1, None).is_err());
+        assert_eq!(load_state(&path, None).unwrap().clients_iter().len(), 1);
+    }
+
+    #[test]
+    fn test_filter_replaced_whole() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dedupe.bin");
+        let mut filter = load_filter(&path, 100, 0.001).unwrap();
+        filter.insert(1);
+        save_filter(&filter, &path).unwrap();
+        filter.insert(2);
+        save_filter(&filter, &path).unwrap();
+
+        let loaded = load_filter(&path, 100, 0.001).unwrap();
+        assert!(loaded.contains(1) && loaded.contains(2));
+        assert!(!dir.path().join("dedupe.bin.tmp").exists());
+        // A save that can't be written leaves the previous filter
+        fs::create_dir(dir.path().join("dedupe.bin.tmp")).unwrap();
+        assert!(save_filter(&filter, &path).is_err());
+        assert!(load_filter(&path, 100, 0.001).unwrap().contains(2));
+    }
+
+    #[test]
+    fn test_sharded_state_saved_over_itself() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("engine.state");
+        let shard = |name: &str| dir.path().join(format!("engine.state.{name}"));
+        let mut engine = Engine::new();
+        let deposit = |client_id: ClientId, tx_id| {
+            Tx::Deposit(DepositTx::new(client_id, tx_id, dec!(1)).unwrap())
+        };
+        engine.process_tx(deposit(1, 1)).unwrap();
+        save_state(&engine, &path, 2, None).unwrap();
+        let first = fs::read(shard("0")).unwrap();
+
+        // The previous shards are left alone while the new ones are written
+        engine.process_tx(deposit(40_000, 2)).unwrap();
+        save_state(&engine, &path, 2, None).unwrap();
+        assert!(shard("0.alt").exists() && shard("1.alt").exists());
+        assert!(!shard("0").exists() && !shard("1").exists());
+        assert_ne!(fs::read(shard("0.alt")).unwrap(), first);
+        assert_eq!(load_state(&path, None).unwrap().clients_iter().len(), 2);
+
+        engine.process_tx(deposit(2, 3)).unwrap();
+        save_state(&engine, &path, 3, None).unwrap();
+        assert!(shard("2").exists() && !shard("0.alt").exists());
+        let restored = load_state(&path, None).unwrap();
+        assert_eq!(restored.clients_iter().len(), 3);
+        assert_eq!(restored.house(), engine.house());
+        let leftovers: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.ends_with(".tmp"))
+            .collect();
+        assert!(leftovers.is_empty(), "{leftovers:?}");
+    }
+
+    #[test]
+    fn test_opening_balances_from_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("balances.csv");
+
+        let mut engine = Engine::new();
+        let txs = [
+            Tx::Deposit(DepositTx::new(1, 1, dec!(10.25)).unwrap()),
+            Tx::Withdrawal(WithdrawalTx::new(1, 2, dec!(0.2500)).unwrap()),
+            Tx::Deposit(DepositTx::new(2, 3, dec!(3)).unwrap()),
+            Tx::Dispute(DisputeTx {
+                client_id: 2,
+                tx_id: 3,
+            }),
+            Tx::Chargeback(ChargebackTx {
+                client_id: 2,
+                tx_id: 3,
+            }),
+        ];
+        for tx in txs {
+            engine.process_tx(tx).unwrap();
+        }
+        let balances = Balances {
+            extended: true,
+            ..Balances::default()
+        };
+        balances
+            .write(File::create(&path).unwrap(), &engine)
+            .unwrap();
+
+        let opened = load_opening_balances(&path).unwrap();
+        assert_eq!(opened.totals(), engine.totals());
+        let client = &opened.client(1).unwrap();
+        assert_eq!(client.available, dec!(10.00));
+        assert_eq!(client.available.scale(), 4);
+        assert!(opened.client(2).unwrap().locked);
+
+        // Held funds would have nothing to release them
+        engine
+            .process_tx(Tx::Dispute(DisputeTx {
+                client_id: 1,
+                tx_id: 1,
+            }))
+            .unwrap();
+        balances
+            .write(File::create(&path).unwrap(), &engine)
+            .unwrap();
+        let err = load_opening_balances(&path).err().unwrap();
+        assert!(err.to_string().contains("held funds"), "{err}");
+    }
+
+    #[test]
+    fn test_encrypted_state_needs_its_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("engine.state");
+        let key = StateKey::parse(&"0f".repeat(32)).unwrap();
+
+        let mut engine = Engine::new();
+        engine
+            .process_tx(Tx::Deposit(DepositTx::new(7, 1, dec!(2.5)).unwrap()))
+            .unwrap();
+
+        for shards in [1, 2] {
+            save_state(&engine, &path, shards, Some(&key)).unwrap();
+            let snapshot = match shards {
+                1 => path.clone(),
+                _ => shard_path(&path, 0, false),
+            };
+            assert!(encryption::is_encrypted(&fs::read(snapshot).unwrap()));
+
+            let err = load_state(&path, None).err().unwrap();
+            assert!(err.to_string().contains("encrypted"), "{err}");
+            let other = StateKey::parse(&"f0".repeat(32)).unwrap();
+            assert!(load_state(&path, Some(&other)).is_err());
+
+            let restored = load_state(&path, Some(&key)).unwrap();
+            assert_eq!(restored.client(7).unwrap().available, dec!(2.5));
+        }
+    }
+
+    #[test]
+    fn test_prior_deposits_from_csv() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("prior.csv");
+        fs::write(
+            &path,
+            "client,tx,amount,state\n1,1,1.5,\n1,2,2.12345678,resolved\n",
+        )
+        .unwrap();
+
+        let mut engine = Engine::new();
+        let err = load_prior_deposits(&mut engine, &path).unwrap_err();
+        assert!(err.to_string().contains("tx 2: amount 2.12345678"), "{err}");
+
+        fs::write(&path, "client,tx,amount,state\n1,1,1.5,\n1,2,2,resolved\n").unwrap();
+        let mut engine = Engine::new();
+        assert_eq!(load_prior_deposits(&mut engine, &path).unwrap(), 2);
+        assert_eq!(engine.tx_state(1, 1), Some(DisputeState::Normal));
+        assert_eq!(engine.tx_state(1, 2), Some(DisputeState::Resolved));
+
+        // Keyed by the run's config, set before they are loaded
+        fs::write(&path, "client,tx,amount\n1,1,5\n2,1,7\n").unwrap();
+        let mut engine = Engine::with_config(EngineConfig {
+            tx_keys: TxKeys::PerClient,
+            ..EngineConfig::default()
+        });
+        assert_eq!(load_prior_deposits(&mut engine, &path).unwrap(), 2);
+        assert_eq!(engine.tx_state(2, 1), Some(DisputeState::Normal));
+        let mut engine = Engine::with_config(EngineConfig {
+            amounts: AmountContext {
+                max_magnitude: Some(dec!(6)),
+                ..AmountContext::default()
+            },
+            ..EngineConfig::default()
+        });
+        let err = load_prior_deposits(&mut engine, &path).unwrap_err();
+        assert!(
+            err.to_string().contains("tx 1 has an invalid amount"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn test_deposit_index_replaced_while_mapped() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("deposits.idx");
+
+        let mut engine = Engine::new();
+        engine
+            .process_tx(Tx::Deposit(DepositTx::new(1, 1, dec!(2)).unwrap()))
+            .unwrap();
+        assert_eq!(save_deposit_index(&engine, &path, None).unwrap(), 1);
+
+        let mut next = Engine::new();
+        next.set_prior_index(load_deposit_index(&path, None).unwrap());
+        next.process_tx(Tx::Deposit(DepositTx::new(1, 2, dec!(3)).unwrap()))
+            .unwrap();
+        next.process_tx(Tx::Dispute(DisputeTx {
+            client_id: 1,
+            tx_id: 1,
+        }))
+        .unwrap();
+        assert_eq!(save_deposit_index(&next, &path, None).unwrap(), 2);
+
+        let mut last = Engine::new();
+        last.set_prior_index(load_deposit_index(&path, None).unwrap());
+        assert_eq!(last.prior_deposits(), 2);
+        fs::write(&path, b"TPEI").unwrap();
+        assert!(load_deposit_index(&path, None).is_err());
+    }
+
+    #[test]
+    fn test_deposit_index_encrypted_with_the_state_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("deposits.idx");
+        let key = StateKey::parse(&"0f".repeat(32)).unwrap();
+
+        let mut engine = Engine::new();
+        engine
+            .process_tx(Tx::Deposit(DepositTx::new(1, 1, dec!(2)).unwrap()))
+            .unwrap();
+        assert_eq!(save_deposit_index(&engine, &path, Some(&key)).unwrap(), 1);
+        assert!(encryption::is_encrypted(&fs::read(&path).unwrap()));
+
+        let err = load_deposit_index(&path, None).err().unwrap();
+        assert!(err.to_string().contains("encrypted"), "{err}");
+        let other = StateKey::parse(&"f0".repeat(32)).unwrap();
+        assert!(load_deposit_index(&path, Some(&other)).is_err());
+        let index = load_deposit_index(&path, Some(&key)).unwrap();
+        assert_eq!(index.get(1, 1).unwrap().unwrap().0.amount(), dec!(2));
+    }
+}
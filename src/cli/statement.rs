@@ -0,0 +1,366 @@
+//! One client's activity from a `--ledger` file as OFX or QIF, for importing
+//! into personal finance tools. Only applied transactions are listed, each
+//! with the change it made to the client's total: deposits and withdrawals
+//! their amount, chargebacks what they took back, disputes and resolves
+//! nothing (they only move funds between available and held).
+
+use std::{
+    error::Error,
+    fmt,
+    io::{self, Write},
+    path::PathBuf,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use clap::Args;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Deserializer, de};
+use toy_payments_engine::{
+    engine::amount::CurrencyRules,
+    types::common::{ClientId, TxId},
+};
+
+use crate::cli::output::{civil_date, parse_currency, read_currency_rules};
+
+/// Export format of a statement.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatementFormat {
+    /// OFX 1.02 (SGML), read by GnuCash, Quicken, Money and most banks' tools
+    Ofx,
+    /// Quicken Interchange Format, bank account type
+    Qif,
+}
+
+impl StatementFormat {
+    pub const ALL: [StatementFormat; 2] = [StatementFormat::Ofx, StatementFormat::Qif];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            StatementFormat::Ofx => "ofx",
+            StatementFormat::Qif => "qif",
+        }
+    }
+}
+
+impl fmt::Display for StatementFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for StatementFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        StatementFormat::ALL
+            .into_iter()
+            .find(|format| format.name() == s)
+            .ok_or_else(|| format!("unknown statement format `{s}`, expected ofx or qif"))
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct StatementArgs {
+    /// Ledger written with `--ledger`
+    #[arg(long, value_name = "PATH")]
+    pub ledger: PathBuf,
+
+    /// Client whose activity to export
+    #[arg(long)]
+    pub client: ClientId,
+
+    /// ofx or qif
+    #[arg(long, value_name = "FORMAT", default_value_t = StatementFormat::Ofx)]
+    pub format: StatementFormat,
+
+    /// Currency code of the amounts in OFX
+    #[arg(long, value_name = "CODE", default_value = "XXX", value_parser = parse_currency)]
+    pub currency: String,
+
+    /// TOML file of rounding and minor unit overrides, see `tpe --currency-rules`.
+    /// Amounts are rounded to the currency's minor units
+    #[arg(long, value_name = "PATH")]
+    pub currency_rules: Option<PathBuf>,
+
+    /// Date (`YYYY-MM-DD`) every entry is posted on, the ledger has no dates. Defaults to today
+    #[arg(long, value_name = "DATE", value_parser = parse_date)]
+    pub date: Option<Date>,
+}
+
+/// A calendar date, UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Date {
+    year: i64,
+    month: u32,
+    day: u32,
+}
+
+impl Date {
+    fn today() -> Result<Date, Box<dyn Error>> {
+        let days = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() / 86_400;
+        let (year, month, day) = civil_date(days);
+        Ok(Date { year, month, day })
+    }
+}
+
+fn parse_date(value: &str) -> Result<Date, String> {
+    let invalid = || format!("`{value}` is not a date like 2026-10-17");
+    let mut parts = value.splitn(3, '-').map(str::parse::<u32>);
+    let (Some(Ok(year)), Some(Ok(month)), Some(Ok(day))) =
+        (parts.next(), parts.next(), parts.next())
+    else {
+        return Err(invalid());
+    };
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return Err(invalid());
+    }
+    Ok(Date {
+        year: year as i64,
+        month,
+        day,
+    })
+}
+
+/// A ledger row, as written by `LedgerWriter`. Amounts are parsed from their
+/// text, serde would take them through `f64` and lose their scale.
+#[derive(Debug, Deserialize)]
+struct LedgerRow {
+    tx: TxId,
+    client: ClientId,
+    r#type: String,
+    #[serde(deserialize_with = "decimal")]
+    amount: Option<Decimal>,
+    status: String,
+    #[serde(deserialize_with = "decimal")]
+    available: Option<Decimal>,
+    #[serde(deserialize_with = "decimal")]
+    total: Option<Decimal>,
+}
+
+fn decimal<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Decimal>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|value| value.parse().map_err(de::Error::custom))
+        .transpose()
+}
+
+#[derive(Debug, PartialEq)]
+struct Entry {
+    tx: TxId,
+    kind: String,
+    /// Change to the client's total
+    amount: Decimal,
+}
+
+/// The client's applied transactions, and its available and total balances after the last one.
+#[derive(Debug, Default)]
+struct ClientActivity {
+    entries: Vec<Entry>,
+    available: Decimal,
+    total: Decimal,
+}
+
+pub fn run(args: StatementArgs) -> Result<(), Box<dyn Error>> {
+    let date = match args.date {
+        Some(date) => date,
+        None => Date::today()?,
+    };
+    let rdr = csv::Reader::from_path(&args.ledger)?;
+    let mut activity = activity(rdr, args.client)?;
+    let rules = read_currency_rules(args.currency_rules.as_deref())?;
+    round(&mut activity, &rules, &args.currency);
+
+    let mut w = io::BufWriter::new(io::stdout().lock());
+    match args.format {
+        StatementFormat::Ofx => write_ofx(&mut w, &activity, args.client, &args.currency, date)?,
+        StatementFormat::Qif => write_qif(&mut w, &activity, date)?,
+    }
+    w.flush()?;
+    Ok(())
+}
+
+/// Rounds the amounts to the currency's minor units. The total is derived
+/// from the rounded available and held balances, so they still add up.
+fn round(activity: &mut ClientActivity, rules: &CurrencyRules, currency: &str) {
+    for entry in &mut activity.entries {
+        entry.amount = rules.round(entry.amount, currency);
+    }
+    let held = rules.round(activity.total - activity.available, currency);
+    activity.available = rules.round(activity.available, currency);
+    activity.total = activity.available + held;
+}
+
+fn activity<R: io::Read>(
+    mut rdr: csv::Reader<R>,
+    client: ClientId,
+) -> Result<ClientActivity, Box<dyn Error>> {
+    let mut activity = ClientActivity::default();
+    let mut previous_total = None;
+    for row in rdr.deserialize() {
+        let row: LedgerRow = row?;
+        if row.client != client || row.status != "applied" {
+            continue;
+        }
+        // Applied rows always carry the balances
+        let (Some(available), Some(total)) = (row.available, row.total) else {
+            continue;
+        };
+        let amount = match (row.r#type.as_str(), row.amount) {
+            ("deposit", Some(amount)) => amount,
+            ("withdrawal", Some(amount)) => -amount,
+            _ => total - previous_total.unwrap_or(total),
+        };
+        previous_total = Some(total);
+        activity.entries.push(Entry {
+            tx: row.tx,
+            kind: row.r#type,
+            amount,
+        });
+        activity.available = available;
+        activity.total = total;
+    }
+    Ok(activity)
+}
+
+fn write_ofx<W: Write>(
+    w: &mut W,
+    activity: &ClientActivity,
+    client: ClientId,
+    currency: &str,
+    date: Date,
+) -> io::Result<()> {
+    let date = format!("{:04}{:02}{:02}", date.year, date.month, date.day);
+    write!(
+        w,
+        "OFXHEADER:100\r\nDATA:OFXSGML\r\nVERSION:102\r\nSECURITY:NONE\r\nENCODING:USASCII\r\n\
+         CHARSET:1252\r\nCOMPRESSION:NONE\r\nOLDFILEUID:NONE\r\nNEWFILEUID:NONE\r\n\r\n"
+    )?;
+    writeln!(w, "<OFX>")?;
+    writeln!(w, "<SIGNONMSGSRSV1><SONRS>")?;
+    writeln!(w, "<STATUS><CODE>0<SEVERITY>INFO</STATUS>")?;
+    writeln!(w, "<DTSERVER>{date}<LANGUAGE>ENG")?;
+    writeln!(w, "</SONRS></SIGNONMSGSRSV1>")?;
+    writeln!(w, "<BANKMSGSRSV1><STMTTRNRS>")?;
+    writeln!(w, "<TRNUID>{client}<STATUS><CODE>0<SEVERITY>INFO</STATUS>")?;
+    writeln!(w, "<STMTRS><CURDEF>{currency}")?;
+    writeln!(
+        w,
+        "<BANKACCTFROM><BANKID>TPE<ACCTID>{client}<ACCTTYPE>CHECKING</BANKACCTFROM>"
+    )?;
+    writeln!(w, "<BANKTRANLIST><DTSTART>{date}<DTEND>{date}")?;
+    for entry in &activity.entries {
+        let trntype = match entry.amount.cmp(&Decimal::ZERO) {
+            std::cmp::Ordering::Greater => "CREDIT",
+            std::cmp::Ordering::Less => "DEBIT",
+            std::cmp::Ordering::Equal => "OTHER",
+        };
+        writeln!(w, "<STMTTRN><TRNTYPE>{trntype}<DTPOSTED>{date}")?;
+        // A transaction id repeats for its dispute, resolve and chargeback
+        writeln!(
+            w,
+            "<TRNAMT>{}<FITID>{}-{}<NAME>{}<MEMO>tx {}",
+            entry.amount, entry.tx, entry.kind, entry.kind, entry.tx
+        )?;
+        writeln!(w, "</STMTTRN>")?;
+    }
+    writeln!(w, "</BANKTRANLIST>")?;
+    writeln!(
+        w,
+        "<LEDGERBAL><BALAMT>{}<DTASOF>{date}</LEDGERBAL>",
+        activity.total
+    )?;
+    writeln!(
+        w,
+        "<AVAILBAL><BALAMT>{}<DTASOF>{date}</AVAILBAL>",
+        activity.available
+    )?;
+    writeln!(w, "</STMTRS></STMTTRNRS></BANKMSGSRSV1>")?;
+    writeln!(w, "</OFX>")
+}
+
+fn write_qif<W: Write>(w: &mut W, activity: &ClientActivity, date: Date) -> io::Result<()> {
+    writeln!(w, "!Type:Bank")?;
+    for entry in &activity.entries {
+        writeln!(w, "D{:02}/{:02}/{:04}", date.month, date.day, date.year)?;
+        writeln!(w, "T{}", entry.amount)?;
+        writeln!(w, "N{}", entry.tx)?;
+        writeln!(w, "P{}", entry.kind)?;
+        writeln!(w, "^")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    const LEDGER: &str = "\
+tx,client,type,amount,status,available,held,total,locked
+1,1,deposit,10.0,applied,10.0,0,10.0,false
+2,2,deposit,5.00,applied,5.00,0,5.00,false
+3,1,withdrawal,4.0,applied,6.0,0,6.0,false
+4,1,withdrawal,9,insufficient_funds,6.0,0,6.0,false
+1,1,dispute,,applied,-4.0,10.0,6.0,false
+1,1,chargeback,,applied,-4.0,0.0,-4.0,true
+";
+
+    #[test]
+    fn test_activity_follows_the_total() {
+        let activity = activity(csv::Reader::from_reader(LEDGER.as_bytes()), 1).unwrap();
+        let amounts: Vec<_> = activity
+            .entries
+            .iter()
+            .map(|e| (e.tx, e.kind.as_str(), e.amount))
+            .collect();
+        assert_eq!(
+            amounts,
+            vec![
+                (1, "deposit", dec!(10.0)),
+                (3, "withdrawal", dec!(-4.0)),
+                (1, "dispute", dec!(0)),
+                (1, "chargeback", dec!(-10.0)),
+            ]
+        );
+        assert_eq!(
+            (activity.available, activity.total),
+            (dec!(-4.0), dec!(-4.0))
+        );
+    }
+
+    #[test]
+    fn test_qif_and_ofx_entries() {
+        let activity = activity(csv::Reader::from_reader(LEDGER.as_bytes()), 2).unwrap();
+        let date = parse_date("2026-10-17").unwrap();
+
+        let mut qif = Vec::new();
+        write_qif(&mut qif, &activity, date).unwrap();
+        assert_eq!(
+            String::from_utf8(qif).unwrap(),
+            "!Type:Bank\nD10/17/2026\nT5.00\nN2\nPdeposit\n^\n"
+        );
+
+        let mut ofx = Vec::new();
+        write_ofx(&mut ofx, &activity, 2, "EUR", date).unwrap();
+        let ofx = String::from_utf8(ofx).unwrap();
+        assert!(ofx.starts_with("OFXHEADER:100\r\n"));
+        assert!(
+            ofx.contains(
+                "<STMTTRN><TRNTYPE>CREDIT<DTPOSTED>20261017\n<TRNAMT>5.00<FITID>2-deposit"
+            )
+        );
+        assert!(ofx.contains("<LEDGERBAL><BALAMT>5.00<DTASOF>20261017</LEDGERBAL>"));
+        assert!(parse_date("2026-13-01").is_err());
+    }
+
+    #[test]
+    fn test_rounded_balances_add_up() {
+        let mut activity = ClientActivity {
+            entries: Vec::new(),
+            available: dec!(0.5),
+            total: dec!(1),
+        };
+        round(&mut activity, &CurrencyRules::default(), "JPY");
+        assert_eq!((activity.available, activity.total), (dec!(0), dec!(0)));
+    }
+}
@@ -0,0 +1,102 @@
+use toy_payments_engine::{
+    engine::Engine,
+    pipeline::results::{Outcome, RowResult},
+};
+
+/// Counters for the end-of-run summary printed with `--summary`.
+#[derive(Debug, Default)]
+pub struct RunSummary {
+    pub rows: u64,
+    pub applied: u64,
+    pub rejected: u64,
+    /// Rows of a type disabled with `--disable`
+    pub skipped: u64,
+}
+
+impl RunSummary {
+    pub fn record(&mut self, result: &RowResult) {
+        // Counted as a rejected (`queued`) row when it was read
+        if result.dequeued {
+            self.rejected -= 1;
+        } else {
+            self.rows += 1;
+        }
+        match result.outcome {
+            Outcome::Applied => self.applied += 1,
+            Outcome::Rejected(_) => self.rejected += 1,
+            Outcome::Skipped => self.skipped += 1,
+        }
+    }
+
+    pub fn lines(&self, engine: &Engine) -> Vec<String> {
+        let stats = engine.stats();
+        let (totals, house) = (&stats.totals, &stats.house);
+
+        let mut lines = vec![
+            format!(
+                "rows: {} (applied {}, rejected {}, skipped {})",
+                self.rows, self.applied, self.rejected, self.skipped
+            ),
+            format!("clients: {} ({} locked)", totals.clients, totals.locked),
+            format!(
+                "house: deposited {}, withdrawn {}, held {}, charged back {}",
+                house.deposited, house.withdrawn, house.held, house.charged_back
+            ),
+        ];
+        if stats.queued > 0 {
+            lines.push(format!(
+                "queued: {} rows waiting for their deposit",
+                stats.queued
+            ));
+        }
+        lines
+    }
+
+    pub fn print(&self, engine: &Engine) {
+        for line in self.lines(engine) {
+            eprintln!("{line}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+    use toy_payments_engine::types::transactions::{ChargebackTx, DepositTx, DisputeTx, Tx};
+
+    #[test]
+    fn test_summary_lines() {
+        let mut engine = Engine::new();
+        let txs = [
+            Tx::Deposit(DepositTx::new(1, 1, dec!(10)).unwrap()),
+            Tx::Dispute(DisputeTx {
+                client_id: 1,
+                tx_id: 1,
+            }),
+            Tx::Chargeback(ChargebackTx {
+                client_id: 1,
+                tx_id: 1,
+            }),
+        ];
+        for tx in txs {
+            engine.process_tx(tx).unwrap();
+        }
+
+        let summary = RunSummary {
+            rows: 4,
+            applied: 3,
+            rejected: 1,
+            skipped: 0,
+        };
+
+        assert_eq!(
+            summary.lines(&engine),
+            vec![
+                "rows: 4 (applied 3, rejected 1, skipped 0)",
+                "clients: 1 (1 locked)",
+                "house: deposited 10, withdrawn 0, held 0, charged back 10",
+            ]
+        );
+    }
+}
@@ -0,0 +1,137 @@
+use std::{cmp::Reverse, collections::BinaryHeap, io::Write};
+
+use rust_decimal::Decimal;
+use toy_payments_engine::types::client::{Balance, Client};
+
+use crate::cli::{output::OutputScale, pseudonym::ClientIds};
+
+/// A client in the heap, larger balances first and the lower id on ties.
+struct Ranked<'a> {
+    balance: Decimal,
+    client: &'a Client,
+}
+
+impl Ord for Ranked<'_> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.balance
+            .cmp(&other.balance)
+            .then_with(|| other.client.id.cmp(&self.client.id))
+    }
+}
+
+impl PartialOrd for Ranked<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Ranked<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for Ranked<'_> {}
+
+/// The `n` clients with the largest `by` balance, largest first. Keeps a
+/// min-heap of at most `n` clients instead of sorting all of them.
+pub fn top_clients<'a>(
+    clients: impl IntoIterator<Item = &'a Client>,
+    n: usize,
+    by: Balance,
+) -> Vec<&'a Client> {
+    if n == 0 {
+        return Vec::new();
+    }
+
+    let mut heap = BinaryHeap::with_capacity(n + 1);
+    for client in clients {
+        heap.push(Reverse(Ranked {
+            balance: by.of(client),
+            client,
+        }));
+        if heap.len() > n {
+            heap.pop();
+        }
+    }
+
+    heap.into_sorted_vec()
+        .into_iter()
+        .map(|Reverse(ranked)| ranked.client)
+        .collect()
+}
+
+/// Writes the ranked clients as CSV: `rank,client,available,held,total,locked`.
+pub fn write<W: Write>(
+    w: W,
+    clients: &[&Client],
+    scale: OutputScale,
+    ids: &ClientIds,
+) -> csv::Result<()> {
+    let mut wtr = csv::Writer::from_writer(w);
+    wtr.write_record(["rank", "client", "available", "held", "total", "locked"])?;
+    for (rank, client) in clients.iter().enumerate() {
+        let client = scale.client(client);
+        wtr.write_record([
+            (rank + 1).to_string(),
+            ids.label(client.id).to_string(),
+            client.available.to_string(),
+            client.held.to_string(),
+            client.total.to_string(),
+            client.locked.to_string(),
+        ])?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn client(id: u16, held: Decimal) -> Client {
+        Client {
+            held,
+            total: held,
+            ..Client::new(id)
+        }
+    }
+
+    #[test]
+    fn test_top_clients_by_held() {
+        let clients = [
+            client(1, dec!(5)),
+            client(2, dec!(50)),
+            client(3, dec!(7)),
+            client(4, dec!(50)),
+            client(5, dec!(0)),
+        ];
+
+        let top: Vec<u16> = top_clients(&clients, 3, Balance::Held)
+            .iter()
+            .map(|c| c.id)
+            .collect();
+        assert_eq!(top, vec![2, 4, 3]);
+
+        assert_eq!(top_clients(&clients, 10, Balance::Held).len(), 5);
+        assert!(top_clients(&clients, 0, Balance::Held).is_empty());
+    }
+
+    #[test]
+    fn test_write_ranks_from_one() {
+        let clients = [client(9, dec!(1.5))];
+        let mut buf = Vec::new();
+        write(
+            &mut buf,
+            &[&clients[0]],
+            OutputScale(Some(2)),
+            &ClientIds::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "rank,client,available,held,total,locked\n1,9,0.00,1.50,1.50,false\n"
+        );
+    }
+}
@@ -0,0 +1,314 @@
+use std::{error::Error, fmt, io, path::PathBuf};
+
+use clap::Args;
+use rust_decimal::Decimal;
+use toy_payments_engine::{
+    engine::{Engine, dispute_state::DisputeState, rules::Rules},
+    pipeline::source::{CsvSource, Row},
+    types::{
+        common::{ClientId, TxId},
+        transactions::Tx,
+    },
+};
+
+use crate::cli::EngineArgs;
+
+#[derive(Debug, Args)]
+pub struct TraceArgs {
+    /// Client whose rows to print
+    #[arg(long, value_name = "ID", required_unless_present = "tx")]
+    pub client: Option<ClientId>,
+
+    /// Transaction whose case file to print: the row that made it and every
+    /// dispute, resolve and chargeback naming it
+    #[arg(long, value_name = "ID", conflicts_with = "client")]
+    pub tx: Option<TxId>,
+
+    /// Rule set to replay the input under
+    #[arg(long, value_name = "VERSION", default_value_t = Rules::V1)]
+    pub rules: Rules,
+
+    #[command(flatten)]
+    pub engine: EngineArgs,
+
+    /// Transactions CSV to replay
+    pub input: PathBuf,
+}
+
+/// Which rows `trace` prints.
+#[derive(Debug, Clone, Copy)]
+enum Filter {
+    Client(ClientId),
+    Tx(TxId),
+}
+
+impl Filter {
+    fn matches(&self, tx: &Tx) -> bool {
+        match self {
+            Filter::Client(client) => tx.client_id() == *client,
+            Filter::Tx(tx_id) => tx.tx_id() == *tx_id,
+        }
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Filter::Client(client) => write!(f, "client {client}"),
+            Filter::Tx(tx_id) => write!(f, "tx {tx_id}"),
+        }
+    }
+}
+
+/// One traced row: what the engine decided and where it left the client.
+#[derive(Debug, PartialEq, serde::Serialize)]
+struct Step {
+    line: Option<u64>,
+    r#type: &'static str,
+    client: ClientId,
+    tx: TxId,
+    amount: Option<Decimal>,
+    /// `applied` or the reject code
+    outcome: &'static str,
+    /// Dispute state of the transaction `tx` names, `before -> after` when the row changed it
+    state: String,
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+    locked: bool,
+}
+
+/// Replays the whole input on a fresh engine and prints every row of one
+/// client, or naming one transaction, with its outcome and the client's
+/// balances right after it.
+pub fn run(args: TraceArgs) -> Result<(), Box<dyn Error>> {
+    let filter = match (args.client, args.tx) {
+        (_, Some(tx_id)) => Filter::Tx(tx_id),
+        (Some(client), None) => Filter::Client(client),
+        (None, None) => unreachable!("clap requires --client or --tx"),
+    };
+    let mut engine = Engine::with_config(args.engine.config(args.rules));
+
+    let mut wtr = csv::Writer::from_writer(io::stdout());
+    let mut steps = 0;
+    let mut owner = None;
+    let rows = trace(CsvSource::open(&args.input)?, &mut engine, filter, |step| {
+        steps += 1;
+        if matches!(step.r#type, "deposit" | "withdrawal") && step.outcome == "applied" {
+            owner.get_or_insert(step.client);
+        }
+        wtr.serialize(step)
+    })?;
+    wtr.flush()?;
+
+    eprintln!("trace: {steps} of {rows} rows match {filter}");
+    if let Filter::Tx(tx_id) = filter {
+        let status = owner.and_then(|client| engine.tx_state(client, tx_id));
+        match status {
+            Some(state) => eprintln!(
+                "trace: tx {tx_id} of client {} ends {state}",
+                owner.unwrap()
+            ),
+            None => eprintln!("trace: tx {tx_id} was never stored"),
+        }
+    }
+    Ok(())
+}
+
+/// Applies `rows` in order and hands the steps `filter` matches to `emit`,
+/// returning the number of rows read. Rows that couldn't be parsed are skipped.
+fn trace<E>(
+    rows: impl Iterator<Item = Row>,
+    engine: &mut Engine,
+    filter: Filter,
+    mut emit: impl FnMut(Step) -> Result<(), E>,
+) -> Result<u64, E> {
+    let mut count = 0;
+    for row in rows {
+        count += 1;
+        let Some(tx) = row.tx else { continue };
+        if !filter.matches(&tx) {
+            engine.process_tx_at(tx, row.timestamp).ok();
+            continue;
+        }
+
+        let client = tx.client_id();
+        let before = engine.tx_state(client, tx.tx_id());
+        let outcome = match engine.process_tx_at(tx, row.timestamp) {
+            Ok(()) => "applied",
+            Err(reason) => reason.code(),
+        };
+        let after = engine.tx_state(client, tx.tx_id());
+        let balance = engine.client(client);
+        emit(Step {
+            line: row.line,
+            r#type: tx.type_name(),
+            client,
+            tx: tx.tx_id(),
+            amount: amount(tx),
+            outcome,
+            state: state_change(before, after),
+            available: balance.map_or(Decimal::ZERO, |c| c.available),
+            held: balance.map_or(Decimal::ZERO, |c| c.held),
+            total: balance.map_or(Decimal::ZERO, |c| c.total),
+            locked: balance.is_some_and(|c| c.locked),
+        })?;
+    }
+    Ok(count)
+}
+
+fn amount(tx: Tx) -> Option<Decimal> {
+    match tx {
+        Tx::Deposit(tx) => Some(tx.amount()),
+        Tx::Withdrawal(tx) => Some(tx.amount()),
+        Tx::OpeningBalance(tx) => Some(tx.amount()),
+        Tx::Dispute(_) | Tx::Resolve(_) | Tx::Chargeback(_) => None,
+    }
+}
+
+fn state_change(before: Option<DisputeState>, after: Option<DisputeState>) -> String {
+    match (before, after) {
+        (Some(before), Some(after)) if before != after => format!("{before} -> {after}"),
+        (_, Some(state)) | (Some(state), None) => state.to_string(),
+        (None, None) => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+    use std::{convert::Infallible, io::Write};
+
+    #[test]
+    fn test_trace_follows_one_client() {
+        let mut input = tempfile::NamedTempFile::new().unwrap();
+        write!(
+            input,
+            "type,client,tx,amount\n\
+             deposit,1,1,10\n\
+             deposit,2,2,5\n\
+             withdrawal,1,3,20\n\
+             dispute,1,1,\n\
+             dispute,2,2,\n\
+             chargeback,1,1,\n\
+             deposit,1,4,1\n"
+        )
+        .unwrap();
+
+        let mut engine = Engine::new();
+        let mut steps = Vec::new();
+        let rows = trace(
+            CsvSource::open(input.path()).unwrap(),
+            &mut engine,
+            Filter::Client(1),
+            |step| {
+                steps.push(step);
+                Ok::<_, Infallible>(())
+            },
+        )
+        .unwrap();
+
+        assert_eq!(rows, 7);
+        let trail: Vec<_> = steps
+            .iter()
+            .map(|step| (step.line, step.outcome, step.state.as_str(), step.total))
+            .collect();
+        assert_eq!(
+            trail,
+            vec![
+                (Some(2), "applied", "normal", dec!(10)),
+                (Some(4), "insufficient_funds", "", dec!(10)),
+                (Some(5), "applied", "normal -> under_dispute", dec!(10)),
+                (Some(7), "applied", "under_dispute -> charged_back", dec!(0)),
+                (Some(8), "account_locked", "", dec!(0)),
+            ]
+        );
+        assert!(steps[3].locked);
+        assert_eq!(steps[2].held, dec!(10));
+    }
+
+    #[test]
+    fn test_trace_takes_the_engine_config_flags() {
+        use crate::cli::{Cli, Command};
+        use clap::Parser;
+        use toy_payments_engine::engine::config::{MissingDeposit, TxKeys, UnlockPolicy};
+
+        let cli = Cli::try_parse_from([
+            "tpe",
+            "trace",
+            "--client",
+            "1",
+            "--tx-keys",
+            "per-client",
+            "--missing-deposit",
+            "queue",
+            "--max-amount",
+            "100",
+            "--max-scale",
+            "2",
+            "--max-balance",
+            "50",
+            "--auto-unlock",
+            "30d",
+            "input.csv",
+        ])
+        .unwrap();
+        let Some(Command::Trace(args)) = cli.command else {
+            panic!("not a trace");
+        };
+        let config = args.engine.config(args.rules);
+        assert_eq!(config.tx_keys, TxKeys::PerClient);
+        assert_eq!(config.missing_deposit, MissingDeposit::Queue);
+        assert_eq!(config.amounts.max_magnitude, Some(dec!(100)));
+        assert_eq!(config.amounts.max_scale, Some(2));
+        assert_eq!(config.max_balance, Some(dec!(50)));
+        assert_eq!(config.unlock, UnlockPolicy::AfterDays(30));
+    }
+
+    #[test]
+    fn test_trace_of_a_transaction() {
+        let mut input = tempfile::NamedTempFile::new().unwrap();
+        write!(
+            input,
+            "type,client,tx,amount\n\
+             deposit,1,7,10\n\
+             deposit,1,8,5\n\
+             dispute,1,7,\n\
+             dispute,2,7,\n\
+             resolve,1,7,\n\
+             dispute,1,7,\n\
+             dispute,1,8,\n"
+        )
+        .unwrap();
+
+        let mut engine = Engine::new();
+        let mut steps = Vec::new();
+        trace(
+            CsvSource::open(input.path()).unwrap(),
+            &mut engine,
+            Filter::Tx(7),
+            |step| {
+                steps.push(step);
+                Ok::<_, Infallible>(())
+            },
+        )
+        .unwrap();
+
+        let trail: Vec<_> = steps
+            .iter()
+            .map(|step| (step.r#type, step.client, step.outcome, step.state.as_str()))
+            .collect();
+        assert_eq!(
+            trail,
+            vec![
+                ("deposit", 1, "applied", "normal"),
+                ("dispute", 1, "applied", "normal -> under_dispute"),
+                ("dispute", 2, "unknown_client", ""),
+                ("resolve", 1, "applied", "under_dispute -> resolved"),
+                ("dispute", 1, "not_disputable", "resolved"),
+            ]
+        );
+        assert_eq!(engine.tx_state(1, 7), Some(DisputeState::Resolved));
+    }
+}
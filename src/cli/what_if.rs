@@ -0,0 +1,169 @@
+use std::{error::Error, path::PathBuf};
+
+use clap::Args;
+use rust_decimal::Decimal;
+use toy_payments_engine::{
+    engine::{Engine, config::EngineConfig, rules::Rules},
+    pipeline::{results::Results, source::CsvSource},
+    types::{common::ClientId, transactions::TxType},
+};
+
+use crate::cli::{encryption::StateKey, state::load_state, summary::RunSummary};
+
+#[derive(Debug, Args)]
+pub struct WhatIfArgs {
+    /// State snapshot saved with `--save-state`, left untouched
+    #[arg(long, value_name = "PATH")]
+    pub state: PathBuf,
+
+    /// Key file of an encrypted snapshot (default: the `TPE_STATE_KEY` environment variable)
+    #[arg(long, value_name = "PATH")]
+    pub state_key_file: Option<PathBuf>,
+
+    /// Rule set to apply the proposed rows under
+    #[arg(long, value_name = "VERSION", default_value_t = Rules::V1)]
+    pub rules: Rules,
+
+    /// CSV of proposed disputes, resolves and chargebacks
+    pub input: PathBuf,
+}
+
+/// A client that would go negative or get locked.
+#[derive(Debug, PartialEq, serde::Serialize)]
+struct Impact {
+    client: ClientId,
+    available_before: Decimal,
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+    goes_negative: bool,
+    gets_locked: bool,
+}
+
+/// Applies the proposed rows to an in-memory copy of the state and prints the
+/// clients they would push below zero or lock. Nothing is written back.
+pub fn run(args: WhatIfArgs) -> Result<(), Box<dyn Error>> {
+    let key = StateKey::resolve(args.state_key_file.as_deref())?;
+    let mut engine = load_state(&args.state, key.as_ref())?;
+    engine.set_config(EngineConfig {
+        rules: args.rules,
+        ..EngineConfig::default()
+    });
+    let before = engine.fork();
+
+    // Only the dispute side is proposed, deposits and withdrawals are skipped
+    let mut summary = RunSummary::default();
+    let results = Results::new(CsvSource::open(&args.input)?, &mut engine)
+        .skip_types(vec![TxType::Deposit, TxType::Withdrawal]);
+    for result in results {
+        summary.record(&result);
+    }
+
+    let impacts = impacts(&before, &engine);
+    eprintln!(
+        "what-if: rows {} (applied {}, rejected {}, skipped {}), {} clients affected",
+        summary.rows,
+        summary.applied,
+        summary.rejected,
+        summary.skipped,
+        impacts.len()
+    );
+
+    let mut wtr = csv::Writer::from_writer(std::io::stdout());
+    for impact in impacts {
+        wtr.serialize(impact)?;
+    }
+    wtr.flush()?;
+
+    Ok(())
+}
+
+fn impacts(before: &Engine, engine: &Engine) -> Vec<Impact> {
+    let mut impacts: Vec<Impact> = engine
+        .clients_iter()
+        .filter_map(|client| {
+            let old = before.client(client.id)?;
+            let goes_negative = client.available < Decimal::ZERO && old.available >= Decimal::ZERO;
+            let gets_locked = client.locked && !old.locked;
+
+            (goes_negative || gets_locked).then_some(Impact {
+                client: client.id,
+                available_before: old.available,
+                available: client.available,
+                held: client.held,
+                total: client.total,
+                goes_negative,
+                gets_locked,
+            })
+        })
+        .collect();
+    impacts.sort_by_key(|impact| impact.client);
+    impacts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+    use toy_payments_engine::types::transactions::{
+        ChargebackTx, DepositTx, DisputeTx, Tx, WithdrawalTx,
+    };
+
+    #[test]
+    fn test_impacts_report_negative_and_locked_clients() {
+        let mut engine = Engine::new();
+        let setup = [
+            Tx::Deposit(DepositTx::new(1, 1, dec!(10)).unwrap()),
+            Tx::Withdrawal(WithdrawalTx::new(1, 2, dec!(8)).unwrap()),
+            Tx::Deposit(DepositTx::new(2, 3, dec!(5)).unwrap()),
+            Tx::Deposit(DepositTx::new(3, 4, dec!(5)).unwrap()),
+        ];
+        for tx in setup {
+            engine.process_tx(tx).unwrap();
+        }
+        let before = engine.fork();
+
+        let proposed = [
+            Tx::Dispute(DisputeTx {
+                client_id: 1,
+                tx_id: 1,
+            }),
+            Tx::Dispute(DisputeTx {
+                client_id: 2,
+                tx_id: 3,
+            }),
+            Tx::Chargeback(ChargebackTx {
+                client_id: 2,
+                tx_id: 3,
+            }),
+        ];
+        for tx in proposed {
+            engine.process_tx(tx).unwrap();
+        }
+
+        let impacts = impacts(&before, &engine);
+        assert_eq!(
+            impacts,
+            vec![
+                Impact {
+                    client: 1,
+                    available_before: dec!(2),
+                    available: dec!(-8),
+                    held: dec!(10),
+                    total: dec!(2),
+                    goes_negative: true,
+                    gets_locked: false,
+                },
+                Impact {
+                    client: 2,
+                    available_before: dec!(5),
+                    available: dec!(0),
+                    held: dec!(0),
+                    total: dec!(0),
+                    goes_negative: false,
+                    gets_locked: true,
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,182 @@
+//! Filter of the deposit and withdrawal ids already applied, persisted
+//! between runs so an input submitted twice isn't credited twice.
+//!
+//! It's a Bloom filter: its size is fixed up front from the expected number
+//! of transactions and the accepted false positive rate, however many runs
+//! it spans. A false positive rejects a new transaction as `duplicate_tx`,
+//! it never lets a replayed one through.
+//!
+//! File layout (little endian): magic `TPEF`, format version (u16), hash
+//! count (u32), capacity (u64), inserted ids (u64), bit count (u64), then
+//! the bits as u64 words.
+
+use std::io::{self, Read, Write};
+
+use crate::types::common::TxId;
+
+const MAGIC: &[u8; 4] = b"TPEF";
+const VERSION: u16 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TxFilter {
+    words: Vec<u64>,
+    bits: u64,
+    hashes: u32,
+    /// Ids the filter was sized for
+    capacity: u64,
+    /// Ids inserted so far
+    len: u64,
+}
+
+impl TxFilter {
+    /// Sizes the filter so that after `capacity` ids a new one is taken for
+    /// a duplicate with probability `fp_rate`.
+    pub fn new(capacity: u64, fp_rate: f64) -> Self {
+        let capacity = capacity.max(1);
+        let ln2 = std::f64::consts::LN_2;
+        let bits = (-(capacity as f64) * fp_rate.ln() / (ln2 * ln2))
+            .ceil()
+            .max(64.0) as u64;
+        let hashes = (bits as f64 / capacity as f64 * ln2).round().max(1.0) as u32;
+
+        TxFilter {
+            words: vec![0; bits.div_ceil(64) as usize],
+            bits,
+            hashes,
+            capacity,
+            len: 0,
+        }
+    }
+
+    pub fn contains(&self, tx_id: TxId) -> bool {
+        self.positions(tx_id)
+            .all(|bit| self.words[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+
+    pub fn insert(&mut self, tx_id: TxId) {
+        for bit in self.positions(tx_id) {
+            self.words[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+        self.len += 1;
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    /// Past its capacity the false positive rate grows beyond the configured one.
+    pub fn is_over_capacity(&self) -> bool {
+        self.len > self.capacity
+    }
+
+    /// Size of the bit array in bytes.
+    pub fn size_bytes(&self) -> usize {
+        self.words.len() * 8
+    }
+
+    // Double hashing over splitmix64, stable across builds unlike std's hasher
+    fn positions(&self, tx_id: TxId) -> impl Iterator<Item = u64> + use<> {
+        let h1 = splitmix64(u64::from(tx_id));
+        let h2 = splitmix64(h1) | 1;
+        let bits = self.bits;
+        (0..u64::from(self.hashes)).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % bits)
+    }
+
+    pub fn write<W: Write>(&self, mut w: W) -> io::Result<()> {
+        w.write_all(MAGIC)?;
+        w.write_all(&VERSION.to_le_bytes())?;
+        w.write_all(&self.hashes.to_le_bytes())?;
+        w.write_all(&self.capacity.to_le_bytes())?;
+        w.write_all(&self.len.to_le_bytes())?;
+        w.write_all(&self.bits.to_le_bytes())?;
+        for word in &self.words {
+            w.write_all(&word.to_le_bytes())?;
+        }
+        w.flush()
+    }
+
+    pub fn read<R: Read>(mut r: R) -> io::Result<Self> {
+        let mut magic = [0; 4];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid_data("not a transaction filter".to_string()));
+        }
+        let version = u16::from_le_bytes(read_bytes(&mut r)?);
+        if version != VERSION {
+            return Err(invalid_data(format!(
+                "unsupported filter version {version}, this build reads {VERSION}"
+            )));
+        }
+
+        let hashes = u32::from_le_bytes(read_bytes(&mut r)?);
+        let capacity = u64::from_le_bytes(read_bytes(&mut r)?);
+        let len = u64::from_le_bytes(read_bytes(&mut r)?);
+        let bits = u64::from_le_bytes(read_bytes(&mut r)?);
+        if bits == 0 || hashes == 0 {
+            return Err(invalid_data("empty transaction filter".to_string()));
+        }
+        let words = (0..bits.div_ceil(64))
+            .map(|_| read_bytes(&mut r).map(u64::from_le_bytes))
+            .collect::<io::Result<_>>()?;
+
+        Ok(TxFilter {
+            words,
+            bits,
+            hashes,
+            capacity,
+            len,
+        })
+    }
+}
+
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_bytes<const N: usize, R: Read>(r: &mut R) -> io::Result<[u8; N]> {
+    let mut buf = [0; N];
+    r.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_round_trip() {
+        let mut filter = TxFilter::new(1000, 0.001);
+        for tx_id in 0..1000 {
+            filter.insert(tx_id * 7);
+        }
+        assert!((0..1000).all(|tx_id| filter.contains(tx_id * 7)));
+        assert!(!filter.is_over_capacity());
+
+        let false_positives = (1_000_000..1_100_000)
+            .filter(|tx_id| filter.contains(*tx_id))
+            .count();
+        assert!(false_positives < 300, "{false_positives} false positives");
+
+        let mut buf = Vec::new();
+        filter.write(&mut buf).unwrap();
+        assert_eq!(TxFilter::read(buf.as_slice()).unwrap(), filter);
+
+        buf[0] = b'X';
+        assert!(TxFilter::read(buf.as_slice()).is_err());
+    }
+}
@@ -1,169 +1,631 @@
-use std::collections::HashMap;
-
-use crate::types::{
-    client::Client,
-    common::{ClientId, TxId},
-    transactions::{ChargebackTx, DepositTx, DisputeTx, ResolveTx, Tx, WithdrawalTx},
+pub mod alerts;
+pub mod amount;
+pub mod batch;
+mod chargeback;
+pub mod clients;
+pub mod config;
+mod deposit;
+mod deposits;
+mod dispute;
+pub mod dispute_state;
+pub mod house;
+#[cfg(feature = "paranoid")]
+mod invariants;
+pub mod live;
+pub mod metrics;
+pub mod opening;
+pub mod prepared;
+pub mod prior;
+mod resolve;
+pub mod revert;
+pub mod rules;
+pub mod settled;
+pub mod snapshot;
+pub mod stats;
+mod table;
+pub mod unlock;
+mod withdrawal;
+
+use std::{collections::HashMap, sync::Arc};
+
+use rust_decimal::Decimal;
+
+use crate::{
+    engine::{
+        clients::ClientTable,
+        config::{EngineConfig, MissingDeposit, TxKeys},
+        deposits::DepositTable,
+        dispute_state::{DisputeEvent, DisputeState},
+        house::HouseAccounts,
+        metrics::{EngineMetrics, NoopMetrics},
+        prior::PriorIndex,
+        settled::SettledTxs,
+        table::TxTable,
+    },
+    types::{
+        client::{Client, LockInfo},
+        common::{ClientId, TxId},
+        reject::RejectReason,
+        transactions::{DepositTx, Tx, TxType, WithdrawalTx},
+    },
 };
 
-#[derive(Debug, PartialEq, Eq)]
-enum DepositStatus {
-    Normal,
-    UnderDispute,
-    Resolved,
-    ChargedBack,
+/// Applies one type of transaction, implemented for `Engine` in the module
+/// named after the type.
+trait TxHandler<T> {
+    fn handle(&mut self, tx: T) -> Result<(), RejectReason>;
+}
+
+/// Which kind of stored transaction a dispute refers to.
+enum Disputed {
+    Deposit,
+    Withdrawal,
+}
+
+/// A deposit or withdrawal that is currently under dispute.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OpenDispute {
+    pub tx_type: TxType,
+    pub client_id: ClientId,
+    pub tx_id: TxId,
+    pub amount: Decimal,
+}
+
+/// Balances summed over a set of clients.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Totals {
+    pub clients: usize,
+    pub locked: usize,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+}
+
+impl Totals {
+    pub fn add_client(&mut self, client: &Client) {
+        self.clients += 1;
+        self.locked += client.locked as usize;
+        self.available += client.available;
+        self.held += client.held;
+        self.total += client.total;
+    }
 }
 
+#[derive(Clone)]
 pub struct Engine {
-    clients: HashMap<ClientId, Client>,
-    deposits: HashMap<TxId, (DepositTx, DepositStatus)>,
+    clients: ClientTable,
+    // Kept up to date so totals never need a pass over the clients
+    locked_clients: usize,
+    // Per client, see `DepositTable`
+    deposits: DepositTable,
+    // Only filled when the rules allow disputing withdrawals
+    withdrawals: TxTable<(WithdrawalTx, DisputeState)>,
+    // Disputes, resolves and chargebacks waiting for the transaction they
+    // name, in arrival order (`MissingDeposit::Queue`)
+    pending: TxTable<Vec<Tx>>,
+    // Outcomes of the queued rows the last `process_tx` took out of `pending`
+    dequeued: Vec<(Tx, Result<(), RejectReason>)>,
+    // Resolved and charged back transactions moved out of the tables above
+    // by `compact`
+    settled: SettledTxs,
+    // Deposits of earlier runs, copied into `deposits` when a row names them
+    prior: Option<Arc<PriorIndex>>,
+    // Last sequence number seen per client, for feeds that number their rows
+    sequences: HashMap<ClientId, u64>,
+    house: HouseAccounts,
+    config: EngineConfig,
+    // Shared with forks
+    metrics: Arc<dyn EngineMetrics>,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Engine {
     pub fn new() -> Self {
+        Self::with_config(EngineConfig::default())
+    }
+
+    pub fn with_config(config: EngineConfig) -> Self {
         Engine {
-            clients: HashMap::new(),
-            deposits: HashMap::new(),
+            clients: ClientTable::new(),
+            locked_clients: 0,
+            deposits: DepositTable::with_keys(config.tx_keys),
+            withdrawals: TxTable::new(),
+            pending: TxTable::new(),
+            dequeued: Vec::new(),
+            settled: SettledTxs::default(),
+            prior: None,
+            sequences: HashMap::new(),
+            house: HouseAccounts::default(),
+            config,
+            metrics: Arc::new(NoopMetrics),
         }
     }
 
-    pub fn clients(&self) -> &HashMap<ClientId, Client> {
+    pub fn config(&self) -> &EngineConfig {
+        &self.config
+    }
+
+    /// Keeps the loaded state but applies new rules from now on.
+    pub fn set_config(&mut self, config: EngineConfig) {
+        self.deposits.set_keys(config.tx_keys);
+        self.config = config;
+    }
+
+    /// Reports every transaction from now on to `metrics` instead of the
+    /// `NoopMetrics` an engine starts with.
+    pub fn set_metrics(&mut self, metrics: Arc<dyn EngineMetrics>) {
+        self.metrics = metrics;
+    }
+
+    /// An independent copy of the engine for what-if runs. The transaction
+    /// tables are shared with `self` and only the parts either side writes to
+    /// are copied, the clients (at most 65536) are copied up front.
+    pub fn fork(&self) -> Engine {
+        self.clone()
+    }
+
+    /// The client table itself. Its type is part of how the engine stores
+    /// clients, which may change, so prefer the accessors below.
+    #[deprecated(note = "use `clients_iter` or `client` instead")]
+    pub fn clients(&self) -> &ClientTable {
         &self.clients
     }
 
-    pub fn process_tx(&mut self, tx: Tx) {
-        match tx {
-            Tx::Deposit(deposit_tx) => {
-                self.process_deposit(deposit_tx);
-            }
-            Tx::Withdrawal(withdrawal_tx) => {
-                self.process_withdrawal(withdrawal_tx);
-            }
-            Tx::Dispute(dispute_tx) => {
-                self.process_dispute(dispute_tx);
-            }
-            Tx::Resolve(resolve_tx) => {
-                self.process_resolve(resolve_tx);
-            }
-            Tx::Chargeback(chargeback_tx) => {
-                self.process_chargeback(chargeback_tx);
-            }
-        }
+    /// Every client, in no particular order.
+    pub fn clients_iter(&self) -> impl ExactSizeIterator<Item = &Client> + '_ {
+        self.clients.values()
     }
 
-    fn process_deposit(&mut self, deposit_tx: DepositTx) {
-        let client = self
+    pub fn client(&self, id: ClientId) -> Option<&Client> {
+        self.clients.get(&id)
+    }
+
+    /// Why and when the client's account was locked, `None` if it isn't.
+    pub fn lock_info(&self, client_id: ClientId) -> Option<&LockInfo> {
+        self.clients.get(&client_id)?.lock.as_ref()
+    }
+
+    /// Records the input line and timestamp of the row `tx_id` that locked
+    /// the client's account, unless they are known already.
+    #[cfg(feature = "csv")]
+    pub(crate) fn stamp_lock(
+        &mut self,
+        client_id: ClientId,
+        tx_id: TxId,
+        line: Option<u64>,
+        timestamp: Option<i64>,
+    ) {
+        let lock = self
             .clients
-            .entry(deposit_tx.client_id)
-            .or_insert(Client::new(deposit_tx.client_id));
+            .get_mut(&client_id)
+            .and_then(|c| c.lock.as_mut());
+        if let Some(lock) = lock
+            && lock.tx_id == Some(tx_id)
+            && lock.line.is_none()
+            && lock.timestamp.is_none()
+        {
+            lock.line = line;
+            lock.timestamp = timestamp;
+        }
+    }
 
-        if client.locked {
-            return; // Account is locked
+    pub fn house(&self) -> &HouseAccounts {
+        &self.house
+    }
+
+    /// Balances over all clients, derived from the house accounts.
+    pub fn totals(&self) -> Totals {
+        let house = &self.house;
+        let total = house.deposited - house.withdrawn - house.charged_back;
+
+        Totals {
+            clients: self.clients.len(),
+            locked: self.locked_clients,
+            available: total - house.held,
+            held: house.held,
+            total,
         }
+    }
 
-        client.available += deposit_tx.amount;
-        client.total += deposit_tx.amount;
+    /// Client a stored deposit (or disputable withdrawal) belongs to.
+    pub fn tx_owner(&self, tx_id: TxId) -> Option<ClientId> {
+        match self.deposits.owner(tx_id) {
+            Some(client_id) => Some(client_id),
+            None => self
+                .withdrawals
+                .get(&tx_id)
+                .map(|(withdrawal_tx, _)| withdrawal_tx.client_id)
+                .or_else(|| self.settled.get(tx_id).map(|tx| tx.client_id)),
+        }
+    }
+
+    /// Dispute state of the client's stored deposit or withdrawal `tx_id`,
+    /// settled ones included.
+    pub fn tx_state(&self, client_id: ClientId, tx_id: TxId) -> Option<DisputeState> {
+        if let Some((_, state)) = self.deposits.get_for(client_id, tx_id) {
+            return Some(*state);
+        }
+        if let Some((withdrawal_tx, state)) = self.withdrawals.get(&tx_id)
+            && withdrawal_tx.client_id == client_id
+        {
+            return Some(*state);
+        }
+        self.settled
+            .get(tx_id)
+            .filter(|tx| tx.client_id == client_id)
+            .map(|tx| tx.state)
+    }
 
-        // Spec claims that the ids are unique, but just to be sure
+    /// The client's deposits kept for disputes, in no particular order.
+    pub fn deposits_of(
+        &self,
+        client_id: ClientId,
+    ) -> impl Iterator<Item = (&DepositTx, DisputeState)> {
         self.deposits
-            .entry(deposit_tx.tx_id)
-            .or_insert((deposit_tx, DepositStatus::Normal));
+            .of_client(client_id)
+            .map(|(deposit_tx, state)| (deposit_tx, *state))
     }
 
-    fn process_withdrawal(&mut self, withdrawal_tx: WithdrawalTx) {
-        let Some(client) = self.clients.get_mut(&withdrawal_tx.client_id) else {
-            return; // Client doesn't exist
-        };
+    /// Deposits and withdrawals kept around for disputes.
+    pub fn tracked_txs(&self) -> usize {
+        self.deposits.len() + self.withdrawals.len()
+    }
 
-        if client.locked {
-            return; // Account is locked
-        }
+    /// Highest sequence number seen for the client's rows.
+    pub fn last_sequence(&self, client_id: ClientId) -> Option<u64> {
+        self.sequences.get(&client_id).copied()
+    }
 
-        if client.available < withdrawal_tx.amount {
-            return; // Insufficient funds
-        }
+    /// Records a row of the client with sequence number `seq`, the highest one is kept.
+    pub fn record_sequence(&mut self, client_id: ClientId, seq: u64) {
+        let last = self.sequences.entry(client_id).or_insert(seq);
+        *last = (*last).max(seq);
+    }
 
-        client.available -= withdrawal_tx.amount;
-        client.total -= withdrawal_tx.amount;
+    /// Rows queued until the transaction they reference arrives.
+    pub fn queued_txs(&self) -> usize {
+        self.pending.values().map(Vec::len).sum()
     }
 
-    fn process_dispute(&mut self, dispute_tx: DisputeTx) {
-        let Some(client) = self.clients.get_mut(&dispute_tx.client_id) else {
-            return; // Client doesn't exist
-        };
+    /// Takes the queued rows the last `process_tx` applied, in the order they
+    /// were queued, with their outcome. They were reported as `queued` when
+    /// they arrived. The next `process_tx` forgets the ones not taken.
+    pub fn drain_dequeued(&mut self) -> impl Iterator<Item = (Tx, Result<(), RejectReason>)> + '_ {
+        self.dequeued.drain(..)
+    }
 
-        let Some((deposit_tx, deposit_status)) = self.deposits.get_mut(&dispute_tx.tx_id) else {
-            return; // Corresponding deposit doesn't exist
-        };
+    /// Rough size of the state in bytes, based on the tables' allocated capacity.
+    pub fn memory_estimate(&self) -> usize {
+        self.clients.bytes()
+            + self.deposits.bytes(None)
+            + tx_table_bytes(&self.withdrawals, None)
+            + tx_table_bytes(&self.pending, None)
+            + self.settled.bytes()
+            + self.queued_txs() * std::mem::size_of::<Tx>()
+    }
+
+    /// Transactions that are currently under dispute, in no particular order.
+    pub fn open_disputes(&self) -> impl Iterator<Item = OpenDispute> + '_ {
+        let deposits = self
+            .deposits
+            .values()
+            .filter(|(_, deposit_status)| *deposit_status == DisputeState::UnderDispute)
+            .map(|(deposit_tx, _)| OpenDispute {
+                tx_type: TxType::Deposit,
+                client_id: deposit_tx.client_id,
+                tx_id: deposit_tx.tx_id,
+                amount: deposit_tx.amount,
+            });
+        let withdrawals = self
+            .withdrawals
+            .values()
+            .filter(|(_, status)| *status == DisputeState::UnderDispute)
+            .map(|(withdrawal_tx, _)| OpenDispute {
+                tx_type: TxType::Withdrawal,
+                client_id: withdrawal_tx.client_id,
+                tx_id: withdrawal_tx.tx_id,
+                amount: withdrawal_tx.amount,
+            });
+
+        deposits.chain(withdrawals)
+    }
+
+    /// Applies `tx`, queueing it instead if it references a transaction that
+    /// hasn't arrived yet and the config says so. The rows queued for `tx`
+    /// are applied right after it, see `drain_dequeued`.
+    pub fn process_tx(&mut self, tx: Tx) -> Result<(), RejectReason> {
+        self.process_tx_at(tx, None)
+    }
 
-        if dispute_tx.client_id != deposit_tx.client_id {
-            return; // Dispute client doesn't match deposit client
+    /// `process_tx` for a row with a timestamp (milliseconds since the Unix
+    /// epoch), which ends a cooling-off period under `UnlockPolicy::AfterDays`.
+    pub fn process_tx_at(&mut self, tx: Tx, now: Option<i64>) -> Result<(), RejectReason> {
+        self.dequeued.clear();
+        let result = self.dispatch(tx, now);
+
+        match (tx, result) {
+            (Tx::Deposit(_) | Tx::Withdrawal(_), Err(RejectReason::CapacityExceeded)) => {}
+            (Tx::Deposit(_) | Tx::Withdrawal(_), _) => {
+                if let Some(queued) = self.pending.remove(&tx.tx_id()) {
+                    for queued_tx in queued {
+                        let queued_result = match result {
+                            Ok(()) => self.dispatch(queued_tx, now),
+                            // Rows waiting for a transaction that was rejected can't apply either
+                            Err(_) => Err(RejectReason::UnknownTx),
+                        };
+                        self.measure(queued_tx, queued_result);
+                        self.dequeued.push((queued_tx, queued_result));
+                    }
+                }
+            }
+            (_, Err(RejectReason::UnknownTx | RejectReason::UnknownClient))
+                if self.config.missing_deposit == MissingDeposit::Queue
+                    && self.tx_owner(tx.tx_id()).is_none() =>
+            {
+                self.pending.entry(tx.tx_id()).or_default().push(tx);
+                self.measure(tx, Err(RejectReason::Queued));
+                return Err(RejectReason::Queued);
+            }
+            _ => {}
         }
 
-        if *deposit_status != DepositStatus::Normal {
-            return; // Deposit is not in a state that can be disputed
+        self.measure(tx, result);
+        result
+    }
+
+    fn measure(&self, tx: Tx, result: Result<(), RejectReason>) {
+        self.metrics.increment(tx.tx_type(), result);
+        match (tx, result) {
+            (Tx::Deposit(deposit_tx), Ok(())) => {
+                self.metrics.observe(TxType::Deposit, deposit_tx.amount)
+            }
+            (Tx::Withdrawal(withdrawal_tx), Ok(())) => self
+                .metrics
+                .observe(TxType::Withdrawal, withdrawal_tx.amount),
+            _ => {}
         }
+    }
 
-        *deposit_status = DepositStatus::UnderDispute;
-        // Available can go negative if funds were already withdrawn (fraud scenario)
-        client.available -= deposit_tx.amount;
-        client.held += deposit_tx.amount;
+    /// Dispatches `tx` to the `TxHandler` for its type.
+    fn dispatch(&mut self, tx: Tx, now: Option<i64>) -> Result<(), RejectReason> {
+        // A cooling-off period ends at the client's next row
+        if let Some(now) = now {
+            self.unlock_cooled_off(tx.client_id(), now);
+        }
+        let result = self
+            .fault_in_prior(tx)
+            .and_then(|()| self.check_conflict(tx))
+            .and_then(|()| match tx {
+                Tx::Deposit(deposit_tx) => self.handle(deposit_tx),
+                Tx::Withdrawal(withdrawal_tx) => self.handle(withdrawal_tx),
+                Tx::Dispute(dispute_tx) => self.handle(dispute_tx),
+                Tx::Resolve(resolve_tx) => self.handle(resolve_tx),
+                Tx::Chargeback(chargeback_tx) => self.handle(chargeback_tx),
+                Tx::OpeningBalance(opening_tx) => self.handle(opening_tx),
+            });
+        if matches!(
+            tx,
+            Tx::Deposit(_) | Tx::Withdrawal(_) | Tx::OpeningBalance(_)
+        ) {
+            self.count(tx, result);
+        }
+        if result.is_ok() && matches!(tx, Tx::Resolve(_) | Tx::Chargeback(_)) {
+            self.unlock_settled(tx.client_id());
+        }
+        #[cfg(feature = "paranoid")]
+        self.check_invariants(tx);
+        result
     }
 
-    fn process_resolve(&mut self, resolve_tx: ResolveTx) {
-        let Some(client) = self.clients.get_mut(&resolve_tx.client_id) else {
-            return; // Client doesn't exist
-        };
+    /// Rejects a deposit or withdrawal reusing the id of a stored transaction.
+    /// A replay repeats the payload and is a duplicate, a different one points
+    /// at corrupt input.
+    fn check_conflict(&self, tx: Tx) -> Result<(), RejectReason> {
+        let amount = match tx {
+            Tx::Deposit(deposit_tx) => deposit_tx.amount,
+            Tx::Withdrawal(withdrawal_tx) => withdrawal_tx.amount,
+            _ => return Ok(()),
+        };
+        let (client_id, tx_id) = (tx.client_id(), tx.tx_id());
+        let deposit = match self.config.tx_keys {
+            TxKeys::Global => self.deposits.get(&tx_id),
+            TxKeys::PerClient => self.deposits.get_for(client_id, tx_id),
+        };
+        let stored = deposit
+            .map(|(deposit_tx, _)| (TxType::Deposit, deposit_tx.client_id, deposit_tx.amount))
+            .or_else(|| {
+                self.withdrawals.get(&tx_id).map(|(withdrawal_tx, _)| {
+                    let (client_id, amount) = (withdrawal_tx.client_id, withdrawal_tx.amount);
+                    (TxType::Withdrawal, client_id, amount)
+                })
+            })
+            .or_else(|| {
+                let settled_tx = self.settled.get(tx_id)?;
+                Some((settled_tx.tx_type, settled_tx.client_id, settled_tx.amount))
+            })
+            // Other clients' ids are theirs to reuse
+            .filter(|&(_, owner, _)| self.config.tx_keys == TxKeys::Global || owner == client_id);
+        match stored {
+            Some(stored) if stored != (tx.tx_type(), client_id, amount) => {
+                Err(RejectReason::ConflictingTx)
+            }
+            Some(_) => Err(RejectReason::DuplicateTx),
+            None => Ok(()),
+        }
+    }
 
-        let Some((deposit_tx, deposit_status)) = self.deposits.get_mut(&resolve_tx.tx_id) else {
-            return; // Corresponding deposit doesn't exist
+    /// Updates the activity counters of the client a deposit, withdrawal or
+    /// opening balance is for.
+    fn count(&mut self, tx: Tx, result: Result<(), RejectReason>) {
+        // Nothing was applied, the row is processed again when the run is resumed
+        if result == Err(RejectReason::CapacityExceeded) {
+            return;
+        }
+        let Some(client) = self.clients.get_mut(&tx.client_id()) else {
+            return;
         };
 
-        if resolve_tx.client_id != deposit_tx.client_id {
-            return; // Dispute client doesn't match deposit client
+        let stats = &mut client.stats;
+        match (tx, result) {
+            (Tx::Deposit(_), Ok(())) => stats.deposits += 1,
+            (Tx::Withdrawal(_), Ok(())) => stats.withdrawals += 1,
+            (Tx::Withdrawal(_), Err(_)) => stats.rejected_withdrawals += 1,
+            (Tx::OpeningBalance(_), Ok(())) => stats.openings += 1,
+            _ => {}
+        }
+    }
+
+    /// Checks the configured limits against the state after adding the given
+    /// client, deposit and withdrawal (those already present don't count).
+    fn check_capacity(
+        &self,
+        client_id: Option<ClientId>,
+        deposit_tx: Option<&DepositTx>,
+        withdrawal_tx_id: Option<TxId>,
+    ) -> Result<(), RejectReason> {
+        let config = &self.config;
+        if config.max_clients.is_none()
+            && config.max_deposits.is_none()
+            && config.max_memory.is_none()
+        {
+            return Ok(());
         }
 
-        if *deposit_status != DepositStatus::UnderDispute {
-            return; // Deposit is not in a state that can be resolved
+        let new_client = client_id.is_some_and(|id| !self.clients.contains_key(&id)) as usize;
+        let new_deposit = deposit_tx.filter(|d| self.deposits.is_new(d));
+        let new_withdrawal = withdrawal_tx_id.filter(|id| !self.withdrawals.contains_key(id));
+
+        let clients = self.clients.len() + new_client;
+        let tracked_txs =
+            self.tracked_txs() + new_deposit.is_some() as usize + new_withdrawal.is_some() as usize;
+        let memory = self.clients.bytes_with(new_client, client_id)
+            + self.deposits.bytes(new_deposit)
+            + tx_table_bytes(&self.withdrawals, new_withdrawal)
+            + self.settled.bytes();
+
+        if config.max_clients.is_some_and(|max| clients > max)
+            || config.max_deposits.is_some_and(|max| tracked_txs > max)
+            || config.max_memory.is_some_and(|max| memory > max)
+        {
+            return Err(RejectReason::CapacityExceeded);
         }
 
-        *deposit_status = DepositStatus::Resolved;
-        client.available += deposit_tx.amount;
-        client.held -= deposit_tx.amount;
+        Ok(())
     }
+}
 
-    fn process_chargeback(&mut self, chargeback_tx: ChargebackTx) {
-        let Some(client) = self.clients.get_mut(&chargeback_tx.client_id) else {
-            return; // Client doesn't exist
-        };
+/// Finds the deposit (or, if the rules allow it, withdrawal) a dispute,
+/// resolve or chargeback refers to and checks `event` is allowed in its
+/// state, returning the state to move it to.
+fn find_disputed<'a>(
+    deposits: &'a mut DepositTable,
+    withdrawals: &'a mut TxTable<(WithdrawalTx, DisputeState)>,
+    settled: &SettledTxs,
+    config: &EngineConfig,
+    client_id: ClientId,
+    tx_id: TxId,
+    event: DisputeEvent,
+) -> Result<(Disputed, Decimal, &'a mut DisputeState, DisputeState), RejectReason> {
+    let (disputed, owner, amount, status) = match deposits.find_for(client_id, tx_id) {
+        Ok((deposit_tx, status)) => (
+            Disputed::Deposit,
+            deposit_tx.client_id,
+            deposit_tx.amount,
+            status,
+        ),
+        // Another client's deposit
+        Err(Some(_)) => return Err(RejectReason::ClientMismatch),
+        Err(None) => {
+            if let Some((withdrawal_tx, status)) = withdrawals
+                .get_mut(&tx_id)
+                .filter(|_| config.rules.policy().withdrawals_disputable())
+            {
+                (
+                    Disputed::Withdrawal,
+                    withdrawal_tx.client_id,
+                    withdrawal_tx.amount,
+                    status,
+                )
+            } else if let Some(settled_tx) = settled.get(tx_id).filter(|tx| {
+                tx.tx_type == TxType::Deposit || config.rules.policy().withdrawals_disputable()
+            }) {
+                // Settled transactions have no transitions left
+                return Err(if client_id == settled_tx.client_id {
+                    RejectReason::NotDisputable
+                } else {
+                    RejectReason::ClientMismatch
+                });
+            } else {
+                return Err(RejectReason::UnknownTx);
+            }
+        }
+    };
 
-        let Some((deposit_tx, deposit_status)) = self.deposits.get_mut(&chargeback_tx.tx_id) else {
-            return; // Corresponding deposit doesn't exist
-        };
+    if client_id != owner {
+        return Err(RejectReason::ClientMismatch);
+    }
 
-        if chargeback_tx.client_id != deposit_tx.client_id {
-            return; // Dispute client doesn't match deposit client
-        }
+    let next = status
+        .transition(event)
+        .map_err(|_| RejectReason::NotDisputable)?;
 
-        if *deposit_status != DepositStatus::UnderDispute {
-            return; // Deposit is not in a state that can be charged back
-        }
+    Ok((disputed, amount, status, next))
+}
 
-        *deposit_status = DepositStatus::ChargedBack;
-        client.total -= deposit_tx.amount;
-        client.held -= deposit_tx.amount;
-        client.locked = true;
+/// Bytes a table takes once `additional` more entries are inserted, counting
+/// the growth of its allocation when it's full.
+fn table_bytes<K, V>(table: &HashMap<K, V>, additional: usize) -> usize {
+    let capacity = if table.len() + additional > table.capacity() {
+        grown_capacity((table.len() + additional).max(table.capacity() + 1))
+    } else {
+        table.capacity()
+    };
+    // One control byte per bucket on top of the entry
+    capacity * (std::mem::size_of::<(K, V)>() + 1)
+}
+
+/// Capacity a full table grows to when it needs room for `items`: a power of
+/// two number of buckets, loaded up to 7/8 once there are more than 8.
+fn grown_capacity(items: usize) -> usize {
+    let buckets = match items {
+        0..4 => 4,
+        4..8 => 8,
+        _ => (items * 8 / 7).next_power_of_two(),
+    };
+    if buckets <= 8 {
+        buckets - 1
+    } else {
+        buckets / 8 * 7
     }
 }
 
+/// `table_bytes` for a `TxTable`, once `new_tx` is inserted.
+fn tx_table_bytes<V>(table: &TxTable<V>, new_tx: Option<TxId>) -> usize {
+    let new_shard = new_tx.map(table::shard_index);
+    table
+        .shards()
+        .enumerate()
+        .map(|(i, shard)| table_bytes(shard, (new_shard == Some(i)) as usize))
+        .sum()
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::types::common::CsvRow;
+    use crate::{
+        engine::{
+            config::{MissingClient, TxKeys},
+            rules::Rules,
+        },
+        types::transactions::{ChargebackTx, DisputeTx, ResolveTx},
+    };
 
     use super::*;
     use rust_decimal_macros::dec;
-    use std::io::Write;
-    use tempfile::NamedTempFile;
 
     #[test]
     fn test_process_deposit_new_client() {
@@ -175,7 +637,7 @@ mod tests {
             amount: dec!(100.0),
         };
 
-        engine.process_deposit(deposit);
+        engine.handle(deposit).unwrap();
 
         let client = engine.clients.get(&1).unwrap();
         assert_eq!(client.available, dec!(100.0));
@@ -184,6 +646,36 @@ mod tests {
         assert!(engine.deposits.contains_key(&1));
     }
 
+    #[test]
+    fn test_non_positive_amounts_rejected() {
+        let mut engine = Engine::new();
+
+        for amount in [dec!(0), dec!(-5)] {
+            let deposit = DepositTx {
+                client_id: 1,
+                tx_id: 1,
+                amount,
+            };
+            assert_eq!(engine.handle(deposit), Err(RejectReason::InvalidAmount));
+        }
+        assert!(engine.clients.is_empty());
+
+        engine
+            .handle(DepositTx {
+                client_id: 1,
+                tx_id: 1,
+                amount: dec!(10),
+            })
+            .unwrap();
+        let withdrawal = WithdrawalTx {
+            client_id: 1,
+            tx_id: 2,
+            amount: dec!(-5),
+        };
+        assert_eq!(engine.handle(withdrawal), Err(RejectReason::InvalidAmount));
+        assert_eq!(engine.clients[&1].available, dec!(10));
+    }
+
     #[test]
     fn test_process_deposit_existing_client() {
         let mut engine = Engine::new();
@@ -200,8 +692,8 @@ mod tests {
             amount: dec!(75.0),
         };
 
-        engine.process_deposit(deposit1);
-        engine.process_deposit(deposit2);
+        engine.handle(deposit1).unwrap();
+        engine.handle(deposit2).unwrap();
 
         let client = engine.clients.get(&1).unwrap();
         assert_eq!(client.available, dec!(125.0));
@@ -220,12 +712,42 @@ mod tests {
             amount: dec!(50.0),
         };
 
-        engine.process_withdrawal(withdrawal);
+        assert_eq!(engine.handle(withdrawal), Err(RejectReason::UnknownClient));
 
         let client = engine.clients.get(&1);
         assert!(client.is_none());
     }
 
+    #[test]
+    fn test_process_withdrawal_no_client_creates_it() {
+        let mut engine = Engine::with_config(EngineConfig {
+            missing_client: MissingClient::Create,
+            max_clients: Some(1),
+            ..EngineConfig::default()
+        });
+        let withdrawal = |client_id| {
+            Tx::Withdrawal(WithdrawalTx {
+                client_id,
+                tx_id: 1,
+                amount: dec!(50.0),
+            })
+        };
+
+        assert_eq!(
+            engine.process_tx(withdrawal(1)),
+            Err(RejectReason::InsufficientFunds)
+        );
+        let client = &engine.client(1).unwrap();
+        assert_eq!(client.total, dec!(0));
+        assert_eq!(client.stats.rejected_withdrawals, 1);
+
+        assert_eq!(
+            engine.process_tx(withdrawal(2)),
+            Err(RejectReason::CapacityExceeded)
+        );
+        assert!(engine.client(2).is_none());
+    }
+
     #[test]
     fn test_process_withdrawal_existing_client_with_balance() {
         let mut engine = Engine::new();
@@ -242,8 +764,8 @@ mod tests {
             amount: dec!(50.0),
         };
 
-        engine.process_deposit(deposit);
-        engine.process_withdrawal(withdrawal);
+        engine.handle(deposit).unwrap();
+        engine.handle(withdrawal).unwrap();
 
         let client = engine.clients.get(&1).unwrap();
         assert_eq!(client.available, dec!(50.0));
@@ -267,8 +789,11 @@ mod tests {
             amount: dec!(99.0),
         };
 
-        engine.process_deposit(deposit);
-        engine.process_withdrawal(withdrawal);
+        engine.handle(deposit).unwrap();
+        assert_eq!(
+            engine.handle(withdrawal),
+            Err(RejectReason::InsufficientFunds)
+        );
 
         let client = engine.clients.get(&1).unwrap();
         assert_eq!(client.available, dec!(10.0));
@@ -291,8 +816,8 @@ mod tests {
             tx_id: 2,
         };
 
-        engine.process_deposit(deposit);
-        engine.process_dispute(dispute);
+        engine.handle(deposit).unwrap();
+        assert_eq!(engine.handle(dispute), Err(RejectReason::UnknownTx));
 
         let client = engine.clients.get(&1).unwrap();
         assert_eq!(client.available, dec!(10.0));
@@ -301,7 +826,7 @@ mod tests {
         assert!(!engine.deposits.contains_key(&2));
 
         let (_, status) = engine.deposits.get(&1).unwrap();
-        assert_eq!(*status, DepositStatus::Normal);
+        assert_eq!(*status, DisputeState::Normal);
     }
 
     #[test]
@@ -319,11 +844,11 @@ mod tests {
             tx_id: 1,
         };
 
-        engine.process_deposit(deposit);
-        engine.process_dispute(dispute);
+        engine.handle(deposit).unwrap();
+        engine.handle(dispute).unwrap();
 
         let (_, status) = engine.deposits.get(&1).unwrap();
-        assert_eq!(*status, DepositStatus::UnderDispute);
+        assert_eq!(*status, DisputeState::UnderDispute);
 
         let client = engine.clients.get(&1).unwrap();
         assert_eq!(client.available, dec!(0));
@@ -351,12 +876,12 @@ mod tests {
             tx_id: 1,
         };
 
-        engine.process_deposit(deposit);
-        engine.process_dispute(dispute1);
-        engine.process_dispute(dispute2);
+        engine.handle(deposit).unwrap();
+        engine.handle(dispute1).unwrap();
+        assert_eq!(engine.handle(dispute2), Err(RejectReason::NotDisputable));
 
         let (_, status) = engine.deposits.get(&1).unwrap();
-        assert_eq!(*status, DepositStatus::UnderDispute);
+        assert_eq!(*status, DisputeState::UnderDispute);
         let client = engine.clients.get(&1).unwrap();
         assert_eq!(client.available, dec!(0));
         assert_eq!(client.total, dec!(10.0));
@@ -389,15 +914,15 @@ mod tests {
             tx_id: 2,
         };
 
-        engine.process_deposit(deposit1);
-        engine.process_deposit(deposit2);
-        engine.process_dispute(dispute1);
-        engine.process_dispute(dispute2);
+        engine.handle(deposit1).unwrap();
+        engine.handle(deposit2).unwrap();
+        engine.handle(dispute1).unwrap();
+        engine.handle(dispute2).unwrap();
 
         let (_, status) = engine.deposits.get(&1).unwrap();
-        assert_eq!(*status, DepositStatus::UnderDispute);
+        assert_eq!(*status, DisputeState::UnderDispute);
         let (_, status) = engine.deposits.get(&2).unwrap();
-        assert_eq!(*status, DepositStatus::UnderDispute);
+        assert_eq!(*status, DisputeState::UnderDispute);
 
         let client = engine.clients.get(&1).unwrap();
         assert_eq!(client.available, dec!(0));
@@ -414,16 +939,16 @@ mod tests {
             tx_id: 1,
             amount: dec!(100.0),
         };
-        engine.process_deposit(deposit);
+        engine.handle(deposit).unwrap();
 
         let dispute = DisputeTx {
             client_id: 2,
             tx_id: 1,
         };
-        engine.process_dispute(dispute);
+        assert_eq!(engine.handle(dispute), Err(RejectReason::UnknownClient));
 
         let (_, status) = engine.deposits.get(&1).unwrap();
-        assert_eq!(*status, DepositStatus::Normal);
+        assert_eq!(*status, DisputeState::Normal);
 
         let client1 = engine.clients.get(&1).unwrap();
         assert_eq!(client1.available, dec!(100.0));
@@ -450,12 +975,12 @@ mod tests {
             tx_id: 1,
         };
 
-        engine.process_deposit(deposit);
-        engine.process_withdrawal(withdrawal);
-        engine.process_dispute(dispute);
+        engine.handle(deposit).unwrap();
+        engine.handle(withdrawal).unwrap();
+        engine.handle(dispute).unwrap();
 
         let (_, status) = engine.deposits.get(&1).unwrap();
-        assert_eq!(*status, DepositStatus::UnderDispute);
+        assert_eq!(*status, DisputeState::UnderDispute);
 
         let client = engine.clients.get(&1).unwrap();
         assert_eq!(client.available, dec!(-10.0));
@@ -478,11 +1003,11 @@ mod tests {
             tx_id: 1,
         };
 
-        engine.process_deposit(deposit);
-        engine.process_resolve(resolve);
+        engine.handle(deposit).unwrap();
+        assert_eq!(engine.handle(resolve), Err(RejectReason::NotDisputable));
 
         let (_, status) = engine.deposits.get(&1).unwrap();
-        assert_eq!(*status, DepositStatus::Normal);
+        assert_eq!(*status, DisputeState::Normal);
 
         let client = engine.clients.get(&1).unwrap();
         assert_eq!(client.available, dec!(10.0));
@@ -510,12 +1035,12 @@ mod tests {
             tx_id: 1,
         };
 
-        engine.process_deposit(deposit);
-        engine.process_dispute(dispute);
-        engine.process_resolve(resolve);
+        engine.handle(deposit).unwrap();
+        engine.handle(dispute).unwrap();
+        engine.handle(resolve).unwrap();
 
         let (_, status) = engine.deposits.get(&1).unwrap();
-        assert_eq!(*status, DepositStatus::Resolved);
+        assert_eq!(*status, DisputeState::Resolved);
 
         let client = engine.clients.get(&1).unwrap();
         assert_eq!(client.available, dec!(20.0));
@@ -549,13 +1074,13 @@ mod tests {
             tx_id: 1,
         };
 
-        engine.process_deposit(deposit);
-        engine.process_dispute(dispute);
-        engine.process_resolve(resolve1);
-        engine.process_resolve(resolve2);
+        engine.handle(deposit).unwrap();
+        engine.handle(dispute).unwrap();
+        engine.handle(resolve1).unwrap();
+        assert_eq!(engine.handle(resolve2), Err(RejectReason::NotDisputable));
 
         let (_, status) = engine.deposits.get(&1).unwrap();
-        assert_eq!(*status, DepositStatus::Resolved);
+        assert_eq!(*status, DisputeState::Resolved);
 
         let client = engine.clients.get(&1).unwrap();
         assert_eq!(client.available, dec!(20.0));
@@ -577,17 +1102,17 @@ mod tests {
             tx_id: 1,
         };
 
-        engine.process_deposit(deposit);
-        engine.process_dispute(dispute);
+        engine.handle(deposit).unwrap();
+        engine.handle(dispute).unwrap();
 
         let resolve = ResolveTx {
             client_id: 2,
             tx_id: 1,
         };
-        engine.process_resolve(resolve);
+        assert_eq!(engine.handle(resolve), Err(RejectReason::UnknownClient));
 
         let (_, status) = engine.deposits.get(&1).unwrap();
-        assert_eq!(*status, DepositStatus::UnderDispute);
+        assert_eq!(*status, DisputeState::UnderDispute);
     }
 
     #[test]
@@ -612,13 +1137,13 @@ mod tests {
             tx_id: 1,
         };
 
-        engine.process_deposit(deposit);
-        engine.process_dispute(dispute1);
-        engine.process_resolve(resolve);
-        engine.process_dispute(dispute2);
+        engine.handle(deposit).unwrap();
+        engine.handle(dispute1).unwrap();
+        engine.handle(resolve).unwrap();
+        assert_eq!(engine.handle(dispute2), Err(RejectReason::NotDisputable));
 
         let (_, status) = engine.deposits.get(&1).unwrap();
-        assert_eq!(*status, DepositStatus::Resolved);
+        assert_eq!(*status, DisputeState::Resolved);
 
         let client = engine.clients.get(&1).unwrap();
         assert_eq!(client.available, dec!(100.0));
@@ -640,11 +1165,11 @@ mod tests {
             tx_id: 1,
         };
 
-        engine.process_deposit(deposit);
-        engine.process_chargeback(chargeback);
+        engine.handle(deposit).unwrap();
+        assert_eq!(engine.handle(chargeback), Err(RejectReason::NotDisputable));
 
         let (_, status) = engine.deposits.get(&1).unwrap();
-        assert_eq!(*status, DepositStatus::Normal);
+        assert_eq!(*status, DisputeState::Normal);
 
         let client = engine.clients.get(&1).unwrap();
         assert_eq!(client.available, dec!(10.0));
@@ -673,12 +1198,12 @@ mod tests {
             tx_id: 1,
         };
 
-        engine.process_deposit(deposit);
-        engine.process_dispute(dispute);
-        engine.process_chargeback(chargeback);
+        engine.handle(deposit).unwrap();
+        engine.handle(dispute).unwrap();
+        engine.handle(chargeback).unwrap();
 
         let (_, status) = engine.deposits.get(&1).unwrap();
-        assert_eq!(*status, DepositStatus::ChargedBack);
+        assert_eq!(*status, DisputeState::ChargedBack);
 
         let client = engine.clients.get(&1).unwrap();
         assert_eq!(client.available, dec!(0));
@@ -712,13 +1237,13 @@ mod tests {
             tx_id: 1,
         };
 
-        engine.process_deposit(deposit);
-        engine.process_dispute(dispute);
-        engine.process_chargeback(chargeback1);
-        engine.process_chargeback(chargeback2);
+        engine.handle(deposit).unwrap();
+        engine.handle(dispute).unwrap();
+        engine.handle(chargeback1).unwrap();
+        assert_eq!(engine.handle(chargeback2), Err(RejectReason::NotDisputable));
 
         let (_, status) = engine.deposits.get(&1).unwrap();
-        assert_eq!(*status, DepositStatus::ChargedBack);
+        assert_eq!(*status, DisputeState::ChargedBack);
 
         let client = engine.clients.get(&1).unwrap();
         assert_eq!(client.available, dec!(0));
@@ -745,16 +1270,16 @@ mod tests {
             tx_id: 1,
         };
 
-        engine.process_deposit(deposit1);
-        engine.process_dispute(dispute);
-        engine.process_chargeback(chargeback);
+        engine.handle(deposit1).unwrap();
+        engine.handle(dispute).unwrap();
+        engine.handle(chargeback).unwrap();
 
         let deposit2 = DepositTx {
             client_id: 1,
             tx_id: 2,
             amount: dec!(50.0),
         };
-        engine.process_deposit(deposit2);
+        assert_eq!(engine.handle(deposit2), Err(RejectReason::AccountLocked));
 
         let client = engine.clients.get(&1).unwrap();
         assert!(client.locked);
@@ -785,17 +1310,17 @@ mod tests {
             tx_id: 1,
         };
 
-        engine.process_deposit(deposit1);
-        engine.process_deposit(deposit2);
-        engine.process_dispute(dispute);
-        engine.process_chargeback(chargeback);
+        engine.handle(deposit1).unwrap();
+        engine.handle(deposit2).unwrap();
+        engine.handle(dispute).unwrap();
+        engine.handle(chargeback).unwrap();
 
         let withdrawal = WithdrawalTx {
             client_id: 1,
             tx_id: 3,
             amount: dec!(25.0),
         };
-        engine.process_withdrawal(withdrawal);
+        assert_eq!(engine.handle(withdrawal), Err(RejectReason::AccountLocked));
 
         let client = engine.clients.get(&1).unwrap();
         assert_eq!(client.available, dec!(50.0));
@@ -837,17 +1362,17 @@ mod tests {
             tx_id: 2,
         };
 
-        engine.process_deposit(deposit1);
-        engine.process_deposit(deposit2);
-        engine.process_dispute(dispute1);
-        engine.process_dispute(dispute2);
+        engine.handle(deposit1).unwrap();
+        engine.handle(deposit2).unwrap();
+        engine.handle(dispute1).unwrap();
+        engine.handle(dispute2).unwrap();
 
         let client = engine.clients.get(&1).unwrap();
         assert_eq!(client.available, dec!(0));
         assert_eq!(client.held, dec!(150.0));
         assert_eq!(client.total, dec!(150.0));
 
-        engine.process_chargeback(chargeback1);
+        engine.handle(chargeback1).unwrap();
 
         let client = engine.clients.get(&1).unwrap();
         assert!(client.locked);
@@ -855,7 +1380,7 @@ mod tests {
         assert_eq!(client.held, dec!(50.0));
         assert_eq!(client.total, dec!(50.0));
 
-        engine.process_resolve(resolve2);
+        engine.handle(resolve2).unwrap();
 
         let client = engine.clients.get(&1).unwrap();
         assert!(client.locked);
@@ -864,10 +1389,10 @@ mod tests {
         assert_eq!(client.total, dec!(50.0));
 
         let (_, status1) = engine.deposits.get(&1).unwrap();
-        assert_eq!(*status1, DepositStatus::ChargedBack);
+        assert_eq!(*status1, DisputeState::ChargedBack);
 
         let (_, status2) = engine.deposits.get(&2).unwrap();
-        assert_eq!(*status2, DepositStatus::Resolved);
+        assert_eq!(*status2, DisputeState::Resolved);
     }
 
     #[test]
@@ -908,32 +1433,32 @@ mod tests {
             amount: dec!(500.0),
         };
 
-        engine.process_deposit(deposit1);
-        engine.process_withdrawal(withdrawal);
-        engine.process_deposit(deposit2);
+        engine.handle(deposit1).unwrap();
+        engine.handle(withdrawal).unwrap();
+        engine.handle(deposit2).unwrap();
 
-        let client = engine.clients().get(&2).unwrap();
+        let client = engine.client(2).unwrap();
         assert_eq!(client.available, dec!(3000.75));
         assert_eq!(client.total, dec!(3000.75));
 
-        engine.process_dispute(dispute);
+        engine.handle(dispute).unwrap();
 
-        let client = engine.clients().get(&2).unwrap();
+        let client = engine.client(2).unwrap();
         assert_eq!(client.available, dec!(1000.0));
         assert_eq!(client.held, dec!(2000.75));
         assert_eq!(client.total, dec!(3000.75));
 
-        engine.process_chargeback(chargeback);
+        engine.handle(chargeback).unwrap();
 
-        let client = engine.clients().get(&2).unwrap();
+        let client = engine.client(2).unwrap();
         assert_eq!(client.available, dec!(1000.0));
         assert_eq!(client.held, dec!(0));
         assert_eq!(client.total, dec!(1000.0));
         assert!(client.locked);
 
-        engine.process_deposit(deposit3);
+        assert_eq!(engine.handle(deposit3), Err(RejectReason::AccountLocked));
 
-        let client = engine.clients().get(&2).unwrap();
+        let client = engine.client(2).unwrap();
         assert_eq!(client.available, dec!(1000.0));
         assert_eq!(client.held, dec!(0));
         assert_eq!(client.total, dec!(1000.0));
@@ -941,7 +1466,610 @@ mod tests {
     }
 
     #[test]
+    fn test_house_accounts_track_charged_back_funds() {
+        let mut engine = Engine::new();
+
+        engine
+            .handle(DepositTx {
+                client_id: 1,
+                tx_id: 1,
+                amount: dec!(100.0),
+            })
+            .unwrap();
+        engine
+            .handle(WithdrawalTx {
+                client_id: 1,
+                tx_id: 2,
+                amount: dec!(100.0),
+            })
+            .unwrap();
+        engine
+            .handle(DisputeTx {
+                client_id: 1,
+                tx_id: 1,
+            })
+            .unwrap();
+
+        assert_eq!(engine.house().held, dec!(100.0));
+
+        engine
+            .handle(ChargebackTx {
+                client_id: 1,
+                tx_id: 1,
+            })
+            .unwrap();
+
+        let client = engine.clients.get(&1).unwrap();
+        assert_eq!(client.total, dec!(-100.0));
+        assert_eq!(
+            *engine.house(),
+            HouseAccounts {
+                deposited: dec!(100.0),
+                withdrawn: dec!(100.0),
+                held: dec!(0),
+                charged_back: dec!(100.0),
+            }
+        );
+    }
+
+    #[test]
+    fn test_process_deposit_overflow_rejected() {
+        let mut engine = Engine::new();
+
+        engine
+            .handle(DepositTx {
+                client_id: 1,
+                tx_id: 1,
+                amount: Decimal::MAX,
+            })
+            .unwrap();
+
+        assert_eq!(
+            engine.handle(DepositTx {
+                client_id: 1,
+                tx_id: 2,
+                amount: dec!(1),
+            }),
+            Err(RejectReason::Overflow)
+        );
+
+        let client = engine.clients.get(&1).unwrap();
+        assert_eq!(client.available, Decimal::MAX);
+        assert_eq!(client.total, Decimal::MAX);
+        assert_eq!(engine.house().deposited, Decimal::MAX);
+        assert!(!engine.deposits.contains_key(&2));
+    }
+
+    #[test]
+    fn test_process_deposit_above_max_balance_rejected() {
+        let mut engine = Engine::with_config(EngineConfig {
+            max_balance: Some(dec!(100)),
+            ..EngineConfig::default()
+        });
+
+        engine
+            .handle(DepositTx {
+                client_id: 1,
+                tx_id: 1,
+                amount: dec!(60),
+            })
+            .unwrap();
+
+        assert_eq!(
+            engine.handle(DepositTx {
+                client_id: 1,
+                tx_id: 2,
+                amount: dec!(50),
+            }),
+            Err(RejectReason::MaxBalanceExceeded)
+        );
+
+        engine
+            .handle(DepositTx {
+                client_id: 1,
+                tx_id: 3,
+                amount: dec!(40),
+            })
+            .unwrap();
+
+        let client = engine.clients.get(&1).unwrap();
+        assert_eq!(client.total, dec!(100));
+        assert_eq!(engine.house().deposited, dec!(100));
+    }
+
+    #[test]
+    fn test_process_deposit_precision_loss_rejected() {
+        let mut engine = Engine::new();
+
+        engine
+            .handle(DepositTx {
+                client_id: 1,
+                tx_id: 1,
+                amount: Decimal::MAX - dec!(1),
+            })
+            .unwrap();
+
+        // Fits in magnitude but the fractional digits would be rounded away
+        assert_eq!(
+            engine.handle(DepositTx {
+                client_id: 1,
+                tx_id: 2,
+                amount: dec!(0.0001),
+            }),
+            Err(RejectReason::Overflow)
+        );
+
+        let client = engine.clients.get(&1).unwrap();
+        assert_eq!(client.total, Decimal::MAX - dec!(1));
+        assert_eq!(client.total, client.available + client.held);
+    }
+
+    #[test]
+    fn test_zero_operand_is_not_precision_loss() {
+        let mut engine = Engine::new();
+        for (tx_id, amount) in [(1, dec!(2.25)), (2, dec!(1.5))] {
+            engine
+                .handle(DepositTx {
+                    client_id: 1,
+                    tx_id,
+                    amount,
+                })
+                .unwrap();
+        }
+        engine
+            .handle(DisputeTx {
+                client_id: 1,
+                tx_id: 1,
+            })
+            .unwrap();
+        engine
+            .handle(ResolveTx {
+                client_id: 1,
+                tx_id: 1,
+            })
+            .unwrap();
+
+        // Held is now `0.00`, and `0.00 + 1.5` comes back as `1.5` with every
+        // digit kept
+        engine
+            .handle(DisputeTx {
+                client_id: 1,
+                tx_id: 2,
+            })
+            .unwrap();
+
+        let client = engine.clients.get(&1).unwrap();
+        assert_eq!((client.available, client.held), (dec!(2.25), dec!(1.5)));
+    }
+
+    #[test]
+    fn test_capacity_limits_reject_without_changes() {
+        let mut engine = Engine::with_config(EngineConfig {
+            max_clients: Some(1),
+            max_deposits: Some(2),
+            ..EngineConfig::default()
+        });
+        let deposit = |client_id, tx_id| DepositTx {
+            client_id,
+            tx_id,
+            amount: dec!(1),
+        };
+
+        engine.handle(deposit(1, 1)).unwrap();
+        assert_eq!(
+            engine.handle(deposit(2, 2)),
+            Err(RejectReason::CapacityExceeded)
+        );
+        assert!(!engine.clients.contains_key(&2));
+
+        engine.handle(deposit(1, 2)).unwrap();
+        assert_eq!(
+            engine.handle(deposit(1, 3)),
+            Err(RejectReason::CapacityExceeded)
+        );
+        assert_eq!(engine.clients.get(&1).unwrap().total, dec!(2));
+        assert_eq!(engine.tracked_txs(), 2);
+    }
+
+    #[test]
+    fn test_memory_limit() {
+        let mut engine = Engine::with_config(EngineConfig {
+            max_memory: Some(10_000),
+            ..EngineConfig::default()
+        });
+
+        let mut tx_id = 0;
+        let reason = loop {
+            tx_id += 1;
+            let result = engine.handle(DepositTx {
+                client_id: 1,
+                tx_id,
+                amount: dec!(1),
+            });
+            if let Err(reason) = result {
+                break reason;
+            }
+        };
+
+        assert_eq!(reason, RejectReason::CapacityExceeded);
+        assert!(engine.memory_estimate() <= 10_000);
+        assert_eq!(engine.tracked_txs(), tx_id as usize - 1);
+    }
+
+    #[test]
+    fn test_open_disputes() {
+        let mut engine = Engine::new();
+
+        for tx_id in 1..=3 {
+            engine
+                .handle(DepositTx {
+                    client_id: 1,
+                    tx_id,
+                    amount: dec!(10.0),
+                })
+                .unwrap();
+        }
+        for tx_id in 1..=2 {
+            engine
+                .handle(DisputeTx {
+                    client_id: 1,
+                    tx_id,
+                })
+                .unwrap();
+        }
+        engine
+            .handle(ResolveTx {
+                client_id: 1,
+                tx_id: 2,
+            })
+            .unwrap();
+
+        let open: Vec<TxId> = engine.open_disputes().map(|d| d.tx_id).collect();
+        assert_eq!(open, vec![1]);
+    }
+
+    fn engine_with_rules(rules: Rules) -> Engine {
+        let mut engine = Engine::with_config(EngineConfig {
+            rules,
+            ..EngineConfig::default()
+        });
+        engine
+            .handle(DepositTx {
+                client_id: 1,
+                tx_id: 1,
+                amount: dec!(100),
+            })
+            .unwrap();
+        engine
+            .handle(WithdrawalTx {
+                client_id: 1,
+                tx_id: 2,
+                amount: dec!(30),
+            })
+            .unwrap();
+        engine
+    }
+
+    #[test]
+    fn test_rules_v1_withdrawal_not_disputable() {
+        let mut engine = engine_with_rules(Rules::V1);
+
+        assert_eq!(
+            engine.handle(DisputeTx {
+                client_id: 1,
+                tx_id: 2,
+            }),
+            Err(RejectReason::UnknownTx)
+        );
+        assert!(engine.withdrawals.is_empty());
+    }
+
+    #[test]
+    fn test_rules_v2_withdrawal_dispute_and_resolve() {
+        let mut engine = engine_with_rules(Rules::V2);
+
+        engine
+            .handle(DisputeTx {
+                client_id: 1,
+                tx_id: 2,
+            })
+            .unwrap();
+        let client = engine.clients.get(&1).unwrap();
+        assert_eq!(client.available, dec!(70));
+        assert_eq!(client.held, dec!(30));
+        assert_eq!(client.total, dec!(100));
+        assert_eq!(engine.house.withdrawn, dec!(0));
+
+        engine
+            .handle(ResolveTx {
+                client_id: 1,
+                tx_id: 2,
+            })
+            .unwrap();
+        let client = engine.clients.get(&1).unwrap();
+        assert_eq!(client.available, dec!(70));
+        assert_eq!(client.held, dec!(0));
+        assert_eq!(client.total, dec!(70));
+        assert_eq!(engine.house.withdrawn, dec!(30));
+    }
+
+    #[test]
+    fn test_rules_v2_withdrawal_chargeback_returns_funds() {
+        let mut engine = engine_with_rules(Rules::V2);
+
+        engine
+            .handle(DisputeTx {
+                client_id: 1,
+                tx_id: 2,
+            })
+            .unwrap();
+        engine
+            .handle(ChargebackTx {
+                client_id: 1,
+                tx_id: 2,
+            })
+            .unwrap();
+
+        let client = engine.clients.get(&1).unwrap();
+        assert_eq!(client.available, dec!(100));
+        assert_eq!(client.held, dec!(0));
+        assert_eq!(client.total, dec!(100));
+        assert!(client.locked);
+        assert_eq!(engine.house.withdrawn, dec!(0));
+        assert_eq!(engine.house.charged_back, dec!(0));
+    }
+
+    #[test]
+    fn test_rules_v2_resolve_on_locked_account_rejected() {
+        let mut engine = engine_with_rules(Rules::V2);
+
+        for tx_id in [1, 2] {
+            engine
+                .handle(DisputeTx {
+                    client_id: 1,
+                    tx_id,
+                })
+                .unwrap();
+        }
+        engine
+            .handle(ChargebackTx {
+                client_id: 1,
+                tx_id: 1,
+            })
+            .unwrap();
+
+        assert_eq!(
+            engine.handle(ResolveTx {
+                client_id: 1,
+                tx_id: 2,
+            }),
+            Err(RejectReason::AccountLocked)
+        );
+        let open: Vec<TxId> = engine.open_disputes().map(|d| d.tx_id).collect();
+        assert_eq!(open, vec![2]);
+    }
+
+    #[test]
+    fn test_fork_is_independent() {
+        let mut engine = Engine::new();
+        engine
+            .process_tx(Tx::Deposit(DepositTx {
+                client_id: 1,
+                tx_id: 1,
+                amount: dec!(10),
+            }))
+            .unwrap();
+
+        let mut fork = engine.fork();
+        fork.process_tx(Tx::Dispute(DisputeTx {
+            client_id: 1,
+            tx_id: 1,
+        }))
+        .unwrap();
+        fork.process_tx(Tx::Chargeback(ChargebackTx {
+            client_id: 1,
+            tx_id: 1,
+        }))
+        .unwrap();
+        assert!(fork.client(1).unwrap().locked);
+
+        let (_, status) = engine.deposits.get(&1).unwrap();
+        assert_eq!(*status, DisputeState::Normal);
+        assert!(!engine.client(1).unwrap().locked);
+        assert_eq!(engine.client(1).unwrap().available, dec!(10));
+    }
+
+    #[test]
+    fn test_per_client_keys_tell_deposits_with_the_same_id_apart() {
+        let mut engine = Engine::with_config(EngineConfig {
+            tx_keys: TxKeys::PerClient,
+            ..EngineConfig::default()
+        });
+        for (client_id, amount) in [(1, dec!(10)), (2, dec!(4))] {
+            engine
+                .process_tx(Tx::Deposit(DepositTx {
+                    client_id,
+                    tx_id: 7,
+                    amount,
+                }))
+                .unwrap();
+        }
+        assert_eq!(engine.tracked_txs(), 2);
+        engine
+            .process_tx(Tx::Dispute(DisputeTx {
+                client_id: 2,
+                tx_id: 7,
+            }))
+            .unwrap();
+        assert_eq!(
+            engine.process_tx(Tx::Dispute(DisputeTx {
+                client_id: 2,
+                tx_id: 8,
+            })),
+            Err(RejectReason::UnknownTx)
+        );
+        assert_eq!(engine.client(1).unwrap().held, dec!(0));
+        assert_eq!(engine.client(2).unwrap().held, dec!(4));
+
+        // Both survive a snapshot, whatever the keys of the reading engine
+        let mut buf = Vec::new();
+        engine.write_snapshot(&mut buf).unwrap();
+        let mut restored = Engine::read_snapshot(buf.as_slice()).unwrap();
+        assert_eq!(restored.tracked_txs(), 2);
+        restored.set_config(engine.config().clone());
+        restored
+            .process_tx(Tx::Resolve(ResolveTx {
+                client_id: 2,
+                tx_id: 7,
+            }))
+            .unwrap();
+        assert_eq!(restored.client(2).unwrap().available, dec!(4));
+
+        // With global ids the second deposit conflicts with the first
+        let mut engine = Engine::new();
+        let results: Vec<_> = [1, 2]
+            .map(|client_id| {
+                engine.process_tx(Tx::Deposit(DepositTx {
+                    client_id,
+                    tx_id: 7,
+                    amount: dec!(1),
+                }))
+            })
+            .into();
+        assert_eq!(results, [Ok(()), Err(RejectReason::ConflictingTx)]);
+        assert_eq!(engine.tracked_txs(), 1);
+    }
+
+    #[test]
+    fn test_reused_id_with_another_payload_is_a_conflict() {
+        let mut engine = Engine::with_config(EngineConfig {
+            rules: Rules::V2,
+            ..EngineConfig::default()
+        });
+        let deposit = |client_id, tx_id, amount| {
+            Tx::Deposit(DepositTx {
+                client_id,
+                tx_id,
+                amount,
+            })
+        };
+        engine.process_tx(deposit(1, 1, dec!(10))).unwrap();
+        engine
+            .process_tx(Tx::Withdrawal(WithdrawalTx {
+                client_id: 1,
+                tx_id: 2,
+                amount: dec!(3),
+            }))
+            .unwrap();
+
+        for tx in [
+            deposit(1, 1, dec!(10.5)),
+            deposit(4, 1, dec!(10)),
+            deposit(1, 2, dec!(3)),
+            Tx::Withdrawal(WithdrawalTx {
+                client_id: 1,
+                tx_id: 1,
+                amount: dec!(10),
+            }),
+        ] {
+            assert_eq!(engine.process_tx(tx), Err(RejectReason::ConflictingTx));
+        }
+        // No client is created for a conflicting row
+        assert!(engine.client(4).is_none());
+        assert_eq!(engine.client(1).unwrap().available, dec!(7));
+    }
+
+    #[test]
+    fn test_replayed_deposit_credited_once() {
+        let mut engine = Engine::new();
+        let deposit = Tx::Deposit(DepositTx::new(1, 1, dec!(10)).unwrap());
+        engine.process_tx(deposit).unwrap();
+        assert_eq!(engine.process_tx(deposit), Err(RejectReason::DuplicateTx));
+        let client = engine.client(1).unwrap();
+        assert_eq!((client.available, client.total), (dec!(10), dec!(10)));
+        assert_eq!(client.stats.deposits, 1);
+    }
+
+    #[test]
+    fn test_queued_dispute_applied_when_deposit_arrives() {
+        let mut engine = Engine::with_config(EngineConfig {
+            missing_deposit: MissingDeposit::Queue,
+            ..EngineConfig::default()
+        });
+
+        let dispute = Tx::Dispute(DisputeTx {
+            client_id: 1,
+            tx_id: 1,
+        });
+        assert_eq!(engine.process_tx(dispute), Err(RejectReason::Queued));
+        // Another client's deposit is a mismatch, not a missing deposit
+        engine
+            .process_tx(Tx::Deposit(DepositTx {
+                client_id: 2,
+                tx_id: 2,
+                amount: dec!(5),
+            }))
+            .unwrap();
+        assert_eq!(
+            engine.process_tx(Tx::Dispute(DisputeTx {
+                client_id: 1,
+                tx_id: 2,
+            })),
+            Err(RejectReason::UnknownClient)
+        );
+        assert_eq!(engine.queued_txs(), 1);
+
+        engine
+            .process_tx(Tx::Deposit(DepositTx {
+                client_id: 1,
+                tx_id: 1,
+                amount: dec!(10),
+            }))
+            .unwrap();
+        let dequeued: Vec<_> = engine
+            .drain_dequeued()
+            .map(|(tx, result)| (tx.tx_type(), result))
+            .collect();
+        assert_eq!(dequeued, vec![(TxType::Dispute, Ok(()))]);
+        let client = engine.clients.get(&1).unwrap();
+        assert_eq!(client.available, dec!(0));
+        assert_eq!(client.held, dec!(10));
+        assert_eq!(engine.queued_txs(), 0);
+
+        // Rows waiting for a rejected deposit are rejected with it
+        let resolve = Tx::Resolve(ResolveTx {
+            client_id: 2,
+            tx_id: 3,
+        });
+        assert_eq!(engine.process_tx(resolve), Err(RejectReason::Queued));
+        let withdrawal = Tx::Withdrawal(WithdrawalTx::new(2, 3, dec!(50)).unwrap());
+        assert_eq!(
+            engine.process_tx(withdrawal),
+            Err(RejectReason::InsufficientFunds)
+        );
+        let dequeued: Vec<_> = engine
+            .drain_dequeued()
+            .map(|(tx, result)| (tx.tx_type(), result))
+            .collect();
+        assert_eq!(
+            dequeued,
+            vec![(TxType::Resolve, Err(RejectReason::UnknownTx))]
+        );
+        assert_eq!(engine.drain_dequeued().count(), 0);
+
+        // Without the policy nothing is queued
+        let mut engine = Engine::new();
+        assert_eq!(engine.process_tx(dispute), Err(RejectReason::UnknownClient));
+        assert_eq!(engine.queued_txs(), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "csv")]
     fn test_end_to_end_csv_processing() {
+        use crate::io::csv::CsvRow;
+        use std::io::Write;
+        use tempfile::NamedTempFile;
+
         // Note: This duplicates CSV processing logic from main.rs
         // Could be extracted to Engine::process_csv() to reduce duplication
         const TEST_CSV: &str = "\
@@ -980,16 +2108,16 @@ deposit,2,6,50.0";
                 Err(_) => continue,
             };
 
-            engine.process_tx(tx);
+            let _ = engine.process_tx(tx);
         }
 
-        let client1 = engine.clients().get(&1).unwrap();
+        let client1 = engine.client(1).unwrap();
         assert_eq!(client1.available, dec!(120.0));
         assert_eq!(client1.held, dec!(0));
         assert_eq!(client1.total, dec!(120.0));
         assert!(!client1.locked);
 
-        let client2 = engine.clients().get(&2).unwrap();
+        let client2 = engine.client(2).unwrap();
         assert_eq!(client2.available, dec!(100.0));
         assert_eq!(client2.held, dec!(0));
         assert_eq!(client2.total, dec!(100.0));
@@ -1000,6 +2128,10 @@ deposit,2,6,50.0";
 #[cfg(test)]
 mod property_tests {
     use super::*;
+    use crate::{
+        engine::rules::Rules,
+        types::transactions::{ChargebackTx, DisputeTx, ResolveTx},
+    };
     use proptest::prelude::*;
     use rust_decimal::Decimal;
 
@@ -1040,14 +2172,71 @@ mod property_tests {
         ]
     }
 
+    fn arb_huge_transaction() -> impl Strategy<Value = Tx> {
+        let amount = prop_oneof![
+            Just(Decimal::MAX),
+            Just(Decimal::MAX / Decimal::TWO),
+            (0i64..100000).prop_map(|amount| Decimal::new(amount, 4)),
+        ];
+
+        prop_oneof![
+            (1u16..5, 1u32..50, amount.clone()).prop_map(|(client, tx, amount)| {
+                Tx::Deposit(DepositTx {
+                    client_id: client,
+                    tx_id: tx,
+                    amount,
+                })
+            }),
+            (1u16..5, 1u32..50, amount).prop_map(|(client, tx, amount)| {
+                Tx::Withdrawal(WithdrawalTx {
+                    client_id: client,
+                    tx_id: tx,
+                    amount,
+                })
+            }),
+            (1u16..5, 1u32..50).prop_map(|(client, tx)| {
+                Tx::Dispute(DisputeTx {
+                    client_id: client,
+                    tx_id: tx,
+                })
+            }),
+            (1u16..5, 1u32..50).prop_map(|(client, tx)| {
+                Tx::Resolve(ResolveTx {
+                    client_id: client,
+                    tx_id: tx,
+                })
+            }),
+            (1u16..5, 1u32..50).prop_map(|(client, tx)| {
+                Tx::Chargeback(ChargebackTx {
+                    client_id: client,
+                    tx_id: tx,
+                })
+            }),
+        ]
+    }
+
     proptest! {
+        #[test]
+        fn test_engine_never_panics_near_decimal_limits(txs in prop::collection::vec(arb_huge_transaction(), 0..300)) {
+            let mut engine = Engine::new();
+
+            for tx in txs {
+                let _ = engine.process_tx(tx);
+
+                for (_, client) in engine.clients.iter() {
+                    prop_assert_eq!(client.total, client.available + client.held);
+                    prop_assert!(client.held >= Decimal::ZERO);
+                }
+            }
+        }
+
         #[test]
         fn test_engine_never_panics(txs in prop::collection::vec(arb_transaction(), 0..1000)) {
             let mut engine = Engine::new();
 
             // Process all transactions - should never panic
             for tx in txs {
-                engine.process_tx(tx);
+                let _ = engine.process_tx(tx);
             }
 
             // Invariant checks
@@ -1058,11 +2247,17 @@ mod property_tests {
         }
 
         #[test]
-        fn test_invariants_hold(txs in prop::collection::vec(arb_transaction(), 0..500)) {
-            let mut engine = Engine::new();
+        fn test_invariants_hold(
+            txs in prop::collection::vec(arb_transaction(), 0..500),
+            rules in prop_oneof![Just(Rules::V1), Just(Rules::V2)],
+        ) {
+            let mut engine = Engine::with_config(EngineConfig {
+                rules,
+                ..EngineConfig::default()
+            });
 
             for tx in txs {
-                engine.process_tx(tx);
+                let _ = engine.process_tx(tx);
 
                 // After every transaction, check invariants
                 for (_, client) in engine.clients.iter() {
@@ -1076,6 +2271,22 @@ mod property_tests {
                         "Invariant violated: held is negative"
                     );
                 }
+
+                let house = engine.house();
+                let total: Decimal = engine.clients.values().map(|c| c.total).sum();
+                let held: Decimal = engine.clients.values().map(|c| c.held).sum();
+                prop_assert_eq!(
+                    total,
+                    house.deposited - house.withdrawn - house.charged_back,
+                    "Invariant violated: client totals don't match the house accounts"
+                );
+                prop_assert_eq!(held, house.held);
+
+                let mut totals = Totals::default();
+                for client in engine.clients.values() {
+                    totals.add_client(client);
+                }
+                prop_assert_eq!(engine.totals(), totals);
             }
         }
     }
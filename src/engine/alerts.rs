@@ -0,0 +1,156 @@
+//! Balance thresholds checked as transactions are applied, so a client
+//! crossing one is flagged mid-run instead of after the batch.
+
+use std::{collections::HashSet, fmt, str::FromStr};
+
+use rust_decimal::Decimal;
+
+use crate::types::{
+    client::{Balance, Client},
+    common::ClientId,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Above,
+    Below,
+}
+
+/// A condition on one balance, written `held>1000000` or `available<-10000`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Threshold {
+    pub balance: Balance,
+    pub comparison: Comparison,
+    pub limit: Decimal,
+}
+
+impl Threshold {
+    pub fn is_crossed(&self, client: &Client) -> bool {
+        let value = self.balance.of(client);
+        match self.comparison {
+            Comparison::Above => value > self.limit,
+            Comparison::Below => value < self.limit,
+        }
+    }
+}
+
+impl fmt::Display for Threshold {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = match self.comparison {
+            Comparison::Above => '>',
+            Comparison::Below => '<',
+        };
+        write!(f, "{}{op}{}", self.balance, self.limit)
+    }
+}
+
+impl FromStr for Threshold {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some(at) = s.find(['>', '<']) else {
+            return Err(format!(
+                "invalid threshold `{s}`, expected e.g. `held>1000000`"
+            ));
+        };
+        let comparison = match &s[at..at + 1] {
+            ">" => Comparison::Above,
+            _ => Comparison::Below,
+        };
+        let balance = s[..at].trim().parse()?;
+        let limit = s[at + 1..]
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid amount in threshold `{s}`"))?;
+
+        Ok(Threshold {
+            balance,
+            comparison,
+            limit,
+        })
+    }
+}
+
+/// Reports a threshold once when a client crosses it and again only after
+/// the client went back and crossed it anew.
+#[derive(Debug, Default)]
+pub struct AlertMonitor {
+    thresholds: Vec<Threshold>,
+    /// Client and index of every threshold currently crossed
+    crossed: HashSet<(ClientId, usize)>,
+}
+
+impl AlertMonitor {
+    pub fn new(thresholds: Vec<Threshold>) -> Self {
+        AlertMonitor {
+            thresholds,
+            crossed: HashSet::new(),
+        }
+    }
+
+    /// Marks what the given clients already cross as reported, so a resumed
+    /// run doesn't alert again for it.
+    pub fn arm<'a>(&mut self, clients: impl IntoIterator<Item = &'a Client>) {
+        for client in clients {
+            self.check(client, |_| {});
+        }
+    }
+
+    /// Calls `on_alert` for every threshold `client` has crossed since it
+    /// was last checked.
+    pub fn check(&mut self, client: &Client, mut on_alert: impl FnMut(&Threshold)) {
+        for (i, threshold) in self.thresholds.iter().enumerate() {
+            if threshold.is_crossed(client) {
+                if self.crossed.insert((client.id, i)) {
+                    on_alert(threshold);
+                }
+            } else {
+                self.crossed.remove(&(client.id, i));
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.thresholds.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_parse_threshold() {
+        let threshold: Threshold = "available<-10000".parse().unwrap();
+        assert_eq!(
+            threshold,
+            Threshold {
+                balance: Balance::Available,
+                comparison: Comparison::Below,
+                limit: dec!(-10000),
+            }
+        );
+        assert_eq!(threshold.to_string(), "available<-10000");
+        assert!("held=5".parse::<Threshold>().is_err());
+        assert!("owed>5".parse::<Threshold>().is_err());
+        assert!("held>lots".parse::<Threshold>().is_err());
+    }
+
+    #[test]
+    fn test_alerts_once_per_crossing() {
+        let mut monitor = AlertMonitor::new(vec!["held>100".parse().unwrap()]);
+        let mut client = Client::new(1);
+        let mut alerts = 0;
+
+        for held in [dec!(50), dec!(150), dec!(200), dec!(10), dec!(101)] {
+            client.held = held;
+            monitor.check(&client, |_| alerts += 1);
+        }
+        assert_eq!(alerts, 2);
+
+        let mut resumed = AlertMonitor::new(vec!["held>100".parse().unwrap()]);
+        resumed.arm([&client]);
+        resumed.check(&client, |_| panic!("already reported"));
+    }
+}
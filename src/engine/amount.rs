@@ -0,0 +1,308 @@
+//! The one place amounts are checked and combined: every deposit and
+//! withdrawal amount goes through `AmountContext::ingress` and every balance
+//! update through `add`/`sub`, so guardrails on scale and magnitude hold for
+//! every value the engine stores. `CurrencyRules` brings amounts to a
+//! currency's minor unit where one is written in a currency.
+
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+use rust_decimal::{Decimal, RoundingStrategy};
+
+use crate::types::reject::RejectReason;
+
+/// Limits and normalization applied to amounts and the balances they lead to.
+/// The default accepts anything `Decimal` can represent exactly.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AmountContext {
+    /// Amounts with more decimal places are rejected as `invalid_amount`
+    pub max_scale: Option<u32>,
+    /// Amounts above this are rejected as `invalid_amount`, client balances
+    /// that would get further from zero than this as `overflow`
+    pub max_magnitude: Option<Decimal>,
+    /// Strip trailing zeros from amounts and balances, `1.50` is stored as `1.5`
+    pub normalize: bool,
+}
+
+impl AmountContext {
+    /// A deposit or withdrawal amount as the engine stores it.
+    pub fn ingress(&self, amount: Decimal) -> Result<Decimal, RejectReason> {
+        if amount <= Decimal::ZERO
+            || self.max_scale.is_some_and(|max| amount.scale() > max)
+            || self.max_magnitude.is_some_and(|max| amount > max)
+        {
+            return Err(RejectReason::InvalidAmount);
+        }
+        Ok(self.finish(amount))
+    }
+
+    // Balances are only updated once every new value is known to fit, so a
+    // rejected transaction never leaves a partial update behind.
+    pub fn add(&self, a: Decimal, b: Decimal) -> Result<Decimal, RejectReason> {
+        self.result(a.checked_add(b), a, b)
+    }
+
+    pub fn sub(&self, a: Decimal, b: Decimal) -> Result<Decimal, RejectReason> {
+        self.result(a.checked_sub(b), a, b)
+    }
+
+    /// `add` for the house totals, which sum up every client and so aren't
+    /// bounded by `max_magnitude`.
+    pub fn add_aggregate(&self, a: Decimal, b: Decimal) -> Result<Decimal, RejectReason> {
+        Ok(self.finish(exact(a.checked_add(b), a, b)?))
+    }
+
+    /// `sub` for the house totals, see `add_aggregate`.
+    pub fn sub_aggregate(&self, a: Decimal, b: Decimal) -> Result<Decimal, RejectReason> {
+        Ok(self.finish(exact(a.checked_sub(b), a, b)?))
+    }
+
+    fn result(
+        &self,
+        value: Option<Decimal>,
+        a: Decimal,
+        b: Decimal,
+    ) -> Result<Decimal, RejectReason> {
+        let value = exact(value, a, b)?;
+        if self.max_magnitude.is_some_and(|max| value.abs() > max) {
+            return Err(RejectReason::Overflow);
+        }
+        Ok(self.finish(value))
+    }
+
+    fn finish(&self, value: Decimal) -> Decimal {
+        if self.normalize {
+            value.normalize()
+        } else {
+            value
+        }
+    }
+}
+
+/// How an amount is brought to a currency's minor unit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Rounding {
+    /// To the nearest unit, halves to the even one (banker's rounding)
+    #[default]
+    HalfEven,
+    /// To the nearest unit, halves away from zero
+    HalfUp,
+    /// Towards zero
+    Down,
+}
+
+impl Rounding {
+    pub const ALL: [Rounding; 3] = [Rounding::HalfEven, Rounding::HalfUp, Rounding::Down];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Rounding::HalfEven => "half-even",
+            Rounding::HalfUp => "half-up",
+            Rounding::Down => "down",
+        }
+    }
+
+    fn strategy(&self) -> RoundingStrategy {
+        match self {
+            Rounding::HalfEven => RoundingStrategy::MidpointNearestEven,
+            Rounding::HalfUp => RoundingStrategy::MidpointAwayFromZero,
+            Rounding::Down => RoundingStrategy::ToZero,
+        }
+    }
+}
+
+impl fmt::Display for Rounding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Rounding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Rounding::ALL
+            .into_iter()
+            .find(|rounding| rounding.name() == s)
+            .ok_or_else(|| format!("unknown rounding `{s}`, expected half-even, half-up or down"))
+    }
+}
+
+/// ISO 4217 minor units of the currencies `CurrencyRules` knows by default.
+const MINOR_UNITS: [(&str, u32); 32] = [
+    ("AUD", 2),
+    ("BHD", 3),
+    ("BRL", 2),
+    ("CAD", 2),
+    ("CHF", 2),
+    ("CLF", 4),
+    ("CLP", 0),
+    ("CNY", 2),
+    ("CZK", 2),
+    ("DKK", 2),
+    ("EUR", 2),
+    ("GBP", 2),
+    ("HKD", 2),
+    ("HUF", 2),
+    ("INR", 2),
+    ("IQD", 3),
+    ("ISK", 0),
+    ("JOD", 3),
+    ("JPY", 0),
+    ("KRW", 0),
+    ("KWD", 3),
+    ("LYD", 3),
+    ("MXN", 2),
+    ("NOK", 2),
+    ("NZD", 2),
+    ("OMR", 3),
+    ("PLN", 2),
+    ("SEK", 2),
+    ("SGD", 2),
+    ("TND", 3),
+    ("USD", 2),
+    ("VND", 0),
+];
+
+/// Minor units per currency code and the rounding that brings amounts to
+/// them, ISO 4217 by default and adjustable per deployment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CurrencyRules {
+    minor_units: BTreeMap<String, u32>,
+    pub rounding: Rounding,
+}
+
+impl Default for CurrencyRules {
+    fn default() -> Self {
+        CurrencyRules {
+            minor_units: MINOR_UNITS
+                .into_iter()
+                .map(|(code, units)| (code.to_string(), units))
+                .collect(),
+            rounding: Rounding::default(),
+        }
+    }
+}
+
+impl CurrencyRules {
+    /// Decimal places of `code`, `None` for a currency without known minor
+    /// units (e.g. `XXX`), whose amounts are left as they are.
+    pub fn minor_units(&self, code: &str) -> Option<u32> {
+        self.minor_units.get(code).copied()
+    }
+
+    pub fn set_minor_units(&mut self, code: &str, units: u32) {
+        self.minor_units.insert(code.to_string(), units);
+    }
+
+    /// `amount` with exactly the minor units of `code`, `12.5` JPY is `12` or `13`.
+    pub fn round(&self, amount: Decimal, code: &str) -> Decimal {
+        match self.minor_units(code) {
+            Some(units) => {
+                let mut amount = amount.round_dp_with_strategy(units, self.rounding.strategy());
+                amount.rescale(units);
+                amount
+            }
+            None => amount,
+        }
+    }
+}
+
+fn exact(result: Option<Decimal>, a: Decimal, b: Decimal) -> Result<Decimal, RejectReason> {
+    match result {
+        // Near the limits Decimal drops fractional digits instead of failing,
+        // with a zero operand it returns the other one as it is
+        Some(value) if value.scale() == a.scale().max(b.scale()) || a.is_zero() || b.is_zero() => {
+            Ok(value)
+        }
+        _ => Err(RejectReason::Overflow),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_ingress_limits() {
+        let amounts = AmountContext {
+            max_scale: Some(2),
+            max_magnitude: Some(dec!(1000)),
+            normalize: false,
+        };
+        assert_eq!(amounts.ingress(dec!(999.99)), Ok(dec!(999.99)));
+        assert_eq!(
+            amounts.ingress(dec!(1.001)),
+            Err(RejectReason::InvalidAmount)
+        );
+        assert_eq!(
+            amounts.ingress(dec!(1000.01)),
+            Err(RejectReason::InvalidAmount)
+        );
+        assert_eq!(amounts.ingress(dec!(0)), Err(RejectReason::InvalidAmount));
+        assert_eq!(
+            AmountContext::default().ingress(dec!(1.00001)),
+            Ok(dec!(1.00001))
+        );
+    }
+
+    #[test]
+    fn test_currency_minor_units() {
+        let mut rules = CurrencyRules::default();
+        assert_eq!(rules.round(dec!(12.5), "JPY").to_string(), "12");
+        assert_eq!(rules.round(dec!(1.2345), "BHD").to_string(), "1.234");
+        assert_eq!(rules.round(dec!(1), "EUR").to_string(), "1.00");
+        assert_eq!(rules.round(dec!(1.23456), "XXX").to_string(), "1.23456");
+
+        rules.rounding = Rounding::HalfUp;
+        rules.set_minor_units("XXX", 1);
+        assert_eq!(rules.round(dec!(12.5), "JPY").to_string(), "13");
+        assert_eq!(rules.round(dec!(-0.25), "XXX").to_string(), "-0.3");
+        rules.rounding = "down".parse().unwrap();
+        assert_eq!(rules.round(dec!(0.29), "XXX").to_string(), "0.2");
+    }
+
+    #[test]
+    fn test_results_are_bounded_and_normalized() {
+        let amounts = AmountContext {
+            max_magnitude: Some(dec!(100)),
+            normalize: true,
+            ..AmountContext::default()
+        };
+        let sum = amounts.add(dec!(1.25), dec!(1.25)).unwrap();
+        assert_eq!((sum, sum.scale()), (dec!(2.5), 1));
+        assert_eq!(amounts.add(dec!(99), dec!(2)), Err(RejectReason::Overflow));
+        assert_eq!(amounts.sub(dec!(-99), dec!(2)), Err(RejectReason::Overflow));
+        assert_eq!(
+            AmountContext::default().add(Decimal::MAX, dec!(0.5)),
+            Err(RejectReason::Overflow)
+        );
+        assert_eq!(amounts.add_aggregate(dec!(99), dec!(2)), Ok(dec!(101)));
+    }
+
+    #[test]
+    fn test_house_totals_are_not_bounded() {
+        use crate::{
+            engine::{Engine, config::EngineConfig},
+            types::transactions::{DepositTx, Tx},
+        };
+
+        let mut engine = Engine::with_config(EngineConfig {
+            amounts: AmountContext {
+                max_magnitude: Some(dec!(1000)),
+                ..AmountContext::default()
+            },
+            ..EngineConfig::default()
+        });
+        for client_id in 1..=2 {
+            let deposit = DepositTx::new(client_id, client_id.into(), dec!(600)).unwrap();
+            engine.process_tx(Tx::Deposit(deposit)).unwrap();
+        }
+        assert_eq!(engine.house().deposited, dec!(1200));
+        let deposit = DepositTx::new(1, 3, dec!(600)).unwrap();
+        assert_eq!(
+            engine.process_tx(Tx::Deposit(deposit)),
+            Err(RejectReason::Overflow)
+        );
+    }
+}
@@ -0,0 +1,331 @@
+//! Applying transactions in batches: all-or-nothing for settlement files that
+//! must not be applied halfway, or with the clients each batch changed for
+//! consumers that keep a copy of the balances up to date.
+
+use std::{collections::BTreeMap, fmt};
+
+use rust_decimal::Decimal;
+
+use crate::{
+    engine::{Engine, prepared::Undo},
+    types::{
+        client::Client,
+        common::{ClientId, TxId},
+        reject::RejectReason,
+        transactions::Tx,
+    },
+};
+
+/// What a batch that went through did.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BatchReport {
+    pub applied: usize,
+    /// Index in the batch and reason of every rejected transaction, only
+    /// possible when rejects are allowed
+    pub rejected: Vec<(usize, RejectReason)>,
+}
+
+/// The invariant that rolled a batch back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Violation {
+    Rejected(RejectReason),
+    Negative(ClientId),
+    Locked(ClientId),
+}
+
+/// A batch that was rolled back, leaving the engine as it was before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchError {
+    /// Index in the batch of the offending transaction
+    pub index: usize,
+    pub tx_id: TxId,
+    pub violation: Violation,
+}
+
+impl fmt::Display for BatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "batch rolled back at #{} (tx {}): ",
+            self.index, self.tx_id
+        )?;
+        match self.violation {
+            Violation::Rejected(reason) => write!(f, "rejected as {reason}"),
+            Violation::Negative(client) => write!(f, "client {client} went negative"),
+            Violation::Locked(client) => write!(f, "client {client} got locked"),
+        }
+    }
+}
+
+impl std::error::Error for BatchError {}
+
+/// The clients a batch changed, as they are after it.
+#[derive(Debug, Clone, Default)]
+pub struct Delta {
+    /// By id, only clients that are new or whose balances or lock changed
+    pub changed: Vec<Client>,
+    /// Index in the batch and reason of every rejected transaction
+    pub rejected: Vec<(usize, RejectReason)>,
+}
+
+/// What a delta compares, activity counters alone aren't a change.
+fn state(client: &Client) -> (Decimal, Decimal, Decimal, bool) {
+    (client.available, client.held, client.total, client.locked)
+}
+
+impl Engine {
+    /// Applies `txs` in order, checking `EngineConfig::batch` after each one.
+    /// The first violation undoes every transaction of the batch applied so
+    /// far. Rejected transactions are counted in their client's activity
+    /// counters only when the batch goes through.
+    pub fn apply_batch(&mut self, txs: Vec<Tx>) -> Result<BatchReport, BatchError> {
+        let invariants = self.config.batch;
+        let mut undos = Vec::with_capacity(txs.len());
+        let mut report = BatchReport::default();
+
+        for (index, &tx) in txs.iter().enumerate() {
+            let undo = Undo::capture(self, tx);
+            let before = self
+                .clients
+                .get(&tx.client_id())
+                .map(|client| (client.available, client.locked));
+            let result = self.process_tx(tx);
+            undos.push(undo);
+
+            let client = self.clients.get(&tx.client_id());
+            let violation = match result {
+                Err(reason) if invariants.no_rejects => Some(Violation::Rejected(reason)),
+                Err(reason) => {
+                    report.rejected.push((index, reason));
+                    None
+                }
+                Ok(()) => {
+                    let (available_before, locked_before) = before.unwrap_or_default();
+                    match client {
+                        Some(client)
+                            if invariants.no_negative
+                                && client.available < Decimal::ZERO
+                                && client.available < available_before =>
+                        {
+                            Some(Violation::Negative(client.id))
+                        }
+                        Some(client) if invariants.no_locks && client.locked && !locked_before => {
+                            Some(Violation::Locked(client.id))
+                        }
+                        _ => {
+                            report.applied += 1;
+                            None
+                        }
+                    }
+                }
+            };
+
+            if let Some(violation) = violation {
+                for (undo, &tx) in undos.into_iter().zip(&txs).rev() {
+                    undo.restore(self, tx);
+                }
+                return Err(BatchError {
+                    index,
+                    tx_id: tx.tx_id(),
+                    violation,
+                });
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Applies `txs` in order like `process_tx` and returns only the clients
+    /// they changed, so a consumer mirroring the balances can upsert a handful
+    /// of rows per batch instead of every account.
+    pub fn process_batch(&mut self, txs: impl IntoIterator<Item = Tx>) -> Delta {
+        // The state of every client a transaction could touch, before the batch
+        let mut before = BTreeMap::new();
+        let mut delta = Delta::default();
+
+        for (index, tx) in txs.into_iter().enumerate() {
+            // A dispute, resolve or chargeback, or rows queued behind a
+            // deposit, change the client owning the referenced transaction
+            for client_id in [Some(tx.client_id()), self.tx_owner(tx.tx_id())]
+                .into_iter()
+                .flatten()
+            {
+                before
+                    .entry(client_id)
+                    .or_insert_with(|| self.clients.get(&client_id).map(state));
+            }
+            if let Err(reason) = self.process_tx(tx) {
+                delta.rejected.push((index, reason));
+            }
+        }
+
+        delta.changed = before
+            .into_iter()
+            .filter_map(|(client_id, before)| {
+                let client = self.clients.get(&client_id)?;
+                (before != Some(state(client))).then(|| client.clone())
+            })
+            .collect();
+        delta
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        engine::config::{BatchInvariants, EngineConfig},
+        types::transactions::{ChargebackTx, DepositTx, DisputeTx, WithdrawalTx},
+    };
+    use rust_decimal_macros::dec;
+
+    fn settlement() -> Vec<Tx> {
+        vec![
+            Tx::Deposit(DepositTx {
+                client_id: 1,
+                tx_id: 1,
+                amount: dec!(10),
+            }),
+            Tx::Deposit(DepositTx {
+                client_id: 2,
+                tx_id: 2,
+                amount: dec!(5),
+            }),
+            Tx::Withdrawal(WithdrawalTx {
+                client_id: 1,
+                tx_id: 3,
+                amount: dec!(8),
+            }),
+            Tx::Dispute(DisputeTx {
+                client_id: 1,
+                tx_id: 1,
+            }),
+            Tx::Chargeback(ChargebackTx {
+                client_id: 1,
+                tx_id: 1,
+            }),
+        ]
+    }
+
+    #[test]
+    fn test_violation_rolls_back_the_whole_batch() {
+        let mut engine = Engine::new();
+        let err = engine.apply_batch(settlement()).unwrap_err();
+        assert_eq!(
+            err,
+            BatchError {
+                index: 3,
+                tx_id: 1,
+                violation: Violation::Negative(1),
+            }
+        );
+        assert!(engine.clients_iter().next().is_none());
+        assert_eq!(engine.tracked_txs(), 0);
+        assert_eq!(engine.totals().total, dec!(0));
+
+        let mut txs = settlement();
+        txs.push(Tx::Withdrawal(WithdrawalTx {
+            client_id: 2,
+            tx_id: 4,
+            amount: dec!(50),
+        }));
+        engine.set_config(EngineConfig {
+            batch: BatchInvariants {
+                no_negative: false,
+                ..BatchInvariants::default()
+            },
+            ..EngineConfig::default()
+        });
+        let err = engine.apply_batch(txs).unwrap_err();
+        assert_eq!(
+            err.violation,
+            Violation::Rejected(RejectReason::InsufficientFunds)
+        );
+        assert!(engine.clients_iter().next().is_none());
+    }
+
+    #[test]
+    fn test_batch_within_invariants_is_applied() {
+        let mut engine = Engine::with_config(EngineConfig {
+            batch: BatchInvariants {
+                no_rejects: false,
+                no_negative: false,
+                no_locks: false,
+            },
+            ..EngineConfig::default()
+        });
+        let mut txs = settlement();
+        txs.push(Tx::Deposit(DepositTx {
+            client_id: 1,
+            tx_id: 5,
+            amount: dec!(1),
+        }));
+
+        let report = engine.apply_batch(txs).unwrap();
+        assert_eq!(report.applied, 5);
+        assert_eq!(report.rejected, vec![(5, RejectReason::AccountLocked)]);
+        assert!(engine.client(1).unwrap().locked);
+        assert_eq!(engine.client(1).unwrap().available, dec!(-8));
+    }
+
+    #[test]
+    fn test_delta_lists_changed_clients_only() {
+        let mut engine = Engine::new();
+        engine.process_batch((1..=100).map(|client_id| {
+            Tx::Deposit(DepositTx {
+                client_id,
+                tx_id: client_id.into(),
+                amount: dec!(10),
+            })
+        }));
+
+        let delta = engine.process_batch([
+            Tx::Withdrawal(WithdrawalTx {
+                client_id: 7,
+                tx_id: 101,
+                amount: dec!(4),
+            }),
+            // Rejected, client 8 doesn't change
+            Tx::Withdrawal(WithdrawalTx {
+                client_id: 8,
+                tx_id: 102,
+                amount: dec!(50),
+            }),
+            // Names the wrong client, rejected
+            Tx::Dispute(DisputeTx {
+                client_id: 9,
+                tx_id: 3,
+            }),
+            Tx::Dispute(DisputeTx {
+                client_id: 5,
+                tx_id: 5,
+            }),
+            Tx::Chargeback(ChargebackTx {
+                client_id: 5,
+                tx_id: 5,
+            }),
+            Tx::Deposit(DepositTx {
+                client_id: 200,
+                tx_id: 103,
+                amount: dec!(1),
+            }),
+        ]);
+
+        let changed: Vec<_> = delta
+            .changed
+            .iter()
+            .map(|client| (client.id, client.total, client.locked))
+            .collect();
+        assert_eq!(
+            changed,
+            vec![
+                (5, dec!(0), true),
+                (7, dec!(6), false),
+                (200, dec!(1), false)
+            ]
+        );
+        assert_eq!(delta.rejected.len(), 2);
+        assert_eq!(delta.rejected[0], (1, RejectReason::InsufficientFunds));
+        assert!(engine.process_batch([]).changed.is_empty());
+    }
+}
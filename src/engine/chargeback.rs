@@ -0,0 +1,61 @@
+use crate::{
+    engine::{Disputed, Engine, TxHandler, dispute_state::DisputeEvent, find_disputed},
+    types::{
+        client::{LockInfo, LockReason},
+        reject::RejectReason,
+        transactions::ChargebackTx,
+    },
+};
+
+impl TxHandler<ChargebackTx> for Engine {
+    fn handle(&mut self, chargeback_tx: ChargebackTx) -> Result<(), RejectReason> {
+        let amounts = self.config.amounts;
+        let Some(client) = self.clients.get_mut(&chargeback_tx.client_id) else {
+            return Err(RejectReason::UnknownClient);
+        };
+
+        // Deposit or withdrawal must be in a state that can be charged back
+        let (disputed, amount, status, next) = find_disputed(
+            &mut self.deposits,
+            &mut self.withdrawals,
+            &self.settled,
+            &self.config,
+            chargeback_tx.client_id,
+            chargeback_tx.tx_id,
+            DisputeEvent::Chargeback,
+        )?;
+
+        let house_held = amounts.sub_aggregate(self.house.held, amount)?;
+        let held = amounts.sub(client.held, amount)?;
+        let (available, total, charged_back) = match disputed {
+            Disputed::Deposit => (
+                client.available,
+                amounts.sub(client.total, amount)?,
+                amounts.add_aggregate(self.house.charged_back, amount)?,
+            ),
+            // The withdrawal is reversed, the held funds go back to the client
+            Disputed::Withdrawal => (
+                amounts.add(client.available, amount)?,
+                client.total,
+                self.house.charged_back,
+            ),
+        };
+
+        *status = next;
+        client.available = available;
+        client.total = total;
+        client.held = held;
+        if !client.locked {
+            client.locked = true;
+            client.lock = Some(LockInfo::new(
+                LockReason::Chargeback,
+                Some(chargeback_tx.tx_id),
+            ));
+            self.locked_clients += 1;
+        }
+        self.house.held = house_held;
+        self.house.charged_back = charged_back;
+
+        Ok(())
+    }
+}
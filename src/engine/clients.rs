@@ -0,0 +1,181 @@
+//! Client storage indexed directly by id. Ids are `u16`, so a slot per
+//! possible id is cheap and a lookup is two array reads instead of a hash.
+
+use std::ops::Index;
+
+use crate::types::{client::Client, common::ClientId};
+
+/// The clients in arrival order, plus a slot per id seen so far pointing
+/// into them. The dense `Vec` keeps iteration as fast as a lookup.
+#[derive(Debug, Clone, Default)]
+pub struct ClientTable {
+    clients: Vec<Client>,
+    /// Position in `clients` plus one by id, 0 for an unknown id
+    slots: Vec<u32>,
+}
+
+impl ClientTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn position(&self, id: ClientId) -> Option<usize> {
+        match self.slots.get(id as usize) {
+            Some(&slot) if slot > 0 => Some(slot as usize - 1),
+            _ => None,
+        }
+    }
+
+    pub fn get(&self, id: &ClientId) -> Option<&Client> {
+        self.position(*id).map(|i| &self.clients[i])
+    }
+
+    pub fn get_mut(&mut self, id: &ClientId) -> Option<&mut Client> {
+        self.position(*id).map(|i| &mut self.clients[i])
+    }
+
+    pub fn contains_key(&self, id: &ClientId) -> bool {
+        self.position(*id).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.clients.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.clients.is_empty()
+    }
+
+    /// The client with `id`, created with zero balances if it's new.
+    pub fn get_or_insert(&mut self, id: ClientId) -> &mut Client {
+        let i = match self.position(id) {
+            Some(i) => i,
+            None => self.push(id, Client::new(id)),
+        };
+        &mut self.clients[i]
+    }
+
+    /// Stores `client` under `id`, returning the client it replaced.
+    pub fn insert(&mut self, id: ClientId, client: Client) -> Option<Client> {
+        match self.position(id) {
+            Some(i) => Some(std::mem::replace(&mut self.clients[i], client)),
+            None => {
+                self.push(id, client);
+                None
+            }
+        }
+    }
+
+    pub fn remove(&mut self, id: &ClientId) -> Option<Client> {
+        let i = self.position(*id)?;
+        self.slots[*id as usize] = 0;
+        let removed = self.clients.swap_remove(i);
+        if let Some(moved) = self.clients.get(i) {
+            self.slots[moved.id as usize] = i as u32 + 1;
+        }
+        Some(removed)
+    }
+
+    fn push(&mut self, id: ClientId, client: Client) -> usize {
+        let id = id as usize;
+        if self.slots.len() <= id {
+            self.slots.resize(id + 1, 0);
+        }
+        self.clients.push(client);
+        self.slots[id] = self.clients.len() as u32;
+        self.clients.len() - 1
+    }
+
+    pub fn values(&self) -> std::slice::Iter<'_, Client> {
+        self.clients.iter()
+    }
+
+    pub fn values_mut(&mut self) -> std::slice::IterMut<'_, Client> {
+        self.clients.iter_mut()
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &ClientId> {
+        self.clients.iter().map(|client| &client.id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&ClientId, &Client)> {
+        self.clients.iter().map(|client| (&client.id, client))
+    }
+
+    /// Bytes taken by the allocated capacity of both arrays.
+    pub(crate) fn bytes(&self) -> usize {
+        self.clients.capacity() * size_of::<Client>() + self.slots.capacity() * size_of::<u32>()
+    }
+
+    /// `bytes` once `additional` more clients with ids up to `max_id` are added.
+    pub(crate) fn bytes_with(&self, additional: usize, max_id: Option<ClientId>) -> usize {
+        let len = self.clients.len() + additional;
+        let capacity = if len > self.clients.capacity() {
+            (self.clients.capacity() * 2).max(len)
+        } else {
+            self.clients.capacity()
+        };
+        let slots = match max_id {
+            Some(id) if id as usize >= self.slots.capacity() => {
+                (self.slots.capacity() * 2).max(id as usize + 1)
+            }
+            _ => self.slots.capacity(),
+        };
+        capacity * size_of::<Client>() + slots * size_of::<u32>()
+    }
+}
+
+impl Extend<(ClientId, Client)> for ClientTable {
+    fn extend<I: IntoIterator<Item = (ClientId, Client)>>(&mut self, iter: I) {
+        for (id, client) in iter {
+            self.insert(id, client);
+        }
+    }
+}
+
+impl IntoIterator for ClientTable {
+    type Item = (ClientId, Client);
+    type IntoIter = std::iter::Map<std::vec::IntoIter<Client>, fn(Client) -> (ClientId, Client)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.clients.into_iter().map(|client| (client.id, client))
+    }
+}
+
+impl Index<&ClientId> for ClientTable {
+    type Output = Client;
+
+    fn index(&self, id: &ClientId) -> &Client {
+        self.get(id).expect("no client with this id")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_lookups_follow_removals() {
+        let mut table = ClientTable::new();
+        for id in [7, 65_535, 0, 300] {
+            table.get_or_insert(id).available = dec!(1);
+        }
+        table.get_or_insert(300).available += dec!(1);
+
+        assert_eq!(table.remove(&7).map(|c| c.id), Some(7));
+        assert!(table.remove(&7).is_none());
+        // The last client took the removed one's place
+        assert_eq!(
+            table.keys().copied().collect::<Vec<_>>(),
+            vec![300, 65_535, 0]
+        );
+        assert_eq!(table[&300].available, dec!(2));
+        assert_eq!(table.get(&65_535).map(|c| c.id), Some(65_535));
+        assert!(!table.contains_key(&1) && !table.contains_key(&7));
+
+        let replaced = table.insert(0, Client::new(0));
+        assert_eq!(replaced.map(|c| c.available), Some(dec!(1)));
+        assert_eq!(table.len(), 3);
+    }
+}
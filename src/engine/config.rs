@@ -0,0 +1,228 @@
+use std::{fmt, str::FromStr};
+
+use rust_decimal::Decimal;
+
+use crate::engine::{amount::AmountContext, rules::Rules};
+
+/// Knobs that change how the engine treats transactions.
+#[derive(Debug, Clone, Default)]
+pub struct EngineConfig {
+    /// Deposits that would take a client's total above this are rejected
+    pub max_balance: Option<Decimal>,
+    /// Rule set deciding what can be disputed and resolved
+    pub rules: Rules,
+    /// Hard limit on the number of clients
+    pub max_clients: Option<usize>,
+    /// Hard limit on the deposits (and withdrawals under rules v2) kept for disputes
+    pub max_deposits: Option<usize>,
+    /// Hard limit on `Engine::memory_estimate`, in bytes
+    pub max_memory: Option<usize>,
+    /// What happens to disputes, resolves and chargebacks naming a transaction
+    /// that hasn't arrived yet
+    pub missing_deposit: MissingDeposit,
+    /// What happens to withdrawals from a client the engine hasn't seen
+    pub missing_client: MissingClient,
+    /// What rolls back a whole `Engine::apply_batch`
+    pub batch: BatchInvariants,
+    /// Scale and magnitude limits on amounts and balances
+    pub amounts: AmountContext,
+    /// Whether deposit ids are unique across clients or only per client
+    pub tx_keys: TxKeys,
+    /// When locked accounts are reinstated
+    pub unlock: UnlockPolicy,
+}
+
+/// Checks `Engine::apply_batch` makes after every transaction, any failing
+/// one rolls back the whole batch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchInvariants {
+    /// A transaction in the batch is rejected
+    pub no_rejects: bool,
+    /// A transaction takes its client's available balance below zero, or
+    /// further below it
+    pub no_negative: bool,
+    /// A chargeback locks an account
+    pub no_locks: bool,
+}
+
+impl Default for BatchInvariants {
+    fn default() -> Self {
+        BatchInvariants {
+            no_rejects: true,
+            no_negative: true,
+            no_locks: false,
+        }
+    }
+}
+
+/// Policy for rows referencing a deposit the engine hasn't seen.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MissingDeposit {
+    /// Rejected as `unknown_tx`, like any other invalid reference
+    #[default]
+    Reject,
+    /// Kept (reported as `queued`) and applied once the deposit arrives,
+    /// for feeds that deliver disputes ahead of their deposits
+    Queue,
+}
+
+impl MissingDeposit {
+    pub const ALL: [MissingDeposit; 2] = [MissingDeposit::Reject, MissingDeposit::Queue];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MissingDeposit::Reject => "reject",
+            MissingDeposit::Queue => "queue",
+        }
+    }
+}
+
+impl fmt::Display for MissingDeposit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for MissingDeposit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        MissingDeposit::ALL
+            .into_iter()
+            .find(|policy| policy.name() == s)
+            .ok_or_else(|| format!("unknown policy `{s}`, expected reject or queue"))
+    }
+}
+
+/// Policy for withdrawals from a client the engine hasn't seen.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MissingClient {
+    /// Rejected as `unknown_client`, the client stays unknown
+    #[default]
+    Reject,
+    /// The client is added with a zero balance, so the withdrawal is rejected
+    /// as `insufficient_funds` and the client shows up in the output
+    Create,
+}
+
+impl MissingClient {
+    pub const ALL: [MissingClient; 2] = [MissingClient::Reject, MissingClient::Create];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MissingClient::Reject => "reject",
+            MissingClient::Create => "create",
+        }
+    }
+}
+
+impl fmt::Display for MissingClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for MissingClient {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        MissingClient::ALL
+            .into_iter()
+            .find(|policy| policy.name() == s)
+            .ok_or_else(|| format!("unknown policy `{s}`, expected reject or create"))
+    }
+}
+
+/// When a locked account is unlocked again, for providers that reinstate
+/// accounts on their own.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UnlockPolicy {
+    /// Locked accounts stay locked
+    #[default]
+    Never,
+    /// Once none of the client's transactions is under dispute, checked after
+    /// each resolve and chargeback of the client
+    DisputesSettled,
+    /// This many days after the row that locked the account, going by the
+    /// rows' timestamps, checked before each row of the client
+    AfterDays(u32),
+}
+
+impl UnlockPolicy {
+    /// The cooling-off period in milliseconds, for `AfterDays`.
+    pub fn cooling_off(&self) -> Option<i64> {
+        match self {
+            UnlockPolicy::AfterDays(days) => Some(i64::from(*days) * 86_400_000),
+            UnlockPolicy::Never | UnlockPolicy::DisputesSettled => None,
+        }
+    }
+}
+
+impl fmt::Display for UnlockPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnlockPolicy::Never => f.write_str("never"),
+            UnlockPolicy::DisputesSettled => f.write_str("disputes-settled"),
+            UnlockPolicy::AfterDays(days) => write!(f, "{days}d"),
+        }
+    }
+}
+
+impl FromStr for UnlockPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "never" => Ok(UnlockPolicy::Never),
+            "disputes-settled" => Ok(UnlockPolicy::DisputesSettled),
+            _ => s
+                .strip_suffix('d')
+                .and_then(|days| days.parse().ok())
+                .map(UnlockPolicy::AfterDays)
+                .ok_or_else(|| {
+                    format!("unknown policy `{s}`, expected never, disputes-settled or <N>d")
+                }),
+        }
+    }
+}
+
+/// How disputes, resolves and chargebacks are matched to the deposit they name.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TxKeys {
+    /// By transaction id, unique across all clients; naming another client's
+    /// deposit is a `client_mismatch`
+    #[default]
+    Global,
+    /// By client and transaction id, for feeds whose ids are only unique per
+    /// client. Withdrawals kept under rules v2, queued rows and compacted
+    /// transactions are still keyed by id alone
+    PerClient,
+}
+
+impl TxKeys {
+    pub const ALL: [TxKeys; 2] = [TxKeys::Global, TxKeys::PerClient];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TxKeys::Global => "global",
+            TxKeys::PerClient => "per-client",
+        }
+    }
+}
+
+impl fmt::Display for TxKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for TxKeys {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        TxKeys::ALL
+            .into_iter()
+            .find(|keys| keys.name() == s)
+            .ok_or_else(|| format!("unknown tx keys `{s}`, expected global or per-client"))
+    }
+}
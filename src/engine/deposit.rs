@@ -0,0 +1,43 @@
+use crate::{
+    engine::{Engine, TxHandler, dispute_state::DisputeState},
+    types::{reject::RejectReason, transactions::DepositTx},
+};
+
+impl TxHandler<DepositTx> for Engine {
+    fn handle(&mut self, mut deposit_tx: DepositTx) -> Result<(), RejectReason> {
+        let amounts = self.config.amounts;
+        // Checked before the client is created
+        deposit_tx.amount = amounts.ingress(deposit_tx.amount)?;
+        let capacity = self.check_capacity(Some(deposit_tx.client_id), Some(&deposit_tx), None);
+        // A new client is added even if the deposit is rejected later on
+        if !self.clients.contains_key(&deposit_tx.client_id) {
+            capacity?;
+        }
+
+        let client = self.clients.get_or_insert(deposit_tx.client_id);
+
+        if client.locked {
+            return Err(RejectReason::AccountLocked);
+        }
+        capacity?;
+
+        let available = amounts.add(client.available, deposit_tx.amount)?;
+        let total = amounts.add(client.total, deposit_tx.amount)?;
+        let deposited = amounts.add_aggregate(self.house.deposited, deposit_tx.amount)?;
+
+        if let Some(max_balance) = self.config.max_balance
+            && total > max_balance
+        {
+            return Err(RejectReason::MaxBalanceExceeded);
+        }
+
+        client.available = available;
+        client.total = total;
+        self.house.deposited = deposited;
+
+        // Spec claims that the ids are unique, but just to be sure
+        self.deposits.insert_new((deposit_tx, DisputeState::Normal));
+
+        Ok(())
+    }
+}
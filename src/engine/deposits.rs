@@ -0,0 +1,307 @@
+//! Deposits kept for disputes, stored per client so a dispute, resolve or
+//! chargeback finds its deposit among the client's own and the client check
+//! comes for free. Whether deposit ids are unique across clients or only
+//! per client is up to the `TxKeys` the table is keyed by.
+
+use std::{collections::HashMap, sync::Arc};
+
+use crate::{
+    engine::{
+        config::TxKeys, dispute_state::DisputeState, table::TxTable, table_bytes, tx_table_bytes,
+    },
+    types::{
+        common::{ClientId, TxId},
+        transactions::DepositTx,
+    },
+};
+
+type Deposit = (DepositTx, DisputeState);
+
+/// One map of deposits per client, indexed by client id, plus the owner of
+/// every deposit. The owners are only read when a client names a deposit it
+/// doesn't have, to tell another client's deposit from an unknown one, and by
+/// the lookups by transaction id alone. With `TxKeys::PerClient` an id can
+/// belong to several clients, the owner is the first one and a deposit the
+/// client doesn't have is unknown.
+///
+/// A client's map is shared with forks until one side writes to it, like the
+/// shards of a `TxTable`.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DepositTable {
+    by_client: Vec<Arc<HashMap<TxId, Deposit>>>,
+    owners: TxTable<ClientId>,
+    keys: TxKeys,
+    len: usize,
+}
+
+impl DepositTable {
+    #[cfg(test)]
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn with_keys(keys: TxKeys) -> Self {
+        DepositTable {
+            keys,
+            ..Self::default()
+        }
+    }
+
+    /// Keys deposits stored from now on by `keys`, those already stored stay.
+    pub(crate) fn set_keys(&mut self, keys: TxKeys) {
+        self.keys = keys;
+    }
+
+    fn client(&self, client_id: ClientId) -> Option<&HashMap<TxId, Deposit>> {
+        self.by_client
+            .get(client_id as usize)
+            .map(|deposits| &**deposits)
+    }
+
+    fn client_mut(&mut self, client_id: ClientId) -> &mut HashMap<TxId, Deposit> {
+        let index = client_id as usize;
+        if self.by_client.len() <= index {
+            self.by_client.resize_with(index + 1, Default::default);
+        }
+        Arc::make_mut(&mut self.by_client[index])
+    }
+
+    /// The client's deposit `tx_id`, or the client the deposit belongs to
+    /// instead if there is one and ids are global.
+    pub(crate) fn find_for(
+        &mut self,
+        client_id: ClientId,
+        tx_id: TxId,
+    ) -> Result<&mut Deposit, Option<ClientId>> {
+        // A miss must not copy a shared map
+        if !self
+            .client(client_id)
+            .is_some_and(|d| d.contains_key(&tx_id))
+        {
+            return Err(match self.keys {
+                TxKeys::Global => self.owner(tx_id),
+                TxKeys::PerClient => None,
+            });
+        }
+        self.client_mut(client_id)
+            .get_mut(&tx_id)
+            .ok_or(Some(client_id))
+    }
+
+    /// The client a deposit belongs to, the first one to use the id with
+    /// `TxKeys::PerClient`.
+    pub(crate) fn owner(&self, tx_id: TxId) -> Option<ClientId> {
+        self.owners.get(&tx_id).copied()
+    }
+
+    pub(crate) fn get(&self, tx_id: &TxId) -> Option<&Deposit> {
+        self.client(self.owner(*tx_id)?)?.get(tx_id)
+    }
+
+    pub(crate) fn get_for(&self, client_id: ClientId, tx_id: TxId) -> Option<&Deposit> {
+        self.client(client_id)?.get(&tx_id)
+    }
+
+    pub(crate) fn get_mut(&mut self, tx_id: &TxId) -> Option<&mut Deposit> {
+        self.find_for(self.owner(*tx_id)?, *tx_id).ok()
+    }
+
+    #[cfg(test)]
+    pub(crate) fn contains_key(&self, tx_id: &TxId) -> bool {
+        self.owners.contains_key(tx_id)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether `insert_new` would store the deposit.
+    pub(crate) fn is_new(&self, deposit_tx: &DepositTx) -> bool {
+        match self.keys {
+            TxKeys::Global => !self.owners.contains_key(&deposit_tx.tx_id),
+            TxKeys::PerClient => self
+                .get_for(deposit_tx.client_id, deposit_tx.tx_id)
+                .is_none(),
+        }
+    }
+
+    /// Stores `deposit` unless a deposit with its key is already stored.
+    pub(crate) fn insert_new(&mut self, deposit: Deposit) {
+        let (deposit_tx, _) = deposit;
+        if self.is_new(&deposit_tx) {
+            self.store(deposit);
+        }
+    }
+
+    /// Stores `deposit` in its client's map without moving other clients',
+    /// whatever the keys, as read from a snapshot.
+    pub(crate) fn store(&mut self, deposit: Deposit) -> Option<Deposit> {
+        let (deposit_tx, _) = deposit;
+        self.owners
+            .entry(deposit_tx.tx_id)
+            .or_insert(deposit_tx.client_id);
+        let previous = self
+            .client_mut(deposit_tx.client_id)
+            .insert(deposit_tx.tx_id, deposit);
+        if previous.is_none() {
+            self.len += 1;
+        }
+        previous
+    }
+
+    /// Stores `deposit` under `tx_id`. With global ids a deposit another
+    /// client had under the id is dropped.
+    pub(crate) fn insert(&mut self, tx_id: TxId, deposit: Deposit) -> Option<Deposit> {
+        let client_id = deposit.0.client_id;
+        let previous = match (self.keys, self.owner(tx_id)) {
+            (TxKeys::Global, Some(owner)) if owner != client_id => self.remove(&tx_id),
+            _ => None,
+        };
+        self.store(deposit).or(previous)
+    }
+
+    /// Removes the deposit `tx_id` of its owner.
+    pub(crate) fn remove(&mut self, tx_id: &TxId) -> Option<Deposit> {
+        self.remove_for(self.owner(*tx_id)?, *tx_id)
+    }
+
+    pub(crate) fn remove_for(&mut self, client_id: ClientId, tx_id: TxId) -> Option<Deposit> {
+        // A miss must not copy a shared map
+        self.get_for(client_id, tx_id)?;
+        let removed = self.client_mut(client_id).remove(&tx_id)?;
+        self.len -= 1;
+        if self.owner(tx_id) == Some(client_id) {
+            self.owners.remove(&tx_id);
+        }
+        Some(removed)
+    }
+
+    pub(crate) fn values(&self) -> impl Iterator<Item = &Deposit> {
+        self.by_client.iter().flat_map(|deposits| deposits.values())
+    }
+
+    /// The deposits of one client, for per-client policies.
+    pub(crate) fn of_client(&self, client_id: ClientId) -> impl Iterator<Item = &Deposit> {
+        self.client(client_id).into_iter().flat_map(HashMap::values)
+    }
+
+    pub(crate) fn shrink_to_fit(&mut self) {
+        for deposits in &mut self.by_client {
+            if deposits.capacity() > deposits.len() * 2 {
+                Arc::make_mut(deposits).shrink_to_fit();
+            }
+        }
+        self.owners.shrink_to_fit();
+    }
+
+    /// Bytes the maps take, once `new` is inserted.
+    pub(crate) fn bytes(&self, new: Option<&DepositTx>) -> usize {
+        let new_client = new.map(|deposit_tx| deposit_tx.client_id as usize);
+        let maps: usize = self
+            .by_client
+            .iter()
+            .enumerate()
+            .map(|(i, deposits)| table_bytes(deposits, (new_client == Some(i)) as usize))
+            .sum();
+        maps + self.by_client.capacity() * size_of::<Arc<()>>()
+            + tx_table_bytes(&self.owners, new.map(|deposit_tx| deposit_tx.tx_id))
+    }
+}
+
+/// Stores every deposit in its client's map whatever the keys, as merged
+/// from another shard.
+impl Extend<(TxId, Deposit)> for DepositTable {
+    fn extend<I: IntoIterator<Item = (TxId, Deposit)>>(&mut self, iter: I) {
+        for (_, deposit) in iter {
+            self.store(deposit);
+        }
+    }
+}
+
+impl IntoIterator for DepositTable {
+    type Item = (TxId, Deposit);
+    type IntoIter = std::vec::IntoIter<(TxId, Deposit)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.by_client
+            .into_iter()
+            .flat_map(|deposits| Arc::unwrap_or_clone(deposits).into_iter())
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+}
+
+impl std::ops::Index<&TxId> for DepositTable {
+    type Output = Deposit;
+
+    fn index(&self, tx_id: &TxId) -> &Deposit {
+        self.get(tx_id).expect("no deposit with this id")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::Decimal;
+
+    fn deposit(client_id: ClientId, tx_id: TxId) -> Deposit {
+        let deposit_tx = DepositTx {
+            client_id,
+            tx_id,
+            amount: Decimal::ONE,
+        };
+        (deposit_tx, DisputeState::Normal)
+    }
+
+    #[test]
+    fn test_lookups_by_client_and_id() {
+        let mut table = DepositTable::new();
+        table.insert_new(deposit(3, 1));
+        table.insert_new(deposit(3, 2));
+        table.insert_new(deposit(9, 3));
+        // The first deposit with an id wins
+        table.insert_new(deposit(9, 1));
+
+        assert_eq!(table.len(), 3);
+        assert_eq!(table.find_for(9, 1).err(), Some(Some(3)));
+        assert_eq!(table.find_for(9, 4).err(), Some(None));
+        assert_eq!(table.owner(1), Some(3));
+        assert_eq!(table.get(&3).map(|(d, _)| d.client_id), Some(9));
+        assert_eq!(table.of_client(3).count(), 2);
+
+        let fork = table.clone();
+        table.find_for(3, 2).unwrap().1 = DisputeState::UnderDispute;
+        assert_eq!(fork[&2].1, DisputeState::Normal);
+        // Only the written client's map was copied
+        assert!(Arc::ptr_eq(&table.by_client[9], &fork.by_client[9]));
+        assert!(!Arc::ptr_eq(&table.by_client[3], &fork.by_client[3]));
+
+        // Moving a deposit to another client drops it from the first one
+        table.insert(2, deposit(9, 2));
+        assert_eq!(
+            (table.of_client(3).count(), table.of_client(9).count()),
+            (1, 2)
+        );
+        assert_eq!(table.remove(&2).map(|(d, _)| d.client_id), Some(9));
+        assert_eq!(table.values().count(), 2);
+    }
+
+    #[test]
+    fn test_per_client_keys() {
+        let mut table = DepositTable::with_keys(TxKeys::PerClient);
+        table.insert_new(deposit(3, 1));
+        table.insert_new(deposit(9, 1));
+        table.insert_new(deposit(9, 1));
+
+        assert_eq!(table.len(), 2);
+        assert!(table.find_for(9, 1).is_ok());
+        // Another client's id is as unknown as any other
+        assert_eq!(table.find_for(5, 1).err(), Some(None));
+        assert_eq!(table.owner(1), Some(3));
+
+        assert!(table.remove_for(3, 1).is_some());
+        assert!(table.remove_for(3, 1).is_none());
+        assert_eq!(table.len(), 1);
+        assert!(table.get_for(9, 1).is_some());
+    }
+}
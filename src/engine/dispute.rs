@@ -0,0 +1,50 @@
+use crate::{
+    engine::{Disputed, Engine, TxHandler, dispute_state::DisputeEvent, find_disputed},
+    types::{reject::RejectReason, transactions::DisputeTx},
+};
+
+impl TxHandler<DisputeTx> for Engine {
+    fn handle(&mut self, dispute_tx: DisputeTx) -> Result<(), RejectReason> {
+        let amounts = self.config.amounts;
+        let Some(client) = self.clients.get_mut(&dispute_tx.client_id) else {
+            return Err(RejectReason::UnknownClient);
+        };
+
+        // Deposit or withdrawal must be in a state that can be disputed
+        let (disputed, amount, status, next) = find_disputed(
+            &mut self.deposits,
+            &mut self.withdrawals,
+            &self.settled,
+            &self.config,
+            dispute_tx.client_id,
+            dispute_tx.tx_id,
+            DisputeEvent::Dispute,
+        )?;
+
+        let house_held = amounts.add_aggregate(self.house.held, amount)?;
+        let held = amounts.add(client.held, amount)?;
+        let (available, total, withdrawn) = match disputed {
+            // Available can go negative if funds were already withdrawn (fraud scenario)
+            Disputed::Deposit => (
+                amounts.sub(client.available, amount)?,
+                client.total,
+                self.house.withdrawn,
+            ),
+            // The withdrawn funds are held until the dispute is settled
+            Disputed::Withdrawal => (
+                client.available,
+                amounts.add(client.total, amount)?,
+                amounts.sub_aggregate(self.house.withdrawn, amount)?,
+            ),
+        };
+
+        *status = next;
+        client.available = available;
+        client.held = held;
+        client.total = total;
+        self.house.held = house_held;
+        self.house.withdrawn = withdrawn;
+
+        Ok(())
+    }
+}
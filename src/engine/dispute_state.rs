@@ -0,0 +1,178 @@
+use std::{fmt, str::FromStr};
+
+/// Where a stored deposit or withdrawal is in its dispute lifecycle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(kani, derive(kani::Arbitrary))]
+pub enum DisputeState {
+    Normal,
+    UnderDispute,
+    Resolved,
+    ChargedBack,
+}
+
+/// What a dispute, resolve or chargeback row does to a stored transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(kani, derive(kani::Arbitrary))]
+pub enum DisputeEvent {
+    Dispute,
+    Resolve,
+    Chargeback,
+}
+
+/// An event that isn't allowed in the current state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidTransition {
+    pub from: DisputeState,
+    pub event: DisputeEvent,
+}
+
+impl DisputeState {
+    pub const ALL: [DisputeState; 4] = [
+        DisputeState::Normal,
+        DisputeState::UnderDispute,
+        DisputeState::Resolved,
+        DisputeState::ChargedBack,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DisputeState::Normal => "normal",
+            DisputeState::UnderDispute => "under_dispute",
+            DisputeState::Resolved => "resolved",
+            DisputeState::ChargedBack => "charged_back",
+        }
+    }
+
+    /// The state after `event`. A transaction can only be disputed once, so
+    /// resolved and charged back are both final.
+    pub fn transition(self, event: DisputeEvent) -> Result<DisputeState, InvalidTransition> {
+        match (self, event) {
+            (DisputeState::Normal, DisputeEvent::Dispute) => Ok(DisputeState::UnderDispute),
+            (DisputeState::UnderDispute, DisputeEvent::Resolve) => Ok(DisputeState::Resolved),
+            (DisputeState::UnderDispute, DisputeEvent::Chargeback) => Ok(DisputeState::ChargedBack),
+            (from, event) => Err(InvalidTransition { from, event }),
+        }
+    }
+}
+
+impl fmt::Display for DisputeState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for DisputeState {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        DisputeState::ALL
+            .into_iter()
+            .find(|state| state.name() == s)
+            .ok_or_else(|| {
+                format!(
+                    "unknown dispute state `{s}`, expected normal, under_dispute, resolved or \
+                     charged_back"
+                )
+            })
+    }
+}
+
+impl fmt::Display for InvalidTransition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} is not allowed in state {:?}",
+            self.event, self.from
+        )
+    }
+}
+
+impl std::error::Error for InvalidTransition {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_all_transitions() {
+        use DisputeEvent::*;
+        use DisputeState::*;
+
+        let events = [Dispute, Resolve, Chargeback];
+        for from in DisputeState::ALL {
+            for event in events {
+                let expected = match (from, event) {
+                    (Normal, Dispute) => Some(UnderDispute),
+                    (UnderDispute, Resolve) => Some(Resolved),
+                    (UnderDispute, Chargeback) => Some(ChargedBack),
+                    _ => None,
+                };
+                assert_eq!(
+                    from.transition(event).ok(),
+                    expected,
+                    "{from:?} + {event:?}"
+                );
+                if expected.is_none() {
+                    assert_eq!(
+                        from.transition(event),
+                        Err(InvalidTransition { from, event })
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Proofs over every state and event sequence, run with `cargo kani`.
+#[cfg(kani)]
+mod verification {
+    use super::*;
+
+    #[kani::proof]
+    fn transitions_only_go_forward() {
+        let from: DisputeState = kani::any();
+        let event: DisputeEvent = kani::any();
+
+        match from.transition(event) {
+            Ok(to) => assert!(matches!(
+                (from, event, to),
+                (
+                    DisputeState::Normal,
+                    DisputeEvent::Dispute,
+                    DisputeState::UnderDispute
+                ) | (
+                    DisputeState::UnderDispute,
+                    DisputeEvent::Resolve,
+                    DisputeState::Resolved
+                ) | (
+                    DisputeState::UnderDispute,
+                    DisputeEvent::Chargeback,
+                    DisputeState::ChargedBack
+                )
+            )),
+            Err(err) => assert_eq!(err, InvalidTransition { from, event }),
+        }
+    }
+
+    #[kani::proof]
+    #[kani::unwind(5)]
+    fn a_transaction_is_disputed_at_most_once() {
+        let mut state = DisputeState::Normal;
+        let mut disputes = 0;
+
+        for _ in 0..4 {
+            let event: DisputeEvent = kani::any();
+            if let Ok(next) = state.transition(event) {
+                disputes += (next == DisputeState::UnderDispute) as u32;
+                state = next;
+            }
+        }
+
+        assert!(disputes <= 1);
+        // Resolved and charged back are final
+        let event: DisputeEvent = kani::any();
+        if matches!(state, DisputeState::Resolved | DisputeState::ChargedBack) {
+            assert!(state.transition(event).is_err());
+        }
+    }
+}
@@ -0,0 +1,17 @@
+use rust_decimal::Decimal;
+
+/// Engine-wide counterpart of the client accounts, recording where money went.
+///
+/// Across all clients `total == deposited - withdrawn - charged_back` and
+/// `held` equals the sum of client `held` balances.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HouseAccounts {
+    /// Accepted deposits
+    pub deposited: Decimal,
+    /// Accepted withdrawals
+    pub withdrawn: Decimal,
+    /// Funds currently held by open disputes
+    pub held: Decimal,
+    /// Deposits reversed by chargebacks
+    pub charged_back: Decimal,
+}
@@ -0,0 +1,107 @@
+//! `paranoid` builds check the engine's invariants after every handler and
+//! panic at the first transaction that breaks one, instead of leaving it to
+//! show up in the final balances. The checks scan the client's deposits and
+//! all withdrawals, so they are for tests, fuzzing and mutation testing only.
+
+use rust_decimal::Decimal;
+
+use crate::{
+    engine::{Engine, dispute_state::DisputeState},
+    types::{common::ClientId, transactions::Tx},
+};
+
+impl Engine {
+    /// Panics unless the client `tx` names and the house accounts are consistent
+    /// with each other and with the stored transactions.
+    pub(crate) fn check_invariants(&self, tx: Tx) {
+        if let Some(client) = self.clients.get(&tx.client_id()) {
+            assert_eq!(
+                client.total,
+                client.available + client.held,
+                "after {tx:?}: total isn't available + held for {client:?}"
+            );
+            assert!(
+                client.held >= Decimal::ZERO,
+                "after {tx:?}: negative held for {client:?}"
+            );
+            assert_eq!(
+                client.held,
+                self.disputed_amount(client.id),
+                "after {tx:?}: held doesn't match the open disputes of {client:?}"
+            );
+            assert_eq!(
+                client.locked,
+                client.lock.is_some(),
+                "after {tx:?}: lock reason doesn't match the lock of {client:?}"
+            );
+        }
+
+        let clients = self.clients.values();
+        let (held, total) = clients.fold((Decimal::ZERO, Decimal::ZERO), |(held, total), c| {
+            (held + c.held, total + c.total)
+        });
+        let house = &self.house;
+        assert_eq!(
+            house.held, held,
+            "after {tx:?}: house held isn't the clients' held"
+        );
+        assert_eq!(
+            house.deposited - house.withdrawn - house.charged_back,
+            total,
+            "after {tx:?}: house accounts don't add up to the clients' total, {house:?}"
+        );
+        assert_eq!(
+            self.locked_clients,
+            self.clients.values().filter(|c| c.locked).count(),
+            "after {tx:?}: locked client count is off"
+        );
+    }
+
+    /// The amounts of the client's deposits and withdrawals under dispute.
+    fn disputed_amount(&self, client_id: ClientId) -> Decimal {
+        let deposits = self
+            .deposits
+            .of_client(client_id)
+            .filter(|(_, state)| *state == DisputeState::UnderDispute)
+            .map(|(deposit_tx, _)| deposit_tx.amount);
+        let withdrawals = self
+            .withdrawals
+            .values()
+            .filter(|(withdrawal_tx, state)| {
+                withdrawal_tx.client_id == client_id && *state == DisputeState::UnderDispute
+            })
+            .map(|(withdrawal_tx, _)| withdrawal_tx.amount);
+        deposits.chain(withdrawals).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        engine::Engine,
+        types::transactions::{DepositTx, Tx},
+    };
+    use rust_decimal_macros::dec;
+
+    fn deposit(tx_id: u32) -> Tx {
+        Tx::Deposit(DepositTx {
+            client_id: 1,
+            tx_id,
+            amount: dec!(10),
+        })
+    }
+
+    #[test]
+    #[should_panic(expected = "held doesn't match the open disputes")]
+    fn test_held_without_a_dispute_is_caught() {
+        let mut engine = Engine::new();
+        engine.process_tx(deposit(1)).unwrap();
+
+        // What a handler forgetting to release held funds would leave
+        let client = engine.clients.get_mut(&1).unwrap();
+        client.available -= dec!(5);
+        client.held += dec!(5);
+        engine.house.held += dec!(5);
+        let _ = engine.process_tx(deposit(2));
+    }
+}
@@ -0,0 +1,285 @@
+//! Balance queries against an engine that is still applying transactions.
+//!
+//! The writer periodically publishes an immutable epoch of client balances.
+//! Readers grab the latest epoch behind a lock that is only ever held for an
+//! `Arc` clone or pointer swap, so neither side waits on the other's work.
+
+use std::collections::{HashMap, HashSet};
+
+#[cfg(loom)]
+use loom::sync::{Arc, RwLock};
+#[cfg(not(loom))]
+use std::sync::{Arc, RwLock};
+
+use crate::{
+    engine::Engine,
+    types::{client::Client, common::ClientId, reject::RejectReason, transactions::Tx},
+};
+
+/// Client balances as of the end of a publish.
+#[derive(Debug, Default)]
+pub struct Epoch {
+    /// Increases by one with every publish
+    pub number: u64,
+    /// Transactions applied by the writer when the epoch was published
+    pub processed: u64,
+    clients: HashMap<ClientId, Client>,
+}
+
+impl Epoch {
+    pub fn client(&self, id: ClientId) -> Option<&Client> {
+        self.clients.get(&id)
+    }
+
+    pub fn clients(&self) -> impl Iterator<Item = &Client> {
+        self.clients.values()
+    }
+}
+
+/// Cheap to clone handle for reading the latest published balances.
+#[derive(Debug, Clone, Default)]
+pub struct BalanceReader {
+    current: Arc<RwLock<Arc<Epoch>>>,
+}
+
+impl BalanceReader {
+    pub fn epoch(&self) -> Arc<Epoch> {
+        self.current
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    pub fn client(&self, id: ClientId) -> Option<Client> {
+        self.epoch().client(id).cloned()
+    }
+}
+
+/// Wraps an `Engine` and publishes the touched clients every `publish_every` transactions.
+pub struct LiveEngine {
+    engine: Engine,
+    reader: BalanceReader,
+    dirty: HashSet<ClientId>,
+    publish_every: u64,
+    processed: u64,
+}
+
+impl LiveEngine {
+    pub fn new(engine: Engine, publish_every: u64) -> Self {
+        let mut live = LiveEngine {
+            engine,
+            reader: BalanceReader::default(),
+            dirty: HashSet::new(),
+            publish_every: publish_every.max(1),
+            processed: 0,
+        };
+        live.dirty.extend(live.engine.clients.keys());
+        live.publish();
+        live
+    }
+
+    pub fn reader(&self) -> BalanceReader {
+        self.reader.clone()
+    }
+
+    pub fn process_tx(&mut self, tx: Tx) -> Result<(), RejectReason> {
+        // Every transaction only ever changes its own client's account, but a
+        // rejected one may still have created it
+        let client_id = tx.client_id();
+        let result = self.engine.process_tx(tx);
+        if self.engine.clients.contains_key(&client_id) {
+            self.dirty.insert(client_id);
+        }
+
+        self.processed += 1;
+        if self.processed.is_multiple_of(self.publish_every) {
+            self.publish();
+        }
+
+        result
+    }
+
+    /// Makes every change applied so far visible to readers.
+    pub fn publish(&mut self) {
+        if self.dirty.is_empty() {
+            return;
+        }
+
+        let previous = self.reader.epoch();
+        let mut clients = previous.clients.clone();
+
+        for id in self.dirty.drain() {
+            if let Some(client) = self.engine.clients.get(&id) {
+                clients.insert(id, client.clone());
+            }
+        }
+
+        let epoch = Arc::new(Epoch {
+            number: previous.number + 1,
+            processed: self.processed,
+            clients,
+        });
+        *self
+            .reader
+            .current
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = epoch;
+    }
+
+    pub fn engine(&self) -> &Engine {
+        &self.engine
+    }
+
+    /// Publishes any outstanding changes and hands back the engine.
+    pub fn into_engine(mut self) -> Engine {
+        self.publish();
+        self.engine
+    }
+}
+
+// Loom's locks only work inside `loom::model`, see `loom_tests`
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::*;
+    use crate::{
+        engine::config::EngineConfig,
+        types::transactions::{DepositTx, WithdrawalTx},
+    };
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+    use std::thread;
+
+    fn deposit(client_id: ClientId, tx_id: u32, amount: Decimal) -> Tx {
+        Tx::Deposit(DepositTx {
+            client_id,
+            tx_id,
+            amount,
+        })
+    }
+
+    #[test]
+    fn test_changes_become_visible_on_publish() {
+        let mut live = LiveEngine::new(Engine::new(), 2);
+        let reader = live.reader();
+
+        live.process_tx(deposit(1, 1, dec!(10))).unwrap();
+        assert!(reader.client(1).is_none());
+
+        live.process_tx(deposit(1, 2, dec!(5))).unwrap();
+        assert_eq!(reader.client(1).unwrap().available, dec!(15));
+        assert_eq!(reader.epoch().processed, 2);
+
+        live.process_tx(Tx::Withdrawal(WithdrawalTx {
+            client_id: 1,
+            tx_id: 3,
+            amount: dec!(15),
+        }))
+        .unwrap();
+        assert_eq!(reader.client(1).unwrap().available, dec!(15));
+
+        let engine = live.into_engine();
+        assert_eq!(reader.client(1).unwrap().available, dec!(0));
+        assert_eq!(engine.client(1).unwrap().available, dec!(0));
+    }
+
+    #[test]
+    fn test_existing_state_is_published_up_front() {
+        let mut engine = Engine::new();
+        engine.process_tx(deposit(7, 1, dec!(3))).unwrap();
+
+        let live = LiveEngine::new(engine, 100);
+        assert_eq!(live.reader().client(7).unwrap().total, dec!(3));
+    }
+
+    #[test]
+    fn test_clients_created_by_rejected_rows_are_published() {
+        let engine = Engine::with_config(EngineConfig {
+            max_balance: Some(dec!(5)),
+            ..EngineConfig::default()
+        });
+        let mut live = LiveEngine::new(engine, 1);
+
+        assert_eq!(
+            live.process_tx(deposit(1, 1, dec!(10))),
+            Err(RejectReason::MaxBalanceExceeded)
+        );
+        assert_eq!(live.reader().client(1).unwrap().total, dec!(0));
+    }
+
+    #[test]
+    fn test_readers_see_consistent_epochs_while_writing() {
+        let mut live = LiveEngine::new(Engine::new(), 10);
+        let reader = live.reader();
+
+        let query = thread::spawn(move || {
+            let mut last_epoch = 0;
+            for _ in 0..10_000 {
+                let epoch = reader.epoch();
+                assert!(epoch.number >= last_epoch);
+                last_epoch = epoch.number;
+                for client in epoch.clients() {
+                    assert_eq!(client.total, client.available + client.held);
+                }
+            }
+        });
+
+        for tx_id in 1..=10_000 {
+            live.process_tx(deposit((tx_id % 50) as ClientId, tx_id, dec!(1)))
+                .unwrap();
+        }
+
+        query.join().unwrap();
+        let reader = live.reader();
+        live.into_engine();
+        let total: Decimal = reader.epoch().clients().map(|c| c.total).sum();
+        assert_eq!(total, dec!(10_000));
+    }
+}
+
+/// Every interleaving of a reader with the writer, run with
+/// `RUSTFLAGS="--cfg loom" cargo test --release --lib live::loom`.
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use super::*;
+    use crate::types::transactions::{DepositTx, WithdrawalTx};
+    use loom::thread;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_readers_only_see_whole_epochs() {
+        loom::model(|| {
+            let mut live = LiveEngine::new(Engine::new(), 1);
+            let reader = live.reader();
+
+            let query = thread::spawn(move || {
+                let first = reader.epoch();
+                let second = reader.epoch();
+                assert!(second.number >= first.number);
+                for epoch in [first, second] {
+                    let available = epoch.client(1).map(|client| client.available);
+                    let expected = match epoch.number {
+                        0 => None,
+                        1 => Some(dec!(10)),
+                        _ => Some(dec!(0)),
+                    };
+                    assert_eq!(available, expected);
+                    assert_eq!(epoch.processed, epoch.number);
+                }
+            });
+
+            live.process_tx(Tx::Deposit(DepositTx {
+                client_id: 1,
+                tx_id: 1,
+                amount: dec!(10),
+            }))
+            .unwrap();
+            live.process_tx(Tx::Withdrawal(WithdrawalTx {
+                client_id: 1,
+                tx_id: 2,
+                amount: dec!(10),
+            }))
+            .unwrap();
+            query.join().unwrap();
+        });
+    }
+}
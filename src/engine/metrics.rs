@@ -0,0 +1,243 @@
+//! Telemetry hooks for embedders: the engine reports every transaction it
+//! applies or rejects to an `EngineMetrics`, so it can feed whatever stack the
+//! embedding service already runs. `NoopMetrics` is the default, the
+//! `prometheus` and `statsd` features add backends for those.
+
+use rust_decimal::Decimal;
+#[cfg(any(feature = "prometheus", feature = "statsd"))]
+use rust_decimal::prelude::ToPrimitive;
+
+use crate::types::{reject::RejectReason, transactions::TxType};
+
+/// Called by the engine on the thread applying transactions, so calls should
+/// be cheap. Transactions later rolled back by `Engine::apply_batch` or an
+/// aborted `PreparedTx` have been reported already.
+pub trait EngineMetrics: Send + Sync {
+    /// A transaction went through its handler, queued rows count once
+    /// when they are queued and again when they are applied
+    fn increment(&self, tx_type: TxType, result: Result<(), RejectReason>);
+
+    /// The amount of an applied deposit or withdrawal
+    fn observe(&self, tx_type: TxType, amount: Decimal);
+}
+
+/// `applied`, or the reject reason's code, for labels and tags.
+pub fn outcome(result: Result<(), RejectReason>) -> &'static str {
+    match result {
+        Ok(()) => "applied",
+        Err(reason) => reason.code(),
+    }
+}
+
+/// Reports nothing.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopMetrics;
+
+impl EngineMetrics for NoopMetrics {
+    fn increment(&self, _tx_type: TxType, _result: Result<(), RejectReason>) {}
+
+    fn observe(&self, _tx_type: TxType, _amount: Decimal) {}
+}
+
+/// `tpe_transactions_total` by `type` and `outcome`, and the
+/// `tpe_transaction_amount` histogram by `type`, in a Prometheus registry.
+#[cfg(feature = "prometheus")]
+#[derive(Clone)]
+pub struct PrometheusMetrics {
+    transactions: prometheus::IntCounterVec,
+    amounts: prometheus::HistogramVec,
+}
+
+#[cfg(feature = "prometheus")]
+impl PrometheusMetrics {
+    pub fn register(registry: &prometheus::Registry) -> prometheus::Result<Self> {
+        let transactions = prometheus::IntCounterVec::new(
+            prometheus::Opts::new("tpe_transactions_total", "Transactions by type and outcome"),
+            &["type", "outcome"],
+        )?;
+        let amounts = prometheus::HistogramVec::new(
+            prometheus::HistogramOpts::new(
+                "tpe_transaction_amount",
+                "Amounts of applied deposits and withdrawals",
+            )
+            .buckets(prometheus::exponential_buckets(0.01, 10.0, 10)?),
+            &["type"],
+        )?;
+        registry.register(Box::new(transactions.clone()))?;
+        registry.register(Box::new(amounts.clone()))?;
+        Ok(PrometheusMetrics {
+            transactions,
+            amounts,
+        })
+    }
+}
+
+#[cfg(feature = "prometheus")]
+impl EngineMetrics for PrometheusMetrics {
+    fn increment(&self, tx_type: TxType, result: Result<(), RejectReason>) {
+        self.transactions
+            .with_label_values(&[tx_type.name(), outcome(result)])
+            .inc();
+    }
+
+    fn observe(&self, tx_type: TxType, amount: Decimal) {
+        self.amounts
+            .with_label_values(&[tx_type.name()])
+            .observe(amount.to_f64().unwrap_or_default());
+    }
+}
+
+/// `transactions` counters tagged with `type` and `outcome`, and
+/// `transaction_amount` histograms tagged with `type`, sent through a cadence
+/// client (which adds its prefix and decides how and where to send them).
+#[cfg(feature = "statsd")]
+pub struct StatsdMetrics {
+    client: cadence::StatsdClient,
+}
+
+#[cfg(feature = "statsd")]
+impl StatsdMetrics {
+    pub fn new(client: cadence::StatsdClient) -> Self {
+        StatsdMetrics { client }
+    }
+}
+
+#[cfg(feature = "statsd")]
+impl EngineMetrics for StatsdMetrics {
+    fn increment(&self, tx_type: TxType, result: Result<(), RejectReason>) {
+        use cadence::prelude::*;
+
+        self.client
+            .incr_with_tags("transactions")
+            .with_tag("type", tx_type.name())
+            .with_tag("outcome", outcome(result))
+            .send();
+    }
+
+    fn observe(&self, tx_type: TxType, amount: Decimal) {
+        use cadence::prelude::*;
+
+        self.client
+            .histogram_with_tags("transaction_amount", amount.to_f64().unwrap_or_default())
+            .with_tag("type", tx_type.name())
+            .send();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    use crate::{
+        engine::Engine,
+        types::transactions::{DepositTx, DisputeTx, Tx, WithdrawalTx},
+    };
+    use rust_decimal_macros::dec;
+
+    fn settlement() -> Vec<Tx> {
+        vec![
+            Tx::Deposit(DepositTx {
+                client_id: 1,
+                tx_id: 1,
+                amount: dec!(10),
+            }),
+            Tx::Withdrawal(WithdrawalTx {
+                client_id: 1,
+                tx_id: 2,
+                amount: dec!(20),
+            }),
+            Tx::Dispute(DisputeTx {
+                client_id: 1,
+                tx_id: 1,
+            }),
+        ]
+    }
+
+    #[derive(Default)]
+    struct Recorded(Mutex<Vec<String>>);
+
+    impl EngineMetrics for Recorded {
+        fn increment(&self, tx_type: TxType, result: Result<(), RejectReason>) {
+            let line = format!("{tx_type} {}", outcome(result));
+            self.0.lock().unwrap().push(line);
+        }
+
+        fn observe(&self, tx_type: TxType, amount: Decimal) {
+            self.0.lock().unwrap().push(format!("{tx_type} {amount}"));
+        }
+    }
+
+    #[test]
+    fn test_every_transaction_is_reported() {
+        let recorded = Arc::new(Recorded::default());
+        let mut engine = Engine::new();
+        engine.set_metrics(recorded.clone());
+        for tx in settlement() {
+            let _ = engine.process_tx(tx);
+        }
+
+        assert_eq!(
+            *recorded.0.lock().unwrap(),
+            vec![
+                "deposit applied",
+                "deposit 10",
+                "withdrawal insufficient_funds",
+                "dispute applied",
+            ]
+        );
+    }
+
+    #[cfg(feature = "prometheus")]
+    #[test]
+    fn test_prometheus_metrics() {
+        use prometheus::Encoder;
+
+        let registry = prometheus::Registry::new();
+        let mut engine = Engine::new();
+        engine.set_metrics(Arc::new(PrometheusMetrics::register(&registry).unwrap()));
+        for tx in settlement() {
+            let _ = engine.process_tx(tx);
+        }
+
+        let mut text = Vec::new();
+        prometheus::TextEncoder::new()
+            .encode(&registry.gather(), &mut text)
+            .unwrap();
+        let text = String::from_utf8(text).unwrap();
+        for line in [
+            r#"tpe_transactions_total{outcome="applied",type="deposit"} 1"#,
+            r#"tpe_transactions_total{outcome="insufficient_funds",type="withdrawal"} 1"#,
+            r#"tpe_transaction_amount_sum{type="deposit"} 10"#,
+        ] {
+            assert!(text.contains(line), "no `{line}` in {text}");
+        }
+    }
+
+    #[cfg(feature = "statsd")]
+    #[test]
+    fn test_statsd_metrics() {
+        let (rx, sink) = cadence::SpyMetricSink::new();
+        let mut engine = Engine::new();
+        engine.set_metrics(Arc::new(StatsdMetrics::new(
+            cadence::StatsdClient::from_sink("tpe", sink),
+        )));
+        for tx in settlement() {
+            let _ = engine.process_tx(tx);
+        }
+
+        let sent: Vec<_> = rx
+            .try_iter()
+            .map(|line| String::from_utf8(line).unwrap())
+            .collect();
+        assert_eq!(
+            sent,
+            vec![
+                "tpe.transactions:1|c|#type:deposit,outcome:applied",
+                "tpe.transaction_amount:10|h|#type:deposit",
+                "tpe.transactions:1|c|#type:withdrawal,outcome:insufficient_funds",
+                "tpe.transactions:1|c|#type:dispute,outcome:applied",
+            ]
+        );
+    }
+}
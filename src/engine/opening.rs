@@ -0,0 +1,340 @@
+//! Opening balances carried over from a previous period's output, for runs
+//! that start from the balances alone instead of a state snapshot, either
+//! loaded directly or replayed as `opening_balance` transactions. Deposits
+//! from before the opening can only be disputed if they are registered with
+//! `add_prior_deposit`.
+
+use std::fmt;
+
+use rust_decimal::Decimal;
+
+use crate::{
+    engine::{Engine, TxHandler, dispute_state::DisputeState},
+    types::{
+        client::{Client, LockInfo, LockReason},
+        common::{ClientId, TxId},
+        reject::RejectReason,
+        transactions::{DepositTx, OpeningBalanceTx},
+    },
+};
+
+/// Why an opening balance was refused, leaving the engine as it was.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpeningError {
+    /// The client already has an account
+    Exists(ClientId),
+    /// Funds are held by disputes whose transactions didn't come along, so
+    /// they could never be released
+    Held(ClientId),
+    /// `total` isn't `available + held`
+    Inconsistent(ClientId),
+    /// A prior deposit's id is already stored
+    DuplicateTx(TxId),
+    /// A prior deposit's amount is zero or less, or outside the amount limits
+    InvalidAmount(TxId),
+    /// A prior deposit is under dispute, but nothing is held for it
+    UnderDispute(TxId),
+}
+
+impl fmt::Display for OpeningError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OpeningError::Exists(client_id) => write!(f, "client {client_id} is listed twice"),
+            OpeningError::Held(client_id) => write!(
+                f,
+                "client {client_id} has held funds, carry open disputes over with a state \
+                 snapshot instead"
+            ),
+            OpeningError::Inconsistent(client_id) => {
+                write!(f, "client {client_id}: total isn't available + held")
+            }
+            OpeningError::DuplicateTx(tx_id) => write!(f, "tx {tx_id} is already stored"),
+            OpeningError::InvalidAmount(tx_id) => write!(f, "tx {tx_id} has an invalid amount"),
+            OpeningError::UnderDispute(tx_id) => write!(
+                f,
+                "tx {tx_id} is under dispute, carry open disputes over with a state snapshot \
+                 instead"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for OpeningError {}
+
+impl Engine {
+    /// Opens the client's account with its balances and lock. The house
+    /// accounts take a positive total as deposited and a negative one as
+    /// withdrawn, the activity counters start from zero. Unlike an
+    /// `opening_balance` transaction it doesn't count as an opening.
+    pub fn open_balance(&mut self, client: &Client) -> Result<(), OpeningError> {
+        if self.clients.get(&client.id).is_some() {
+            return Err(OpeningError::Exists(client.id));
+        }
+        if client.held != Decimal::ZERO {
+            return Err(OpeningError::Held(client.id));
+        }
+        if client.total != client.available + client.held {
+            return Err(OpeningError::Inconsistent(client.id));
+        }
+
+        if client.total >= Decimal::ZERO {
+            self.house.deposited += client.total;
+        } else {
+            self.house.withdrawn -= client.total;
+        }
+        self.locked_clients += client.locked as usize;
+        self.clients.insert(
+            client.id,
+            Client {
+                stats: Default::default(),
+                lock: match (client.locked, client.lock) {
+                    (false, _) => None,
+                    (true, Some(lock)) => Some(lock),
+                    (true, None) => Some(LockInfo::new(LockReason::CarriedOver, None)),
+                },
+                ..client.clone()
+            },
+        );
+        Ok(())
+    }
+}
+
+impl Engine {
+    /// Stores a deposit from before the opening balances so that disputes,
+    /// resolves and chargebacks can still name it. Its amount is part of the
+    /// client's opening balance already, so no balance changes. The client
+    /// may open later, with an `opening_balance` transaction. The deposit is
+    /// keyed and its amount checked by the engine's config, so set that first.
+    pub fn add_prior_deposit(
+        &mut self,
+        mut deposit_tx: DepositTx,
+        state: DisputeState,
+    ) -> Result<(), OpeningError> {
+        let tx_id = deposit_tx.tx_id;
+        if state == DisputeState::UnderDispute {
+            return Err(OpeningError::UnderDispute(tx_id));
+        }
+        deposit_tx.amount = self
+            .config
+            .amounts
+            .ingress(deposit_tx.amount)
+            .map_err(|_| OpeningError::InvalidAmount(tx_id))?;
+        if !self.deposits.is_new(&deposit_tx) || self.withdrawals.contains_key(&tx_id) {
+            return Err(OpeningError::DuplicateTx(tx_id));
+        }
+        self.deposits.insert_new((deposit_tx, state));
+        Ok(())
+    }
+}
+
+impl TxHandler<OpeningBalanceTx> for Engine {
+    fn handle(&mut self, opening_tx: OpeningBalanceTx) -> Result<(), RejectReason> {
+        let client_id = opening_tx.client_id;
+        if self.clients.contains_key(&client_id) {
+            return Err(RejectReason::AccountExists);
+        }
+        let amounts = self.config.amounts;
+        let amount = amounts.add(Decimal::ZERO, opening_tx.amount)?;
+        let (deposited, withdrawn) = if amount >= Decimal::ZERO {
+            (
+                amounts.add_aggregate(self.house.deposited, amount)?,
+                self.house.withdrawn,
+            )
+        } else {
+            (
+                self.house.deposited,
+                amounts.sub_aggregate(self.house.withdrawn, amount)?,
+            )
+        };
+        if let Some(max_balance) = self.config.max_balance
+            && amount > max_balance
+        {
+            return Err(RejectReason::MaxBalanceExceeded);
+        }
+        self.check_capacity(Some(client_id), None, None)?;
+
+        self.house.deposited = deposited;
+        self.house.withdrawn = withdrawn;
+        self.locked_clients += opening_tx.locked as usize;
+        self.clients.insert(
+            client_id,
+            Client {
+                available: amount,
+                total: amount,
+                locked: opening_tx.locked,
+                lock: opening_tx
+                    .locked
+                    .then(|| LockInfo::new(LockReason::OpeningBalance, Some(opening_tx.tx_id))),
+                ..Client::new(client_id)
+            },
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::transactions::{ChargebackTx, DisputeTx, ResolveTx, Tx, WithdrawalTx};
+    use rust_decimal_macros::dec;
+
+    fn client(id: ClientId, available: Decimal, held: Decimal, locked: bool) -> Client {
+        Client {
+            available,
+            held,
+            total: available + held,
+            locked,
+            ..Client::new(id)
+        }
+    }
+
+    #[test]
+    fn test_opening_balances_carry_on() {
+        let mut engine = Engine::new();
+        engine
+            .open_balance(&client(1, dec!(10), dec!(0), false))
+            .unwrap();
+        engine
+            .open_balance(&client(2, dec!(-3), dec!(0), true))
+            .unwrap();
+
+        let house = engine.house();
+        assert_eq!((house.deposited, house.withdrawn), (dec!(10), dec!(3)));
+        assert_eq!(engine.totals().locked, 1);
+
+        let withdrawal = WithdrawalTx::new(1, 1, dec!(4)).unwrap();
+        engine.process_tx(Tx::Withdrawal(withdrawal)).unwrap();
+        assert_eq!(engine.client(1).unwrap().total, dec!(6));
+        let deposit = DepositTx::new(2, 2, dec!(5)).unwrap();
+        assert!(engine.process_tx(Tx::Deposit(deposit)).is_err());
+    }
+
+    #[test]
+    fn test_refused_openings() {
+        let mut engine = Engine::new();
+        engine
+            .open_balance(&client(1, dec!(1), dec!(0), false))
+            .unwrap();
+
+        assert_eq!(
+            engine.open_balance(&client(1, dec!(1), dec!(0), false)),
+            Err(OpeningError::Exists(1))
+        );
+        assert_eq!(
+            engine.open_balance(&client(2, dec!(1), dec!(2), false)),
+            Err(OpeningError::Held(2))
+        );
+        let mut inconsistent = client(3, dec!(1), dec!(0), false);
+        inconsistent.total = dec!(5);
+        assert_eq!(
+            engine.open_balance(&inconsistent),
+            Err(OpeningError::Inconsistent(3))
+        );
+        assert_eq!(engine.clients_iter().len(), 1);
+    }
+
+    #[test]
+    fn test_opening_balance_transactions() {
+        let opening = |client_id, tx_id, amount, locked| {
+            Tx::OpeningBalance(OpeningBalanceTx::new(client_id, tx_id, amount, locked).unwrap())
+        };
+        let mut engine = Engine::new();
+        engine.process_tx(opening(1, 1, dec!(7.5), false)).unwrap();
+        engine.process_tx(opening(2, 2, dec!(-2), true)).unwrap();
+        assert_eq!(
+            engine.process_tx(opening(1, 3, dec!(1), false)),
+            Err(RejectReason::AccountExists)
+        );
+
+        let client = &engine.client(1).unwrap();
+        assert_eq!((client.available, client.total), (dec!(7.5), dec!(7.5)));
+        assert_eq!(engine.totals().total, dec!(5.5));
+        // The refused one isn't counted
+        assert_eq!(client.stats.openings, 1);
+        assert_eq!(engine.stats().activity.openings, 2);
+        assert!(engine.client(2).unwrap().locked);
+
+        // Nothing was stored to dispute
+        let dispute = Tx::Dispute(DisputeTx {
+            client_id: 1,
+            tx_id: 1,
+        });
+        assert_eq!(engine.process_tx(dispute), Err(RejectReason::UnknownTx));
+        let withdrawal = WithdrawalTx::new(1, 4, dec!(7.5)).unwrap();
+        engine.process_tx(Tx::Withdrawal(withdrawal)).unwrap();
+        assert_eq!(engine.client(1).unwrap().total, dec!(0));
+    }
+
+    #[test]
+    fn test_prior_deposits_can_be_disputed() {
+        let mut engine = Engine::new();
+        engine
+            .open_balance(&client(1, dec!(10), dec!(0), false))
+            .unwrap();
+        let prior = |tx_id, amount| DepositTx::new(1, tx_id, amount).unwrap();
+        engine
+            .add_prior_deposit(prior(1, dec!(4)), DisputeState::Normal)
+            .unwrap();
+        engine
+            .add_prior_deposit(prior(2, dec!(6)), DisputeState::Normal)
+            .unwrap();
+        engine
+            .add_prior_deposit(prior(3, dec!(1)), DisputeState::Resolved)
+            .unwrap();
+        assert_eq!(engine.totals().total, dec!(10));
+
+        let dispute = |tx_id| {
+            Tx::Dispute(DisputeTx {
+                client_id: 1,
+                tx_id,
+            })
+        };
+        engine.process_tx(dispute(1)).unwrap();
+        engine.process_tx(dispute(2)).unwrap();
+        assert_eq!(engine.client(1).unwrap().held, dec!(10));
+        engine
+            .process_tx(Tx::Resolve(ResolveTx {
+                client_id: 1,
+                tx_id: 1,
+            }))
+            .unwrap();
+        engine
+            .process_tx(Tx::Chargeback(ChargebackTx {
+                client_id: 1,
+                tx_id: 2,
+            }))
+            .unwrap();
+        assert_eq!(
+            engine.process_tx(dispute(3)),
+            Err(RejectReason::NotDisputable)
+        );
+
+        let client = &engine.client(1).unwrap();
+        assert_eq!((client.available, client.held), (dec!(4), dec!(0)));
+        assert!(client.locked);
+        let house = engine.house();
+        assert_eq!((house.deposited, house.charged_back), (dec!(10), dec!(6)));
+        assert_eq!(engine.tx_state(1, 2), Some(DisputeState::ChargedBack));
+    }
+
+    #[test]
+    fn test_refused_prior_deposits() {
+        let mut engine = Engine::new();
+        engine
+            .process_tx(Tx::Deposit(DepositTx::new(1, 1, dec!(1)).unwrap()))
+            .unwrap();
+
+        assert_eq!(
+            engine.add_prior_deposit(DepositTx::new(1, 1, dec!(2)).unwrap(), DisputeState::Normal),
+            Err(OpeningError::DuplicateTx(1))
+        );
+        assert_eq!(
+            engine.add_prior_deposit(
+                DepositTx::new(1, 3, dec!(1)).unwrap(),
+                DisputeState::UnderDispute
+            ),
+            Err(OpeningError::UnderDispute(3))
+        );
+        assert_eq!(engine.tx_state(1, 3), None);
+    }
+}
@@ -0,0 +1,199 @@
+//! Two-phase application of a transaction, so an embedding service can apply
+//! it here and in another system atomically: `prepare` it, do the other
+//! side, then `commit` or `abort`.
+
+use crate::{
+    engine::{Engine, dispute_state::DisputeState, house::HouseAccounts},
+    types::{
+        client::Client,
+        common::ClientId,
+        reject::RejectReason,
+        transactions::{DepositTx, Tx, WithdrawalTx},
+    },
+};
+
+/// A transaction applied tentatively by `Engine::prepare`.
+///
+/// It keeps the engine borrowed until it is committed or aborted, so nothing
+/// else can be applied in between. Dropping it without committing aborts it.
+#[must_use = "a prepared transaction is aborted when dropped"]
+pub struct PreparedTx<'e> {
+    engine: &'e mut Engine,
+    tx: Tx,
+    /// `None` once committed
+    undo: Option<Undo>,
+}
+
+/// Everything a transaction can change, as it was before the transaction.
+pub(super) struct Undo {
+    client_id: ClientId,
+    client: Option<Client>,
+    locked_clients: usize,
+    deposit: Option<(DepositTx, DisputeState)>,
+    withdrawal: Option<(WithdrawalTx, DisputeState)>,
+    pending: Option<Vec<Tx>>,
+    house: HouseAccounts,
+}
+
+impl Undo {
+    /// A transaction only touches its client, the stored transaction with
+    /// its id (and the rows queued for it) and the house accounts.
+    pub(super) fn capture(engine: &Engine, tx: Tx) -> Self {
+        let tx_id = tx.tx_id();
+        Undo {
+            client_id: tx.client_id(),
+            client: engine.clients.get(&tx.client_id()).cloned(),
+            locked_clients: engine.locked_clients,
+            deposit: engine.deposits.get_for(tx.client_id(), tx_id).copied(),
+            withdrawal: engine.withdrawals.get(&tx_id).copied(),
+            pending: engine.pending.get(&tx_id).cloned(),
+            house: engine.house.clone(),
+        }
+    }
+
+    pub(super) fn restore(self, engine: &mut Engine, tx: Tx) {
+        let tx_id = tx.tx_id();
+        match self.client {
+            Some(client) => engine.clients.insert(self.client_id, client),
+            None => engine.clients.remove(&self.client_id),
+        };
+        match self.deposit {
+            Some(deposit) => engine.deposits.insert(tx_id, deposit),
+            None => engine.deposits.remove_for(self.client_id, tx_id),
+        };
+        match self.withdrawal {
+            Some(withdrawal) => engine.withdrawals.insert(tx_id, withdrawal),
+            None => engine.withdrawals.remove(&tx_id),
+        };
+        match self.pending {
+            Some(pending) => engine.pending.insert(tx_id, pending),
+            None => engine.pending.remove(&tx_id),
+        };
+        engine.locked_clients = self.locked_clients;
+        engine.house = self.house;
+    }
+}
+
+impl Engine {
+    /// Applies `tx` tentatively, see `PreparedTx`. A rejected transaction
+    /// (including a `queued` one) leaves the engine as it was, unlike
+    /// `process_tx` it isn't counted in the client's activity counters.
+    pub fn prepare(&mut self, tx: Tx) -> Result<PreparedTx<'_>, RejectReason> {
+        let undo = Undo::capture(self, tx);
+        match self.process_tx(tx) {
+            Ok(()) => Ok(PreparedTx {
+                engine: self,
+                tx,
+                undo: Some(undo),
+            }),
+            Err(reason) => {
+                undo.restore(self, tx);
+                Err(reason)
+            }
+        }
+    }
+}
+
+impl PreparedTx<'_> {
+    pub fn tx(&self) -> &Tx {
+        &self.tx
+    }
+
+    /// The engine with the transaction applied, as it will be after `commit`.
+    pub fn engine(&self) -> &Engine {
+        self.engine
+    }
+
+    /// Keeps the transaction.
+    pub fn commit(mut self) {
+        self.undo = None;
+    }
+
+    /// Undoes the transaction, leaving the engine as it was before `prepare`.
+    pub fn abort(self) {}
+}
+
+impl Drop for PreparedTx<'_> {
+    fn drop(&mut self) {
+        if let Some(undo) = self.undo.take() {
+            undo.restore(self.engine, self.tx);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::transactions::{ChargebackTx, DisputeTx};
+    use rust_decimal_macros::dec;
+
+    fn engine_with_deposit() -> Engine {
+        let mut engine = Engine::new();
+        engine
+            .process_tx(Tx::Deposit(DepositTx {
+                client_id: 1,
+                tx_id: 1,
+                amount: dec!(10),
+            }))
+            .unwrap();
+        engine
+    }
+
+    #[test]
+    fn test_commit_keeps_and_abort_undoes() {
+        let mut engine = engine_with_deposit();
+        let dispute = Tx::Dispute(DisputeTx {
+            client_id: 1,
+            tx_id: 1,
+        });
+        let chargeback = Tx::Chargeback(ChargebackTx {
+            client_id: 1,
+            tx_id: 1,
+        });
+
+        let prepared = engine.prepare(dispute).unwrap();
+        assert_eq!(prepared.engine().client(1).unwrap().held, dec!(10));
+        prepared.abort();
+        assert_eq!(engine.client(1).unwrap().held, dec!(0));
+        assert_eq!(engine.open_disputes().count(), 0);
+
+        engine.prepare(dispute).unwrap().commit();
+        // Dropped without a decision
+        let _ = engine.prepare(chargeback).unwrap();
+        let client = &engine.client(1).unwrap();
+        assert_eq!(client.held, dec!(10));
+        assert!(!client.locked);
+        assert_eq!(engine.totals().locked, 0);
+        assert_eq!(engine.house().charged_back, dec!(0));
+
+        engine.prepare(chargeback).unwrap().commit();
+        assert!(engine.client(1).unwrap().locked);
+        assert_eq!(engine.totals().locked, 1);
+    }
+
+    #[test]
+    fn test_rejected_prepare_leaves_engine_untouched() {
+        let mut engine = Engine::new();
+        let deposit = Tx::Deposit(DepositTx {
+            client_id: 2,
+            tx_id: 5,
+            amount: dec!(1),
+        });
+        let prepared = engine.prepare(deposit).unwrap();
+        prepared.abort();
+        assert!(engine.clients_iter().next().is_none());
+        assert_eq!(engine.tracked_txs(), 0);
+
+        let mut engine = engine_with_deposit();
+        let withdrawal = Tx::Withdrawal(WithdrawalTx {
+            client_id: 1,
+            tx_id: 2,
+            amount: dec!(50),
+        });
+        assert_eq!(
+            engine.prepare(withdrawal).err(),
+            Some(RejectReason::InsufficientFunds)
+        );
+        assert_eq!(engine.client(1).unwrap().stats.rejected_withdrawals, 0);
+    }
+}
@@ -0,0 +1,404 @@
+//! On-disk index of the deposits of earlier runs, so disputes can still name
+//! them without a snapshot holding every deposit in memory. A run saves the
+//! index with `write_prior_index`, the next one maps it and searches it in
+//! place: a deposit is only copied into the hot tables when a row names it.
+//!
+//! Layout (little endian):
+//! - magic `TPEI` and format version (u16)
+//! - deposit count (u64), then per deposit a fixed-size record: tx id (u32),
+//!   client id (u16), amount (16 bytes, `Decimal::serialize`), status (u8),
+//!   sorted by tx id and then client id
+
+use std::{
+    cell::Cell,
+    io::{self, Write},
+    iter::Peekable,
+    sync::Arc,
+};
+
+use rust_decimal::Decimal;
+
+use crate::{
+    engine::{Engine, config::TxKeys, dispute_state::DisputeState},
+    types::{
+        common::{ClientId, TxId},
+        reject::RejectReason,
+        transactions::{DepositTx, Tx, TxType},
+    },
+};
+
+const MAGIC: &[u8; 4] = b"TPEI";
+const VERSION: u16 = 1;
+const HEADER_LEN: usize = 14;
+const RECORD_LEN: usize = 23;
+
+type Deposit = (DepositTx, DisputeState);
+
+/// A deposit index loaded by `PriorIndex::new`, usually from a memory map.
+pub struct PriorIndex {
+    bytes: Box<dyn AsRef<[u8]> + Send + Sync>,
+    len: usize,
+}
+
+impl PriorIndex {
+    /// Checks the header and that the size matches the deposit count. Records
+    /// are only checked when read, so a large index maps in constant time.
+    pub fn new(bytes: impl AsRef<[u8]> + Send + Sync + 'static) -> io::Result<Self> {
+        let data = bytes.as_ref();
+        if data.len() < HEADER_LEN || &data[..4] != MAGIC {
+            return Err(invalid_data("not a deposit index".to_string()));
+        }
+        let version = u16::from_le_bytes([data[4], data[5]]);
+        if version != VERSION {
+            return Err(invalid_data(format!(
+                "unsupported deposit index version {version}"
+            )));
+        }
+        let count = u64::from_le_bytes(data[6..HEADER_LEN].try_into().unwrap());
+        let len = usize::try_from(count)
+            .ok()
+            .filter(|&len| Some(data.len() - HEADER_LEN) == len.checked_mul(RECORD_LEN))
+            .ok_or_else(|| {
+                invalid_data(format!(
+                    "deposit index size doesn't match its {count} deposits"
+                ))
+            })?;
+
+        Ok(PriorIndex {
+            bytes: Box::new(bytes),
+            len,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The client's deposit `tx_id`, or else another client's deposit with
+    /// that id. Fails on a record with an unknown status.
+    pub fn get(&self, client_id: ClientId, tx_id: TxId) -> io::Result<Option<Deposit>> {
+        let first = self.partition_point(|(id, _)| id < tx_id);
+        let same_id = || (first..self.len).take_while(|&i| self.key(i).0 == tx_id);
+        same_id()
+            .find(|&i| self.key(i).1 == client_id)
+            .or_else(|| same_id().next())
+            .map(|i| decode(self.record(i)))
+            .transpose()
+    }
+
+    /// Every deposit, by tx id and then client id. Fails at a record with an
+    /// unknown status or out of order.
+    pub fn iter(&self) -> impl Iterator<Item = io::Result<Deposit>> + '_ {
+        (0..self.len).map(|i| {
+            if i > 0 && self.key(i - 1) >= self.key(i) {
+                return Err(invalid_data(format!(
+                    "deposit index isn't sorted at tx {}",
+                    self.key(i).0
+                )));
+            }
+            decode(self.record(i))
+        })
+    }
+
+    fn record(&self, i: usize) -> &[u8] {
+        let start = HEADER_LEN + i * RECORD_LEN;
+        &(*self.bytes).as_ref()[start..start + RECORD_LEN]
+    }
+
+    fn key(&self, i: usize) -> (TxId, ClientId) {
+        key(self.record(i))
+    }
+
+    fn partition_point(&self, pred: impl Fn((TxId, ClientId)) -> bool) -> usize {
+        let (mut low, mut high) = (0, self.len);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if pred(self.key(mid)) {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        low
+    }
+}
+
+impl Engine {
+    /// Looks up deposits missing from the hot tables in `index` from now on.
+    pub fn set_prior_index(&mut self, index: PriorIndex) {
+        self.prior = Some(Arc::new(index));
+    }
+
+    /// Deposits in the prior index, zero without one.
+    pub fn prior_deposits(&self) -> usize {
+        self.prior.as_ref().map_or(0, |prior| prior.len())
+    }
+
+    /// Copies the deposit `tx` names from the prior index into the hot
+    /// tables, unless a transaction with its key is stored already. A broken
+    /// record is `ConflictingTx`, like other corrupt input.
+    pub(crate) fn fault_in_prior(&mut self, tx: Tx) -> Result<(), RejectReason> {
+        let Some(prior) = &self.prior else {
+            return Ok(());
+        };
+        if matches!(tx, Tx::OpeningBalance(_)) {
+            return Ok(());
+        }
+        let (client_id, tx_id) = (tx.client_id(), tx.tx_id());
+        let stored = match self.config.tx_keys {
+            TxKeys::Global => self.tx_owner(tx_id).is_some(),
+            TxKeys::PerClient => self.tx_state(client_id, tx_id).is_some(),
+        };
+        if stored {
+            return Ok(());
+        }
+        let deposit = prior
+            .get(client_id, tx_id)
+            .map_err(|_| RejectReason::ConflictingTx)?;
+        if let Some(deposit) = deposit {
+            self.check_capacity(None, Some(&deposit.0), None)?;
+            self.deposits.insert_new(deposit);
+        }
+        Ok(())
+    }
+
+    /// Writes the deposit index for the next run: the prior index with the
+    /// deposits of this run, settled ones included, taking precedence.
+    pub fn write_prior_index<W: Write>(&self, mut w: W) -> io::Result<usize> {
+        // Set at the first broken record of the prior index, which ends it
+        let broken = Cell::new(None);
+        let settled = self.settled.iter().filter_map(|tx| match tx.tx_type {
+            TxType::Deposit => Some((
+                DepositTx {
+                    client_id: tx.client_id,
+                    tx_id: tx.tx_id,
+                    amount: tx.amount,
+                },
+                tx.state,
+            )),
+            _ => None,
+        });
+        let mut hot: Vec<Deposit> = self.deposits.values().copied().chain(settled).collect();
+        hot.sort_unstable_by_key(|(deposit_tx, _)| (deposit_tx.tx_id, deposit_tx.client_id));
+
+        let prior = || {
+            self.prior
+                .iter()
+                .flat_map(|prior| prior.iter())
+                .map_while(|deposit| deposit.map_err(|err| broken.set(Some(err))).ok())
+        };
+        let count = Merge::new(hot.iter().copied(), prior()).count();
+        if let Some(err) = broken.take() {
+            return Err(err);
+        }
+        w.write_all(MAGIC)?;
+        w.write_all(&VERSION.to_le_bytes())?;
+        w.write_all(&(count as u64).to_le_bytes())?;
+        for (deposit_tx, status) in Merge::new(hot.iter().copied(), prior()) {
+            w.write_all(&deposit_tx.tx_id.to_le_bytes())?;
+            w.write_all(&deposit_tx.client_id.to_le_bytes())?;
+            w.write_all(&deposit_tx.amount.serialize())?;
+            w.write_all(&[status.to_byte()])?;
+        }
+        w.flush()?;
+        Ok(count)
+    }
+}
+
+/// Merges two sorted deposit streams, the first one winning on equal keys.
+struct Merge<A: Iterator<Item = Deposit>, B: Iterator<Item = Deposit>> {
+    hot: Peekable<A>,
+    prior: Peekable<B>,
+}
+
+impl<A: Iterator<Item = Deposit>, B: Iterator<Item = Deposit>> Merge<A, B> {
+    fn new(hot: A, prior: B) -> Self {
+        Merge {
+            hot: hot.peekable(),
+            prior: prior.peekable(),
+        }
+    }
+}
+
+impl<A: Iterator<Item = Deposit>, B: Iterator<Item = Deposit>> Iterator for Merge<A, B> {
+    type Item = Deposit;
+
+    fn next(&mut self) -> Option<Deposit> {
+        let order = |(deposit_tx, _): &Deposit| (deposit_tx.tx_id, deposit_tx.client_id);
+        match (self.hot.peek(), self.prior.peek()) {
+            (Some(hot), Some(prior)) if order(prior) < order(hot) => self.prior.next(),
+            (Some(hot), Some(prior)) => {
+                if order(prior) == order(hot) {
+                    self.prior.next();
+                }
+                self.hot.next()
+            }
+            (Some(_), None) => self.hot.next(),
+            (None, _) => self.prior.next(),
+        }
+    }
+}
+
+fn key(record: &[u8]) -> (TxId, ClientId) {
+    (
+        u32::from_le_bytes(record[..4].try_into().unwrap()),
+        u16::from_le_bytes(record[4..6].try_into().unwrap()),
+    )
+}
+
+fn decode(record: &[u8]) -> io::Result<Deposit> {
+    let (tx_id, client_id) = key(record);
+    let amount = Decimal::deserialize(record[6..22].try_into().unwrap());
+    let status = DisputeState::from_byte(record[22])?;
+    Ok((
+        DepositTx {
+            client_id,
+            tx_id,
+            amount,
+        },
+        status,
+    ))
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        engine::config::EngineConfig,
+        types::transactions::{ChargebackTx, DisputeTx},
+    };
+    use rust_decimal_macros::dec;
+
+    fn index_of(engine: &Engine) -> PriorIndex {
+        let mut bytes = Vec::new();
+        engine.write_prior_index(&mut bytes).unwrap();
+        PriorIndex::new(bytes).unwrap()
+    }
+
+    fn deposit(client_id: ClientId, tx_id: TxId, amount: Decimal) -> Tx {
+        Tx::Deposit(DepositTx::new(client_id, tx_id, amount).unwrap())
+    }
+
+    #[test]
+    fn test_disputes_reach_into_the_index() {
+        let mut day1 = Engine::new();
+        day1.process_tx(deposit(1, 10, dec!(5))).unwrap();
+        day1.process_tx(deposit(2, 3, dec!(1.5))).unwrap();
+        day1.process_tx(deposit(1, 7, dec!(2))).unwrap();
+        let index = index_of(&day1);
+        assert_eq!(index.len(), 3);
+        let ids: Vec<_> = index.iter().map(|d| d.unwrap().0.tx_id).collect();
+        assert_eq!(ids, vec![3, 7, 10]);
+        assert_eq!(index.get(2, 7).unwrap().unwrap().0.client_id, 1);
+        assert!(index.get(1, 8).unwrap().is_none());
+
+        let mut day2 = Engine::new();
+        for client in day1.clients_iter() {
+            day2.open_balance(client).unwrap();
+        }
+        day2.set_prior_index(index);
+        let dispute = |client_id, tx_id| Tx::Dispute(DisputeTx { client_id, tx_id });
+        assert_eq!(
+            day2.process_tx(dispute(2, 10)),
+            Err(RejectReason::ClientMismatch)
+        );
+        day2.process_tx(dispute(1, 10)).unwrap();
+        day2.process_tx(Tx::Chargeback(ChargebackTx {
+            client_id: 1,
+            tx_id: 10,
+        }))
+        .unwrap();
+        assert_eq!(day2.client(1).unwrap().total, dec!(2));
+        // Only the deposits rows named were copied in
+        assert_eq!(day2.deposits.len(), 1);
+
+        // Reusing an indexed id conflicts like reusing a stored one
+        assert_eq!(
+            day2.process_tx(deposit(2, 3, dec!(9))),
+            Err(RejectReason::ConflictingTx)
+        );
+        day2.process_tx(deposit(2, 4, dec!(1))).unwrap();
+
+        let index = index_of(&day2);
+        let states: Vec<_> = index
+            .iter()
+            .map(|d| d.map(|(d, s)| (d.tx_id, s)).unwrap())
+            .collect();
+        assert_eq!(
+            states,
+            vec![
+                (3, DisputeState::Normal),
+                (4, DisputeState::Normal),
+                (7, DisputeState::Normal),
+                (10, DisputeState::ChargedBack),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_broken_indexes_are_refused() {
+        let mut engine = Engine::new();
+        engine.process_tx(deposit(1, 1, dec!(1))).unwrap();
+        engine.process_tx(deposit(1, 2, dec!(1))).unwrap();
+        let mut bytes = Vec::new();
+        engine.write_prior_index(&mut bytes).unwrap();
+
+        assert!(PriorIndex::new(b"TPES".to_vec()).is_err());
+        assert!(PriorIndex::new(bytes[..bytes.len() - 1].to_vec()).is_err());
+        let mut unsorted = bytes.clone();
+        unsorted[HEADER_LEN..HEADER_LEN + 4].copy_from_slice(&5u32.to_le_bytes());
+        let unsorted = PriorIndex::new(unsorted).unwrap();
+        assert!(unsorted.iter().any(|deposit| deposit.is_err()));
+
+        // Records are checked when read, a broken one fails the row naming it
+        let mut bad_status = bytes;
+        *bad_status.last_mut().unwrap() = 9;
+        let index = PriorIndex::new(bad_status).unwrap();
+        assert!(index.get(1, 1).unwrap().is_some());
+        assert!(index.get(1, 2).is_err());
+        let mut engine = Engine::new();
+        engine.set_prior_index(index);
+        let dispute = |tx_id| {
+            Tx::Dispute(DisputeTx {
+                client_id: 1,
+                tx_id,
+            })
+        };
+        assert_eq!(
+            engine.process_tx(dispute(2)),
+            Err(RejectReason::ConflictingTx)
+        );
+        assert!(engine.write_prior_index(Vec::new()).is_err());
+        engine.process_tx(deposit(1, 3, dec!(1))).unwrap();
+        engine.process_tx(dispute(1)).unwrap();
+    }
+
+    #[test]
+    fn test_faulting_in_respects_capacity() {
+        let mut day1 = Engine::new();
+        day1.process_tx(deposit(1, 1, dec!(5))).unwrap();
+        let mut day2 = Engine::with_config(EngineConfig {
+            max_deposits: Some(1),
+            ..EngineConfig::default()
+        });
+        day2.process_tx(deposit(1, 2, dec!(1))).unwrap();
+        day2.set_prior_index(index_of(&day1));
+        let dispute = Tx::Dispute(DisputeTx {
+            client_id: 1,
+            tx_id: 1,
+        });
+        assert_eq!(
+            day2.process_tx(dispute),
+            Err(RejectReason::CapacityExceeded)
+        );
+        assert_eq!(day2.deposits.len(), 1);
+    }
+}
@@ -0,0 +1,53 @@
+use crate::{
+    engine::{Disputed, Engine, TxHandler, dispute_state::DisputeEvent, find_disputed},
+    types::{reject::RejectReason, transactions::ResolveTx},
+};
+
+impl TxHandler<ResolveTx> for Engine {
+    fn handle(&mut self, resolve_tx: ResolveTx) -> Result<(), RejectReason> {
+        let amounts = self.config.amounts;
+        let Some(client) = self.clients.get_mut(&resolve_tx.client_id) else {
+            return Err(RejectReason::UnknownClient);
+        };
+
+        if client.locked && !self.config.rules.policy().resolve_when_locked() {
+            return Err(RejectReason::AccountLocked);
+        }
+
+        // Deposit or withdrawal must be in a state that can be resolved
+        let (disputed, amount, status, next) = find_disputed(
+            &mut self.deposits,
+            &mut self.withdrawals,
+            &self.settled,
+            &self.config,
+            resolve_tx.client_id,
+            resolve_tx.tx_id,
+            DisputeEvent::Resolve,
+        )?;
+
+        let house_held = amounts.sub_aggregate(self.house.held, amount)?;
+        let held = amounts.sub(client.held, amount)?;
+        let (available, total, withdrawn) = match disputed {
+            Disputed::Deposit => (
+                amounts.add(client.available, amount)?,
+                client.total,
+                self.house.withdrawn,
+            ),
+            // The withdrawal stands, the held funds leave again
+            Disputed::Withdrawal => (
+                client.available,
+                amounts.sub(client.total, amount)?,
+                amounts.add_aggregate(self.house.withdrawn, amount)?,
+            ),
+        };
+
+        *status = next;
+        client.available = available;
+        client.held = held;
+        client.total = total;
+        self.house.held = house_held;
+        self.house.withdrawn = withdrawn;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,253 @@
+//! Compensating entries for a transaction that was accepted by mistake, so
+//! operators can fix a state without editing snapshots by hand.
+
+use rust_decimal::Decimal;
+
+use crate::{
+    engine::{Disputed, Engine, dispute_state::DisputeState},
+    types::{
+        common::{ClientId, TxId},
+        reject::RejectReason,
+    },
+};
+
+/// The step `Engine::revert` undid, always the latest one on the transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reverted {
+    /// The deposit was taken back out and forgotten
+    Deposit,
+    /// The withdrawal was credited back and forgotten
+    Withdrawal,
+    /// The open dispute was unwound, the transaction is back to normal
+    Dispute,
+    /// The resolve was undone, the transaction is under dispute again
+    Resolve,
+}
+
+impl Reverted {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Reverted::Deposit => "deposit",
+            Reverted::Withdrawal => "withdrawal",
+            Reverted::Dispute => "dispute",
+            Reverted::Resolve => "resolve",
+        }
+    }
+}
+
+/// What a revert did, for the audit trail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reversal {
+    pub reverted: Reverted,
+    pub client_id: ClientId,
+    pub tx_id: TxId,
+    pub amount: Decimal,
+}
+
+impl Engine {
+    /// Undoes the latest accepted step on a stored deposit (or withdrawal
+    /// under rules v2): the transaction itself, or the dispute or resolve on
+    /// it. A chargeback isn't reverted, it may have locked the account for
+    /// other reasons too. Works on locked accounts, and taking back a deposit
+    /// can leave `available` negative like a dispute does.
+    pub fn revert(&mut self, tx_id: TxId) -> Result<Reversal, RejectReason> {
+        let amounts = self.config.amounts;
+        self.unsettle(tx_id);
+        let (disputed, client_id, amount, status) =
+            if let Some((deposit_tx, status)) = self.deposits.get(&tx_id) {
+                (
+                    Disputed::Deposit,
+                    deposit_tx.client_id,
+                    deposit_tx.amount,
+                    *status,
+                )
+            } else if let Some((withdrawal_tx, status)) = self.withdrawals.get(&tx_id) {
+                (
+                    Disputed::Withdrawal,
+                    withdrawal_tx.client_id,
+                    withdrawal_tx.amount,
+                    *status,
+                )
+            } else {
+                return Err(RejectReason::UnknownTx);
+            };
+        let Some(client) = self.clients.get_mut(&client_id) else {
+            return Err(RejectReason::UnknownClient);
+        };
+        let house = &mut self.house;
+
+        let (mut available, mut held, mut total) = (client.available, client.held, client.total);
+        let (mut deposited, mut withdrawn, mut house_held) =
+            (house.deposited, house.withdrawn, house.held);
+        let (reverted, next) = match (status, &disputed) {
+            (DisputeState::ChargedBack, _) => return Err(RejectReason::NotDisputable),
+            (DisputeState::Normal, Disputed::Deposit) => {
+                available = amounts.sub(available, amount)?;
+                total = amounts.sub(total, amount)?;
+                deposited = amounts.sub_aggregate(deposited, amount)?;
+                (Reverted::Deposit, None)
+            }
+            (DisputeState::Normal, Disputed::Withdrawal) => {
+                available = amounts.add(available, amount)?;
+                total = amounts.add(total, amount)?;
+                withdrawn = amounts.sub_aggregate(withdrawn, amount)?;
+                (Reverted::Withdrawal, None)
+            }
+            // The reverse of the dispute handler
+            (DisputeState::UnderDispute, _) => {
+                held = amounts.sub(held, amount)?;
+                house_held = amounts.sub_aggregate(house_held, amount)?;
+                match disputed {
+                    Disputed::Deposit => available = amounts.add(available, amount)?,
+                    Disputed::Withdrawal => {
+                        total = amounts.sub(total, amount)?;
+                        withdrawn = amounts.add_aggregate(withdrawn, amount)?;
+                    }
+                }
+                (Reverted::Dispute, Some(DisputeState::Normal))
+            }
+            // The reverse of the resolve handler
+            (DisputeState::Resolved, _) => {
+                held = amounts.add(held, amount)?;
+                house_held = amounts.add_aggregate(house_held, amount)?;
+                match disputed {
+                    Disputed::Deposit => available = amounts.sub(available, amount)?,
+                    Disputed::Withdrawal => {
+                        total = amounts.add(total, amount)?;
+                        withdrawn = amounts.sub_aggregate(withdrawn, amount)?;
+                    }
+                }
+                (Reverted::Resolve, Some(DisputeState::UnderDispute))
+            }
+        };
+
+        client.available = available;
+        client.held = held;
+        client.total = total;
+        house.deposited = deposited;
+        house.withdrawn = withdrawn;
+        house.held = house_held;
+        match (reverted, next) {
+            (Reverted::Deposit, _) => {
+                client.stats.deposits = client.stats.deposits.saturating_sub(1);
+                self.deposits.remove(&tx_id);
+            }
+            (Reverted::Withdrawal, _) => {
+                client.stats.withdrawals = client.stats.withdrawals.saturating_sub(1);
+                self.withdrawals.remove(&tx_id);
+            }
+            (_, Some(next)) => {
+                if let Some((_, status)) = self.deposits.get_mut(&tx_id) {
+                    *status = next;
+                } else if let Some((_, status)) = self.withdrawals.get_mut(&tx_id) {
+                    *status = next;
+                }
+            }
+            (_, None) => {}
+        }
+
+        Ok(Reversal {
+            reverted,
+            client_id,
+            tx_id,
+            amount,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        engine::{config::EngineConfig, rules::Rules},
+        types::transactions::{ChargebackTx, DepositTx, DisputeTx, ResolveTx, Tx, WithdrawalTx},
+    };
+    use rust_decimal_macros::dec;
+
+    fn apply(engine: &mut Engine, txs: &[Tx]) {
+        for tx in txs {
+            engine.process_tx(*tx).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_revert_steps_back_through_a_deposit() {
+        let mut engine = Engine::new();
+        apply(
+            &mut engine,
+            &[
+                Tx::Deposit(DepositTx {
+                    client_id: 1,
+                    tx_id: 1,
+                    amount: dec!(10),
+                }),
+                Tx::Dispute(DisputeTx {
+                    client_id: 1,
+                    tx_id: 1,
+                }),
+                Tx::Resolve(ResolveTx {
+                    client_id: 1,
+                    tx_id: 1,
+                }),
+            ],
+        );
+
+        let steps: Vec<_> = (0..3).map(|_| engine.revert(1).unwrap().reverted).collect();
+        assert_eq!(
+            steps,
+            vec![Reverted::Resolve, Reverted::Dispute, Reverted::Deposit]
+        );
+        assert_eq!(engine.revert(1), Err(RejectReason::UnknownTx));
+
+        let client = &engine.client(1).unwrap();
+        assert_eq!(
+            (client.available, client.held, client.total),
+            (dec!(0), dec!(0), dec!(0))
+        );
+        assert_eq!(client.stats.deposits, 0);
+        assert_eq!(engine.house().deposited, dec!(0));
+        assert_eq!(engine.house().held, dec!(0));
+    }
+
+    #[test]
+    fn test_revert_withdrawal_and_chargeback() {
+        let mut engine = Engine::with_config(EngineConfig {
+            rules: Rules::V2,
+            ..EngineConfig::default()
+        });
+        apply(
+            &mut engine,
+            &[
+                Tx::Deposit(DepositTx {
+                    client_id: 1,
+                    tx_id: 1,
+                    amount: dec!(10),
+                }),
+                Tx::Withdrawal(WithdrawalTx {
+                    client_id: 1,
+                    tx_id: 2,
+                    amount: dec!(4),
+                }),
+                Tx::Dispute(DisputeTx {
+                    client_id: 1,
+                    tx_id: 1,
+                }),
+                Tx::Chargeback(ChargebackTx {
+                    client_id: 1,
+                    tx_id: 1,
+                }),
+            ],
+        );
+
+        assert_eq!(engine.revert(1), Err(RejectReason::NotDisputable));
+        let reversal = engine.revert(2).unwrap();
+        assert_eq!(reversal.reverted, Reverted::Withdrawal);
+        assert_eq!(reversal.amount, dec!(4));
+
+        let client = &engine.client(1).unwrap();
+        assert_eq!(client.available, dec!(0));
+        assert_eq!(client.total, dec!(0));
+        assert_eq!(engine.house().withdrawn, dec!(0));
+        assert_eq!(engine.totals().total, dec!(0));
+    }
+}
@@ -0,0 +1,82 @@
+use std::{fmt, str::FromStr};
+
+/// What a rule set allows, one implementation per version.
+///
+/// A version's behavior never changes once released, rule changes go into a
+/// new version so historic runs can be reproduced exactly.
+pub trait RulePolicy: Sync {
+    /// Whether withdrawals are kept around so they can be disputed
+    fn withdrawals_disputable(&self) -> bool;
+
+    /// Whether disputes on a locked account can still be resolved
+    fn resolve_when_locked(&self) -> bool;
+}
+
+/// The original rules: only deposits can be disputed.
+pub struct RulesV1;
+
+impl RulePolicy for RulesV1 {
+    fn withdrawals_disputable(&self) -> bool {
+        false
+    }
+
+    fn resolve_when_locked(&self) -> bool {
+        true
+    }
+}
+
+/// Withdrawals can be disputed, locked accounts are frozen for resolves.
+pub struct RulesV2;
+
+impl RulePolicy for RulesV2 {
+    fn withdrawals_disputable(&self) -> bool {
+        true
+    }
+
+    fn resolve_when_locked(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Rules {
+    #[default]
+    V1,
+    V2,
+}
+
+impl Rules {
+    pub fn policy(&self) -> &'static dyn RulePolicy {
+        match self {
+            Rules::V1 => &RulesV1,
+            Rules::V2 => &RulesV2,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Rules::V1 => "v1",
+            Rules::V2 => "v2",
+        }
+    }
+}
+
+impl fmt::Display for Rules {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Rules {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "v1" => Ok(Rules::V1),
+            "v2" => Ok(Rules::V2),
+            _ => Err(format!("unknown rule set `{s}`, expected v1 or v2")),
+        }
+    }
+}
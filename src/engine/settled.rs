@@ -0,0 +1,465 @@
+//! Cold storage for deposits and withdrawals whose dispute is over. Nothing
+//! can happen to a resolved or charged back transaction any more, but rows
+//! naming it must still be rejected as `not_disputable` rather than
+//! `unknown_tx`, so `Engine::compact` moves them out of the hot tables into
+//! sorted, delta-encoded runs instead of forgetting them.
+
+use std::{fmt, str::FromStr, sync::Arc};
+
+use rust_decimal::Decimal;
+
+use crate::{
+    engine::{Engine, dispute_state::DisputeState},
+    types::{
+        common::{ClientId, TxId},
+        transactions::{DepositTx, TxType, WithdrawalTx},
+    },
+};
+
+/// Entries per block, a lookup decodes at most one block.
+const BLOCK_LEN: usize = 64;
+/// Runs kept before they are merged into one, a lookup searches each run.
+const MAX_RUNS: usize = 8;
+
+/// What `Engine::compact` does with settled transactions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SettledPolicy {
+    /// Keep them compressed, rows naming them are still `not_disputable`
+    Compress,
+    /// Forget them, rows naming them become `unknown_tx`
+    Drop,
+}
+
+impl SettledPolicy {
+    pub const ALL: [SettledPolicy; 2] = [SettledPolicy::Compress, SettledPolicy::Drop];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SettledPolicy::Compress => "compress",
+            SettledPolicy::Drop => "drop",
+        }
+    }
+}
+
+impl fmt::Display for SettledPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for SettledPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        SettledPolicy::ALL
+            .into_iter()
+            .find(|policy| policy.name() == s)
+            .ok_or_else(|| format!("unknown policy `{s}`, expected compress or drop"))
+    }
+}
+
+/// What a compaction pass moved out of the hot tables.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Compaction {
+    pub compressed: usize,
+    pub dropped: usize,
+}
+
+/// A resolved or charged back deposit or withdrawal.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct SettledTx {
+    pub(crate) tx_type: TxType,
+    pub(crate) client_id: ClientId,
+    pub(crate) tx_id: TxId,
+    pub(crate) amount: Decimal,
+    pub(crate) state: DisputeState,
+}
+
+impl SettledTx {
+    /// Orders the runs, deposits before withdrawals with the same id like
+    /// the hot tables are searched.
+    fn key(&self) -> (TxId, bool) {
+        (self.tx_id, self.tx_type == TxType::Withdrawal)
+    }
+}
+
+/// The settled transactions, as immutable runs shared between forks.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SettledTxs {
+    runs: Vec<Arc<Run>>,
+}
+
+impl SettledTxs {
+    pub(crate) fn len(&self) -> usize {
+        self.runs.iter().map(|run| run.len).sum()
+    }
+
+    pub(crate) fn bytes(&self) -> usize {
+        self.runs.iter().map(|run| run.bytes()).sum()
+    }
+
+    pub(crate) fn get(&self, tx_id: TxId) -> Option<SettledTx> {
+        // A transaction is in one run at most, a revert takes it out first
+        self.runs.iter().find_map(|run| run.get(tx_id))
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = SettledTx> + '_ {
+        self.runs.iter().flat_map(|run| run.iter())
+    }
+
+    pub(crate) fn add(&mut self, mut txs: Vec<SettledTx>) {
+        if txs.is_empty() {
+            return;
+        }
+        if self.runs.len() >= MAX_RUNS {
+            txs.extend(self.iter());
+            self.runs.clear();
+        }
+        txs.sort_unstable_by_key(SettledTx::key);
+        self.runs.push(Arc::new(Run::encode(&txs)));
+    }
+
+    /// Takes `tx_id` out, re-encoding the run it was in.
+    pub(crate) fn remove(&mut self, tx_id: TxId) -> Option<SettledTx> {
+        let index = self.runs.iter().position(|run| run.get(tx_id).is_some())?;
+        let mut txs: Vec<_> = self.runs[index].iter().collect();
+        let removed = txs.remove(txs.iter().position(|tx| tx.tx_id == tx_id)?);
+        if txs.is_empty() {
+            self.runs.remove(index);
+        } else {
+            self.runs[index] = Arc::new(Run::encode(&txs));
+        }
+        Some(removed)
+    }
+}
+
+/// Transactions sorted by id and encoded in blocks of `BLOCK_LEN`. Each entry
+/// is the id as a delta from the previous one, the client id, a flags byte
+/// (withdrawal, charged back, negative, scale) and the amount's mantissa, all
+/// as LEB128 varints.
+#[derive(Debug)]
+struct Run {
+    /// Id of the first entry of each block
+    firsts: Vec<TxId>,
+    /// Offset of each block in `bytes`
+    offsets: Vec<usize>,
+    bytes: Vec<u8>,
+    len: usize,
+}
+
+const WITHDRAWAL: u8 = 1;
+const CHARGED_BACK: u8 = 2;
+const NEGATIVE: u8 = 4;
+const SCALE_SHIFT: u32 = 3;
+
+impl Run {
+    fn encode(txs: &[SettledTx]) -> Run {
+        let mut run = Run {
+            firsts: Vec::with_capacity(txs.len().div_ceil(BLOCK_LEN)),
+            offsets: Vec::with_capacity(txs.len().div_ceil(BLOCK_LEN)),
+            bytes: Vec::new(),
+            len: txs.len(),
+        };
+        for block in txs.chunks(BLOCK_LEN) {
+            run.firsts.push(block[0].tx_id);
+            run.offsets.push(run.bytes.len());
+            let mut previous = block[0].tx_id;
+            for tx in block {
+                let mut flags = (tx.amount.scale() as u8) << SCALE_SHIFT;
+                flags |= if tx.tx_type == TxType::Withdrawal {
+                    WITHDRAWAL
+                } else {
+                    0
+                };
+                flags |= if tx.state == DisputeState::ChargedBack {
+                    CHARGED_BACK
+                } else {
+                    0
+                };
+                flags |= if tx.amount.is_sign_negative() {
+                    NEGATIVE
+                } else {
+                    0
+                };
+
+                put_varint(&mut run.bytes, u128::from(tx.tx_id - previous));
+                put_varint(&mut run.bytes, u128::from(tx.client_id));
+                run.bytes.push(flags);
+                put_varint(&mut run.bytes, tx.amount.mantissa().unsigned_abs());
+                previous = tx.tx_id;
+            }
+        }
+        run.bytes.shrink_to_fit();
+        run
+    }
+
+    fn bytes(&self) -> usize {
+        self.bytes.capacity()
+            + self.firsts.capacity() * size_of::<TxId>()
+            + self.offsets.capacity() * size_of::<usize>()
+    }
+
+    fn block(&self, index: usize) -> impl Iterator<Item = SettledTx> + '_ {
+        let len = BLOCK_LEN.min(self.len - index * BLOCK_LEN);
+        let mut pos = self.offsets[index];
+        let mut tx_id = self.firsts[index];
+        (0..len).map(move |_| {
+            tx_id += get_varint(&self.bytes, &mut pos) as TxId;
+            let client_id = get_varint(&self.bytes, &mut pos) as ClientId;
+            let flags = self.bytes[pos];
+            pos += 1;
+            let mantissa = get_varint(&self.bytes, &mut pos) as i128;
+            let mut amount =
+                Decimal::from_i128_with_scale(mantissa, u32::from(flags >> SCALE_SHIFT));
+            amount.set_sign_negative(flags & NEGATIVE != 0);
+
+            SettledTx {
+                tx_type: if flags & WITHDRAWAL != 0 {
+                    TxType::Withdrawal
+                } else {
+                    TxType::Deposit
+                },
+                client_id,
+                tx_id,
+                amount,
+                state: if flags & CHARGED_BACK != 0 {
+                    DisputeState::ChargedBack
+                } else {
+                    DisputeState::Resolved
+                },
+            }
+        })
+    }
+
+    fn get(&self, tx_id: TxId) -> Option<SettledTx> {
+        let index = self.firsts.partition_point(|first| *first <= tx_id);
+        self.block(index.checked_sub(1)?)
+            .find(|tx| tx.tx_id >= tx_id)
+            .filter(|tx| tx.tx_id == tx_id)
+    }
+
+    fn iter(&self) -> impl Iterator<Item = SettledTx> + '_ {
+        (0..self.firsts.len()).flat_map(|index| self.block(index))
+    }
+}
+
+fn put_varint(out: &mut Vec<u8>, mut value: u128) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn get_varint(bytes: &[u8], pos: &mut usize) -> u128 {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = bytes[*pos];
+        *pos += 1;
+        value |= u128::from(byte & 0x7F) << shift;
+        if byte < 0x80 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+fn is_settled(state: DisputeState) -> bool {
+    matches!(state, DisputeState::Resolved | DisputeState::ChargedBack)
+}
+
+impl Engine {
+    /// Moves every resolved or charged back deposit and withdrawal out of the
+    /// tables kept for disputes, shrinking them. Deposits that later reuse a
+    /// compacted id are stored again instead of being ignored.
+    pub fn compact(&mut self, policy: SettledPolicy) -> Compaction {
+        let deposits: Vec<_> = self
+            .deposits
+            .values()
+            .filter(|(_, state)| is_settled(*state))
+            .map(|(deposit_tx, state)| SettledTx {
+                tx_type: TxType::Deposit,
+                client_id: deposit_tx.client_id,
+                tx_id: deposit_tx.tx_id,
+                amount: deposit_tx.amount,
+                state: *state,
+            })
+            .collect();
+        let withdrawals: Vec<_> = self
+            .withdrawals
+            .values()
+            .filter(|(_, state)| is_settled(*state))
+            .map(|(withdrawal_tx, state)| SettledTx {
+                tx_type: TxType::Withdrawal,
+                client_id: withdrawal_tx.client_id,
+                tx_id: withdrawal_tx.tx_id,
+                amount: withdrawal_tx.amount,
+                state: *state,
+            })
+            .collect();
+        for tx in &deposits {
+            self.deposits.remove_for(tx.client_id, tx.tx_id);
+        }
+        for tx in &withdrawals {
+            self.withdrawals.remove(&tx.tx_id);
+        }
+        self.deposits.shrink_to_fit();
+        self.withdrawals.shrink_to_fit();
+
+        let moved = deposits.len() + withdrawals.len();
+        match policy {
+            SettledPolicy::Compress => {
+                let mut settled = deposits;
+                settled.extend(withdrawals);
+                self.settled.add(settled);
+                Compaction {
+                    compressed: moved,
+                    dropped: 0,
+                }
+            }
+            SettledPolicy::Drop => Compaction {
+                compressed: 0,
+                dropped: moved,
+            },
+        }
+    }
+
+    /// Resolved and charged back transactions moved out by `compact`.
+    pub fn settled_txs(&self) -> usize {
+        self.settled.len()
+    }
+
+    /// Moves a compacted transaction back into its hot table, for a revert.
+    pub(crate) fn unsettle(&mut self, tx_id: TxId) {
+        let Some(tx) = self.settled.remove(tx_id) else {
+            return;
+        };
+        match tx.tx_type {
+            TxType::Withdrawal => {
+                let withdrawal_tx = WithdrawalTx {
+                    client_id: tx.client_id,
+                    tx_id,
+                    amount: tx.amount,
+                };
+                self.withdrawals.insert(tx_id, (withdrawal_tx, tx.state));
+            }
+            _ => {
+                let deposit_tx = DepositTx {
+                    client_id: tx.client_id,
+                    tx_id,
+                    amount: tx.amount,
+                };
+                self.deposits.insert(tx_id, (deposit_tx, tx.state));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{
+        reject::RejectReason,
+        transactions::{ChargebackTx, DisputeTx, ResolveTx, Tx},
+    };
+    use rust_decimal_macros::dec;
+
+    /// Deposits 1..=n, each by the client with the same id, every third one
+    /// resolved and every third one charged back.
+    fn engine_with_settled(n: TxId) -> Engine {
+        let mut engine = Engine::new();
+        for tx_id in 1..=n {
+            let client_id = tx_id as ClientId;
+            let amount = Decimal::new(i64::from(tx_id) * 125, 3);
+            let txs = [
+                Some(Tx::Deposit(DepositTx {
+                    client_id,
+                    tx_id,
+                    amount,
+                })),
+                (tx_id % 3 != 0).then_some(Tx::Dispute(DisputeTx { client_id, tx_id })),
+                match tx_id % 3 {
+                    1 => Some(Tx::Resolve(ResolveTx { client_id, tx_id })),
+                    2 => Some(Tx::Chargeback(ChargebackTx { client_id, tx_id })),
+                    _ => None,
+                },
+            ];
+            for tx in txs.into_iter().flatten() {
+                engine.process_tx(tx).unwrap();
+            }
+        }
+        engine
+    }
+
+    #[test]
+    fn test_compacted_transactions_stay_not_disputable() {
+        let mut engine = engine_with_settled(300);
+        let totals = engine.totals();
+
+        let compaction = engine.compact(SettledPolicy::Compress);
+        assert_eq!(compaction.compressed, 200);
+        assert_eq!((engine.deposits.len(), engine.settled_txs()), (100, 200));
+        assert_eq!(engine.totals(), totals);
+        let settled = engine.settled.get(5).unwrap();
+        assert_eq!(
+            (settled.amount, settled.amount.scale(), settled.state),
+            (dec!(0.625), 3, DisputeState::ChargedBack)
+        );
+
+        let dispute = |client_id, tx_id| Tx::Dispute(DisputeTx { client_id, tx_id });
+        assert_eq!(
+            engine.process_tx(dispute(4, 4)),
+            Err(RejectReason::NotDisputable)
+        );
+        assert_eq!(
+            engine.process_tx(dispute(1, 4)),
+            Err(RejectReason::ClientMismatch)
+        );
+        assert_eq!(engine.process_tx(dispute(3, 3)), Ok(()));
+
+        // A revert brings the transaction back under dispute
+        engine.revert(4).unwrap();
+        assert_eq!(engine.deposits[&4].1, DisputeState::UnderDispute);
+        assert_eq!(engine.settled_txs(), 199);
+
+        let mut snapshot = Vec::new();
+        engine.write_snapshot(&mut snapshot).unwrap();
+        let restored = Engine::read_snapshot(snapshot.as_slice()).unwrap();
+        assert_eq!(restored.deposits.len(), 300);
+        assert_eq!(restored.deposits[&5].1, DisputeState::ChargedBack);
+
+        let mut engine = engine_with_settled(300);
+        assert_eq!(engine.compact(SettledPolicy::Drop).dropped, 200);
+        assert_eq!(
+            engine.process_tx(dispute(4, 4)),
+            Err(RejectReason::UnknownTx)
+        );
+    }
+
+    #[test]
+    fn test_runs_merge_and_shrink() {
+        let mut settled = SettledTxs::default();
+        let tx = |tx_id| SettledTx {
+            tx_type: TxType::Deposit,
+            client_id: (tx_id % 7) as ClientId,
+            tx_id,
+            amount: Decimal::new(-i64::from(tx_id), 2),
+            state: DisputeState::Resolved,
+        };
+        // Interleaved ids, one run per pass until they are merged
+        for pass in 0..=MAX_RUNS as TxId {
+            settled.add((0..100).map(|i| tx(i * 20 + pass)).collect());
+        }
+        assert_eq!(settled.runs.len(), 1);
+        assert_eq!(settled.len(), 900);
+
+        assert_eq!(settled.remove(41), Some(tx(41)));
+        assert_eq!(settled.get(41), None);
+        assert_eq!(settled.get(1_999), None);
+        for tx_id in [0, 8, 40, 42, 1_988] {
+            assert_eq!(settled.get(tx_id), Some(tx(tx_id)));
+        }
+        assert_eq!(settled.iter().count(), 899);
+    }
+}
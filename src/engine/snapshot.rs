@@ -0,0 +1,947 @@
+//! Binary snapshot of the engine state, so a run can be resumed later.
+//!
+//! Layout (little endian):
+//! - magic `TPES` and format version (u16)
+//! - client count (u64), then per client a record: id (u16), available,
+//!   held, total (16 bytes each, `Decimal::serialize`), locked (u8), then
+//!   deposit, withdrawal and rejected withdrawal counts (u64 each, appended
+//!   in version 3), then the lock reason (0 for none, else 1 plus its index
+//!   in `LockReason::ALL`), its tx id and line (plus one, 0 for unknown),
+//!   whether it has a timestamp (0 or 1) and the timestamp, then the opening
+//!   balance count (u64 each, appended later)
+//! - deposit count (u64), then per deposit a record: tx id (u32),
+//!   client id (u16), amount (16 bytes), status (u8)
+//! - a house accounts record: deposited, withdrawn, held, charged back
+//!   (since version 2)
+//! - withdrawal count (u64), then per withdrawal kept for disputes a record
+//!   laid out like a deposit (since version 3)
+//! - queued row count (u64), then per dispute, resolve or chargeback waiting
+//!   for its transaction a record: tx id (u32), client id (u16), type (u8),
+//!   in arrival order (since version 4)
+//! - sequence count (u64), then per client whose rows carry sequence numbers
+//!   a record: client id (u16), last sequence number (u64) (since version 5)
+//!
+//! Since version 1 every record is prefixed with its length (u16). New
+//! fields are only ever appended to a record, so older snapshots are read
+//! with defaults for the missing fields and fields from newer ones are
+//! skipped. The version only changes for layout changes that can't be
+//! expressed that way, each old version keeping its reader as an upgrade
+//! path.
+//!
+//! Large states can be split into shards by client id range, each shard being
+//! a regular snapshot with the clients in its range and their transactions.
+//! Only one shard carries the house accounts, the others store zeroes.
+//!
+//! Version 0 snapshots (written before versioning) have no header and
+//! fixed-size records. Snapshots before version 2 have no house accounts,
+//! they are derived from the clients and deposits instead. Snapshots before
+//! version 3 have no withdrawals, which only rules v2 keeps. Snapshots before
+//! version 4 have no queued rows, before version 5 no sequence numbers.
+
+use std::{
+    io::{self, Read, Write},
+    ops::RangeInclusive,
+};
+
+use rust_decimal::Decimal;
+
+use crate::{
+    engine::{Engine, dispute_state::DisputeState, house::HouseAccounts},
+    types::{
+        client::{Client, ClientStats, LockInfo, LockReason},
+        common::ClientId,
+        transactions::{ChargebackTx, DepositTx, DisputeTx, ResolveTx, Tx, TxType, WithdrawalTx},
+    },
+};
+
+const MAGIC: &[u8; 4] = b"TPES";
+const VERSION: u16 = 5;
+
+impl Engine {
+    pub fn write_snapshot<W: Write>(&self, w: W) -> io::Result<()> {
+        self.write_snapshot_part(w, |_| true, &self.house)
+    }
+
+    /// Writes one shard: the clients in `clients` and their transactions,
+    /// plus the house accounts if `with_house` is set.
+    pub fn write_snapshot_shard<W: Write>(
+        &self,
+        w: W,
+        clients: RangeInclusive<ClientId>,
+        with_house: bool,
+    ) -> io::Result<()> {
+        let house = if with_house {
+            self.house.clone()
+        } else {
+            HouseAccounts::default()
+        };
+        self.write_snapshot_part(w, |client_id| clients.contains(&client_id), &house)
+    }
+
+    /// Adds a shard read with `read_snapshot` to this engine.
+    pub fn merge_shard(&mut self, shard: Engine) {
+        self.clients.extend(shard.clients);
+        self.locked_clients += shard.locked_clients;
+        self.deposits.extend(shard.deposits);
+        self.withdrawals.extend(shard.withdrawals);
+        self.settled.add(shard.settled.iter().collect());
+        for (tx_id, queued) in shard.pending {
+            self.pending.entry(tx_id).or_default().extend(queued);
+        }
+        self.sequences.extend(shard.sequences);
+        self.house.deposited += shard.house.deposited;
+        self.house.withdrawn += shard.house.withdrawn;
+        self.house.held += shard.house.held;
+        self.house.charged_back += shard.house.charged_back;
+    }
+
+    fn write_snapshot_part<W: Write>(
+        &self,
+        mut w: W,
+        include: impl Fn(ClientId) -> bool,
+        house: &HouseAccounts,
+    ) -> io::Result<()> {
+        w.write_all(MAGIC)?;
+        w.write_all(&VERSION.to_le_bytes())?;
+
+        let mut record = Vec::new();
+
+        let clients = self.clients.values().filter(|c| include(c.id));
+        w.write_all(&(clients.clone().count() as u64).to_le_bytes())?;
+        for client in clients {
+            record.clear();
+            write_client(&mut record, client)?;
+            write_record(&mut w, &record)?;
+        }
+
+        // Compacted transactions are written like the others, so the format
+        // doesn't depend on compaction and they are loaded back as hot ones
+        let deposits = || {
+            let settled = self.settled.iter().filter_map(|tx| match tx.tx_type {
+                TxType::Deposit => Some((
+                    DepositTx {
+                        client_id: tx.client_id,
+                        tx_id: tx.tx_id,
+                        amount: tx.amount,
+                    },
+                    tx.state,
+                )),
+                _ => None,
+            });
+            self.deposits
+                .values()
+                .copied()
+                .chain(settled)
+                .filter(|(d, _)| include(d.client_id))
+        };
+        w.write_all(&(deposits().count() as u64).to_le_bytes())?;
+        for (deposit_tx, deposit_status) in deposits() {
+            record.clear();
+            write_deposit(&mut record, &deposit_tx, &deposit_status)?;
+            write_record(&mut w, &record)?;
+        }
+
+        record.clear();
+        write_house(&mut record, house)?;
+        write_record(&mut w, &record)?;
+
+        let withdrawals = || {
+            let settled = self.settled.iter().filter_map(|tx| match tx.tx_type {
+                TxType::Withdrawal => Some((
+                    WithdrawalTx {
+                        client_id: tx.client_id,
+                        tx_id: tx.tx_id,
+                        amount: tx.amount,
+                    },
+                    tx.state,
+                )),
+                _ => None,
+            });
+            self.withdrawals
+                .values()
+                .copied()
+                .chain(settled)
+                .filter(|(withdrawal_tx, _)| include(withdrawal_tx.client_id))
+        };
+        w.write_all(&(withdrawals().count() as u64).to_le_bytes())?;
+        for (withdrawal_tx, status) in withdrawals() {
+            record.clear();
+            write_withdrawal(&mut record, &withdrawal_tx, &status)?;
+            write_record(&mut w, &record)?;
+        }
+
+        let queued = || {
+            self.pending
+                .values()
+                .flatten()
+                .filter(|tx| include(tx.client_id()))
+        };
+        w.write_all(&(queued().count() as u64).to_le_bytes())?;
+        for tx in queued() {
+            record.clear();
+            write_queued(&mut record, tx)?;
+            write_record(&mut w, &record)?;
+        }
+
+        let sequences = self
+            .sequences
+            .iter()
+            .filter(|(client_id, _)| include(**client_id));
+        w.write_all(&(sequences.clone().count() as u64).to_le_bytes())?;
+        for (client_id, seq) in sequences {
+            record.clear();
+            record.extend_from_slice(&client_id.to_le_bytes());
+            record.extend_from_slice(&seq.to_le_bytes());
+            write_record(&mut w, &record)?;
+        }
+
+        w.flush()
+    }
+
+    pub fn read_snapshot<R: Read>(mut r: R) -> io::Result<Engine> {
+        let magic: [u8; 4] = read_bytes(&mut r)?;
+        if &magic != MAGIC {
+            // Unversioned snapshots start straight with the client count
+            return read_sections(&mut magic.as_slice().chain(r), 0);
+        }
+
+        match u16::from_le_bytes(read_bytes(&mut r)?) {
+            version @ 1..=5 => read_sections(&mut r, version),
+            version => Err(invalid_data(format!(
+                "unsupported snapshot version {version}, this build reads up to {VERSION}"
+            ))),
+        }
+    }
+}
+
+/// Splits the client id space into `shards` contiguous ranges.
+pub fn shard_ranges(shards: u16) -> Vec<RangeInclusive<ClientId>> {
+    let shards = u32::from(shards.max(1));
+    let size = (u32::from(ClientId::MAX) + 1).div_ceil(shards);
+
+    (0..shards)
+        .map(|i| i * size)
+        .take_while(|start| *start <= u32::from(ClientId::MAX))
+        .map(|start| {
+            let end = (start + size - 1).min(u32::from(ClientId::MAX));
+            start as ClientId..=end as ClientId
+        })
+        .collect()
+}
+
+fn read_sections<R: Read>(r: &mut R, version: u16) -> io::Result<Engine> {
+    let mut engine = Engine::new();
+
+    read_records(r, version, |record| {
+        let client = read_client(record, version)?;
+        engine.locked_clients += client.locked as usize;
+        engine.clients.insert(client.id, client);
+        Ok(())
+    })?;
+
+    read_records(r, version, |record| {
+        let (deposit_tx, deposit_status) = read_deposit(record)?;
+        engine.deposits.store((deposit_tx, deposit_status));
+        Ok(())
+    })?;
+
+    engine.house = if version >= 2 {
+        let len = u16::from_le_bytes(read_bytes(r)?);
+        let mut record = vec![0; len as usize];
+        r.read_exact(&mut record)?;
+        read_house(&mut record.as_slice())?
+    } else {
+        derive_house(&engine)
+    };
+
+    if version >= 3 {
+        read_records(r, version, |record| {
+            let (withdrawal_tx, status) = read_withdrawal(record)?;
+            engine
+                .withdrawals
+                .insert(withdrawal_tx.tx_id, (withdrawal_tx, status));
+            Ok(())
+        })?;
+    }
+
+    if version >= 4 {
+        read_records(r, version, |record| {
+            let tx = read_queued(record)?;
+            engine.pending.entry(tx.tx_id()).or_default().push(tx);
+            Ok(())
+        })?;
+    }
+
+    if version >= 5 {
+        read_records(r, version, |record| {
+            let client_id = u16::from_le_bytes(read_bytes(record)?);
+            let seq = u64::from_le_bytes(read_bytes(record)?);
+            engine.sequences.insert(client_id, seq);
+            Ok(())
+        })?;
+    }
+
+    Ok(engine)
+}
+
+/// Reconstructs the house accounts of snapshots that didn't store them.
+fn derive_house(engine: &Engine) -> HouseAccounts {
+    let mut house = HouseAccounts::default();
+
+    for (deposit_tx, deposit_status) in engine.deposits.values() {
+        house.deposited += deposit_tx.amount;
+        if *deposit_status == DisputeState::ChargedBack {
+            house.charged_back += deposit_tx.amount;
+        }
+    }
+    for client in engine.clients.values() {
+        house.held += client.held;
+    }
+    // Whatever left the clients' totals and wasn't charged back was withdrawn
+    let total: Decimal = engine.clients.values().map(|c| c.total).sum();
+    house.withdrawn = house.deposited - house.charged_back - total;
+
+    house
+}
+
+/// Reads a count followed by that many records, handing each one to `read`.
+fn read_records<R: Read>(
+    r: &mut R,
+    version: u16,
+    mut read: impl FnMut(&mut dyn Read) -> io::Result<()>,
+) -> io::Result<()> {
+    let count = u64::from_le_bytes(read_bytes(r)?);
+    let mut record = Vec::new();
+
+    for _ in 0..count {
+        if version == 0 {
+            read(r)?;
+        } else {
+            let len = u16::from_le_bytes(read_bytes(r)?);
+            record.resize(len as usize, 0);
+            r.read_exact(&mut record)?;
+            // Whatever `read` leaves unread was appended by a newer version
+            read(&mut record.as_slice())?;
+        }
+    }
+
+    Ok(())
+}
+
+fn write_record<W: Write>(w: &mut W, record: &[u8]) -> io::Result<()> {
+    let len = u16::try_from(record.len())
+        .map_err(|_| invalid_data("snapshot record too long".to_string()))?;
+    w.write_all(&len.to_le_bytes())?;
+    w.write_all(record)
+}
+
+fn write_client<W: Write>(w: &mut W, client: &Client) -> io::Result<()> {
+    w.write_all(&client.id.to_le_bytes())?;
+    write_decimal(w, client.available)?;
+    write_decimal(w, client.held)?;
+    write_decimal(w, client.total)?;
+    w.write_all(&[client.locked as u8])?;
+    w.write_all(&client.stats.deposits.to_le_bytes())?;
+    w.write_all(&client.stats.withdrawals.to_le_bytes())?;
+    w.write_all(&client.stats.rejected_withdrawals.to_le_bytes())?;
+
+    let lock = client.lock.as_ref();
+    let reason = lock.map_or(0, |lock| {
+        1 + LockReason::ALL
+            .iter()
+            .position(|r| *r == lock.reason)
+            .unwrap() as u64
+    });
+    let plus_one = |value: Option<u64>| value.map_or(0, |value| value + 1);
+    let timestamp = lock.and_then(|lock| lock.timestamp);
+    for field in [
+        reason,
+        plus_one(lock.and_then(|lock| lock.tx_id).map(u64::from)),
+        plus_one(lock.and_then(|lock| lock.line)),
+        timestamp.is_some() as u64,
+        timestamp.unwrap_or_default() as u64,
+        client.stats.openings,
+    ] {
+        w.write_all(&field.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+fn read_client(r: &mut dyn Read, version: u16) -> io::Result<Client> {
+    let mut client = Client {
+        id: u16::from_le_bytes(read_bytes(r)?),
+        available: read_decimal(r)?,
+        held: read_decimal(r)?,
+        total: read_decimal(r)?,
+        locked: read_bytes::<1, _>(r)?[0] != 0,
+        stats: ClientStats::default(),
+        lock: None,
+    };
+    // Fixed-size version 0 records can't have appended fields
+    if version >= 1 {
+        client.stats = ClientStats {
+            deposits: read_appended_u64(r)?,
+            withdrawals: read_appended_u64(r)?,
+            rejected_withdrawals: read_appended_u64(r)?,
+            openings: 0,
+        };
+        client.lock = read_lock(r)?;
+        client.stats.openings = read_appended_u64(r)?;
+    }
+    // Older snapshots only know that the account is locked
+    if client.locked && client.lock.is_none() {
+        client.lock = Some(LockInfo::new(LockReason::CarriedOver, None));
+    }
+    if !client.locked {
+        client.lock = None;
+    }
+    Ok(client)
+}
+
+/// Reads all of the lock fields, also when there's no lock, so the fields
+/// appended after them line up.
+fn read_lock(r: &mut dyn Read) -> io::Result<Option<LockInfo>> {
+    let mut fields = [0; 5];
+    for field in &mut fields {
+        *field = read_appended_u64(r)?;
+    }
+    let [reason, tx_id, line, has_timestamp, timestamp] = fields;
+    let reason = match reason {
+        0 => return Ok(None),
+        n => *usize::try_from(n - 1)
+            .ok()
+            .and_then(|i| LockReason::ALL.get(i))
+            .ok_or_else(|| invalid_data(format!("invalid lock reason {n} in snapshot")))?,
+    };
+    let minus_one = |value: u64| value.checked_sub(1);
+    let tx_id = minus_one(tx_id)
+        .map(|tx_id| u32::try_from(tx_id).map_err(|_| invalid_data("invalid lock tx id".into())))
+        .transpose()?;
+    let line = minus_one(line);
+    let has_timestamp = has_timestamp != 0;
+    let timestamp = timestamp as i64;
+    Ok(Some(LockInfo {
+        reason,
+        tx_id,
+        line,
+        timestamp: has_timestamp.then_some(timestamp),
+    }))
+}
+
+fn write_deposit<W: Write>(
+    w: &mut W,
+    deposit_tx: &DepositTx,
+    deposit_status: &DisputeState,
+) -> io::Result<()> {
+    w.write_all(&deposit_tx.tx_id.to_le_bytes())?;
+    w.write_all(&deposit_tx.client_id.to_le_bytes())?;
+    write_decimal(w, deposit_tx.amount)?;
+    w.write_all(&[deposit_status.to_byte()])
+}
+
+fn read_deposit(r: &mut dyn Read) -> io::Result<(DepositTx, DisputeState)> {
+    let deposit_tx = DepositTx {
+        tx_id: u32::from_le_bytes(read_bytes(r)?),
+        client_id: u16::from_le_bytes(read_bytes(r)?),
+        amount: read_decimal(r)?,
+    };
+    let deposit_status = DisputeState::from_byte(read_bytes::<1, _>(r)?[0])?;
+
+    Ok((deposit_tx, deposit_status))
+}
+
+fn write_withdrawal<W: Write>(
+    w: &mut W,
+    withdrawal_tx: &WithdrawalTx,
+    status: &DisputeState,
+) -> io::Result<()> {
+    w.write_all(&withdrawal_tx.tx_id.to_le_bytes())?;
+    w.write_all(&withdrawal_tx.client_id.to_le_bytes())?;
+    write_decimal(w, withdrawal_tx.amount)?;
+    w.write_all(&[status.to_byte()])
+}
+
+fn read_withdrawal(r: &mut dyn Read) -> io::Result<(WithdrawalTx, DisputeState)> {
+    let withdrawal_tx = WithdrawalTx {
+        tx_id: u32::from_le_bytes(read_bytes(r)?),
+        client_id: u16::from_le_bytes(read_bytes(r)?),
+        amount: read_decimal(r)?,
+    };
+    let status = DisputeState::from_byte(read_bytes::<1, _>(r)?[0])?;
+
+    Ok((withdrawal_tx, status))
+}
+
+fn write_queued<W: Write>(w: &mut W, tx: &Tx) -> io::Result<()> {
+    let tx_type = match tx {
+        Tx::Dispute(_) => 0,
+        Tx::Resolve(_) => 1,
+        Tx::Chargeback(_) => 2,
+        Tx::Deposit(_) | Tx::Withdrawal(_) | Tx::OpeningBalance(_) => {
+            return Err(invalid_data(format!("{} can't be queued", tx.type_name())));
+        }
+    };
+    w.write_all(&tx.tx_id().to_le_bytes())?;
+    w.write_all(&tx.client_id().to_le_bytes())?;
+    w.write_all(&[tx_type])
+}
+
+fn read_queued(r: &mut dyn Read) -> io::Result<Tx> {
+    let tx_id = u32::from_le_bytes(read_bytes(r)?);
+    let client_id = u16::from_le_bytes(read_bytes(r)?);
+    match read_bytes::<1, _>(r)?[0] {
+        0 => Ok(Tx::Dispute(DisputeTx { client_id, tx_id })),
+        1 => Ok(Tx::Resolve(ResolveTx { client_id, tx_id })),
+        2 => Ok(Tx::Chargeback(ChargebackTx { client_id, tx_id })),
+        byte => Err(invalid_data(format!(
+            "invalid queued transaction type {byte} in snapshot"
+        ))),
+    }
+}
+
+impl DisputeState {
+    pub(crate) fn to_byte(self) -> u8 {
+        match self {
+            DisputeState::Normal => 0,
+            DisputeState::UnderDispute => 1,
+            DisputeState::Resolved => 2,
+            DisputeState::ChargedBack => 3,
+        }
+    }
+
+    pub(crate) fn from_byte(byte: u8) -> io::Result<Self> {
+        match byte {
+            0 => Ok(DisputeState::Normal),
+            1 => Ok(DisputeState::UnderDispute),
+            2 => Ok(DisputeState::Resolved),
+            3 => Ok(DisputeState::ChargedBack),
+            _ => Err(invalid_data(format!("invalid dispute state {byte}"))),
+        }
+    }
+}
+
+fn write_house<W: Write>(w: &mut W, house: &HouseAccounts) -> io::Result<()> {
+    write_decimal(w, house.deposited)?;
+    write_decimal(w, house.withdrawn)?;
+    write_decimal(w, house.held)?;
+    write_decimal(w, house.charged_back)
+}
+
+fn read_house(r: &mut dyn Read) -> io::Result<HouseAccounts> {
+    Ok(HouseAccounts {
+        deposited: read_decimal(r)?,
+        withdrawn: read_decimal(r)?,
+        held: read_decimal(r)?,
+        charged_back: read_decimal(r)?,
+    })
+}
+
+fn write_decimal<W: Write>(w: &mut W, value: Decimal) -> io::Result<()> {
+    w.write_all(&value.serialize())
+}
+
+fn read_decimal<R: Read + ?Sized>(r: &mut R) -> io::Result<Decimal> {
+    Ok(Decimal::deserialize(read_bytes(r)?))
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_bytes<const N: usize, R: Read + ?Sized>(r: &mut R) -> io::Result<[u8; N]> {
+    let mut buf = [0; N];
+    r.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+/// Reads a field appended to a record, zero if the record was written by a
+/// build that didn't have it yet.
+fn read_appended_u64(r: &mut dyn Read) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    let mut filled = 0;
+    while filled < buf.len() {
+        match r.read(&mut buf[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    match filled {
+        0 => Ok(0),
+        8 => Ok(u64::from_le_bytes(buf)),
+        _ => Err(io::ErrorKind::UnexpectedEof.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        engine::{
+            config::{EngineConfig, MissingDeposit},
+            rules::Rules,
+        },
+        types::{reject::RejectReason, transactions::TxType},
+    };
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let mut engine = Engine::new();
+        let txs = [
+            Tx::Deposit(DepositTx {
+                client_id: 1,
+                tx_id: 1,
+                amount: dec!(100.1234),
+            }),
+            Tx::Deposit(DepositTx {
+                client_id: 1,
+                tx_id: 2,
+                amount: dec!(50),
+            }),
+            Tx::Withdrawal(WithdrawalTx {
+                client_id: 1,
+                tx_id: 3,
+                amount: dec!(25.5),
+            }),
+            Tx::Dispute(DisputeTx {
+                client_id: 1,
+                tx_id: 2,
+            }),
+            Tx::Deposit(DepositTx {
+                client_id: 2,
+                tx_id: 4,
+                amount: dec!(10),
+            }),
+            Tx::Dispute(DisputeTx {
+                client_id: 2,
+                tx_id: 4,
+            }),
+            Tx::Chargeback(ChargebackTx {
+                client_id: 2,
+                tx_id: 4,
+            }),
+        ];
+        for tx in txs {
+            engine.process_tx(tx).unwrap();
+        }
+
+        let mut buf = Vec::new();
+        engine.write_snapshot(&mut buf).unwrap();
+        let restored = Engine::read_snapshot(buf.as_slice()).unwrap();
+
+        assert_eq!(restored.clients.len(), 2);
+        for (id, client) in engine.clients.iter() {
+            let restored_client = restored.clients.get(id).unwrap();
+            assert_eq!(restored_client.available, client.available);
+            assert_eq!(restored_client.held, client.held);
+            assert_eq!(restored_client.total, client.total);
+            assert_eq!(restored_client.locked, client.locked);
+        }
+
+        assert_eq!(restored.house, engine.house);
+        assert_eq!(restored.deposits.len(), 3);
+        let (deposit_tx, deposit_status) = restored.deposits.get(&2).unwrap();
+        assert_eq!(deposit_tx.client_id, 1);
+        assert_eq!(deposit_tx.amount, dec!(50));
+        assert_eq!(*deposit_status, DisputeState::UnderDispute);
+        let (_, deposit_status) = restored.deposits.get(&4).unwrap();
+        assert_eq!(*deposit_status, DisputeState::ChargedBack);
+    }
+
+    #[test]
+    fn test_snapshot_round_trip_keeps_disputable_withdrawals() {
+        let mut engine = Engine::with_config(EngineConfig {
+            rules: Rules::V2,
+            ..EngineConfig::default()
+        });
+        let txs = [
+            Tx::Deposit(DepositTx {
+                client_id: 1,
+                tx_id: 1,
+                amount: dec!(10),
+            }),
+            Tx::Withdrawal(WithdrawalTx {
+                client_id: 1,
+                tx_id: 2,
+                amount: dec!(4),
+            }),
+            Tx::Dispute(DisputeTx {
+                client_id: 1,
+                tx_id: 2,
+            }),
+        ];
+        for tx in txs {
+            engine.process_tx(tx).unwrap();
+        }
+
+        let mut buf = Vec::new();
+        engine.write_snapshot(&mut buf).unwrap();
+        let restored = Engine::read_snapshot(buf.as_slice()).unwrap();
+
+        let (withdrawal_tx, status) = restored.withdrawals.get(&2).unwrap();
+        assert_eq!(withdrawal_tx.amount, dec!(4));
+        assert_eq!(*status, DisputeState::UnderDispute);
+        assert_eq!(restored.house, engine.house);
+    }
+
+    #[test]
+    fn test_snapshot_round_trip_keeps_queued_rows() {
+        let mut engine = Engine::with_config(EngineConfig {
+            missing_deposit: MissingDeposit::Queue,
+            ..EngineConfig::default()
+        });
+        let queued = [
+            Tx::Dispute(DisputeTx {
+                client_id: 1,
+                tx_id: 7,
+            }),
+            Tx::Chargeback(ChargebackTx {
+                client_id: 1,
+                tx_id: 7,
+            }),
+        ];
+        for tx in queued {
+            assert_eq!(engine.process_tx(tx), Err(RejectReason::Queued));
+        }
+
+        let mut buf = Vec::new();
+        engine.write_snapshot(&mut buf).unwrap();
+        let restored = Engine::read_snapshot(buf.as_slice()).unwrap();
+        let types: Vec<_> = restored.pending[&7].iter().map(Tx::tx_type).collect();
+        assert_eq!(types, vec![TxType::Dispute, TxType::Chargeback]);
+    }
+
+    #[test]
+    fn test_snapshot_round_trip_keeps_sequences() {
+        let mut engine = Engine::new();
+        engine.record_sequence(4, 17);
+        engine.record_sequence(4, 12);
+
+        let mut buf = Vec::new();
+        engine.write_snapshot(&mut buf).unwrap();
+        let restored = Engine::read_snapshot(buf.as_slice()).unwrap();
+        assert_eq!(restored.last_sequence(4), Some(17));
+        assert_eq!(restored.last_sequence(5), None);
+    }
+
+    #[test]
+    fn test_sharded_snapshot_merges_back() {
+        let mut engine = engine_with_dispute();
+        for client_id in [0, 1, 30_000, u16::MAX] {
+            engine
+                .process_tx(Tx::Deposit(DepositTx {
+                    client_id,
+                    tx_id: 100 + u32::from(client_id),
+                    amount: dec!(2),
+                }))
+                .unwrap();
+        }
+
+        let ranges = shard_ranges(3);
+        assert_eq!(ranges.first().map(|r| *r.start()), Some(0));
+        assert_eq!(ranges.last().map(|r| *r.end()), Some(u16::MAX));
+
+        let mut restored = Engine::new();
+        for (i, range) in ranges.into_iter().enumerate() {
+            let mut buf = Vec::new();
+            engine
+                .write_snapshot_shard(&mut buf, range, i == 0)
+                .unwrap();
+            restored.merge_shard(Engine::read_snapshot(buf.as_slice()).unwrap());
+        }
+
+        assert_eq!(restored.clients.len(), engine.clients.len());
+        assert_eq!(restored.deposits.len(), engine.deposits.len());
+        assert_eq!(restored.house, engine.house);
+        assert_eq!(restored.clients.get(&3).unwrap().held, dec!(12.5));
+        let (_, deposit_status) = restored.deposits.get(&9).unwrap();
+        assert_eq!(*deposit_status, DisputeState::UnderDispute);
+    }
+
+    #[test]
+    fn test_truncated_snapshot_is_an_error() {
+        let mut engine = Engine::new();
+        engine
+            .process_tx(Tx::Deposit(DepositTx {
+                client_id: 1,
+                tx_id: 1,
+                amount: dec!(1),
+            }))
+            .unwrap();
+
+        let mut buf = Vec::new();
+        engine.write_snapshot(&mut buf).unwrap();
+        buf.truncate(buf.len() - 1);
+
+        assert!(Engine::read_snapshot(buf.as_slice()).is_err());
+    }
+
+    fn engine_with_dispute() -> Engine {
+        let mut engine = Engine::new();
+        let txs = [
+            Tx::Deposit(DepositTx {
+                client_id: 3,
+                tx_id: 9,
+                amount: dec!(12.5),
+            }),
+            Tx::Dispute(DisputeTx {
+                client_id: 3,
+                tx_id: 9,
+            }),
+        ];
+        for tx in txs {
+            engine.process_tx(tx).unwrap();
+        }
+        engine
+    }
+
+    fn assert_engine_with_dispute(engine: &Engine) {
+        let client = engine.clients.get(&3).unwrap();
+        assert_eq!(client.available, dec!(0));
+        assert_eq!(client.held, dec!(12.5));
+        assert_eq!(client.total, dec!(12.5));
+        assert!(!client.locked);
+
+        let (deposit_tx, deposit_status) = engine.deposits.get(&9).unwrap();
+        assert_eq!(deposit_tx.client_id, 3);
+        assert_eq!(deposit_tx.amount, dec!(12.5));
+        assert_eq!(*deposit_status, DisputeState::UnderDispute);
+        assert_eq!(engine.house.held, dec!(12.5));
+        assert_eq!(engine.house.deposited, dec!(12.5));
+    }
+
+    #[test]
+    fn test_unversioned_snapshot_is_upgraded() {
+        let engine = engine_with_dispute();
+
+        // Layout written before the header and record lengths were introduced
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&1u64.to_le_bytes());
+        let mut client = Vec::new();
+        write_client(&mut client, engine.clients.get(&3).unwrap()).unwrap();
+        // Without the counters appended since
+        buf.extend_from_slice(&client[..2 + 3 * 16 + 1]);
+        buf.extend_from_slice(&1u64.to_le_bytes());
+        let (deposit_tx, deposit_status) = engine.deposits.get(&9).unwrap();
+        write_deposit(&mut buf, deposit_tx, deposit_status).unwrap();
+
+        assert_engine_with_dispute(&Engine::read_snapshot(buf.as_slice()).unwrap());
+    }
+
+    #[test]
+    fn test_fields_appended_by_newer_versions_are_skipped() {
+        let engine = engine_with_dispute();
+
+        let mut buf = Vec::new();
+        buf.extend_from_slice(MAGIC);
+        buf.extend_from_slice(&VERSION.to_le_bytes());
+
+        let mut record = Vec::new();
+        write_client(&mut record, engine.clients.get(&3).unwrap()).unwrap();
+        record.extend_from_slice(&[0xAA; 7]);
+        buf.extend_from_slice(&1u64.to_le_bytes());
+        write_record(&mut buf, &record).unwrap();
+
+        record.clear();
+        let (deposit_tx, deposit_status) = engine.deposits.get(&9).unwrap();
+        write_deposit(&mut record, deposit_tx, deposit_status).unwrap();
+        record.extend_from_slice(&[0xBB; 3]);
+        buf.extend_from_slice(&1u64.to_le_bytes());
+        write_record(&mut buf, &record).unwrap();
+
+        record.clear();
+        write_house(&mut record, &engine.house).unwrap();
+        record.extend_from_slice(&[0xCC; 5]);
+        write_record(&mut buf, &record).unwrap();
+        // No withdrawals, queued rows or sequence numbers
+        buf.extend_from_slice(&0u64.to_le_bytes());
+        buf.extend_from_slice(&0u64.to_le_bytes());
+        buf.extend_from_slice(&0u64.to_le_bytes());
+
+        assert_engine_with_dispute(&Engine::read_snapshot(buf.as_slice()).unwrap());
+    }
+
+    #[test]
+    fn test_client_stats_are_appended() {
+        let mut engine = engine_with_dispute();
+        engine
+            .process_tx(Tx::Withdrawal(WithdrawalTx {
+                client_id: 3,
+                tx_id: 10,
+                amount: dec!(1),
+            }))
+            .unwrap_err();
+        let client = engine.clients.get(&3).unwrap();
+        assert_eq!(
+            client.stats,
+            ClientStats {
+                deposits: 1,
+                withdrawals: 0,
+                rejected_withdrawals: 1,
+                openings: 0,
+            }
+        );
+
+        let mut record = Vec::new();
+        write_client(&mut record, client).unwrap();
+        assert_eq!(
+            read_client(&mut record.as_slice(), VERSION).unwrap().stats,
+            client.stats
+        );
+
+        // Records written before the counters existed read as zero
+        let old = read_client(&mut &record[..2 + 3 * 16 + 1], VERSION).unwrap();
+        assert_eq!(old.stats, ClientStats::default());
+        assert!(read_client(&mut &record[..2 + 3 * 16 + 5], VERSION).is_err());
+
+        // Appended after the lock fields, also for an unlocked account
+        let mut opened = client.clone();
+        opened.stats.openings = 1;
+        let mut record = Vec::new();
+        write_client(&mut record, &opened).unwrap();
+        let read = read_client(&mut record.as_slice(), VERSION).unwrap();
+        assert_eq!((read.stats, read.lock), (opened.stats, None));
+    }
+
+    #[test]
+    fn test_lock_info_is_appended() {
+        let mut client = Client::new(4);
+        client.locked = true;
+        client.lock = Some(LockInfo {
+            reason: LockReason::Chargeback,
+            tx_id: Some(7),
+            line: Some(12),
+            timestamp: Some(-5),
+        });
+
+        let mut record = Vec::new();
+        write_client(&mut record, &client).unwrap();
+        assert_eq!(
+            read_client(&mut record.as_slice(), VERSION).unwrap().lock,
+            client.lock
+        );
+
+        // A lock from before the reasons were kept is carried over
+        let stats_end = 2 + 3 * 16 + 1 + 3 * 8;
+        let old = read_client(&mut &record[..stats_end], VERSION).unwrap();
+        assert_eq!(old.lock, Some(LockInfo::new(LockReason::CarriedOver, None)));
+        client.lock = Some(LockInfo::new(LockReason::OpeningBalance, None));
+        record.clear();
+        write_client(&mut record, &client).unwrap();
+        assert_eq!(
+            read_client(&mut record.as_slice(), VERSION).unwrap().lock,
+            client.lock
+        );
+    }
+
+    #[test]
+    fn test_unsupported_version_is_an_error() {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(MAGIC);
+        buf.extend_from_slice(&(VERSION + 1).to_le_bytes());
+        buf.extend_from_slice(&[0; 16]);
+
+        let err = Engine::read_snapshot(buf.as_slice()).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,149 @@
+//! A snapshot of the numbers the CLI summary prints, for services embedding
+//! the engine.
+
+use rust_decimal::Decimal;
+
+use crate::{
+    engine::{Engine, Totals, dispute_state::DisputeState, house::HouseAccounts},
+    types::{client::ClientStats, transactions::TxType},
+};
+
+/// Stored deposits and withdrawals in one dispute state.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StateTotals {
+    pub state: DisputeState,
+    pub count: usize,
+    pub amount: Decimal,
+}
+
+/// Engine-wide counters, see `Engine::stats`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EngineStats {
+    /// Clients, locked clients and balances over all of them
+    pub totals: Totals,
+    pub house: HouseAccounts,
+    /// Deposits and withdrawals currently under dispute
+    pub open_disputes: usize,
+    /// Stored deposits and withdrawals per state, in `DisputeState::ALL` order
+    pub states: [StateTotals; 4],
+    /// The clients' activity counters summed up
+    pub activity: ClientStats,
+    /// Rows waiting for the transaction they name
+    pub queued: usize,
+}
+
+impl EngineStats {
+    pub fn state(&self, state: DisputeState) -> &StateTotals {
+        &self.states[index(state)]
+    }
+}
+
+fn index(state: DisputeState) -> usize {
+    DisputeState::ALL.iter().position(|s| *s == state).unwrap()
+}
+
+impl Engine {
+    /// Counts what the engine holds in one pass over its tables. Settled
+    /// transactions count in their state, deposits only in the prior index
+    /// don't count.
+    pub fn stats(&self) -> EngineStats {
+        let mut states = DisputeState::ALL.map(|state| StateTotals {
+            state,
+            count: 0,
+            amount: Decimal::ZERO,
+        });
+        let mut add = |state: DisputeState, amount: Decimal| {
+            let totals = &mut states[index(state)];
+            totals.count += 1;
+            totals.amount += amount;
+        };
+        for (deposit_tx, state) in self.deposits.values() {
+            add(*state, deposit_tx.amount);
+        }
+        for (withdrawal_tx, state) in self.withdrawals.values() {
+            add(*state, withdrawal_tx.amount);
+        }
+        for settled_tx in self.settled.iter() {
+            if matches!(settled_tx.tx_type, TxType::Deposit | TxType::Withdrawal) {
+                add(settled_tx.state, settled_tx.amount);
+            }
+        }
+
+        let activity = self
+            .clients
+            .values()
+            .fold(ClientStats::default(), |sum, c| ClientStats {
+                deposits: sum.deposits + c.stats.deposits,
+                withdrawals: sum.withdrawals + c.stats.withdrawals,
+                rejected_withdrawals: sum.rejected_withdrawals + c.stats.rejected_withdrawals,
+                openings: sum.openings + c.stats.openings,
+            });
+        let open_disputes = states[index(DisputeState::UnderDispute)].count;
+
+        EngineStats {
+            totals: self.totals(),
+            house: self.house.clone(),
+            open_disputes,
+            states,
+            activity,
+            queued: self.queued_txs(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::transactions::{ChargebackTx, DepositTx, DisputeTx, Tx, WithdrawalTx};
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_stats() {
+        let mut engine = Engine::new();
+        let txs = [
+            Tx::Deposit(DepositTx::new(1, 1, dec!(10)).unwrap()),
+            Tx::Deposit(DepositTx::new(1, 2, dec!(4)).unwrap()),
+            Tx::Deposit(DepositTx::new(2, 3, dec!(3)).unwrap()),
+            Tx::Withdrawal(WithdrawalTx::new(1, 4, dec!(1)).unwrap()),
+            Tx::Dispute(DisputeTx {
+                client_id: 1,
+                tx_id: 2,
+            }),
+            Tx::Dispute(DisputeTx {
+                client_id: 2,
+                tx_id: 3,
+            }),
+            Tx::Chargeback(ChargebackTx {
+                client_id: 2,
+                tx_id: 3,
+            }),
+        ];
+        for tx in txs {
+            engine.process_tx(tx).unwrap();
+        }
+        let withdrawal = WithdrawalTx::new(2, 5, dec!(1)).unwrap();
+        assert!(engine.process_tx(Tx::Withdrawal(withdrawal)).is_err());
+
+        let stats = engine.stats();
+        assert_eq!((stats.totals.clients, stats.totals.locked), (2, 1));
+        assert_eq!(stats.totals.total, dec!(13));
+        assert_eq!(stats.house.charged_back, dec!(3));
+        assert_eq!(stats.open_disputes, 1);
+        let normal = stats.state(DisputeState::Normal);
+        assert_eq!((normal.count, normal.amount), (1, dec!(10)));
+        let disputed = stats.state(DisputeState::UnderDispute);
+        assert_eq!((disputed.count, disputed.amount), (1, dec!(4)));
+        assert_eq!(stats.state(DisputeState::ChargedBack).count, 1);
+        assert_eq!(stats.state(DisputeState::Resolved).count, 0);
+        assert_eq!(
+            stats.activity,
+            ClientStats {
+                deposits: 3,
+                withdrawals: 1,
+                rejected_withdrawals: 1,
+                openings: 0,
+            }
+        );
+        assert_eq!(stats.queued, 0);
+    }
+}
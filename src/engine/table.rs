@@ -0,0 +1,164 @@
+//! Copy-on-write tables for the transactions, so a forked engine shares them
+//! with the original instead of copying millions of entries.
+
+use std::{
+    collections::{HashMap, hash_map::Entry},
+    ops::Index,
+    sync::Arc,
+};
+
+use crate::types::common::TxId;
+
+/// Ids are spread over the shards by their low bits.
+const SHARDS: usize = 64;
+
+pub(crate) fn shard_index(tx_id: TxId) -> usize {
+    tx_id as usize % SHARDS
+}
+
+/// A map from transaction ids split into shards behind `Arc`s. Cloning it
+/// only bumps the shards' reference counts, a write copies the one shard it
+/// lands in if that shard is still shared with a clone.
+#[derive(Debug, Clone)]
+pub(crate) struct TxTable<V> {
+    shards: Vec<Arc<HashMap<TxId, V>>>,
+}
+
+impl<V> Default for TxTable<V> {
+    fn default() -> Self {
+        TxTable {
+            shards: (0..SHARDS).map(|_| Arc::new(HashMap::new())).collect(),
+        }
+    }
+}
+
+impl<V> TxTable<V> {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    fn shard(&self, tx_id: &TxId) -> &HashMap<TxId, V> {
+        &self.shards[shard_index(*tx_id)]
+    }
+
+    pub(crate) fn get(&self, tx_id: &TxId) -> Option<&V> {
+        self.shard(tx_id).get(tx_id)
+    }
+
+    pub(crate) fn contains_key(&self, tx_id: &TxId) -> bool {
+        self.shard(tx_id).contains_key(tx_id)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.len()).sum()
+    }
+
+    #[cfg(test)]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.shards.iter().all(|shard| shard.is_empty())
+    }
+
+    pub(crate) fn values(&self) -> impl Iterator<Item = &V> {
+        self.shards.iter().flat_map(|shard| shard.values())
+    }
+
+    pub(crate) fn shards(&self) -> impl Iterator<Item = &HashMap<TxId, V>> {
+        self.shards.iter().map(|shard| &**shard)
+    }
+}
+
+impl<V: Clone> TxTable<V> {
+    fn shard_mut(&mut self, tx_id: &TxId) -> &mut HashMap<TxId, V> {
+        Arc::make_mut(&mut self.shards[shard_index(*tx_id)])
+    }
+
+    pub(crate) fn get_mut(&mut self, tx_id: &TxId) -> Option<&mut V> {
+        // Looking up a missing id must not copy a shared shard
+        if !self.contains_key(tx_id) {
+            return None;
+        }
+        self.shard_mut(tx_id).get_mut(tx_id)
+    }
+
+    pub(crate) fn insert(&mut self, tx_id: TxId, value: V) -> Option<V> {
+        self.shard_mut(&tx_id).insert(tx_id, value)
+    }
+
+    pub(crate) fn remove(&mut self, tx_id: &TxId) -> Option<V> {
+        if !self.contains_key(tx_id) {
+            return None;
+        }
+        self.shard_mut(tx_id).remove(tx_id)
+    }
+
+    pub(crate) fn entry(&mut self, tx_id: TxId) -> Entry<'_, TxId, V> {
+        self.shard_mut(&tx_id).entry(tx_id)
+    }
+
+    /// Gives back the memory of shards at most half full, copying them if shared.
+    pub(crate) fn shrink_to_fit(&mut self) {
+        for shard in &mut self.shards {
+            if shard.capacity() > shard.len() * 2 {
+                Arc::make_mut(shard).shrink_to_fit();
+            }
+        }
+    }
+}
+
+impl<V: Clone> Extend<(TxId, V)> for TxTable<V> {
+    fn extend<I: IntoIterator<Item = (TxId, V)>>(&mut self, iter: I) {
+        for (tx_id, value) in iter {
+            self.insert(tx_id, value);
+        }
+    }
+}
+
+impl<V: Clone> IntoIterator for TxTable<V> {
+    type Item = (TxId, V);
+    type IntoIter = std::vec::IntoIter<(TxId, V)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.shards
+            .into_iter()
+            .flat_map(|shard| Arc::unwrap_or_clone(shard).into_iter())
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+}
+
+impl<V> Index<&TxId> for TxTable<V> {
+    type Output = V;
+
+    fn index(&self, tx_id: &TxId) -> &V {
+        &self.shard(tx_id)[tx_id]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clone_shares_until_written() {
+        let mut table = TxTable::new();
+        for tx_id in 0..1_000 {
+            table.insert(tx_id, tx_id * 2);
+        }
+        let mut fork = table.clone();
+        fork.insert(3, 0);
+        fork.remove(&4);
+        assert_eq!(fork.get_mut(&5_000), None);
+
+        assert_eq!((table[&3], table.get(&4)), (6, Some(&8)));
+        assert_eq!((fork[&3], fork.get(&4)), (0, None));
+        assert_eq!((table.len(), fork.len()), (1_000, 999));
+        // Only the shards holding ids 3 and 4 were copied
+        let shared = table
+            .shards
+            .iter()
+            .zip(&fork.shards)
+            .filter(|(a, b)| Arc::ptr_eq(a, b))
+            .count();
+        assert_eq!(shared, SHARDS - 2);
+    }
+}
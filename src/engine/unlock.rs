@@ -0,0 +1,154 @@
+//! Reinstating locked accounts, by hand or by the `UnlockPolicy` in the
+//! config.
+
+use crate::{
+    engine::{Engine, config::UnlockPolicy},
+    types::common::ClientId,
+};
+
+impl Engine {
+    /// Unlocks the client's account and forgets why it was locked. Returns
+    /// whether it was locked.
+    pub fn unlock(&mut self, client_id: ClientId) -> bool {
+        let Some(client) = self.clients.get_mut(&client_id).filter(|c| c.locked) else {
+            return false;
+        };
+        client.locked = false;
+        client.lock = None;
+        self.locked_clients -= 1;
+        true
+    }
+
+    /// Under `UnlockPolicy::DisputesSettled`, unlocks the client once nothing
+    /// of theirs is under dispute, which is when nothing is held.
+    pub(crate) fn unlock_settled(&mut self, client_id: ClientId) {
+        if self.config.unlock == UnlockPolicy::DisputesSettled
+            && self
+                .clients
+                .get(&client_id)
+                .is_some_and(|c| c.locked && c.held.is_zero())
+        {
+            self.unlock(client_id);
+        }
+    }
+
+    /// Under `UnlockPolicy::AfterDays`, unlocks the client if the account was
+    /// locked at least that long before `now` (milliseconds since the Unix
+    /// epoch). Locks without a timestamp never expire. Returns whether the
+    /// account was unlocked.
+    pub(crate) fn unlock_cooled_off(&mut self, client_id: ClientId, now: i64) -> bool {
+        let Some(cooling_off) = self.config.unlock.cooling_off() else {
+            return false;
+        };
+        let locked_at = self.lock_info(client_id).and_then(|lock| lock.timestamp);
+        match locked_at {
+            Some(locked_at) if now.saturating_sub(locked_at) >= cooling_off => {
+                self.unlock(client_id)
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        engine::config::EngineConfig,
+        types::{
+            reject::RejectReason,
+            transactions::{ChargebackTx, DepositTx, DisputeTx, ResolveTx, Tx},
+        },
+    };
+    use rust_decimal_macros::dec;
+
+    fn engine(unlock: UnlockPolicy) -> Engine {
+        Engine::with_config(EngineConfig {
+            unlock,
+            ..EngineConfig::default()
+        })
+    }
+
+    fn dispute(client_id: ClientId, tx_id: u32) -> Tx {
+        Tx::Dispute(DisputeTx { client_id, tx_id })
+    }
+
+    #[test]
+    fn test_unlock_once_disputes_settle() {
+        let mut engine = engine(UnlockPolicy::DisputesSettled);
+        for tx_id in 1..=2 {
+            let deposit = DepositTx::new(1, tx_id, dec!(5)).unwrap();
+            engine.process_tx(Tx::Deposit(deposit)).unwrap();
+            engine.process_tx(dispute(1, tx_id)).unwrap();
+        }
+        engine
+            .process_tx(Tx::Chargeback(ChargebackTx {
+                client_id: 1,
+                tx_id: 1,
+            }))
+            .unwrap();
+        // Tx 2 is still under dispute
+        assert!(engine.client(1).unwrap().locked);
+        let deposit = DepositTx::new(1, 3, dec!(1)).unwrap();
+        assert_eq!(
+            engine.process_tx(Tx::Deposit(deposit)),
+            Err(RejectReason::AccountLocked)
+        );
+
+        engine
+            .process_tx(Tx::Resolve(ResolveTx {
+                client_id: 1,
+                tx_id: 2,
+            }))
+            .unwrap();
+        assert!(!engine.client(1).unwrap().locked);
+        assert_eq!(engine.lock_info(1), None);
+        assert_eq!(engine.totals().locked, 0);
+        engine.process_tx(Tx::Deposit(deposit)).unwrap();
+        assert_eq!(engine.client(1).unwrap().total, dec!(6));
+    }
+
+    #[test]
+    fn test_parse_policy() {
+        assert_eq!("never".parse(), Ok(UnlockPolicy::Never));
+        assert_eq!(
+            "disputes-settled".parse(),
+            Ok(UnlockPolicy::DisputesSettled)
+        );
+        assert_eq!("30d".parse(), Ok(UnlockPolicy::AfterDays(30)));
+        assert_eq!(UnlockPolicy::AfterDays(30).to_string(), "30d");
+        assert!("30".parse::<UnlockPolicy>().is_err());
+        assert!("d".parse::<UnlockPolicy>().is_err());
+    }
+
+    #[test]
+    fn test_unlock_after_cooling_off() {
+        let day = 86_400_000;
+        let mut engine = engine(UnlockPolicy::AfterDays(2));
+        let deposit = DepositTx::new(1, 1, dec!(5)).unwrap();
+        engine.process_tx(Tx::Deposit(deposit)).unwrap();
+        engine.process_tx(dispute(1, 1)).unwrap();
+        engine
+            .process_tx(Tx::Chargeback(ChargebackTx {
+                client_id: 1,
+                tx_id: 1,
+            }))
+            .unwrap();
+        // Without the row's timestamp the lock never expires
+        assert!(!engine.unlock_cooled_off(1, 10 * day));
+        let lock = engine.clients.get_mut(&1).unwrap().lock.as_mut().unwrap();
+        lock.timestamp = Some(day);
+
+        assert!(!engine.unlock_cooled_off(1, 3 * day - 1));
+        // The client's next row ends it, and goes through
+        let deposit = Tx::Deposit(DepositTx::new(1, 2, dec!(1)).unwrap());
+        assert_eq!(
+            engine.process_tx_at(deposit, Some(3 * day - 1)),
+            Err(RejectReason::AccountLocked)
+        );
+        engine.process_tx_at(deposit, Some(3 * day)).unwrap();
+        assert!(!engine.client(1).unwrap().locked);
+        assert_eq!(engine.totals().locked, 0);
+        assert!(!engine.unlock(1));
+    }
+}
@@ -0,0 +1,54 @@
+use crate::{
+    engine::{Engine, TxHandler, config::MissingClient, dispute_state::DisputeState},
+    types::{reject::RejectReason, transactions::WithdrawalTx},
+};
+
+impl TxHandler<WithdrawalTx> for Engine {
+    fn handle(&mut self, mut withdrawal_tx: WithdrawalTx) -> Result<(), RejectReason> {
+        let amounts = self.config.amounts;
+        withdrawal_tx.amount = amounts.ingress(withdrawal_tx.amount)?;
+        let tracked = self.config.rules.policy().withdrawals_disputable();
+        let capacity = if tracked {
+            self.check_capacity(None, None, Some(withdrawal_tx.tx_id))
+        } else {
+            Ok(())
+        };
+
+        let client_id = withdrawal_tx.client_id;
+        if self.config.missing_client == MissingClient::Create
+            && !self.clients.contains_key(&client_id)
+        {
+            self.check_capacity(Some(client_id), None, None)?;
+            // Added even though the withdrawal is rejected below, like a deposit's client
+            self.clients.get_or_insert(client_id);
+        }
+        let Some(client) = self.clients.get_mut(&client_id) else {
+            return Err(RejectReason::UnknownClient);
+        };
+
+        if client.locked {
+            return Err(RejectReason::AccountLocked);
+        }
+
+        if client.available < withdrawal_tx.amount {
+            return Err(RejectReason::InsufficientFunds);
+        }
+        capacity?;
+
+        let available = amounts.sub(client.available, withdrawal_tx.amount)?;
+        let total = amounts.sub(client.total, withdrawal_tx.amount)?;
+        let withdrawn = amounts.add_aggregate(self.house.withdrawn, withdrawal_tx.amount)?;
+
+        client.available = available;
+        client.total = total;
+        self.house.withdrawn = withdrawn;
+
+        if tracked {
+            self.withdrawals
+                .entry(withdrawal_tx.tx_id)
+                .or_insert((withdrawal_tx, DisputeState::Normal));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,177 @@
+//! Amount parsing for the hot path: plain `123.4567` amounts are parsed eight
+//! digits at a time (SWAR), anything else goes through `Decimal::from_str`.
+
+use std::{fmt, str::FromStr};
+
+use rust_decimal::Decimal;
+use serde::{
+    Deserializer,
+    de::{self, Visitor},
+};
+
+/// Digits a `u64` mantissa always holds
+const MAX_DIGITS: usize = 19;
+
+/// Parses `s` to exactly the `Decimal` `Decimal::from_str` gives, scale
+/// included.
+pub fn parse_amount(s: &str) -> Option<Decimal> {
+    parse_plain(s).or_else(|| Decimal::from_str(s).ok())
+}
+
+/// Unsigned `digits[.digits]` with at most `MAX_DIGITS` digits in total.
+fn parse_plain(s: &str) -> Option<Decimal> {
+    let (int, frac) = match s.split_once('.') {
+        Some((int, frac)) if !frac.is_empty() => (int, frac),
+        Some(_) => return None,
+        None => (s, ""),
+    };
+    if int.is_empty() || int.len() + frac.len() > MAX_DIGITS {
+        return None;
+    }
+
+    let mantissa = parse_digits(int.as_bytes())? * 10u64.pow(frac.len() as u32)
+        + parse_digits(frac.as_bytes())?;
+    Some(Decimal::from_i128_with_scale(
+        mantissa as i128,
+        frac.len() as u32,
+    ))
+}
+
+/// Value of up to `MAX_DIGITS` ASCII digits.
+fn parse_digits(digits: &[u8]) -> Option<u64> {
+    let mut value = 0u64;
+    let mut chunks = digits.chunks_exact(8);
+    for chunk in &mut chunks {
+        let chunk = u64::from_le_bytes(chunk.try_into().ok()?);
+        value = value * 100_000_000 + parse_eight(chunk)?;
+    }
+    for &digit in chunks.remainder() {
+        if !digit.is_ascii_digit() {
+            return None;
+        }
+        value = value * 10 + u64::from(digit - b'0');
+    }
+    Some(value)
+}
+
+/// Value of eight ASCII digits packed little-endian into a `u64`.
+fn parse_eight(chunk: u64) -> Option<u64> {
+    const ZEROS: u64 = 0x3030_3030_3030_3030;
+    // Every byte in `0x30..=0x39`: high nibble 3, and adding 6 doesn't carry
+    // into the high nibble
+    if chunk & 0xF0F0_F0F0_F0F0_F0F0 != ZEROS
+        || (chunk + 0x0606_0606_0606_0606) & 0xF0F0_F0F0_F0F0_F0F0 != ZEROS
+    {
+        return None;
+    }
+    // Combine neighbouring digits, then pairs, then quads
+    let v = chunk - ZEROS;
+    let v = (v * 10 + (v >> 8)) & 0x00FF_00FF_00FF_00FF;
+    let v = (v * 100 + (v >> 16)) & 0x0000_FFFF_0000_FFFF;
+    Some((v * 10_000 + (v >> 32)) & 0xFFFF_FFFF)
+}
+
+/// `deserialize_with` for an optional amount column, a missing or empty field
+/// is `None`. The field is parsed as text, csv would hand `Decimal` an `f64`
+/// otherwise and trailing zeros (`1.50`) would be lost.
+pub(crate) fn deserialize_amount<'de, D>(deserializer: D) -> Result<Option<Decimal>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_option(AmountVisitor)
+}
+
+struct AmountVisitor;
+
+impl<'de> Visitor<'de> for AmountVisitor {
+    type Value = Option<Decimal>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an amount")
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_str(self)
+    }
+
+    fn visit_str<E: de::Error>(self, field: &str) -> Result<Self::Value, E> {
+        if field.is_empty() {
+            return Ok(None);
+        }
+        #[cfg(feature = "fast-parse")]
+        let amount = parse_amount(field);
+        #[cfg(not(feature = "fast-parse"))]
+        let amount = Decimal::from_str(field).ok();
+        amount
+            .map(Some)
+            .ok_or_else(|| E::custom(format!("invalid amount `{field}`")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_parse_amount() {
+        for s in [
+            "0",
+            "1.5",
+            "1.50",
+            "12345678",
+            "123456789.0123",
+            "0000000000001.0000",
+            "9999999999999999999",
+            "99999999999999999999",
+            "1.23456789012345678901",
+            "-1.5",
+        ] {
+            let expected = Decimal::from_str(s).unwrap();
+            let parsed = parse_amount(s).unwrap();
+            assert_eq!(parsed, expected, "{s}");
+            assert_eq!(parsed.scale(), expected.scale(), "{s}");
+        }
+        for s in ["", ".", "1.", "1.2.3", "12a45678.5", "1234567/.5", "１"] {
+            assert_eq!(parse_plain(s), None, "{s}");
+        }
+        assert_eq!(parse_amount("1e3"), None);
+    }
+
+    #[test]
+    fn test_csv_amounts_keep_their_scale() {
+        let data = "type,client,tx,amount\ndeposit,1,1,1.50\ndispute,1,1,\ndispute,1,1\n";
+        let mut rdr = csv::ReaderBuilder::new()
+            .flexible(true)
+            .from_reader(data.as_bytes());
+        let amounts: Vec<_> = rdr
+            .deserialize::<crate::io::csv::CsvRow>()
+            .map(|row| row.unwrap().amount)
+            .collect();
+        assert_eq!(amounts, [Some(Decimal::new(150, 2)), None, None]);
+        assert_eq!(amounts[0].unwrap().scale(), 2);
+
+        let mut rdr =
+            csv::Reader::from_reader("type,client,tx,amount\ndeposit,1,1,1,5\n".as_bytes());
+        assert!(
+            rdr.deserialize::<crate::io::csv::CsvRow>()
+                .next()
+                .unwrap()
+                .is_err()
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn prop_matches_from_str(s in "[0-9]{1,12}(\\.[0-9]{1,10})?") {
+            let parsed = parse_amount(&s).unwrap();
+            let expected = Decimal::from_str(&s).unwrap();
+            prop_assert_eq!(parsed, expected);
+            prop_assert_eq!(parsed.scale(), expected.scale());
+        }
+    }
+}
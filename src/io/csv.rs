@@ -0,0 +1,122 @@
+//! Rows of the transactions CSV and their conversion to `Tx`.
+
+use rust_decimal::Decimal;
+
+use crate::types::{
+    common::{ClientId, TxId},
+    transactions::{
+        ChargebackTx, DepositTx, DisputeTx, OPENING_BALANCE_LOCKED, OpeningBalanceTx, ResolveTx,
+        Tx, TxType, WithdrawalTx,
+    },
+};
+
+#[derive(Debug, serde::Deserialize)]
+pub struct CsvRow {
+    pub r#type: String,
+    pub client: ClientId,
+    pub tx: TxId,
+    #[serde(default, deserialize_with = "crate::io::amount::deserialize_amount")]
+    pub amount: Option<Decimal>,
+}
+
+/// `CsvRow` borrowing its type from the record it was read from, so the
+/// hot path doesn't allocate per row.
+#[derive(Debug, serde::Deserialize)]
+pub(crate) struct CsvRowRef<'a> {
+    pub r#type: &'a str,
+    pub client: ClientId,
+    pub tx: TxId,
+    #[serde(default, deserialize_with = "crate::io::amount::deserialize_amount")]
+    pub amount: Option<Decimal>,
+}
+
+impl CsvRow {
+    pub(crate) fn as_ref(&self) -> CsvRowRef<'_> {
+        CsvRowRef {
+            r#type: &self.r#type,
+            client: self.client,
+            tx: self.tx,
+            amount: self.amount,
+        }
+    }
+}
+
+impl TryFrom<CsvRow> for Tx {
+    // Simple error type as we are ignoring malformed rows anyway
+    type Error = ();
+
+    /// Fails for an unknown type, a missing amount, or a deposit or withdrawal
+    /// amount `DepositTx::new` refuses.
+    fn try_from(value: CsvRow) -> Result<Self, Self::Error> {
+        value.as_ref().to_tx(false).and_then(checked).ok_or(())
+    }
+}
+
+impl Tx {
+    /// Like `Tx::try_from`, but the type is parsed with `TxType::parse_lenient`.
+    pub fn try_from_lenient(value: CsvRow) -> Option<Self> {
+        value.as_ref().to_tx(true).and_then(checked)
+    }
+}
+
+/// `tx` built again through the checked constructors.
+fn checked(tx: Tx) -> Option<Tx> {
+    match tx {
+        Tx::Deposit(deposit_tx) => {
+            DepositTx::new(deposit_tx.client_id, deposit_tx.tx_id, deposit_tx.amount)
+                .ok()
+                .map(Tx::Deposit)
+        }
+        Tx::Withdrawal(withdrawal_tx) => WithdrawalTx::new(
+            withdrawal_tx.client_id,
+            withdrawal_tx.tx_id,
+            withdrawal_tx.amount,
+        )
+        .ok()
+        .map(Tx::Withdrawal),
+        Tx::OpeningBalance(opening_tx) => OpeningBalanceTx::new(
+            opening_tx.client_id,
+            opening_tx.tx_id,
+            opening_tx.amount,
+            opening_tx.locked,
+        )
+        .ok()
+        .map(Tx::OpeningBalance),
+        _ => Some(tx),
+    }
+}
+
+impl CsvRowRef<'_> {
+    /// The transaction, `None` for an unknown type or a missing amount. With
+    /// `lenient` the type is parsed with `TxType::parse_lenient`. Amounts are
+    /// left to the engine to check.
+    pub(crate) fn to_tx(&self, lenient: bool) -> Option<Tx> {
+        let tx_type = if lenient {
+            TxType::parse_lenient(self.r#type)?
+        } else {
+            self.r#type.parse().ok()?
+        };
+        let (client_id, tx_id) = (self.client, self.tx);
+        match tx_type {
+            TxType::Deposit => Some(Tx::Deposit(DepositTx {
+                client_id,
+                tx_id,
+                amount: self.amount?,
+            })),
+            TxType::Withdrawal => Some(Tx::Withdrawal(WithdrawalTx {
+                client_id,
+                tx_id,
+                amount: self.amount?,
+            })),
+            TxType::Dispute => Some(Tx::Dispute(DisputeTx { client_id, tx_id })),
+            TxType::Resolve => Some(Tx::Resolve(ResolveTx { client_id, tx_id })),
+            TxType::Chargeback => Some(Tx::Chargeback(ChargebackTx { client_id, tx_id })),
+            TxType::OpeningBalance => Some(Tx::OpeningBalance(OpeningBalanceTx {
+                client_id,
+                tx_id,
+                amount: self.amount?,
+                locked: self.r#type.eq_ignore_ascii_case(OPENING_BALANCE_LOCKED),
+            })),
+        }
+    }
+}
@@ -0,0 +1,7 @@
+pub mod dedupe;
+pub mod engine;
+#[cfg(feature = "csv")]
+pub mod io;
+#[cfg(feature = "csv")]
+pub mod pipeline;
+pub mod types;
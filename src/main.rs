@@ -1,55 +1,43 @@
-mod engine;
-mod types;
+mod cli;
 
-use std::{env, error::Error, ffi::OsString, process};
+use std::{error::Error, process};
 
-use crate::{
-    engine::Engine,
-    types::{common::CsvRow, transactions::Tx},
-};
+use clap::Parser;
 
-fn run() -> Result<(), Box<dyn Error>> {
-    let file_path = get_first_arg()?;
-
-    let mut rdr = csv::ReaderBuilder::new()
-        .trim(csv::Trim::All)
-        .flexible(true)
-        .from_path(file_path)?;
-    let mut engine = Engine::new();
-
-    for result in rdr.deserialize() {
-        let record: CsvRow = match result {
-            Ok(r) => r,
-            Err(_) => continue, // Skip malformed CSV rows
-        };
-
-        let tx = match Tx::try_from(record) {
-            Ok(t) => t,
-            Err(_) => continue, // Skip invalid transaction types
-        };
-
-        engine.process_tx(tx);
-    }
+use crate::cli::{Cli, Command, diagnostic::InputError};
 
-    let mut wtr = csv::Writer::from_writer(std::io::stdout());
-    for (_client_id, client) in engine.clients().iter() {
-        wtr.serialize(client)?;
-    }
-    wtr.flush()?;
+#[cfg(feature = "heap-stats")]
+#[global_allocator]
+static ALLOC: cli::resources::CountingAlloc = cli::resources::CountingAlloc;
 
-    Ok(())
-}
-
-fn get_first_arg() -> Result<OsString, Box<dyn Error>> {
-    match env::args_os().nth(1) {
-        None => Err(From::from("Expected 1 argument, but got none")),
-        Some(file_path) => Ok(file_path),
+fn run() -> Result<(), Box<dyn Error>> {
+    let cli = Cli::parse();
+
+    match cli.command {
+        Some(Command::Gen(args)) => cli::generate::run(args),
+        Some(Command::Query(args)) => cli::query::run(args),
+        Some(Command::Opening(args)) => cli::opening::run(args),
+        Some(Command::WhatIf(args)) => cli::what_if::run(args),
+        Some(Command::Trace(args)) => cli::trace::run(args),
+        Some(Command::Revert(args)) => cli::revert::run(args),
+        Some(Command::Audit(args)) => cli::audit::run(args),
+        Some(Command::Scenario(args)) => cli::scenario::run(args),
+        Some(Command::Difftest(args)) => cli::difftest::run(args),
+        Some(Command::Bench(args)) => cli::bench::run(args),
+        Some(Command::Statement(args)) => cli::statement::run(args),
+        Some(Command::Inspect(args)) => cli::inspect::run(args),
+        Some(Command::Completions(args)) => cli::completions::run(args),
+        Some(Command::Man(args)) => cli::completions::run_man(args),
+        None => cli::process::run(cli.process),
     }
 }
 
 fn main() {
     if let Err(err) = run() {
-        eprintln!("{}", err);
+        match err.downcast::<InputError>() {
+            Ok(err) => eprintln!("{:?}", miette::Report::new(*err)),
+            Err(err) => eprintln!("{}", err),
+        }
         process::exit(1);
     }
 }
@@ -0,0 +1,187 @@
+pub mod number_format;
+pub mod reorder;
+pub mod results;
+pub mod sequence;
+pub mod source;
+
+use std::{
+    fmt,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+    },
+    thread::{self, JoinHandle},
+};
+
+use crate::{
+    engine::Engine,
+    pipeline::{results::Results, source::Row},
+};
+
+/// Where a row ends in the input, to report progress and to resume from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InputOffset {
+    pub byte: u64,
+    pub line: u64,
+    /// Records read so far, the header included
+    pub record: u64,
+}
+
+/// Queue depth counters shared by the parser thread and the engine loop.
+#[derive(Default)]
+pub struct QueueMetrics {
+    depth: AtomicUsize,
+    max_depth: AtomicUsize,
+    depth_sum: AtomicU64,
+    received: AtomicU64,
+    producer_blocked: AtomicU64,
+}
+
+impl QueueMetrics {
+    pub fn depth(&self) -> usize {
+        self.depth.load(Ordering::Relaxed)
+    }
+
+    fn sent(&self) {
+        let depth = self.depth.fetch_add(1, Ordering::Relaxed) + 1;
+        self.max_depth.fetch_max(depth, Ordering::Relaxed);
+    }
+
+    fn received(&self) {
+        let depth = self.depth.fetch_sub(1, Ordering::Relaxed);
+        self.depth_sum.fetch_add(depth as u64, Ordering::Relaxed);
+        self.received.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl fmt::Display for QueueMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let received = self.received.load(Ordering::Relaxed);
+        let mean = match received {
+            0 => 0.0,
+            n => self.depth_sum.load(Ordering::Relaxed) as f64 / n as f64,
+        };
+
+        write!(
+            f,
+            "queue depth max {} mean {:.1}, parser blocked on a full queue {} times",
+            self.max_depth.load(Ordering::Relaxed),
+            mean,
+            self.producer_blocked.load(Ordering::Relaxed)
+        )
+    }
+}
+
+/// Parses rows on a separate thread and hands them over through a bounded
+/// channel, so the parser can't run more than `capacity` rows ahead of the engine.
+pub struct Pipeline {
+    rx: Receiver<Row>,
+    metrics: Arc<QueueMetrics>,
+    parser: Option<JoinHandle<()>>,
+}
+
+impl Pipeline {
+    pub fn spawn<I>(source: I, capacity: usize) -> Self
+    where
+        I: Iterator<Item = Row> + Send + 'static,
+    {
+        let (tx, rx) = mpsc::sync_channel(capacity);
+        let metrics = Arc::new(QueueMetrics::default());
+
+        let parser_metrics = metrics.clone();
+        let parser = thread::spawn(move || {
+            for row in source {
+                if send(&tx, row, &parser_metrics).is_err() {
+                    break; // The engine loop stopped early
+                }
+            }
+        });
+
+        Pipeline {
+            rx,
+            metrics,
+            parser: Some(parser),
+        }
+    }
+
+    pub fn metrics(&self) -> &Arc<QueueMetrics> {
+        &self.metrics
+    }
+
+    /// Applies the parsed rows to `engine`, see `Results`.
+    pub fn results(self, engine: &mut Engine) -> Results<'_, Self> {
+        Results::new(self, engine)
+    }
+}
+
+fn send(tx: &SyncSender<Row>, row: Row, metrics: &QueueMetrics) -> Result<(), ()> {
+    // Count the increment before the row is visible to the receiver
+    metrics.sent();
+    let result = match tx.try_send(row) {
+        Ok(()) => Ok(()),
+        Err(TrySendError::Full(row)) => {
+            metrics.producer_blocked.fetch_add(1, Ordering::Relaxed);
+            tx.send(row).map_err(|_| ())
+        }
+        Err(TrySendError::Disconnected(_)) => Err(()),
+    };
+    if result.is_err() {
+        metrics.depth.fetch_sub(1, Ordering::Relaxed);
+    }
+    result
+}
+
+impl Iterator for Pipeline {
+    type Item = Row;
+
+    fn next(&mut self) -> Option<Row> {
+        match self.rx.recv() {
+            Ok(row) => {
+                self.metrics.received();
+                Some(row)
+            }
+            Err(_) => {
+                if let Some(parser) = self.parser.take() {
+                    let _ = parser.join();
+                }
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows(n: u64) -> impl Iterator<Item = Row> + Send + 'static {
+        (1..=n).map(|line| Row {
+            line: Some(line),
+            tx: None,
+            timestamp: None,
+            seq: None,
+            position: InputOffset::default(),
+        })
+    }
+
+    #[test]
+    fn test_pipeline_preserves_order() {
+        let pipeline = Pipeline::spawn(rows(1000), 8);
+        let metrics = pipeline.metrics().clone();
+        let lines: Vec<_> = pipeline.map(|row| row.line.unwrap()).collect();
+
+        assert_eq!(lines, (1..=1000).collect::<Vec<_>>());
+        assert_eq!(metrics.depth(), 0);
+        // The buffered rows, plus one the parser is waiting to push and one being received
+        assert!(metrics.max_depth.load(Ordering::Relaxed) <= 8 + 2);
+    }
+
+    #[test]
+    fn test_pipeline_stops_parser_when_dropped() {
+        let mut pipeline = Pipeline::spawn(rows(u64::MAX), 4);
+        assert_eq!(pipeline.next().unwrap().line, Some(1));
+        // Dropping the receiver must unblock the parser thread
+        drop(pipeline);
+    }
+}
@@ -0,0 +1,123 @@
+use std::{borrow::Cow, fmt, str::FromStr};
+
+/// How amounts are written in the input.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NumberFormat {
+    /// `1234.56`, what `Decimal` parses directly
+    #[default]
+    Plain,
+    /// `1,234.56`
+    CommaThousands,
+    /// `1.234,56`
+    DotThousands,
+}
+
+impl NumberFormat {
+    const ALL: [NumberFormat; 3] = [
+        NumberFormat::Plain,
+        NumberFormat::CommaThousands,
+        NumberFormat::DotThousands,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            NumberFormat::Plain => "plain",
+            NumberFormat::CommaThousands => "comma-thousands",
+            NumberFormat::DotThousands => "dot-thousands",
+        }
+    }
+
+    fn separators(&self) -> (char, char) {
+        match self {
+            NumberFormat::Plain | NumberFormat::CommaThousands => (',', '.'),
+            NumberFormat::DotThousands => ('.', ','),
+        }
+    }
+
+    /// Rewrites `amount` to the plain format. Group separators are only
+    /// accepted between groups of three digits, so a misconfigured format
+    /// fails the row instead of silently scaling the amount.
+    pub fn normalize<'a>(&self, amount: &'a str) -> Option<Cow<'a, str>> {
+        if *self == NumberFormat::Plain {
+            return Some(Cow::Borrowed(amount));
+        }
+
+        let (group, decimal) = self.separators();
+        let (integer, fraction) = match amount.split_once(decimal) {
+            Some((integer, fraction)) => (integer, Some(fraction)),
+            None => (amount, None),
+        };
+        let digits = integer.trim_start_matches(['-', '+']);
+        let sign = &integer[..integer.len() - digits.len()];
+
+        let mut groups = digits.split(group);
+        let first = groups.next().unwrap_or_default();
+        let grouped = digits.contains(group);
+        if grouped && !(1..=3).contains(&first.len()) {
+            return None;
+        }
+        if groups.any(|g| g.len() != 3) {
+            return None;
+        }
+
+        let mut plain = String::with_capacity(amount.len());
+        plain.push_str(sign);
+        plain.extend(digits.chars().filter(|c| *c != group));
+        if let Some(fraction) = fraction {
+            if fraction.contains(group) {
+                return None;
+            }
+            plain.push('.');
+            plain.push_str(fraction);
+        }
+        Some(Cow::Owned(plain))
+    }
+}
+
+impl fmt::Display for NumberFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for NumberFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        NumberFormat::ALL
+            .into_iter()
+            .find(|format| format.name() == s)
+            .ok_or_else(|| {
+                format!(
+                    "unknown number format `{s}`, expected plain, comma-thousands or dot-thousands"
+                )
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        let cases = [
+            (NumberFormat::CommaThousands, "1,234.56", Some("1234.56")),
+            (NumberFormat::CommaThousands, "-1,234,567", Some("-1234567")),
+            (NumberFormat::CommaThousands, "12.5", Some("12.5")),
+            (NumberFormat::CommaThousands, "1,23.5", None),
+            (NumberFormat::CommaThousands, "1234,567.5", None),
+            (NumberFormat::DotThousands, "1.234,56", Some("1234.56")),
+            (NumberFormat::DotThousands, "0,5", Some("0.5")),
+            (NumberFormat::DotThousands, "1.234.5", None),
+            (NumberFormat::DotThousands, "1,2.3", None),
+        ];
+        for (format, amount, expected) in cases {
+            assert_eq!(
+                format.normalize(amount).as_deref(),
+                expected,
+                "{format} {amount}"
+            );
+        }
+    }
+}
@@ -0,0 +1,188 @@
+//! Puts rows of slightly out-of-order feeds back in timestamp order before
+//! they reach the engine.
+
+use std::{
+    cmp::{Ordering as CmpOrdering, Reverse},
+    collections::BinaryHeap,
+    fmt,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use crate::pipeline::source::Row;
+
+/// Counters shared with whoever reports on the run.
+#[derive(Default)]
+pub struct ReorderMetrics {
+    max_buffered: AtomicU64,
+    late: AtomicU64,
+}
+
+impl ReorderMetrics {
+    /// Rows older than one already handed out, applied out of order.
+    pub fn late(&self) -> u64 {
+        self.late.load(Ordering::Relaxed)
+    }
+}
+
+impl fmt::Display for ReorderMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "buffered at most {} rows, {} rows arrived after the window and were applied late",
+            self.max_buffered.load(Ordering::Relaxed),
+            self.late()
+        )
+    }
+}
+
+/// A buffered row, ordered by timestamp and then by arrival.
+struct Buffered {
+    timestamp: i64,
+    seq: u64,
+    row: Row,
+}
+
+impl Ord for Buffered {
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        (self.timestamp, self.seq).cmp(&(other.timestamp, other.seq))
+    }
+}
+
+impl PartialOrd for Buffered {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Buffered {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for Buffered {}
+
+/// Holds each row back until one at least `window` newer has been read, then
+/// yields rows oldest first. Rows with equal timestamps keep their input
+/// order, rows without a timestamp are taken to be as new as the newest one
+/// read so far.
+pub struct Reorder<I> {
+    rows: I,
+    window: i64,
+    buffer: BinaryHeap<Reverse<Buffered>>,
+    seq: u64,
+    /// Newest timestamp read so far
+    newest: Option<i64>,
+    /// Timestamp of the last row handed out
+    released: Option<i64>,
+    done: bool,
+    metrics: Arc<ReorderMetrics>,
+}
+
+impl<I: Iterator<Item = Row>> Reorder<I> {
+    pub fn new(rows: I, window: Duration) -> Self {
+        Reorder {
+            rows,
+            window: i64::try_from(window.as_millis()).unwrap_or(i64::MAX),
+            buffer: BinaryHeap::new(),
+            seq: 0,
+            newest: None,
+            released: None,
+            done: false,
+            metrics: Arc::new(ReorderMetrics::default()),
+        }
+    }
+
+    pub fn metrics(&self) -> &Arc<ReorderMetrics> {
+        &self.metrics
+    }
+
+    fn is_ready(&self, timestamp: i64) -> bool {
+        self.done
+            || self
+                .newest
+                .is_some_and(|newest| newest.saturating_sub(timestamp) >= self.window)
+    }
+}
+
+impl<I: Iterator<Item = Row>> Iterator for Reorder<I> {
+    type Item = Row;
+
+    fn next(&mut self) -> Option<Row> {
+        loop {
+            if let Some(Reverse(oldest)) = self.buffer.peek()
+                && self.is_ready(oldest.timestamp)
+            {
+                let Reverse(oldest) = self.buffer.pop()?;
+                self.released = Some(oldest.timestamp);
+                return Some(oldest.row);
+            }
+            if self.done {
+                return None;
+            }
+
+            let Some(row) = self.rows.next() else {
+                self.done = true;
+                continue;
+            };
+            let timestamp = row.timestamp.or(self.newest).unwrap_or(i64::MIN);
+            if self.released.is_some_and(|released| timestamp < released) {
+                self.metrics.late.fetch_add(1, Ordering::Relaxed);
+            }
+            self.newest = self.newest.max(Some(timestamp));
+            self.seq += 1;
+            self.buffer.push(Reverse(Buffered {
+                timestamp,
+                seq: self.seq,
+                row,
+            }));
+            self.metrics
+                .max_buffered
+                .fetch_max(self.buffer.len() as u64, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::InputOffset;
+
+    fn rows(timestamps: &[Option<i64>]) -> Vec<Row> {
+        timestamps
+            .iter()
+            .enumerate()
+            .map(|(i, timestamp)| Row {
+                line: Some(i as u64 + 2),
+                tx: None,
+                timestamp: *timestamp,
+                seq: None,
+                position: InputOffset::default(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_rows_within_the_window_are_sorted() {
+        let input = rows(&[
+            Some(1_000),
+            Some(3_000),
+            Some(2_000),
+            None,
+            Some(2_000),
+            Some(9_000),
+            Some(1_500),
+        ]);
+        let reorder = Reorder::new(input.into_iter(), Duration::from_secs(5));
+        let metrics = reorder.metrics().clone();
+        let lines: Vec<_> = reorder.map(|row| row.line.unwrap()).collect();
+
+        // The untimed row counts as 3000, the last one is beyond the window
+        assert_eq!(lines, vec![2, 4, 6, 3, 5, 8, 7]);
+        assert_eq!(metrics.late(), 1);
+    }
+}
@@ -0,0 +1,671 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+use crate::{
+    dedupe::TxFilter,
+    engine::Engine,
+    pipeline::{
+        InputOffset,
+        sequence::{SequenceIssue, SequencePolicy},
+        source::Row,
+    },
+    types::{
+        common::{ClientId, TxId},
+        reject::RejectReason,
+        transactions::{Tx, TxType},
+    },
+};
+
+/// What happened to a row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Applied,
+    /// Not applied, including rows that couldn't be parsed (`ParseError`)
+    Rejected(RejectReason),
+    /// Of a transaction type the caller asked to skip
+    Skipped,
+}
+
+/// The result of feeding one input row to the engine.
+#[derive(Debug, Clone)]
+pub struct RowResult {
+    pub line: Option<u64>,
+    pub tx_id: Option<TxId>,
+    /// `None` when the row couldn't be parsed
+    pub tx: Option<Tx>,
+    pub outcome: Outcome,
+    /// Set when the row's sequence number doesn't follow the client's previous one
+    pub sequence: Option<SequenceIssue>,
+    /// Input position right after this row
+    pub position: InputOffset,
+    /// A row reported as `queued` before, applied now that the deposit it
+    /// names arrived (or rejected with it). It isn't a new input row: `line`
+    /// is where it was read, `position` that of the row that released it.
+    pub dequeued: bool,
+}
+
+impl RowResult {
+    /// The engine's verdict, `None` for unparsed and skipped rows.
+    pub fn engine_result(&self) -> Option<(&Tx, Result<(), RejectReason>)> {
+        let result = match self.outcome {
+            Outcome::Applied => Ok(()),
+            Outcome::Rejected(reason) => Err(reason),
+            Outcome::Skipped => return None,
+        };
+        self.tx.as_ref().map(|tx| (tx, result))
+    }
+}
+
+/// Line and timestamp of a row the engine queued.
+type QueuedRow = (Option<u64>, Option<i64>);
+
+/// Applies rows to an engine one at a time, yielding a `RowResult` for each.
+///
+/// Nothing is applied ahead of the consumer, so stopping early leaves the
+/// engine right after the last yielded row. `engine()` gives access to the
+/// state in between calls to `next`.
+pub struct Results<'e, I> {
+    rows: I,
+    engine: &'e mut Engine,
+    skip: Vec<TxType>,
+    dedupe: Option<&'e mut TxFilter>,
+    sequence: Option<SequencePolicy>,
+    /// Rows held back after a sequence gap, by client and sequence number
+    held: HashMap<ClientId, BTreeMap<u64, Row>>,
+    /// Held rows whose turn has come, processed before the next input row
+    released: VecDeque<Row>,
+    /// Rows the engine queued, by the tx id they name
+    queued: HashMap<TxId, VecDeque<QueuedRow>>,
+    /// Results of queued rows the engine applied along with the last row
+    dequeued: VecDeque<RowResult>,
+}
+
+impl<'e, I: Iterator<Item = Row>> Results<'e, I> {
+    pub fn new(rows: I, engine: &'e mut Engine) -> Self {
+        Results {
+            rows,
+            engine,
+            skip: Vec::new(),
+            dedupe: None,
+            sequence: None,
+            held: HashMap::new(),
+            released: VecDeque::new(),
+            queued: HashMap::new(),
+            dequeued: VecDeque::new(),
+        }
+    }
+
+    /// Rows of these types are yielded as `Outcome::Skipped` without touching the engine.
+    pub fn skip_types(mut self, types: Vec<TxType>) -> Self {
+        self.skip = types;
+        self
+    }
+
+    /// Deposits and withdrawals whose id is in `filter` are rejected as
+    /// `DuplicateTx`, the ids of the others are added to it once processed.
+    pub fn dedupe(mut self, filter: &'e mut TxFilter) -> Self {
+        self.dedupe = Some(filter);
+        self
+    }
+
+    /// Checks the `seq` column of each row against the client's previous
+    /// sequence number, handling rows that don't follow it by `policy`.
+    pub fn sequence(mut self, policy: SequencePolicy) -> Self {
+        self.sequence = Some(policy);
+        self
+    }
+
+    pub fn engine(&self) -> &Engine {
+        self.engine
+    }
+
+    /// For maintenance between rows, such as `Engine::compact`.
+    pub fn engine_mut(&mut self) -> &mut Engine {
+        self.engine
+    }
+
+    /// Whether results of rows the engine already applied are still to be
+    /// yielded, so stopping now would leave them unreported.
+    pub fn has_dequeued(&self) -> bool {
+        !self.dequeued.is_empty()
+    }
+}
+
+impl<I> Results<'_, I> {
+    fn apply(&mut self, tx: Tx, timestamp: Option<i64>) -> Result<(), RejectReason> {
+        let replayable = matches!(tx, Tx::Deposit(_) | Tx::Withdrawal(_));
+        let Some(filter) = self.dedupe.as_deref_mut().filter(|_| replayable) else {
+            return self.engine.process_tx_at(tx, timestamp);
+        };
+        if filter.contains(tx.tx_id()) {
+            return Err(RejectReason::DuplicateTx);
+        }
+
+        let result = self.engine.process_tx_at(tx, timestamp);
+        // A row stopped by a limit is processed again when the run is resumed
+        if result != Err(RejectReason::CapacityExceeded) {
+            filter.insert(tx.tx_id());
+        }
+        result
+    }
+}
+
+impl<I: Iterator<Item = Row>> Iterator for Results<'_, I> {
+    type Item = RowResult;
+
+    fn next(&mut self) -> Option<RowResult> {
+        if let Some(result) = self.dequeued.pop_front() {
+            return Some(result);
+        }
+        loop {
+            let row = match self.released.pop_front() {
+                Some(row) => row,
+                None => match self.rows.next() {
+                    Some(row) => row,
+                    None => return self.reject_held(),
+                },
+            };
+
+            let sequenced = match (self.sequence, row.tx, row.seq) {
+                (Some(policy), Some(tx), Some(seq)) => Some((policy, tx.client_id(), seq)),
+                _ => None,
+            };
+            let mut sequence = None;
+            if let Some((policy, client_id, seq)) = sequenced {
+                sequence = SequenceIssue::check(self.engine.last_sequence(client_id), seq);
+                match (policy, sequence) {
+                    (SequencePolicy::Buffer, Some(SequenceIssue::Gap { .. })) => {
+                        let held = self.held.entry(client_id).or_default();
+                        // One row is held per number, the one a later row with
+                        // the same number displaces is rejected
+                        if let Some(displaced) = held.insert(seq, row) {
+                            let issue = SequenceIssue::Displaced { seq };
+                            let outcome = Outcome::Rejected(issue.reason());
+                            return Some(row_result(displaced, outcome, Some(issue)));
+                        }
+                        continue;
+                    }
+                    // Already past this number, holding it back can't help
+                    (SequencePolicy::Buffer, Some(issue @ SequenceIssue::OutOfOrder { .. })) => {
+                        return Some(row_result(row, Outcome::Rejected(issue.reason()), sequence));
+                    }
+                    _ => {}
+                }
+            }
+
+            let outcome = match (row.tx, sequence) {
+                (Some(_), Some(issue)) if self.sequence == Some(SequencePolicy::Reject) => {
+                    Outcome::Rejected(issue.reason())
+                }
+                (Some(tx), _) if self.skip.contains(&tx.tx_type()) => Outcome::Skipped,
+                (Some(tx), _) => {
+                    let result = self.apply(tx, row.timestamp);
+                    self.collect_dequeued(row.position);
+                    match result {
+                        Ok(()) => {
+                            self.stamp_lock(tx, row.line, row.timestamp);
+                            Outcome::Applied
+                        }
+                        Err(RejectReason::Queued) => {
+                            let queued = self.queued.entry(tx.tx_id()).or_default();
+                            queued.push_back((row.line, row.timestamp));
+                            Outcome::Rejected(RejectReason::Queued)
+                        }
+                        Err(reason) => Outcome::Rejected(reason),
+                    }
+                }
+                // Malformed rows and invalid transaction types
+                (None, _) => Outcome::Rejected(RejectReason::ParseError),
+            };
+
+            // The number counts as delivered whatever happened to the row,
+            // except for a row that is processed again when the run is resumed
+            if let Some((_, client_id, seq)) = sequenced
+                && outcome != Outcome::Rejected(RejectReason::CapacityExceeded)
+            {
+                self.engine.record_sequence(client_id, seq);
+                self.release(client_id, seq);
+            }
+
+            return Some(row_result(row, outcome, sequence));
+        }
+    }
+}
+
+impl<I> Results<'_, I> {
+    /// Turns the queued rows the engine applied along with the last row into
+    /// results, yielded right after that row's.
+    fn collect_dequeued(&mut self, position: InputOffset) {
+        let dequeued: Vec<_> = self.engine.drain_dequeued().collect();
+        for (tx, result) in dequeued {
+            // Rows queued before a resumed run are only known to the engine
+            let (line, timestamp) = match self.queued.get_mut(&tx.tx_id()) {
+                Some(queued) => {
+                    let row = queued.pop_front().unwrap_or_default();
+                    if queued.is_empty() {
+                        self.queued.remove(&tx.tx_id());
+                    }
+                    row
+                }
+                None => (None, None),
+            };
+            if result.is_ok() {
+                self.stamp_lock(tx, line, timestamp);
+            }
+            self.dequeued.push_back(RowResult {
+                line,
+                tx_id: Some(tx.tx_id()),
+                tx: Some(tx),
+                outcome: result.map_or_else(Outcome::Rejected, |()| Outcome::Applied),
+                sequence: None,
+                position,
+                dequeued: true,
+            });
+        }
+    }
+
+    fn stamp_lock(&mut self, tx: Tx, line: Option<u64>, timestamp: Option<i64>) {
+        if matches!(tx, Tx::Chargeback(_) | Tx::OpeningBalance(_)) {
+            self.engine
+                .stamp_lock(tx.client_id(), tx.tx_id(), line, timestamp);
+        }
+    }
+
+    /// Queues the client's held row numbered right after `seq`, if any.
+    fn release(&mut self, client_id: ClientId, seq: u64) {
+        let Some(held) = self.held.get_mut(&client_id) else {
+            return;
+        };
+        if let Some(entry) = held.first_entry()
+            && seq.checked_add(1) == Some(*entry.key())
+        {
+            self.released.push_back(entry.remove());
+        }
+        if held.is_empty() {
+            self.held.remove(&client_id);
+        }
+    }
+
+    /// Rejects the rows still held once the input is exhausted, lowest
+    /// client id and sequence number first.
+    fn reject_held(&mut self) -> Option<RowResult> {
+        let client_id = *self.held.keys().min()?;
+        let held = self.held.get_mut(&client_id)?;
+        let (seq, row) = held.pop_first()?;
+        if held.is_empty() {
+            self.held.remove(&client_id);
+        }
+
+        let expected = self
+            .engine
+            .last_sequence(client_id)
+            .map_or(seq, |last| last.saturating_add(1));
+        let issue = SequenceIssue::Gap { expected, seq };
+        Some(row_result(
+            row,
+            Outcome::Rejected(issue.reason()),
+            Some(issue),
+        ))
+    }
+}
+
+fn row_result(row: Row, outcome: Outcome, sequence: Option<SequenceIssue>) -> RowResult {
+    RowResult {
+        line: row.line,
+        tx_id: row.tx.map(|tx| tx.tx_id()),
+        tx: row.tx,
+        outcome,
+        sequence,
+        position: row.position,
+        dequeued: false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::transactions::{DepositTx, WithdrawalTx};
+    use rust_decimal_macros::dec;
+
+    fn row(line: u64, tx: Option<Tx>) -> Row {
+        Row {
+            line: Some(line),
+            tx,
+            timestamp: None,
+            seq: None,
+            position: InputOffset::default(),
+        }
+    }
+
+    #[test]
+    fn test_results_report_every_row() {
+        let mut engine = Engine::new();
+        let rows = vec![
+            row(
+                2,
+                Some(Tx::Deposit(DepositTx {
+                    client_id: 1,
+                    tx_id: 1,
+                    amount: dec!(10),
+                })),
+            ),
+            row(3, None),
+            row(
+                4,
+                Some(Tx::Withdrawal(WithdrawalTx {
+                    client_id: 1,
+                    tx_id: 2,
+                    amount: dec!(20),
+                })),
+            ),
+            row(
+                5,
+                Some(Tx::Withdrawal(WithdrawalTx {
+                    client_id: 1,
+                    tx_id: 3,
+                    amount: dec!(1),
+                })),
+            ),
+        ];
+
+        let mut results = Results::new(rows.into_iter(), &mut engine);
+        let first = results.next().unwrap();
+        assert_eq!(first.outcome, Outcome::Applied);
+        assert_eq!(first.tx_id, Some(1));
+        assert_eq!(results.engine().client(1).unwrap().available, dec!(10));
+
+        let outcomes: Vec<_> = results
+            .map(|result| (result.line, result.outcome))
+            .collect();
+        assert_eq!(
+            outcomes,
+            vec![
+                (Some(3), Outcome::Rejected(RejectReason::ParseError)),
+                (Some(4), Outcome::Rejected(RejectReason::InsufficientFunds)),
+                (Some(5), Outcome::Applied),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_skipped_types_leave_engine_untouched() {
+        let mut engine = Engine::new();
+        let rows = vec![row(
+            2,
+            Some(Tx::Deposit(DepositTx {
+                client_id: 1,
+                tx_id: 1,
+                amount: dec!(10),
+            })),
+        )];
+
+        let results: Vec<_> = Results::new(rows.into_iter(), &mut engine)
+            .skip_types(vec![TxType::Deposit])
+            .collect();
+
+        assert_eq!(results[0].outcome, Outcome::Skipped);
+        assert!(results[0].engine_result().is_none());
+        assert!(engine.clients_iter().next().is_none());
+    }
+
+    #[test]
+    fn test_locks_cool_off_at_the_next_row() {
+        use crate::{
+            engine::config::{EngineConfig, UnlockPolicy},
+            types::transactions::{ChargebackTx, DisputeTx},
+        };
+
+        let mut engine = Engine::with_config(EngineConfig {
+            unlock: UnlockPolicy::AfterDays(1),
+            ..EngineConfig::default()
+        });
+        let day = 86_400_000;
+        let deposit = |tx_id| Tx::Deposit(DepositTx::new(1, tx_id, dec!(5)).unwrap());
+        let at = |line, timestamp, tx| Row {
+            timestamp: Some(timestamp),
+            ..row(line, Some(tx))
+        };
+        let rows = vec![
+            at(2, 0, deposit(1)),
+            at(
+                3,
+                0,
+                Tx::Dispute(DisputeTx {
+                    client_id: 1,
+                    tx_id: 1,
+                }),
+            ),
+            at(
+                4,
+                day,
+                Tx::Chargeback(ChargebackTx {
+                    client_id: 1,
+                    tx_id: 1,
+                }),
+            ),
+            at(5, 2 * day - 1, deposit(2)),
+            // Rejected before it reaches the engine, so it doesn't unlock
+            at(6, 2 * day, deposit(1)),
+        ];
+        let mut filter = TxFilter::new(100, 0.0001);
+
+        let outcomes: Vec<_> = Results::new(rows.into_iter(), &mut engine)
+            .dedupe(&mut filter)
+            .map(|result| result.outcome)
+            .collect();
+        assert_eq!(
+            outcomes[3..],
+            [
+                Outcome::Rejected(RejectReason::AccountLocked),
+                Outcome::Rejected(RejectReason::DuplicateTx),
+            ]
+        );
+        assert!(engine.client(1).unwrap().locked);
+
+        let outcomes: Vec<_> =
+            Results::new(vec![at(7, 2 * day, deposit(3))].into_iter(), &mut engine)
+                .dedupe(&mut filter)
+                .map(|result| result.outcome)
+                .collect();
+        assert_eq!(outcomes, [Outcome::Applied]);
+        assert!(engine.lock_info(1).is_none());
+        assert_eq!(engine.client(1).unwrap().total, dec!(5));
+    }
+
+    #[test]
+    fn test_dequeued_rows_are_reported() {
+        use crate::{
+            engine::config::{EngineConfig, MissingDeposit},
+            types::transactions::DisputeTx,
+        };
+
+        let mut engine = Engine::with_config(EngineConfig {
+            missing_deposit: MissingDeposit::Queue,
+            ..EngineConfig::default()
+        });
+        let dispute = |tx_id| {
+            Some(Tx::Dispute(DisputeTx {
+                client_id: 1,
+                tx_id,
+            }))
+        };
+        let deposit = |tx_id, amount| Some(Tx::Deposit(DepositTx::new(1, tx_id, amount).unwrap()));
+        let rows = vec![
+            row(2, dispute(1)),
+            row(3, dispute(2)),
+            row(4, dispute(1)),
+            row(5, deposit(1, dec!(5))),
+            row(
+                6,
+                Some(Tx::Withdrawal(WithdrawalTx::new(1, 2, dec!(9)).unwrap())),
+            ),
+            row(7, deposit(3, dec!(1))),
+        ];
+
+        let results: Vec<_> = Results::new(rows.into_iter(), &mut engine)
+            .map(|result| (result.line, result.outcome, result.dequeued))
+            .collect();
+        let queued = Outcome::Rejected(RejectReason::Queued);
+        assert_eq!(
+            results,
+            vec![
+                (Some(2), queued, false),
+                (Some(3), queued, false),
+                (Some(4), queued, false),
+                (Some(5), Outcome::Applied, false),
+                (Some(2), Outcome::Applied, true),
+                (
+                    Some(4),
+                    Outcome::Rejected(RejectReason::NotDisputable),
+                    true
+                ),
+                (
+                    Some(6),
+                    Outcome::Rejected(RejectReason::InsufficientFunds),
+                    false
+                ),
+                (Some(3), Outcome::Rejected(RejectReason::UnknownTx), true),
+                (Some(7), Outcome::Applied, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dedupe_rejects_replayed_ids() {
+        let deposit = |tx_id| {
+            Some(Tx::Deposit(DepositTx {
+                client_id: 1,
+                tx_id,
+                amount: dec!(10),
+            }))
+        };
+        let mut engine = Engine::new();
+        let mut filter = TxFilter::new(100, 0.0001);
+
+        let outcomes: Vec<_> = Results::new(
+            vec![row(2, deposit(1)), row(3, deposit(2))].into_iter(),
+            &mut engine,
+        )
+        .dedupe(&mut filter)
+        .map(|result| result.outcome)
+        .collect();
+        assert_eq!(outcomes, vec![Outcome::Applied, Outcome::Applied]);
+
+        // The same file submitted again, plus one new deposit
+        let outcomes: Vec<_> = Results::new(
+            vec![row(2, deposit(1)), row(3, deposit(2)), row(4, deposit(3))].into_iter(),
+            &mut engine,
+        )
+        .dedupe(&mut filter)
+        .map(|result| result.outcome)
+        .collect();
+        assert_eq!(
+            outcomes,
+            vec![
+                Outcome::Rejected(RejectReason::DuplicateTx),
+                Outcome::Rejected(RejectReason::DuplicateTx),
+                Outcome::Applied,
+            ]
+        );
+        assert_eq!(engine.client(1).unwrap().total, dec!(30));
+        assert_eq!(filter.len(), 3);
+    }
+
+    fn sequenced(line: u64, seq: u64, tx_id: TxId) -> Row {
+        Row {
+            seq: Some(seq),
+            ..row(
+                line,
+                Some(Tx::Deposit(DepositTx {
+                    client_id: 1,
+                    tx_id,
+                    amount: dec!(1),
+                })),
+            )
+        }
+    }
+
+    #[test]
+    fn test_sequence_gap_buffered_until_filled() {
+        let mut engine = Engine::new();
+        let rows = vec![
+            sequenced(2, 1, 1),
+            sequenced(3, 3, 3),
+            sequenced(4, 4, 4),
+            sequenced(5, 2, 2),
+            sequenced(6, 2, 5),
+            sequenced(7, 7, 7),
+        ];
+
+        let results: Vec<_> = Results::new(rows.into_iter(), &mut engine)
+            .sequence(SequencePolicy::Buffer)
+            .map(|result| (result.line, result.outcome))
+            .collect();
+        assert_eq!(
+            results,
+            vec![
+                (Some(2), Outcome::Applied),
+                (Some(5), Outcome::Applied),
+                (Some(3), Outcome::Applied),
+                (Some(4), Outcome::Applied),
+                (Some(6), Outcome::Rejected(RejectReason::OutOfSequence)),
+                (Some(7), Outcome::Rejected(RejectReason::SequenceGap)),
+            ]
+        );
+        assert_eq!(engine.last_sequence(1), Some(4));
+    }
+
+    #[test]
+    fn test_held_row_displaced_by_its_number() {
+        let mut engine = Engine::new();
+        let rows = vec![
+            sequenced(2, 1, 1),
+            sequenced(3, 3, 3),
+            sequenced(4, 3, 4),
+            sequenced(5, 2, 2),
+        ];
+
+        let results: Vec<_> = Results::new(rows.into_iter(), &mut engine)
+            .sequence(SequencePolicy::Buffer)
+            .map(|result| (result.line, result.outcome, result.sequence))
+            .collect();
+        assert_eq!(
+            results,
+            vec![
+                (Some(2), Outcome::Applied, None),
+                (
+                    Some(3),
+                    Outcome::Rejected(RejectReason::OutOfSequence),
+                    Some(SequenceIssue::Displaced { seq: 3 })
+                ),
+                (Some(5), Outcome::Applied, None),
+                (Some(4), Outcome::Applied, None),
+            ]
+        );
+        assert_eq!(engine.client(1).unwrap().total, dec!(3));
+    }
+
+    #[test]
+    fn test_sequence_gap_rejected() {
+        let mut engine = Engine::new();
+        let rows = vec![sequenced(2, 1, 1), sequenced(3, 3, 3), sequenced(4, 4, 4)];
+
+        let results: Vec<_> = Results::new(rows.into_iter(), &mut engine)
+            .sequence(SequencePolicy::Reject)
+            .map(|result| (result.outcome, result.sequence))
+            .collect();
+        assert_eq!(
+            results,
+            vec![
+                (Outcome::Applied, None),
+                (
+                    Outcome::Rejected(RejectReason::SequenceGap),
+                    Some(SequenceIssue::Gap {
+                        expected: 2,
+                        seq: 3
+                    })
+                ),
+                (Outcome::Applied, None),
+            ]
+        );
+    }
+}
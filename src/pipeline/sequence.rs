@@ -0,0 +1,134 @@
+//! Per-client sequence numbers from the optional `seq` column, checked so
+//! an upstream's ordering guarantee can be verified.
+
+use std::{fmt, str::FromStr};
+
+use crate::types::reject::RejectReason;
+
+/// What `Results` does with a row whose sequence number doesn't follow the
+/// client's previous one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SequencePolicy {
+    /// Apply it anyway and report the issue with the row
+    Warn,
+    /// Reject it as `sequence_gap` or `out_of_sequence`
+    Reject,
+    /// Hold rows after a gap until the missing ones arrive, rows still held
+    /// at the end of the input are rejected as `sequence_gap`
+    Buffer,
+}
+
+impl SequencePolicy {
+    pub const ALL: [SequencePolicy; 3] = [
+        SequencePolicy::Warn,
+        SequencePolicy::Reject,
+        SequencePolicy::Buffer,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SequencePolicy::Warn => "warn",
+            SequencePolicy::Reject => "reject",
+            SequencePolicy::Buffer => "buffer",
+        }
+    }
+}
+
+impl fmt::Display for SequencePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for SequencePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        SequencePolicy::ALL
+            .into_iter()
+            .find(|policy| policy.name() == s)
+            .ok_or_else(|| format!("unknown policy `{s}`, expected warn, reject or buffer"))
+    }
+}
+
+/// A sequence number that doesn't follow the client's previous one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SequenceIssue {
+    /// Numbers between the previous one and `seq` are missing
+    Gap { expected: u64, seq: u64 },
+    /// `seq` is not above the highest number already seen, a replay or a
+    /// row delivered late
+    OutOfOrder { last: u64, seq: u64 },
+    /// A row held back after a gap whose number `seq` a later row took
+    Displaced { seq: u64 },
+}
+
+impl SequenceIssue {
+    /// Checks `seq` against the client's last sequence number, the first one
+    /// seen for a client is always in order.
+    pub fn check(last: Option<u64>, seq: u64) -> Option<SequenceIssue> {
+        let last = last?;
+        match last.checked_add(1) {
+            Some(expected) if seq == expected => None,
+            Some(expected) if seq > expected => Some(SequenceIssue::Gap { expected, seq }),
+            _ => Some(SequenceIssue::OutOfOrder { last, seq }),
+        }
+    }
+
+    pub fn reason(&self) -> RejectReason {
+        match self {
+            SequenceIssue::Gap { .. } => RejectReason::SequenceGap,
+            SequenceIssue::OutOfOrder { .. } | SequenceIssue::Displaced { .. } => {
+                RejectReason::OutOfSequence
+            }
+        }
+    }
+}
+
+impl fmt::Display for SequenceIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SequenceIssue::Gap { expected, seq } => {
+                write!(f, "sequence gap, expected {expected} but got {seq}")
+            }
+            SequenceIssue::OutOfOrder { last, seq } => {
+                write!(f, "sequence {seq} out of order, already at {last}")
+            }
+            SequenceIssue::Displaced { seq } => {
+                write!(
+                    f,
+                    "sequence {seq} taken by a later row with the same number"
+                )
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_sequence() {
+        assert_eq!(SequenceIssue::check(None, 40), None);
+        assert_eq!(SequenceIssue::check(Some(4), 5), None);
+        assert_eq!(
+            SequenceIssue::check(Some(4), 7),
+            Some(SequenceIssue::Gap {
+                expected: 5,
+                seq: 7
+            })
+        );
+        assert_eq!(
+            SequenceIssue::check(Some(4), 4),
+            Some(SequenceIssue::OutOfOrder { last: 4, seq: 4 })
+        );
+        assert_eq!(
+            SequenceIssue::check(Some(u64::MAX), 0),
+            Some(SequenceIssue::OutOfOrder {
+                last: u64::MAX,
+                seq: 0
+            })
+        );
+    }
+}
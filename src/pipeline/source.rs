@@ -0,0 +1,585 @@
+use std::{
+    error::Error,
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+    sync::{Arc, OnceLock},
+    time::{Duration, Instant},
+};
+
+use encoding_rs::Encoding;
+use encoding_rs_io::{DecodeReaderBytes, DecodeReaderBytesBuilder};
+use rust_decimal::{Decimal, prelude::ToPrimitive};
+
+use crate::{
+    io::csv::CsvRowRef,
+    pipeline::{InputOffset, number_format::NumberFormat},
+    types::transactions::Tx,
+};
+
+/// A row read from the input, `tx` is `None` when it couldn't be parsed.
+pub struct Row {
+    pub line: Option<u64>,
+    pub tx: Option<Tx>,
+    /// From the optional `timestamp` column, in milliseconds since the Unix epoch
+    pub timestamp: Option<i64>,
+    /// From the optional `seq` column, numbering the rows of each client
+    pub seq: Option<u64>,
+    /// Input position right after this row
+    pub position: InputOffset,
+}
+
+/// Time `CsvSource` spent on each stage of the rows read so far.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StageTimings {
+    /// Reading and splitting records, transcoding included
+    pub read: Duration,
+    /// Deserializing the fields
+    pub parse: Duration,
+    /// Turning the fields into a `Tx`
+    pub convert: Duration,
+}
+
+/// Measures the time between laps, or nothing when disabled.
+struct Stopwatch(Option<Instant>);
+
+impl Stopwatch {
+    fn start(enabled: bool) -> Self {
+        Stopwatch(enabled.then(Instant::now))
+    }
+
+    fn lap(&mut self) -> Duration {
+        match &mut self.0 {
+            Some(last) => {
+                let now = Instant::now();
+                let lap = now - *last;
+                *last = now;
+                lap
+            }
+            None => Duration::ZERO,
+        }
+    }
+}
+
+/// Sees every chunk of the raw input file as it is read, see `CsvSource::open_tapped`.
+pub type Tap = Box<dyn FnMut(&[u8]) + Send>;
+
+/// Called before every read of the input file, an error fails that read, see
+/// `CsvSource::inject_faults`.
+#[cfg(feature = "chaos")]
+pub type Fault = Box<dyn FnMut() -> io::Result<()> + Send>;
+
+/// The input file, passing what is read through the tap if there is one.
+struct Tapped {
+    file: File,
+    tap: Option<Tap>,
+    #[cfg(feature = "chaos")]
+    fault: Option<Fault>,
+}
+
+impl Read for Tapped {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        #[cfg(feature = "chaos")]
+        if let Some(fault) = &mut self.fault {
+            fault()?;
+        }
+        let n = self.file.read(buf)?;
+        if let Some(tap) = &mut self.tap {
+            tap(&buf[..n]);
+        }
+        Ok(n)
+    }
+}
+
+impl Seek for Tapped {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.file.seek(pos)
+    }
+}
+
+/// The input file, transcoded to UTF-8 when it isn't UTF-8 already.
+enum Input {
+    Utf8(Tapped),
+    Decoded(DecodeReaderBytes<Tapped, Vec<u8>>),
+}
+
+impl Read for Input {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Input::Utf8(file) => file.read(buf),
+            Input::Decoded(rdr) => rdr.read(buf),
+        }
+    }
+}
+
+impl Seek for Input {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            Input::Utf8(file) => file.seek(pos),
+            // Positions are offsets into the transcoded stream, not the file
+            Input::Decoded(_) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "can't seek in a transcoded input, only UTF-8 input can be resumed",
+            )),
+        }
+    }
+}
+
+/// Streams rows out of a transactions CSV, one record buffer reused for all of them.
+pub struct CsvSource {
+    rdr: csv::Reader<Input>,
+    headers: csv::StringRecord,
+    record: csv::StringRecord,
+    // The record with its amount normalized, for non-plain number formats
+    normalized: csv::StringRecord,
+    lenient_types: bool,
+    number_format: NumberFormat,
+    amount_column: Option<usize>,
+    timestamp_column: Option<usize>,
+    seq_column: Option<usize>,
+    timings: Option<StageTimings>,
+    read_error: Arc<OnceLock<io::Error>>,
+}
+
+impl CsvSource {
+    pub fn open(path: &Path) -> Result<Self, Box<dyn Error>> {
+        CsvSource::open_encoded(path, None)
+    }
+
+    /// Opens `path` transcoding it from `encoding` to UTF-8. A UTF-16 byte
+    /// order mark wins over `encoding`, UTF-8 input (with or without a BOM)
+    /// is read as is.
+    pub fn open_encoded(
+        path: &Path,
+        encoding: Option<&'static Encoding>,
+    ) -> Result<Self, Box<dyn Error>> {
+        CsvSource::open_tapped(path, encoding, None)
+    }
+
+    /// Like `open_encoded`, and passes the file's bytes to `tap` as they are
+    /// read, before any transcoding. Seeking skips bytes without tapping them.
+    pub fn open_tapped(
+        path: &Path,
+        encoding: Option<&'static Encoding>,
+        tap: Option<Tap>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut file = File::open(path)?;
+        let mut bom = [0; 2];
+        let utf16_bom = file.read(&mut bom)? == 2 && matches!(bom, [0xFF, 0xFE] | [0xFE, 0xFF]);
+        file.rewind()?;
+        let file = Tapped {
+            file,
+            tap,
+            #[cfg(feature = "chaos")]
+            fault: None,
+        };
+
+        let input = match encoding {
+            Some(encoding) if encoding != encoding_rs::UTF_8 => Input::Decoded(
+                DecodeReaderBytesBuilder::new()
+                    .encoding(Some(encoding))
+                    .build(file),
+            ),
+            // Sniffs the BOM, the csv reader already skips a UTF-8 one
+            _ if utf16_bom => Input::Decoded(DecodeReaderBytes::new(file)),
+            _ => Input::Utf8(file),
+        };
+
+        let mut rdr = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .flexible(true)
+            .from_reader(input);
+        let headers = rdr.headers()?.clone();
+
+        let mut source = CsvSource {
+            rdr,
+            headers: csv::StringRecord::new(),
+            record: csv::StringRecord::new(),
+            normalized: csv::StringRecord::new(),
+            lenient_types: false,
+            number_format: NumberFormat::Plain,
+            amount_column: None,
+            timestamp_column: None,
+            seq_column: None,
+            timings: None,
+            read_error: Arc::default(),
+        };
+        source.set_headers(headers);
+        Ok(source)
+    }
+
+    fn set_headers(&mut self, headers: csv::StringRecord) {
+        let column = |name| headers.iter().position(|header| header == name);
+        self.amount_column = column("amount");
+        self.timestamp_column = column("timestamp");
+        self.seq_column = column("seq");
+        self.headers = headers;
+    }
+
+    /// Reads the column named `from` as `to` for every `(from, to)` pair, for
+    /// inputs with their own column names. A column already named like one of
+    /// the targets but not renamed to it is ignored.
+    pub fn rename_columns(mut self, renames: &[(&str, &str)]) -> Self {
+        let headers = self
+            .headers
+            .iter()
+            .map(
+                |header| match renames.iter().find(|(from, _)| *from == header) {
+                    Some((_, to)) => to,
+                    None if renames.iter().any(|(_, to)| *to == header) => "",
+                    None => header,
+                },
+            )
+            .collect();
+        self.set_headers(headers);
+        self
+    }
+
+    /// Reads amounts written in `format` instead of the plain `1234.56`.
+    pub fn number_format(mut self, format: NumberFormat) -> Self {
+        self.number_format = format;
+        self
+    }
+
+    /// Accepts transaction types in any case and common provider aliases.
+    pub fn lenient_types(mut self, lenient: bool) -> Self {
+        self.lenient_types = lenient;
+        self
+    }
+
+    /// Times every row's stages, see `timings`. Costs a few clock reads per row.
+    pub fn timed(mut self, timed: bool) -> Self {
+        self.timings = timed.then(StageTimings::default);
+        self
+    }
+
+    /// Stage times of the rows read so far, `None` unless `timed`.
+    pub fn timings(&self) -> Option<StageTimings> {
+        self.timings
+    }
+
+    /// Set when reading the input failed, which ends the rows right before
+    /// the row that couldn't be read. Shared, so it can still be checked
+    /// once the source has moved into a `Pipeline`.
+    pub fn read_error(&self) -> Arc<OnceLock<io::Error>> {
+        self.read_error.clone()
+    }
+
+    /// Runs `fault` before every read of the input file from now on, failing
+    /// the read when it returns an error. For fault injection tests, and like
+    /// seeking only for UTF-8 input, transcoded input never fails.
+    #[cfg(feature = "chaos")]
+    pub fn inject_faults(mut self, fault: Fault) -> Self {
+        if let Input::Utf8(file) = self.rdr.get_mut() {
+            file.fault = Some(fault);
+        }
+        self
+    }
+
+    pub fn headers(&self) -> &csv::StringRecord {
+        &self.headers
+    }
+
+    pub fn seek(&mut self, offset: InputOffset) -> csv::Result<()> {
+        let mut position = csv::Position::new();
+        position
+            .set_byte(offset.byte)
+            .set_line(offset.line)
+            .set_record(offset.record);
+        self.rdr.seek(position)
+    }
+
+    pub fn position(&self) -> InputOffset {
+        offset(self.rdr.position())
+    }
+}
+
+impl CsvSource {
+    fn parse_record(&mut self) -> Option<CsvRowRef<'_>> {
+        let row = match (self.number_format, self.amount_column) {
+            (NumberFormat::Plain, _) | (_, None) => {
+                self.record.deserialize(Some(&self.headers)).ok()?
+            }
+            (format, Some(column)) => {
+                let amount = format.normalize(self.record.get(column).unwrap_or_default())?;
+                self.normalized.clear();
+                for (i, field) in self.record.iter().enumerate() {
+                    self.normalized
+                        .push_field(if i == column { &amount } else { field });
+                }
+                self.normalized.deserialize(Some(&self.headers)).ok()?
+            }
+        };
+        Some(row)
+    }
+}
+
+/// Reads Unix time in seconds, with or without a fraction (`1717171717.25`).
+fn parse_timestamp(field: &str) -> Option<i64> {
+    let seconds: Decimal = field.parse().ok()?;
+    seconds.checked_mul(Decimal::ONE_THOUSAND)?.floor().to_i64()
+}
+
+impl Iterator for CsvSource {
+    type Item = Row;
+
+    fn next(&mut self) -> Option<Row> {
+        let mut stopwatch = Stopwatch::start(self.timings.is_some());
+        let read = self.rdr.read_record(&mut self.record);
+        let read_time = stopwatch.lap();
+
+        let lenient_types = self.lenient_types;
+        let (tx, mut parse_time, convert_time) = match read {
+            Ok(true) => {
+                let row = self.parse_record();
+                let parse_time = stopwatch.lap();
+                let tx = row.and_then(|row| row.to_tx(lenient_types));
+                (tx, parse_time, stopwatch.lap())
+            }
+            Ok(false) => return None,
+            // Whatever is left of the input is unknown, not malformed
+            Err(err) if err.is_io_error() => {
+                let _ = self.read_error.set(err.into());
+                return None;
+            }
+            Err(_) => (None, Duration::ZERO, Duration::ZERO),
+        };
+        let timestamp = self
+            .timestamp_column
+            .and_then(|column| self.record.get(column))
+            .and_then(parse_timestamp);
+        let seq = self
+            .seq_column
+            .and_then(|column| self.record.get(column))
+            .and_then(|field| field.parse().ok());
+        parse_time += stopwatch.lap();
+
+        if let Some(timings) = &mut self.timings {
+            timings.read += read_time;
+            timings.parse += parse_time;
+            timings.convert += convert_time;
+        }
+        Some(Row {
+            line: self.record.position().map(|p| p.line()),
+            tx,
+            timestamp,
+            seq,
+            position: offset(self.rdr.position()),
+        })
+    }
+}
+
+fn offset(position: &csv::Position) -> InputOffset {
+    InputOffset {
+        byte: position.byte(),
+        line: position.line(),
+        record: position.record(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    use crate::types::transactions::{DepositTx, TxType};
+
+    const INPUT: &str = "type,client,tx,amount\ndeposit,1,1,2.5\n";
+
+    fn read_amounts(txs: impl Iterator<Item = Option<Tx>>) -> Vec<Option<String>> {
+        txs.map(|tx| match tx {
+            Some(Tx::Deposit(tx)) => Some(tx.amount.to_string()),
+            _ => None,
+        })
+        .collect()
+    }
+
+    #[test]
+    fn test_lenient_types() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        write!(
+            file,
+            "type,client,tx,amount\n\
+             DEPOSIT,1,1,2.0\n\
+             withdraw,1,2,1.0\n\
+             Charge_Back,1,1,\n\
+             refund,1,3,1.0\n"
+        )
+        .unwrap();
+
+        let strict: Vec<_> = CsvSource::open(file.path())
+            .unwrap()
+            .map(|row| row.tx.map(|tx| tx.tx_type()))
+            .collect();
+        assert_eq!(strict, vec![None, None, None, None]);
+
+        let lenient: Vec<_> = CsvSource::open(file.path())
+            .unwrap()
+            .lenient_types(true)
+            .map(|row| row.tx.map(|tx| tx.tx_type()))
+            .collect();
+        assert_eq!(
+            lenient,
+            vec![
+                Some(TxType::Deposit),
+                Some(TxType::Withdrawal),
+                Some(TxType::Chargeback),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_utf16_with_bom_is_transcoded() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&[0xFF, 0xFE]).unwrap();
+        for unit in INPUT.encode_utf16() {
+            file.write_all(&unit.to_le_bytes()).unwrap();
+        }
+
+        let source = CsvSource::open(file.path()).unwrap();
+        assert_eq!(
+            read_amounts(source.map(|row| row.tx)),
+            vec![Some("2.5".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_latin1_input_is_transcoded() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        // A Latin-1 `é` in a trailing column that would otherwise fail UTF-8 validation
+        file.write_all(b"type,client,tx,amount,note\ndeposit,1,1,2.5,caf\xe9\n")
+            .unwrap();
+
+        let source = CsvSource::open(file.path()).unwrap();
+        assert_eq!(read_amounts(source.map(|row| row.tx)), vec![None]);
+
+        let latin1 = Encoding::for_label(b"latin1");
+        let source = CsvSource::open_encoded(file.path(), latin1).unwrap();
+        assert_eq!(
+            read_amounts(source.map(|row| row.tx)),
+            vec![Some("2.5".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_tap_sees_the_raw_bytes() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&[0xFF, 0xFE]).unwrap();
+        for unit in INPUT.encode_utf16() {
+            file.write_all(&unit.to_le_bytes()).unwrap();
+        }
+
+        let tapped = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = tapped.clone();
+        let tap: Tap = Box::new(move |bytes| sink.lock().unwrap().extend_from_slice(bytes));
+        let source = CsvSource::open_tapped(file.path(), None, Some(tap)).unwrap();
+        assert_eq!(source.count(), 1);
+        assert_eq!(*tapped.lock().unwrap(), std::fs::read(file.path()).unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_read_error_ends_the_rows() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        write!(file, "{INPUT}").unwrap();
+        for tx_id in 2..10_000 {
+            writeln!(file, "deposit,1,{tx_id},1.0").unwrap();
+        }
+
+        let mut source = CsvSource::open(file.path()).unwrap();
+        let read_error = source.read_error();
+        // A directory opens fine on unix but every read of it fails, once
+        // the rows already buffered are used up
+        let dir = tempfile::tempdir().unwrap();
+        *source.rdr.get_mut() = Input::Utf8(Tapped {
+            file: File::open(dir.path()).unwrap(),
+            tap: None,
+            #[cfg(feature = "chaos")]
+            fault: None,
+        });
+
+        let rows: Vec<_> = source.collect();
+        assert!(rows.len() < 10_000);
+        assert!(rows.iter().all(|row| row.tx.is_some()));
+        assert!(read_error.get().is_some());
+    }
+
+    #[test]
+    fn test_dot_thousands_amounts() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        write!(
+            file,
+            "type,client,tx,amount\n\
+             deposit,1,1,\"1.234,56\"\n\
+             deposit,1,2,\"1,5\"\n\
+             dispute,1,1,\n"
+        )
+        .unwrap();
+
+        let source = CsvSource::open(file.path())
+            .unwrap()
+            .number_format(NumberFormat::DotThousands);
+        let rows: Vec<_> = source.collect();
+        assert_eq!(
+            read_amounts(rows.iter().map(|row| row.tx)),
+            vec![Some("1234.56".to_string()), Some("1.5".to_string()), None]
+        );
+        assert!(matches!(rows[2].tx, Some(Tx::Dispute(_))));
+    }
+
+    #[test]
+    fn test_timestamp_column() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        write!(
+            file,
+            "type,client,tx,amount,timestamp\n\
+             deposit,1,1,1.0,1717171717.25\n\
+             deposit,1,2,1.0,\n\
+             deposit,1,3,1.0,yesterday\n"
+        )
+        .unwrap();
+
+        let timestamps: Vec<_> = CsvSource::open(file.path())
+            .unwrap()
+            .map(|row| (row.tx.is_some(), row.timestamp))
+            .collect();
+        assert_eq!(
+            timestamps,
+            vec![(true, Some(1_717_171_717_250)), (true, None), (true, None)]
+        );
+    }
+
+    #[test]
+    fn test_renamed_columns() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        write!(
+            file,
+            "kind,customer,id,value,type,created\n\
+             deposit,7,1,2.5,card,1717171717\n"
+        )
+        .unwrap();
+
+        let renames = [
+            ("kind", "type"),
+            ("customer", "client"),
+            ("id", "tx"),
+            ("value", "amount"),
+            ("created", "timestamp"),
+        ];
+        let rows: Vec<_> = CsvSource::open(file.path())
+            .unwrap()
+            .rename_columns(&renames)
+            .collect();
+        // The input's own `type` column doesn't shadow the renamed one
+        assert!(matches!(
+            rows[0].tx,
+            Some(Tx::Deposit(DepositTx {
+                client_id: 7,
+                tx_id: 1,
+                ..
+            }))
+        ));
+        assert_eq!(rows[0].timestamp, Some(1_717_171_717_000));
+    }
+}
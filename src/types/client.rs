@@ -1,15 +1,41 @@
+use std::{fmt, str::FromStr};
+
 use rust_decimal::{Decimal, prelude::Zero};
 
-use crate::types::common::ClientId;
+use crate::types::common::{ClientId, TxId};
 
-#[derive(Debug, serde::Serialize)]
+/// Deserializes from the balances output, e.g. a previous run's CSV to open
+/// the next one with. Other columns are ignored and the counters start at zero.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Client {
-    #[serde(rename = "client")]
+    #[cfg_attr(feature = "serde", serde(rename = "client"))]
     pub id: ClientId,
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "decimal_from_str"))]
     pub available: Decimal,
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "decimal_from_str"))]
     pub held: Decimal,
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "decimal_from_str"))]
     pub total: Decimal,
     pub locked: bool,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub stats: ClientStats,
+    /// Why the account is locked, `None` while it isn't
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub lock: Option<LockInfo>,
+}
+
+/// Activity counters kept per client, across resumed runs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ClientStats {
+    /// Accepted deposits
+    pub deposits: u64,
+    /// Accepted withdrawals
+    pub withdrawals: u64,
+    /// Withdrawals rejected for any reason other than a capacity limit
+    pub rejected_withdrawals: u64,
+    /// Accepted opening balance transactions
+    pub openings: u64,
 }
 
 impl Client {
@@ -20,6 +46,122 @@ impl Client {
             held: Decimal::zero(),
             total: Decimal::zero(),
             locked: false,
+            stats: ClientStats::default(),
+            lock: None,
+        }
+    }
+}
+
+/// What locked an account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockReason {
+    Chargeback,
+    /// An `opening_balance_locked` row
+    OpeningBalance,
+    /// Locked in the opening balances, or in a snapshot saved before lock
+    /// reasons were kept
+    CarriedOver,
+}
+
+impl LockReason {
+    pub const ALL: [LockReason; 3] = [
+        LockReason::Chargeback,
+        LockReason::OpeningBalance,
+        LockReason::CarriedOver,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LockReason::Chargeback => "chargeback",
+            LockReason::OpeningBalance => "opening_balance",
+            LockReason::CarriedOver => "carried_over",
+        }
+    }
+}
+
+impl fmt::Display for LockReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Why and when an account was locked, kept across resumed runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LockInfo {
+    pub reason: LockReason,
+    /// The chargeback or opening transaction that locked it
+    pub tx_id: Option<TxId>,
+    /// Input line of the row that locked it, unknown when the engine was fed
+    /// without the pipeline or the row was applied from the queue
+    pub line: Option<u64>,
+    /// That row's `timestamp` in milliseconds since the Unix epoch, if the
+    /// input has the column
+    pub timestamp: Option<i64>,
+}
+
+impl LockInfo {
+    pub fn new(reason: LockReason, tx_id: Option<TxId>) -> Self {
+        LockInfo {
+            reason,
+            tx_id,
+            line: None,
+            timestamp: None,
         }
     }
 }
+
+/// `Decimal`'s own `Deserialize` takes whatever type a self-describing format
+/// guesses, and CSV guesses `f64` for `1.5`, losing digits and the scale.
+#[cfg(feature = "serde")]
+fn decimal_from_str<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Decimal, D::Error> {
+    let s = <std::borrow::Cow<'de, str> as serde::Deserialize>::deserialize(deserializer)?;
+    Decimal::from_str(&s).map_err(serde::de::Error::custom)
+}
+
+/// One of the balances of a client.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Balance {
+    Available,
+    Held,
+    #[default]
+    Total,
+}
+
+impl Balance {
+    const ALL: [Balance; 3] = [Balance::Available, Balance::Held, Balance::Total];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Balance::Available => "available",
+            Balance::Held => "held",
+            Balance::Total => "total",
+        }
+    }
+
+    pub fn of(&self, client: &Client) -> Decimal {
+        match self {
+            Balance::Available => client.available,
+            Balance::Held => client.held,
+            Balance::Total => client.total,
+        }
+    }
+}
+
+impl fmt::Display for Balance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Balance {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Balance::ALL
+            .into_iter()
+            .find(|balance| balance.name() == s)
+            .ok_or_else(|| format!("unknown balance `{s}`, expected available, held or total"))
+    }
+}
@@ -1,3 +1,4 @@
 pub mod client;
 pub mod common;
+pub mod reject;
 pub mod transactions;
@@ -0,0 +1,132 @@
+use std::{fmt, str::FromStr};
+
+/// Why a row was not applied to the engine state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectReason {
+    /// The row could not be parsed into a transaction
+    ParseError,
+    /// The client has never deposited
+    UnknownClient,
+    /// Deposits and withdrawals are blocked after a chargeback
+    AccountLocked,
+    /// Withdrawal exceeds the available funds
+    InsufficientFunds,
+    /// The referenced deposit doesn't exist
+    UnknownTx,
+    /// The referenced deposit belongs to another client
+    ClientMismatch,
+    /// The referenced deposit is not in the state the operation requires
+    NotDisputable,
+    /// A balance would overflow the decimal range
+    Overflow,
+    /// The deposit would take the client above the configured max balance
+    MaxBalanceExceeded,
+    /// Applying the row would exceed a configured client, deposit or memory limit
+    CapacityExceeded,
+    /// The deposit or withdrawal id was already applied, in this or an earlier run
+    DuplicateTx,
+    /// The referenced deposit hasn't arrived yet, the row is applied when it does
+    Queued,
+    /// Rows with lower sequence numbers of the client are missing
+    SequenceGap,
+    /// The sequence number is not above the client's last one
+    OutOfSequence,
+    /// A deposit or withdrawal amount is zero or negative, or outside the configured limits
+    InvalidAmount,
+    /// The deposit or withdrawal id belongs to a stored transaction with another type,
+    /// client or amount, or the row names a broken deposit index record, a sign of
+    /// corrupt input
+    ConflictingTx,
+    /// An opening balance for a client that already has an account
+    AccountExists,
+}
+
+impl RejectReason {
+    pub const ALL: [RejectReason; 17] = [
+        RejectReason::ParseError,
+        RejectReason::UnknownClient,
+        RejectReason::AccountLocked,
+        RejectReason::InsufficientFunds,
+        RejectReason::UnknownTx,
+        RejectReason::ClientMismatch,
+        RejectReason::NotDisputable,
+        RejectReason::Overflow,
+        RejectReason::MaxBalanceExceeded,
+        RejectReason::CapacityExceeded,
+        RejectReason::DuplicateTx,
+        RejectReason::Queued,
+        RejectReason::SequenceGap,
+        RejectReason::OutOfSequence,
+        RejectReason::InvalidAmount,
+        RejectReason::ConflictingTx,
+        RejectReason::AccountExists,
+    ];
+
+    /// Stable snake_case code used in every report.
+    pub fn code(&self) -> &'static str {
+        match self {
+            RejectReason::ParseError => "parse_error",
+            RejectReason::UnknownClient => "unknown_client",
+            RejectReason::AccountLocked => "account_locked",
+            RejectReason::InsufficientFunds => "insufficient_funds",
+            RejectReason::UnknownTx => "unknown_tx",
+            RejectReason::ClientMismatch => "client_mismatch",
+            RejectReason::NotDisputable => "not_disputable",
+            RejectReason::Overflow => "overflow",
+            RejectReason::MaxBalanceExceeded => "max_balance_exceeded",
+            RejectReason::CapacityExceeded => "capacity_exceeded",
+            RejectReason::DuplicateTx => "duplicate_tx",
+            RejectReason::Queued => "queued",
+            RejectReason::SequenceGap => "sequence_gap",
+            RejectReason::OutOfSequence => "out_of_sequence",
+            RejectReason::InvalidAmount => "invalid_amount",
+            RejectReason::ConflictingTx => "conflicting_tx",
+            RejectReason::AccountExists => "account_exists",
+        }
+    }
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+impl FromStr for RejectReason {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        RejectReason::ALL
+            .into_iter()
+            .find(|reason| reason.code() == s)
+            .ok_or_else(|| format!("unknown reject reason `{s}`"))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for RejectReason {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.code())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for RejectReason {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let code = <std::borrow::Cow<'de, str>>::deserialize(deserializer)?;
+        code.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codes_round_trip() {
+        for reason in RejectReason::ALL {
+            assert_eq!(reason.code().parse(), Ok(reason));
+        }
+        assert!("insufficient funds".parse::<RejectReason>().is_err());
+    }
+}
@@ -1,80 +1,349 @@
+use std::{fmt, str::FromStr};
+
 use rust_decimal::Decimal;
 
-use crate::types::common::{ClientId, CsvRow, TxId};
+use crate::types::{
+    common::{ClientId, TxId},
+    reject::RejectReason,
+};
+
+/// Most decimal places `DepositTx::new` and `WithdrawalTx::new` accept, the
+/// precision the input format is specified with. Trailing zeros don't count,
+/// `1.50000` is fine. `AmountContext::max_scale` can only tighten it.
+pub const MAX_AMOUNT_SCALE: u32 = 4;
 
-#[derive(Debug)]
+/// A deposit. Outside the crate it can only be built with `new` (which
+/// `Tx::try_from` uses too), so its amount is positive and has at most
+/// `MAX_AMOUNT_SCALE` decimal places. The CSV source builds them unchecked,
+/// so that a bad amount in the input is rejected by the engine as
+/// `invalid_amount` instead of as a parse error.
+#[derive(Debug, Clone, Copy)]
 pub struct DepositTx {
-    pub client_id: ClientId,
-    pub tx_id: TxId,
-    pub amount: Decimal,
+    pub(crate) client_id: ClientId,
+    pub(crate) tx_id: TxId,
+    pub(crate) amount: Decimal,
 }
 
-// We allow the dead code as tx_id is never used in this impl.
-// We keep it for consistecy though.
-#[allow(dead_code)]
-#[derive(Debug)]
+/// A withdrawal, built with `new` like `DepositTx`.
+#[derive(Debug, Clone, Copy)]
 pub struct WithdrawalTx {
-    pub client_id: ClientId,
-    pub tx_id: TxId,
-    pub amount: Decimal,
+    pub(crate) client_id: ClientId,
+    pub(crate) tx_id: TxId,
+    pub(crate) amount: Decimal,
+}
+
+impl DepositTx {
+    /// Fails with `invalid_amount` unless `amount` is positive with at most
+    /// `MAX_AMOUNT_SCALE` decimal places.
+    pub fn new(client_id: ClientId, tx_id: TxId, amount: Decimal) -> Result<Self, RejectReason> {
+        Ok(DepositTx {
+            client_id,
+            tx_id,
+            amount: checked_amount(amount)?,
+        })
+    }
+
+    pub fn client_id(&self) -> ClientId {
+        self.client_id
+    }
+
+    pub fn tx_id(&self) -> TxId {
+        self.tx_id
+    }
+
+    pub fn amount(&self) -> Decimal {
+        self.amount
+    }
+}
+
+impl WithdrawalTx {
+    /// Fails with `invalid_amount` unless `amount` is positive with at most
+    /// `MAX_AMOUNT_SCALE` decimal places.
+    pub fn new(client_id: ClientId, tx_id: TxId, amount: Decimal) -> Result<Self, RejectReason> {
+        Ok(WithdrawalTx {
+            client_id,
+            tx_id,
+            amount: checked_amount(amount)?,
+        })
+    }
+
+    pub fn client_id(&self) -> ClientId {
+        self.client_id
+    }
+
+    pub fn tx_id(&self) -> TxId {
+        self.tx_id
+    }
+
+    pub fn amount(&self) -> Decimal {
+        self.amount
+    }
+}
+
+fn checked_amount(amount: Decimal) -> Result<Decimal, RejectReason> {
+    if amount <= Decimal::ZERO || amount.normalize().scale() > MAX_AMOUNT_SCALE {
+        return Err(RejectReason::InvalidAmount);
+    }
+    Ok(amount)
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct DisputeTx {
     pub client_id: ClientId,
     pub tx_id: TxId,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct ResolveTx {
     pub client_id: ClientId,
     pub tx_id: TxId,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct ChargebackTx {
     pub client_id: ClientId,
     pub tx_id: TxId,
 }
 
-#[derive(Debug)]
+/// Opens a client's account with the balance and lock it ended the previous
+/// period with, written as `opening_balance` (or `opening_balance_locked`)
+/// rows ahead of the period's feed. Nothing is stored for disputes. Built
+/// with `new` like `DepositTx`.
+#[derive(Debug, Clone, Copy)]
+pub struct OpeningBalanceTx {
+    pub(crate) client_id: ClientId,
+    pub(crate) tx_id: TxId,
+    /// Available and total, zero or negative too
+    pub(crate) amount: Decimal,
+    pub(crate) locked: bool,
+}
+
+impl OpeningBalanceTx {
+    /// Fails with `invalid_amount` if `amount` has more than
+    /// `MAX_AMOUNT_SCALE` decimal places, zero and negative ones are fine.
+    pub fn new(
+        client_id: ClientId,
+        tx_id: TxId,
+        amount: Decimal,
+        locked: bool,
+    ) -> Result<Self, RejectReason> {
+        if amount.normalize().scale() > MAX_AMOUNT_SCALE {
+            return Err(RejectReason::InvalidAmount);
+        }
+        Ok(OpeningBalanceTx {
+            client_id,
+            tx_id,
+            amount,
+            locked,
+        })
+    }
+
+    pub fn client_id(&self) -> ClientId {
+        self.client_id
+    }
+
+    pub fn tx_id(&self) -> TxId {
+        self.tx_id
+    }
+
+    pub fn amount(&self) -> Decimal {
+        self.amount
+    }
+
+    pub fn locked(&self) -> bool {
+        self.locked
+    }
+}
+
+/// Type column of an opening balance of a locked account.
+pub const OPENING_BALANCE_LOCKED: &str = "opening_balance_locked";
+
+#[derive(Debug, Clone, Copy)]
 pub enum Tx {
     Deposit(DepositTx),
     Withdrawal(WithdrawalTx),
     Dispute(DisputeTx),
     Resolve(ResolveTx),
     Chargeback(ChargebackTx),
+    OpeningBalance(OpeningBalanceTx),
+}
+
+/// Provider spellings accepted in lenient mode besides the canonical names,
+/// matched after lowercasing.
+const TYPE_ALIASES: &[(&str, TxType)] = &[
+    ("withdraw", TxType::Withdrawal),
+    ("charge_back", TxType::Chargeback),
+    ("charge-back", TxType::Chargeback),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TxType {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+    OpeningBalance,
+}
+
+impl TxType {
+    pub const ALL: [TxType; 6] = [
+        TxType::Deposit,
+        TxType::Withdrawal,
+        TxType::Dispute,
+        TxType::Resolve,
+        TxType::Chargeback,
+        TxType::OpeningBalance,
+    ];
+
+    /// Name used in the `type` column.
+    pub fn name(&self) -> &'static str {
+        match self {
+            TxType::Deposit => "deposit",
+            TxType::Withdrawal => "withdrawal",
+            TxType::Dispute => "dispute",
+            TxType::Resolve => "resolve",
+            TxType::Chargeback => "chargeback",
+            TxType::OpeningBalance => "opening_balance",
+        }
+    }
+
+    /// Parses `s` ignoring case and accepting the aliases in `TYPE_ALIASES`.
+    pub fn parse_lenient(s: &str) -> Option<TxType> {
+        TxType::ALL
+            .into_iter()
+            .map(|tx_type| (tx_type.name(), tx_type))
+            .chain([(OPENING_BALANCE_LOCKED, TxType::OpeningBalance)])
+            .chain(TYPE_ALIASES.iter().copied())
+            .find(|(name, _)| name.eq_ignore_ascii_case(s))
+            .map(|(_, tx_type)| tx_type)
+    }
+}
+
+impl fmt::Display for TxType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for TxType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == OPENING_BALANCE_LOCKED {
+            return Ok(TxType::OpeningBalance);
+        }
+        TxType::ALL
+            .into_iter()
+            .find(|tx_type| tx_type.name() == s)
+            .ok_or_else(|| format!("unknown transaction type `{s}`"))
+    }
 }
 
-impl TryFrom<CsvRow> for Tx {
-    // Simple error type as we are ignoring malformed rows anyway
-    type Error = ();
-
-    fn try_from(value: CsvRow) -> Result<Self, Self::Error> {
-        match value.r#type.as_str() {
-            "deposit" => Ok(Tx::Deposit(DepositTx {
-                client_id: value.client,
-                tx_id: value.tx,
-                amount: value.amount.ok_or(())?,
-            })),
-            "withdrawal" => Ok(Tx::Withdrawal(WithdrawalTx {
-                client_id: value.client,
-                tx_id: value.tx,
-                amount: value.amount.ok_or(())?,
-            })),
-            "dispute" => Ok(Tx::Dispute(DisputeTx {
-                client_id: value.client,
-                tx_id: value.tx,
-            })),
-            "resolve" => Ok(Tx::Resolve(ResolveTx {
-                client_id: value.client,
-                tx_id: value.tx,
-            })),
-            "chargeback" => Ok(Tx::Chargeback(ChargebackTx {
-                client_id: value.client,
-                tx_id: value.tx,
-            })),
-            _ => Err(()),
+impl Tx {
+    pub fn tx_type(&self) -> TxType {
+        match self {
+            Tx::Deposit(_) => TxType::Deposit,
+            Tx::Withdrawal(_) => TxType::Withdrawal,
+            Tx::Dispute(_) => TxType::Dispute,
+            Tx::Resolve(_) => TxType::Resolve,
+            Tx::Chargeback(_) => TxType::Chargeback,
+            Tx::OpeningBalance(_) => TxType::OpeningBalance,
         }
     }
+
+    /// Name in the `type` column, which tells locked opening balances apart.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Tx::OpeningBalance(tx) if tx.locked => OPENING_BALANCE_LOCKED,
+            _ => self.tx_type().name(),
+        }
+    }
+
+    pub fn client_id(&self) -> ClientId {
+        match self {
+            Tx::Deposit(tx) => tx.client_id,
+            Tx::Withdrawal(tx) => tx.client_id,
+            Tx::Dispute(tx) => tx.client_id,
+            Tx::Resolve(tx) => tx.client_id,
+            Tx::Chargeback(tx) => tx.client_id,
+            Tx::OpeningBalance(tx) => tx.client_id,
+        }
+    }
+
+    pub fn tx_id(&self) -> TxId {
+        match self {
+            Tx::Deposit(tx) => tx.tx_id,
+            Tx::Withdrawal(tx) => tx.tx_id,
+            Tx::Dispute(tx) => tx.tx_id,
+            Tx::Resolve(tx) => tx.tx_id,
+            Tx::Chargeback(tx) => tx.tx_id,
+            Tx::OpeningBalance(tx) => tx.tx_id,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_constructors_check_the_amount() {
+        let deposit = DepositTx::new(1, 2, dec!(1.2345)).unwrap();
+        assert_eq!(
+            (deposit.client_id(), deposit.tx_id(), deposit.amount()),
+            (1, 2, dec!(1.2345))
+        );
+        assert!(WithdrawalTx::new(1, 3, dec!(0.0001)).is_ok());
+        // Trailing zeros are kept, but don't count against the scale
+        let deposit = DepositTx::new(1, 2, dec!(1.50000)).unwrap();
+        assert_eq!(deposit.amount().to_string(), "1.50000");
+
+        for amount in [dec!(0), dec!(-1), dec!(1.23456)] {
+            assert_eq!(
+                DepositTx::new(1, 2, amount).unwrap_err(),
+                RejectReason::InvalidAmount
+            );
+            assert_eq!(
+                WithdrawalTx::new(1, 2, amount).unwrap_err(),
+                RejectReason::InvalidAmount
+            );
+        }
+
+        let opening = OpeningBalanceTx::new(1, 4, dec!(-2.50), true).unwrap();
+        assert_eq!(
+            (
+                opening.client_id(),
+                opening.tx_id(),
+                opening.amount(),
+                opening.locked()
+            ),
+            (1, 4, dec!(-2.50), true)
+        );
+        assert!(OpeningBalanceTx::new(1, 4, dec!(0), false).is_ok());
+        assert_eq!(
+            OpeningBalanceTx::new(1, 4, dec!(1.23456), false).unwrap_err(),
+            RejectReason::InvalidAmount
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "csv")]
+    fn test_csv_rows_are_checked_too() {
+        use crate::io::csv::CsvRow;
+
+        let row = |r#type: &str, amount| CsvRow {
+            r#type: r#type.to_string(),
+            client: 1,
+            tx: 2,
+            amount: Some(amount),
+        };
+        assert!(Tx::try_from(row("deposit", dec!(1.5))).is_ok());
+        assert!(Tx::try_from(row("deposit", dec!(-1))).is_err());
+        assert!(Tx::try_from_lenient(row("Withdrawal", dec!(1.23456))).is_none());
+        // Opening balances may be negative
+        assert!(Tx::try_from(row("opening_balance", dec!(-1))).is_ok());
+        assert!(Tx::try_from(row("opening_balance", dec!(1.23456))).is_err());
+    }
 }
@@ -0,0 +1,231 @@
+//! Runs killed, interrupted or failing at the worst moments recover to the
+//! balances of an undisturbed run. Needs `--features chaos`.
+#![cfg(feature = "chaos")]
+
+use std::{
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+const ROWS: u64 = 2_000;
+
+/// Deposits, withdrawals and disputes over 20 clients, larger than the
+/// reader's buffer so that it is read in several chunks.
+fn transactions() -> String {
+    let mut input = String::from("type,client,tx,amount\n");
+    for tx in 1..=ROWS {
+        let client = tx % 20 + 1;
+        let row = match tx % 10 {
+            0..=5 => format!("deposit,{client},{tx},{}.{}", tx % 7 + 1, tx % 10),
+            6 | 7 => format!("withdrawal,{client},{tx},{}.5", tx % 3),
+            8 => format!("dispute,{client},{},", tx.saturating_sub(20)),
+            _ => match tx % 3 {
+                0 => format!("resolve,{client},{},", tx.saturating_sub(21)),
+                1 => format!("chargeback,{client},{},", tx.saturating_sub(21)),
+                _ => format!("dispute,{client},{},", tx.saturating_sub(19)),
+            },
+        };
+        writeln!(input, "{row}").unwrap();
+    }
+    input
+}
+
+struct Run {
+    dir: tempfile::TempDir,
+    input: PathBuf,
+}
+
+impl Run {
+    fn new() -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input.csv");
+        fs::write(&input, transactions()).unwrap();
+        Run { dir, input }
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.path().join(name)
+    }
+
+    fn tpe(&self, args: &[&str]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_tpe"))
+            .arg(&self.input)
+            .args(args)
+            .current_dir(self.dir.path())
+            .output()
+            .unwrap()
+    }
+
+    /// The balances of a run, sorted as clients come out in no set order.
+    fn balances(&self, args: &[&str]) -> Vec<String> {
+        let output = self.tpe(args);
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        let mut lines: Vec<_> = String::from_utf8(output.stdout)
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect();
+        lines.sort();
+        lines
+    }
+}
+
+fn status(manifest: &Path) -> String {
+    let manifest: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(manifest).unwrap()).unwrap();
+    manifest["status"].as_str().unwrap().to_string()
+}
+
+#[test]
+fn test_interrupted_anywhere_resumes_to_the_same_balances() {
+    let run = Run::new();
+    let expected = run.balances(&[]);
+
+    for rows in [0, 1, 7, 999, 1_000, ROWS - 1] {
+        let chaos = format!("interrupt={rows}");
+        let checkpoint = ["--save-state", "state", "--manifest", "manifest.json"];
+        let output = run.tpe(&[&["--chaos", &chaos][..], &checkpoint].concat());
+        assert!(!output.status.success());
+        assert!(output.stdout.is_empty());
+        assert_eq!(status(&run.path("manifest.json")), "interrupted");
+
+        let resumed = run.balances(&[&["--resume", "manifest.json"][..], &checkpoint].concat());
+        assert_eq!(resumed, expected, "interrupted after {rows} rows");
+    }
+}
+
+#[test]
+fn test_crash_leaves_the_last_checkpoint_usable() {
+    let run = Run::new();
+    let expected = run.balances(&[]);
+    let output = run.tpe(&[
+        "--chaos",
+        "interrupt=700",
+        "--save-state",
+        "state",
+        "--manifest",
+        "manifest.json",
+    ]);
+    assert!(!output.status.success());
+    let checkpoint = fs::read(run.path("manifest.json")).unwrap();
+
+    for abort in ["0", "500", "state", "manifest", "output"] {
+        let output = run.tpe(&[
+            "--chaos",
+            &format!("abort={abort}"),
+            "--resume",
+            "manifest.json",
+            "--save-state",
+            "state.next",
+            "--manifest",
+            "manifest.next.json",
+        ]);
+        assert!(!output.status.success(), "abort={abort}");
+        assert!(output.stdout.is_empty(), "abort={abort}");
+        assert_eq!(fs::read(run.path("manifest.json")).unwrap(), checkpoint);
+
+        let resumed = run.balances(&["--resume", "manifest.json"]);
+        assert_eq!(resumed, expected, "abort={abort}");
+    }
+    // Killed only once everything but the balances was saved
+    assert_eq!(status(&run.path("manifest.next.json")), "completed");
+    assert_eq!(run.balances(&["--resume", "manifest.next.json"]), expected);
+}
+
+#[test]
+fn test_read_errors_are_resumed_past() {
+    let run = Run::new();
+    let expected = run.balances(&[]);
+    let checkpoint = ["--save-state", "state", "--manifest", "manifest.json"];
+
+    let mut failures = 0;
+    for seed in 0.. {
+        let chaos = format!("read-errors=0.2,seed={seed}");
+        let resume: &[&str] = if seed == 0 {
+            &[]
+        } else {
+            &["--resume", "manifest.json"]
+        };
+        let output = run.tpe(&[&["--chaos", &chaos][..], resume, &checkpoint].concat());
+        if output.status.success() {
+            let mut balances: Vec<_> = String::from_utf8(output.stdout)
+                .unwrap()
+                .lines()
+                .map(str::to_string)
+                .collect();
+            balances.sort();
+            assert_eq!(balances, expected);
+            break;
+        }
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("injected read error"), "{stderr}");
+        assert!(output.stdout.is_empty());
+        assert_eq!(status(&run.path("manifest.json")), "read_failed");
+        failures += 1;
+        assert!(failures < 100, "never got through the input");
+    }
+    assert!(failures > 0, "no read failed, the test proves nothing");
+}
+
+#[test]
+fn test_random_seed_is_printed() {
+    let run = Run::new();
+    let output = run.tpe(&["--chaos", "read-errors=1"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let seed = stderr
+        .lines()
+        .find_map(|line| line.strip_prefix("chaos: seed "))
+        .unwrap_or_else(|| panic!("no seed in {stderr}"));
+    assert!(seed.parse::<u64>().is_ok(), "{seed}");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_signal_while_saving_still_completes() {
+    use std::io::{BufRead, BufReader, Read};
+
+    let run = Run::new();
+    let expected = run.balances(&[]);
+    let mut child = Command::new(env!("CARGO_BIN_EXE_tpe"))
+        .arg(&run.input)
+        .args(["--chaos", "slow-storage=2s", "--save-state", "state"])
+        .args(["--manifest", "manifest.json"])
+        .current_dir(run.dir.path())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    // Signalled once all rows are applied and the run stalls before writing the state
+    let mut stderr = BufReader::new(child.stderr.take().unwrap());
+    let mut seen = String::new();
+    loop {
+        let mut line = String::new();
+        assert!(
+            stderr.read_line(&mut line).unwrap() > 0,
+            "never stalled: {seen}"
+        );
+        seen += &line;
+        if line.starts_with("chaos: stalling") {
+            break;
+        }
+    }
+    let kill = Command::new("kill")
+        .args(["-TERM", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(kill.success());
+
+    stderr.read_to_string(&mut seen).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{seen}");
+    assert_eq!(status(&run.path("manifest.json")), "completed");
+    assert_eq!(run.balances(&["--resume", "manifest.json"]), expected);
+}
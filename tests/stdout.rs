@@ -0,0 +1,214 @@
+//! Stdout carries the balances and nothing else, whatever else a run reports.
+#![cfg(feature = "cli")]
+
+use std::{
+    fs,
+    process::{Command, Output},
+};
+
+const INPUT: &str = "\
+type,client,tx,amount
+deposit,1,1,5.0
+deposit,2,2,3.0
+withdrawal,1,3,1.5
+dispute,2,1,
+deposit,1,4,abc
+";
+
+const BALANCES: &str = "\
+client,available,held,total,locked
+1,3.5,0,3.5,false
+2,3.0,0,3.0,false
+";
+
+fn tpe(args: &[&str]) -> Output {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("input.csv");
+    fs::write(&input, INPUT).unwrap();
+    let security = dir.path().join("security.csv");
+    Command::new(env!("CARGO_BIN_EXE_tpe"))
+        .arg(&input)
+        .args(["--pipeline", "--alert", "total>4", "--security-report"])
+        .arg(&security)
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn test_diagnostics_stay_off_stdout() {
+    let output = tpe(&["--summary", "--top-n", "1"]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), BALANCES);
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    for diagnostic in ["alert:", "pipeline:", "rows:", "top 1", "security:"] {
+        assert!(stderr.contains(diagnostic), "no `{diagnostic}` in {stderr}");
+    }
+}
+
+#[test]
+fn test_quiet_prints_only_balances() {
+    let output = tpe(&["--quiet"]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), BALANCES);
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "");
+
+    // Errors are still reported
+    let output = tpe(&["--quiet", "--max-clients", "1"]);
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(!output.stderr.is_empty());
+}
+
+#[test]
+fn test_disabled_types_are_skipped() {
+    let dir = tempfile::tempdir().unwrap();
+    let rejects = dir.path().join("rejects.csv");
+    let output = tpe(&[
+        "--disable",
+        "withdrawal",
+        "--summary",
+        "--rejects",
+        rejects.to_str().unwrap(),
+    ]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+client,available,held,total,locked
+1,5.0,0,5.0,false
+2,3.0,0,3.0,false
+"
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("rows: 5 (applied 2, rejected 2, skipped 1)"),
+        "{stderr}"
+    );
+    // Neither applied nor rejected
+    let rejects = fs::read_to_string(&rejects).unwrap();
+    assert_eq!(rejects.lines().count(), 3, "{rejects}");
+    assert!(!rejects.contains("withdrawal"), "{rejects}");
+}
+
+#[test]
+fn test_input_not_matching_its_manifest_prints_nothing() {
+    use sha2::{Digest, Sha256};
+
+    let dir = tempfile::tempdir().unwrap();
+    let manifest = dir.path().join("input.json");
+    let sha256: String = Sha256::digest(INPUT)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    let write_manifest = |rows: u64, sha256: &str| {
+        fs::write(
+            &manifest,
+            format!(r#"{{"rows": {rows}, "sha256": "{sha256}"}}"#),
+        )
+        .unwrap()
+    };
+    let manifest = manifest.to_str().unwrap();
+    let rejects = dir.path().join("rejects.csv");
+    let args = [
+        "--input-manifest",
+        manifest,
+        "--rejects",
+        rejects.to_str().unwrap(),
+    ];
+
+    write_manifest(5, &sha256);
+    let output = tpe(&args);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), BALANCES);
+    assert!(rejects.exists());
+
+    // Reports of input that doesn't match its manifest are removed
+    write_manifest(6, &sha256);
+    let output = tpe(&args);
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("5 rows instead of the 6"));
+    assert!(!rejects.exists());
+
+    write_manifest(4, &sha256);
+    let output = tpe(&args);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("more than the 4 rows"));
+    assert!(!rejects.exists());
+
+    write_manifest(5, &"0".repeat(64));
+    let output = tpe(&args);
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("truncated or was altered"));
+    assert!(!rejects.exists());
+}
+
+#[cfg(feature = "otel")]
+#[test]
+fn test_otel_exports_beside_the_balances() {
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    // A stand-in collector noting the path of every request
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let endpoint = format!("http://{}", listener.local_addr().unwrap());
+    let paths = Arc::new(Mutex::new(Vec::new()));
+    let seen = paths.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let seen = seen.clone();
+            thread::spawn(move || {
+                let mut rdr = BufReader::new(stream.unwrap());
+                loop {
+                    let mut request = String::new();
+                    if rdr.read_line(&mut request).unwrap_or(0) == 0 {
+                        return;
+                    }
+                    let mut length = 0;
+                    loop {
+                        let mut header = String::new();
+                        rdr.read_line(&mut header).unwrap();
+                        if header.trim().is_empty() {
+                            break;
+                        }
+                        if let Some((name, value)) = header.split_once(':')
+                            && name.eq_ignore_ascii_case("content-length")
+                        {
+                            length = value.trim().parse().unwrap();
+                        }
+                    }
+                    rdr.read_exact(&mut vec![0; length]).unwrap();
+                    let path = request.split_whitespace().nth(1).unwrap_or_default();
+                    seen.lock().unwrap().push(path.to_string());
+                    rdr.get_mut()
+                        .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                        .unwrap();
+                }
+            });
+        }
+    });
+
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("input.csv");
+    fs::write(&input, INPUT).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_tpe"))
+        .arg(&input)
+        .arg("--otel")
+        .env("OTEL_EXPORTER_OTLP_ENDPOINT", &endpoint)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), BALANCES);
+
+    let paths = paths.lock().unwrap();
+    for path in ["/v1/traces", "/v1/metrics"] {
+        assert!(paths.iter().any(|p| p == path), "nothing sent to {path}");
+    }
+}